@@ -0,0 +1,266 @@
+//! Computation of the calibration from touches on known targets and the mapping of touches onto the monitor,
+//! shared by the calibrator frontends, the driver and the packet viewer.
+
+use serde::{Deserialize, Serialize};
+
+use crate::geo::{fit_affine, Affine, NormalizedPoint, Origin, Point2D, Rotation, Transform, AABB};
+use crate::units::*;
+
+/// Number of calibration targets.
+pub const TARGETS_NUM: usize = 4;
+/// Number of calibration targets for an affine calibration, the first ones of [TARGET_FRACTIONS].
+pub const AFFINE_TARGETS_NUM: usize = 3;
+
+/// Positions of the calibration targets as fractions of the monitor size.
+/// The order is upper-left, upper-right, lower-left, lower-right.
+pub const TARGET_FRACTIONS: [(f32, f32); TARGETS_NUM] =
+    [(0.1, 0.1), (0.9, 0.1), (0.1, 0.9), (0.9, 0.9)];
+
+/// Compute the positions of the calibration targets within a monitor area.
+pub fn target_positions(area: AABB<ScreenSpace>) -> [Point2D<ScreenSpace>; TARGETS_NUM] {
+    TARGET_FRACTIONS.map(|(fx, fy)| area.denormalize(NormalizedPoint::new(fx, fy)))
+}
+
+/// Everything needed to map raw touch coordinates onto a monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Touch coordinates of the monitor edges, after swapping axes.
+    pub calibration_points: AABB<TouchSpace>,
+    /// The corner of the panel where the touchscreen reports its minimum coordinates.
+    pub origin: Origin,
+    /// If raw X coordinates run along the monitor's Y axis (e.g. a rotated foil).
+    pub swap_axes: bool,
+    /// Rotation of the monitor since the calibration points were measured, e.g. for a panel mounted in portrait orientation.
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// If set, raw touch positions are mapped with this matrix instead of the calibration points,
+    /// which also works for panels that are slightly rotated or skewed. The other fields are then only an approximation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affine: Option<Affine>,
+}
+
+impl Calibration {
+    /// Compute the calibration from one touch coordinate per target, in the order of [TARGET_FRACTIONS].
+    pub fn from_touches(touches: &[Point2D<TouchSpace>; TARGETS_NUM]) -> Self {
+        let swap_axes = axes_swapped(touches);
+        let touches = if swap_axes {
+            touches.map(|p| Transform::swap_axes().apply(p))
+        } else {
+            *touches
+        };
+
+        let (left, right) = (TARGET_FRACTIONS[0].0, TARGET_FRACTIONS[1].0);
+        let (top, bottom) = (TARGET_FRACTIONS[0].1, TARGET_FRACTIONS[2].1);
+
+        // Average the two touches on each side of the monitor and extrapolate to the edges.
+        let (x_min, x_max) = extrapolate_to_edges(
+            (touches[0].x.float() + touches[2].x.float()) / 2.0,
+            (touches[1].x.float() + touches[3].x.float()) / 2.0,
+            left,
+            right,
+        );
+        let (y_min, y_max) = extrapolate_to_edges(
+            (touches[0].y.float() + touches[1].y.float()) / 2.0,
+            (touches[2].y.float() + touches[3].y.float()) / 2.0,
+            top,
+            bottom,
+        );
+
+        // If coordinates decrease towards the right or bottom edge the panel is mirrored.
+        let origin = Origin::from_flips(x_min > x_max, y_min > y_max);
+
+        let calibration_points = AABB::new(
+            udim::round_from(x_min),
+            udim::round_from(y_min),
+            udim::round_from(x_max),
+            udim::round_from(y_max),
+        );
+
+        // The targets were shown in the current rotation of the monitor, so it is already accounted for.
+        Self {
+            calibration_points,
+            origin,
+            swap_axes,
+            rotation: Rotation::Normal,
+            affine: None,
+        }
+    }
+
+    /// Compute an affine calibration from one touch coordinate on each of the first targets, in the order of
+    /// [TARGET_FRACTIONS]. The affine map is a least-squares fit, so touching more than [AFFINE_TARGETS_NUM] targets
+    /// averages out the error of single touches.
+    /// Returns `None` for fewer than [AFFINE_TARGETS_NUM] touches or if they lie on a line.
+    pub fn from_affine_touches(touches: &[Point2D<TouchSpace>]) -> Option<Self> {
+        let mut pairs = [(Point2D::from((0, 0)), NormalizedPoint::new(0.0, 0.0)); TARGETS_NUM];
+        for (pair, (touch, (fx, fy))) in pairs.iter_mut().zip(touches.iter().zip(TARGET_FRACTIONS))
+        {
+            *pair = (*touch, NormalizedPoint::new(fx, fy));
+        }
+        let affine = fit_affine(&pairs[..touches.len().min(TARGETS_NUM)])?;
+
+        // The first three targets are corners of a rectangle, so without a touch on the lower-right one
+        // it would have been touched at the fourth corner of the parallelogram spanned by the touches.
+        let [upper_left, upper_right, lower_left] = [touches[0], touches[1], touches[2]];
+        let lower_right = touches.get(3).copied().unwrap_or_else(|| {
+            Point2D::from((
+                upper_right.x.value() + lower_left.x.value() - upper_left.x.value(),
+                upper_right.y.value() + lower_left.y.value() - upper_left.y.value(),
+            ))
+        });
+        Some(Self {
+            affine: Some(affine),
+            ..Self::from_touches(&[upper_left, upper_right, lower_left, lower_right])
+        })
+    }
+
+    /// The transformation of raw touch positions so that their axes run in the same directions as the monitor's.
+    /// Panels whose origin is not in the upper-left corner are mirrored within the calibration points.
+    pub fn touch_transform(&self) -> Transform<TouchSpace> {
+        let swap = if self.swap_axes {
+            Transform::swap_axes()
+        } else {
+            Transform::identity()
+        };
+        swap.then(Transform::mirror_within(
+            self.calibration_points,
+            self.origin.flips_x(),
+            self.origin.flips_y(),
+        ))
+    }
+
+    /// Map a raw touch position to its position relative to the monitor, rotated like the monitor.
+    pub fn normalize(&self, position: Point2D<TouchSpace>) -> NormalizedPoint {
+        let normalized = match self.affine {
+            Some(affine) => affine.apply(position),
+            None => self
+                .calibration_points
+                .normalize(self.touch_transform().apply(position)),
+        };
+        normalized.rotate(self.rotation.quarter_turns())
+    }
+
+    /// Map a raw touch position to a position within the monitor area.
+    pub fn map_to(
+        &self,
+        position: Point2D<TouchSpace>,
+        monitor_area: AABB<ScreenSpace>,
+    ) -> Point2D<ScreenSpace> {
+        monitor_area.denormalize(self.normalize(position))
+    }
+}
+
+/// Checks if the touch coordinates change more along the raw Y axis than the raw X axis
+/// when moving between targets that only differ in their screen X coordinate.
+fn axes_swapped(touches: &[Point2D<TouchSpace>; TARGETS_NUM]) -> bool {
+    let upper = touches[1] - touches[0];
+    let lower = touches[3] - touches[2];
+
+    let dx = upper.x.value().abs() + lower.x.value().abs();
+    let dy = upper.y.value().abs() + lower.y.value().abs();
+    dy > dx
+}
+
+/// Given coordinates `a` and `b` measured at the fractions `fa` and `fb` of the monitor size,
+/// linearly extrapolate the coordinates at the monitor edges (fractions 0 and 1).
+fn extrapolate_to_edges(a: f32, b: f32, fa: f32, fb: f32) -> (f32, f32) {
+    let slope = (b - a) / (fb - fa);
+    (a - fa * slope, a + (1.0 - fa) * slope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONITOR: (i32, i32, i32, i32) = (0, 0, 1000, 1000);
+
+    #[test]
+    fn test_calibration_regular() {
+        let touches: [Point2D<TouchSpace>; 4] = [
+            (400, 400).into(),
+            (3600, 400).into(),
+            (400, 3600).into(),
+            (3600, 3600).into(),
+        ];
+        let calibration = Calibration::from_touches(&touches);
+
+        assert!(!calibration.swap_axes);
+        assert_eq!(Origin::TopLeft, calibration.origin);
+        assert_eq!(
+            AABB::from((0, 0, 4000, 4000)),
+            calibration.calibration_points
+        );
+        assert_eq!(
+            Point2D::from((100, 900)),
+            calibration.map_to((400, 3600).into(), MONITOR.into())
+        );
+    }
+
+    #[test]
+    fn test_calibration_swapped_and_mirrored() {
+        // Raw Y grows to the right of the screen, raw X grows upward.
+        let touches: [Point2D<TouchSpace>; 4] = [
+            (3600, 400).into(),
+            (3600, 3600).into(),
+            (400, 400).into(),
+            (400, 3600).into(),
+        ];
+        let calibration = Calibration::from_touches(&touches);
+
+        assert!(calibration.swap_axes);
+        assert_eq!(Origin::BottomLeft, calibration.origin);
+        for (touch, target) in touches.iter().zip(target_positions(MONITOR.into())) {
+            assert_eq!(target, calibration.map_to(*touch, MONITOR.into()));
+        }
+    }
+
+    #[test]
+    fn test_calibration_rotated() {
+        let mut calibration = Calibration {
+            calibration_points: AABB::from((0, 0, 4000, 4000)),
+            origin: Origin::TopLeft,
+            swap_axes: false,
+            rotation: Rotation::Normal,
+            affine: None,
+        };
+        // A touch near the upper left corner of the unrotated panel.
+        let touch = Point2D::from((400, 800));
+        for (rotation, expected) in [
+            (Rotation::Normal, (100, 200)),
+            (Rotation::Left, (800, 100)),
+            (Rotation::Inverted, (900, 800)),
+            (Rotation::Right, (200, 900)),
+        ] {
+            calibration.rotation = rotation;
+            assert_eq!(
+                Point2D::from(expected),
+                calibration.map_to(touch, MONITOR.into()),
+                "{}",
+                rotation
+            );
+        }
+    }
+
+    #[test]
+    fn test_calibration_affine() {
+        // The panel is slightly rotated, so the raw coordinates of targets in the same row differ in Y.
+        let touches: [Point2D<TouchSpace>; 4] = [
+            (400, 400).into(),
+            (3600, 560).into(),
+            (240, 3600).into(),
+            (3440, 3760).into(),
+        ];
+        let calibration = Calibration::from_affine_touches(&touches[..3]).unwrap();
+        assert!(!calibration.swap_axes);
+        assert_eq!(Origin::TopLeft, calibration.origin);
+        for (touch, target) in touches.iter().zip(target_positions(MONITOR.into())) {
+            assert_eq!(target, calibration.map_to(*touch, MONITOR.into()));
+        }
+
+        // With the fourth corner the fit is the same, since it lies on the same map.
+        let fitted = Calibration::from_affine_touches(&touches).unwrap();
+        for (touch, target) in touches.iter().zip(target_positions(MONITOR.into())) {
+            assert_eq!(target, fitted.map_to(*touch, MONITOR.into()));
+        }
+        assert_eq!(None, Calibration::from_affine_touches(&touches[..2]));
+    }
+}
@@ -0,0 +1,39 @@
+//! Errors of packet parsing.
+
+use core::fmt;
+
+use crate::units::DimE;
+
+/// Errors that can happen during parsing of a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsePacketError {
+    UnexpectedTag(u8),
+    WrongResolution(DimE),
+    UnsupportedResolution(u8),
+    /// A byte of a serial packet has the start bit set where it must not, or the first byte lacks it.
+    SerialFraming(u8),
+}
+
+impl fmt::Display for ParsePacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePacketError::UnexpectedTag(tag) => {
+                f.write_fmt(format_args!("Unexpected packet tag: {}", tag))
+            }
+            ParsePacketError::WrongResolution(dim) => f.write_fmt(format_args!(
+                "{:?} value is out of range of given resolution",
+                dim
+            )),
+            ParsePacketError::UnsupportedResolution(resolution) => {
+                f.write_fmt(format_args!("Unsupported resolution: {} bits", resolution))
+            }
+            ParsePacketError::SerialFraming(byte) => f.write_fmt(format_args!(
+                "Byte {:#04x} does not fit the framing of a serial packet",
+                byte
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParsePacketError {}
@@ -0,0 +1,207 @@
+//! Canned packets of typical touches together with what they decode to, shared by the tests of the protocol,
+//! the driver, the filters and the gestures. Only compiled for tests and with the `fixtures` feature.
+//!
+//! All touches are reported at a resolution of 12 bits, so (2048, 2048) is the center of the panel.
+
+use crate::error::ParsePacketError;
+use crate::packet::{Contact, Packet, RawPacket, TouchState, USBPacket};
+use crate::units::{DimE, UdimRepr};
+
+/// Resolution in bits of all canned touches.
+pub const RESOLUTION: u8 = 12;
+
+/// A raw packet as sent by the touchscreen, and what it must decode to.
+#[derive(Debug, Clone, Copy)]
+pub struct Canned {
+    /// When the packet arrives, in milliseconds since the first packet of the fixture.
+    pub offset_ms: u64,
+    pub raw: RawPacket,
+    pub contact: u8,
+    pub touch_state: TouchState,
+    /// The decoded raw coordinates.
+    pub position: (UdimRepr, UdimRepr),
+}
+
+const fn canned(
+    offset_ms: u64,
+    raw: [u8; 6],
+    contact: u8,
+    touching: bool,
+    position: (UdimRepr, UdimRepr),
+) -> Canned {
+    Canned {
+        offset_ms,
+        raw: RawPacket(raw),
+        contact,
+        touch_state: if touching {
+            TouchState::IsTouching
+        } else {
+            TouchState::NotTouching
+        },
+        position,
+    }
+}
+
+impl Canned {
+    /// The packet that the raw packet must decode to.
+    pub fn decoded(&self) -> Packet {
+        let packet = USBPacket::new(self.touch_state, self.position.into(), RESOLUTION)
+            .expect("canned positions fit the resolution");
+        match self.contact {
+            0 => Packet::Touch(packet),
+            id => Packet::MultiTouch(Contact { id, packet }),
+        }
+    }
+}
+
+/// A short tap in the center of the panel that wobbles by a few units.
+pub const TAP: &[Canned] = &[
+    canned(
+        0,
+        [0x02, 0x03, 0x00, 0x08, 0x00, 0x08],
+        0,
+        true,
+        (2048, 2048),
+    ),
+    canned(
+        10,
+        [0x02, 0x03, 0xfe, 0x07, 0x02, 0x08],
+        0,
+        true,
+        (2050, 2046),
+    ),
+    canned(
+        80,
+        [0x02, 0x02, 0xfe, 0x07, 0x02, 0x08],
+        0,
+        false,
+        (2050, 2046),
+    ),
+];
+
+/// A drag from the left to the right across half of the panel.
+pub const DRAG: &[Canned] = &[
+    canned(
+        0,
+        [0x02, 0x03, 0xd0, 0x07, 0xe8, 0x03],
+        0,
+        true,
+        (1000, 2000),
+    ),
+    canned(
+        20,
+        [0x02, 0x03, 0xd0, 0x07, 0xdc, 0x05],
+        0,
+        true,
+        (1500, 2000),
+    ),
+    canned(
+        40,
+        [0x02, 0x03, 0xd0, 0x07, 0xd0, 0x07],
+        0,
+        true,
+        (2000, 2000),
+    ),
+    canned(
+        60,
+        [0x02, 0x03, 0xd0, 0x07, 0xc4, 0x09],
+        0,
+        true,
+        (2500, 2000),
+    ),
+    canned(
+        80,
+        [0x02, 0x03, 0xd0, 0x07, 0xb8, 0x0b],
+        0,
+        true,
+        (3000, 2000),
+    ),
+    canned(
+        100,
+        [0x02, 0x02, 0xd0, 0x07, 0xb8, 0x0b],
+        0,
+        false,
+        (3000, 2000),
+    ),
+];
+
+/// A second finger that touches and lifts while the first one rests in the upper left.
+pub const TWO_FINGERS: &[Canned] = &[
+    canned(
+        0,
+        [0x02, 0x03, 0xe8, 0x03, 0xe8, 0x03],
+        0,
+        true,
+        (1000, 1000),
+    ),
+    canned(
+        20,
+        [0x06, 0x0b, 0xb8, 0x0b, 0xb8, 0x0b],
+        1,
+        true,
+        (3000, 3000),
+    ),
+    canned(
+        40,
+        [0x02, 0x03, 0xe8, 0x03, 0xfc, 0x03],
+        0,
+        true,
+        (1020, 1000),
+    ),
+    canned(
+        60,
+        [0x06, 0x0a, 0xb8, 0x0b, 0xb8, 0x0b],
+        1,
+        false,
+        (3000, 3000),
+    ),
+    canned(
+        80,
+        [0x02, 0x02, 0xe8, 0x03, 0xfc, 0x03],
+        0,
+        false,
+        (1020, 1000),
+    ),
+];
+
+/// Raw packets that must be rejected when touch events are expected, and why.
+pub const MALFORMED: &[(RawPacket, ParsePacketError)] = &[
+    (
+        RawPacket([0xaa, 0x02, 0x35, 0x01, 0x39, 0x01]),
+        ParsePacketError::UnexpectedTag(0xaa),
+    ),
+    (
+        RawPacket([0x02, 0x02, 0x35, 0x11, 0x39, 0x01]),
+        ParsePacketError::WrongResolution(DimE::Y),
+    ),
+    (
+        RawPacket([0x02, 0x02, 0x35, 0x01, 0x39, 0x11]),
+        ParsePacketError::WrongResolution(DimE::X),
+    ),
+];
+
+/// The bytes of a fixture as read from the hidraw node.
+pub fn bytes(fixture: &[Canned]) -> impl Iterator<Item = u8> + '_ {
+    fixture.iter().flat_map(|canned| canned.raw.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketTag;
+
+    #[test]
+    fn test_fixtures_decode() {
+        let expected_tag = Some(PacketTag::TouchEvent);
+        for canned in [TAP, DRAG, TWO_FINGERS].concat() {
+            assert_eq!(
+                Ok(canned.decoded()),
+                Packet::try_parse(canned.raw, expected_tag)
+            );
+            assert_eq!(canned.raw.0, canned.decoded().encode().0);
+        }
+        for (raw, error) in MALFORMED {
+            assert_eq!(Err(*error), Packet::try_parse(*raw, expected_tag));
+        }
+    }
+}
@@ -0,0 +1,862 @@
+//! Representation of screen geometry.
+
+use core::{
+    cmp::{max, min},
+    fmt,
+    marker::PhantomData,
+    ops::Sub,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::units::*;
+
+/// A point of two coordinates in X and Y dimensions of a coordinate space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Point2D<S: Space> {
+    pub x: dimX<S>,
+    pub y: dimY<S>,
+}
+
+impl<S: Space> Point2D<S> {
+    /// Computes the Euclidean distance between two points.
+    pub fn euclidean_distance_to(&self, other: &Self) -> f32 {
+        let dx = (other.x - self.x).float();
+        let dy = (other.y - self.y).float();
+
+        libm::sqrtf(dx * dx + dy * dy)
+    }
+
+    /// Computes the Manhattan distance between two points.
+    pub fn manhattan_distance_to(&self, other: &Self) -> f32 {
+        let dx = libm::fabsf((other.x - self.x).float());
+        let dy = libm::fabsf((other.y - self.y).float());
+
+        dx + dy
+    }
+
+    /// Computes the magnitude of Vector.
+    pub fn vec_magnitude(&self) -> f32 {
+        self.euclidean_distance_to(&(0, 0).into())
+    }
+
+    /// Exchanges the X and Y coordinates, e.g. for touchscreens whose foil is rotated.
+    pub fn swapped(&self) -> Self {
+        Point2D {
+            x: udim::new(self.y.value()),
+            y: udim::new(self.x.value()),
+        }
+    }
+}
+
+impl<S: Space> Sub for Point2D<S> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Point2D {
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+        }
+    }
+}
+
+impl<S: Space> fmt::Display for Point2D<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!("(x: {}, y: {})", self.x, self.y))
+    }
+}
+
+/// Generic From instance to convert various things into Point2Ds.
+impl<S: Space> From<(UdimRepr, UdimRepr)> for Point2D<S> {
+    fn from((x, y): (UdimRepr, UdimRepr)) -> Self {
+        Point2D {
+            x: udim::new(x),
+            y: udim::new(y),
+        }
+    }
+}
+
+/// A point in normalized coordinates relative to some area, e.g. the calibration points or a monitor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizedPoint {
+    pub x: NormX,
+    pub y: NormY,
+}
+
+impl NormalizedPoint {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self {
+            x: Normalized::new(x),
+            y: Normalized::new(y),
+        }
+    }
+
+    /// Rotation about the center of the area by `quarter_turns` times 90 degrees, clockwise on the screen like [Transform::rotate].
+    pub fn rotate(self, quarter_turns: i32) -> Self {
+        let (x, y) = (self.x.value(), self.y.value());
+        let (x, y) = match quarter_turns.rem_euclid(4) {
+            0 => (x, y),
+            1 => (1.0 - y, x),
+            2 => (1.0 - x, 1.0 - y),
+            _ => (y, 1.0 - x),
+        };
+        Self::new(x, y)
+    }
+
+    /// Restricts the point to the area it is relative to.
+    pub fn clamped(self) -> Self {
+        Self {
+            x: self.x.clamped(),
+            y: self.y.clamped(),
+        }
+    }
+}
+
+impl fmt::Display for NormalizedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "(x: {:.3}, y: {:.3})",
+            self.x.value(),
+            self.y.value()
+        ))
+    }
+}
+
+/// The corner of the panel at which the touchscreen reports its minimum coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Origin {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Origin {
+    /// The origin of a panel whose X coordinates grow to the left if `flips_x` and whose Y coordinates grow upward if `flips_y`.
+    pub fn from_flips(flips_x: bool, flips_y: bool) -> Self {
+        match (flips_x, flips_y) {
+            (false, false) => Origin::TopLeft,
+            (true, false) => Origin::TopRight,
+            (false, true) => Origin::BottomLeft,
+            (true, true) => Origin::BottomRight,
+        }
+    }
+
+    /// The origin after additionally mirroring the X and/or Y coordinates.
+    pub fn mirrored(self, invert_x: bool, invert_y: bool) -> Self {
+        Self::from_flips(self.flips_x() != invert_x, self.flips_y() != invert_y)
+    }
+
+    /// If touch X coordinates grow to the left.
+    pub fn flips_x(&self) -> bool {
+        matches!(self, Origin::TopRight | Origin::BottomRight)
+    }
+
+    /// If touch Y coordinates grow upward.
+    pub fn flips_y(&self) -> bool {
+        matches!(self, Origin::BottomLeft | Origin::BottomRight)
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            Origin::TopLeft => "top-left",
+            Origin::TopRight => "top-right",
+            Origin::BottomLeft => "bottom-left",
+            Origin::BottomRight => "bottom-right",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Rotation of a display as reported by xrandr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    #[default]
+    Normal,
+    /// Rotated 90 degrees counter-clockwise.
+    Left,
+    Inverted,
+    /// Rotated 90 degrees clockwise.
+    Right,
+}
+
+impl Rotation {
+    /// The rotation by `quarter_turns`, the inverse of [Rotation::quarter_turns].
+    pub fn from_quarter_turns(quarter_turns: i32) -> Self {
+        match quarter_turns.rem_euclid(4) {
+            0 => Rotation::Normal,
+            1 => Rotation::Left,
+            2 => Rotation::Inverted,
+            _ => Rotation::Right,
+        }
+    }
+
+    /// Checks if the display is higher than wide, assuming a landscape panel.
+    pub fn is_portrait(&self) -> bool {
+        matches!(self, Rotation::Left | Rotation::Right)
+    }
+
+    /// Quarter turns that map positions on the unrotated monitor to the rotated one, see [Transform::rotate].
+    pub fn quarter_turns(&self) -> i32 {
+        match self {
+            Rotation::Normal => 0,
+            Rotation::Left => 1,
+            Rotation::Inverted => 2,
+            Rotation::Right => 3,
+        }
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Rotation::Normal => "normal",
+            Rotation::Left => "left",
+            Rotation::Inverted => "inverted",
+            Rotation::Right => "right",
+        };
+        f.write_str(description)
+    }
+}
+
+/// A range of values between a minimum and maximum.
+/// The fields are private to uphold the invariant that min <= max.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Range<D: Dim, S: Space> {
+    min: udim<D, S>,
+    max: udim<D, S>,
+}
+
+impl<D: Dim, S: Space> Range<D, S> {
+    /// Creates a new Range between x1 and x2.
+    pub fn new(x1: udim<D, S>, x2: udim<D, S>) -> Self {
+        Self {
+            min: min(x1, x2),
+            max: max(x1, x2),
+        }
+    }
+
+    /// Returns the minimum value of the Range.
+    pub fn min(&self) -> udim<D, S> {
+        self.min
+    }
+
+    /// Returns the maximum value of the Range.
+    pub fn max(&self) -> udim<D, S> {
+        self.max
+    }
+
+    /// Returns the length of a Range.
+    pub fn length(&self) -> udim<D, S> {
+        self.max - self.min
+    }
+
+    /// Computes the linear factor of a value inside a range.
+    /// Note that the factor is 1 at the minimum and 0 at the maximum, use [Range::normalize] for the reverse.
+    pub fn linear_factor(&self, x: udim<D, S>) -> f32 {
+        // x = t * min + (1 - t) * max
+        // solve for t
+        // => t = (max - x)/(max - min)
+        if self.max == self.min {
+            0.0
+        } else {
+            let t = (self.max - x).float() / (self.max - self.min).float();
+            t
+        }
+    }
+
+    /// Computes a linear interpolation in a range.
+    pub fn lerp(&self, t: f32) -> udim<D, S> {
+        self.min * t + self.max * (1.0 - t)
+    }
+
+    /// Computes the midpoint of a range.
+    pub fn midpoint(&self) -> udim<D, S> {
+        self.lerp(0.5)
+    }
+
+    /// Computes the position of a value relative to the range.
+    pub fn normalize(&self, x: udim<D, S>) -> Normalized<D> {
+        if self.max == self.min {
+            Normalized::new(0.0)
+        } else {
+            Normalized::new((x - self.min).float() / self.length().float())
+        }
+    }
+
+    /// Computes the value at a relative position in the range, rounded to the nearest integer.
+    pub fn denormalize(&self, t: Normalized<D>) -> udim<D, S> {
+        self.min + udim::round_from(self.length().float() * t.value())
+    }
+}
+
+impl<D: Dim, S: Space> fmt::Display for Range<D, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!("({}, {})", self.min, self.max))
+    }
+}
+
+/// Generic From instance to convert various things into Ranges.
+impl<D: Dim, S: Space, T: Into<udim<D, S>>> From<(T, T)> for Range<D, S> {
+    fn from((min, max): (T, T)) -> Self {
+        Range {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+}
+
+/// An axis-aligned bounding box consisting of an upper-left corner (x1, y1) and lower-right corner (x2, y2)
+/// This assumes that x coordinates grow to the right and y coordinates grow downward.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct AABB<S: Space> {
+    x1: dimX<S>,
+    y1: dimY<S>,
+    x2: dimX<S>,
+    y2: dimY<S>,
+}
+
+impl<S: Space> AABB<S> {
+    /// Create a new AABB given the coordinates of the endpoints.
+    pub fn new(x1: dimX<S>, y1: dimY<S>, x2: dimX<S>, y2: dimY<S>) -> Self {
+        AABB {
+            x1: min(x1, x2),
+            y1: min(y1, y2),
+            x2: max(x1, x2),
+            y2: max(y1, y2),
+        }
+    }
+
+    /// Create a new AABB from the upper-left corner and a width & height.
+    pub fn new_wh(x: dimX<S>, y: dimY<S>, width: dimX<S>, height: dimY<S>) -> Self {
+        AABB::new(x, y, x + width, y + height)
+    }
+
+    /// Combines two AABBs by creating the smallest AABB that contains both.
+    pub fn union(self, rhs: Self) -> Self {
+        AABB {
+            x1: min(self.x1, rhs.x1),
+            y1: min(self.y1, rhs.y1),
+            x2: max(self.x2, rhs.x2),
+            y2: max(self.y2, rhs.y2),
+        }
+    }
+
+    /// Grows the AABB so that it also contains point.
+    pub fn grow_to_point(self, point: &Point2D<S>) -> Self {
+        AABB {
+            x1: min(self.x1, point.x),
+            y1: min(self.y1, point.y),
+            x2: max(self.x2, point.x),
+            y2: max(self.y2, point.y),
+        }
+    }
+
+    /// Shift x1, x2 by x and y1, y2 by y
+    pub fn translate(self, x: dimX<S>, y: dimY<S>) -> Self {
+        AABB::new(self.x1 + x, self.y1 + y, self.x2 + x, self.y2 + y)
+    }
+
+    /// Returns the AABB's range in the X dimension.
+    pub fn xrange(&self) -> Range<X, S> {
+        Range::new(self.x1, self.x2)
+    }
+
+    /// Returns the AABB's range in the Y dimension.
+    pub fn yrange(&self) -> Range<Y, S> {
+        Range::new(self.y1, self.y2)
+    }
+
+    /// Returns the AABB's width.
+    pub fn width(&self) -> dimX<S> {
+        self.xrange().length()
+    }
+
+    /// Returns the AABB's height.
+    pub fn height(&self) -> dimY<S> {
+        self.yrange().length()
+    }
+
+    /// Returns the AABB's midpoint.
+    pub fn midpoint(&self) -> Point2D<S> {
+        Point2D {
+            x: self.xrange().midpoint(),
+            y: self.yrange().midpoint(),
+        }
+    }
+
+    /// Computes the position of a point relative to the AABB.
+    pub fn normalize(&self, point: Point2D<S>) -> NormalizedPoint {
+        NormalizedPoint {
+            x: self.xrange().normalize(point.x),
+            y: self.yrange().normalize(point.y),
+        }
+    }
+
+    /// Computes the point at a relative position in the AABB.
+    pub fn denormalize(&self, point: NormalizedPoint) -> Point2D<S> {
+        Point2D {
+            x: self.xrange().denormalize(point.x),
+            y: self.yrange().denormalize(point.y),
+        }
+    }
+
+    /// Checks if the point lies inside the AABB, including its edges.
+    pub fn contains(&self, point: &Point2D<S>) -> bool {
+        self.x1 <= point.x && point.x <= self.x2 && self.y1 <= point.y && point.y <= self.y2
+    }
+
+    /// Computes the overlap of two AABBs, or `None` if they are disjoint.
+    pub fn intersect(self, rhs: Self) -> Option<Self> {
+        let x1 = max(self.x1, rhs.x1);
+        let y1 = max(self.y1, rhs.y1);
+        let x2 = min(self.x2, rhs.x2);
+        let y2 = min(self.y2, rhs.y2);
+        (x1 <= x2 && y1 <= y2).then_some(AABB { x1, y1, x2, y2 })
+    }
+
+    /// Returns the AABB's area. This is a plain number since it has no single dimension.
+    pub fn area(&self) -> i64 {
+        self.width().value() as i64 * self.height().value() as i64
+    }
+
+    /// Scales all coordinates by `x` horizontally and `y` vertically, e.g. to move an area to another resolution.
+    pub fn scale(self, x: f32, y: f32) -> Self {
+        AABB {
+            x1: self.x1 * x,
+            y1: self.y1 * y,
+            x2: self.x2 * x,
+            y2: self.y2 * y,
+        }
+    }
+
+    /// Scales width and height by `factor` while keeping the midpoint in place.
+    pub fn scale_about_center(self, factor: f32) -> Self {
+        let midpoint = self.midpoint();
+        let width = self.width() * factor;
+        let height = self.height() * factor;
+        AABB::new_wh(
+            midpoint.x - width * 0.5,
+            midpoint.y - height * 0.5,
+            width,
+            height,
+        )
+    }
+
+    /// Moves every edge inward by `x` horizontally and `y` vertically.
+    /// If the AABB is too small it collapses onto its midpoint in that dimension.
+    pub fn inset(self, x: dimX<S>, y: dimY<S>) -> Self {
+        let midpoint = self.midpoint();
+        let (x1, x2) = if x * 2.0 > self.width() {
+            (midpoint.x, midpoint.x)
+        } else {
+            (self.x1 + x, self.x2 - x)
+        };
+        let (y1, y2) = if y * 2.0 > self.height() {
+            (midpoint.y, midpoint.y)
+        } else {
+            (self.y1 + y, self.y2 - y)
+        };
+        AABB { x1, y1, x2, y2 }
+    }
+
+    /// Moves every edge outward by `x` horizontally and `y` vertically.
+    pub fn outset(self, x: dimX<S>, y: dimY<S>) -> Self {
+        AABB::new(self.x1 - x, self.y1 - y, self.x2 + x, self.y2 + y)
+    }
+}
+
+impl AABB<ScreenSpace> {
+    /// Converts a point in screen space into coordinates relative to the monitor that covers this area.
+    pub fn to_monitor(&self, p: Point2D<ScreenSpace>) -> Point2D<MonitorSpace> {
+        Point2D {
+            x: udim::new((p.x - self.x1).value()),
+            y: udim::new((p.y - self.y1).value()),
+        }
+    }
+
+    /// Converts coordinates relative to the monitor that covers this area into screen space.
+    pub fn to_screen(&self, p: Point2D<MonitorSpace>) -> Point2D<ScreenSpace> {
+        Point2D {
+            x: self.x1 + udim::new(p.x.value()),
+            y: self.y1 + udim::new(p.y.value()),
+        }
+    }
+}
+
+impl<S: Space> Default for AABB<S> {
+    fn default() -> Self {
+        Self {
+            x1: udim::new(0),
+            y1: udim::new(0),
+            x2: udim::new(0),
+            y2: udim::new(0),
+        }
+    }
+}
+
+impl<S: Space> fmt::Display for AABB<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "ul: ({}, {})\tlr: ({}, {})",
+            self.x1, self.y1, self.x2, self.y2
+        ))
+    }
+}
+
+/// Generic From instance to convert various things into AABBs.
+impl<S: Space> From<(UdimRepr, UdimRepr, UdimRepr, UdimRepr)> for AABB<S> {
+    fn from((x1, y1, x2, y2): (UdimRepr, UdimRepr, UdimRepr, UdimRepr)) -> Self {
+        AABB::new(udim::new(x1), udim::new(y1), udim::new(x2), udim::new(y2))
+    }
+}
+
+/// An affine transformation within a coordinate space that is composed of rotations by multiples of 90 degrees,
+/// flips and translations. Since these never need rounding, composing and applying them is exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform<S: Space> {
+    /// The linear part as a row-major 2x2 matrix with entries in {-1, 0, 1}.
+    matrix: [[UdimRepr; 2]; 2],
+    /// Applied after the linear part.
+    translation: [UdimRepr; 2],
+    space: PhantomData<S>,
+}
+
+impl<S: Space> Transform<S> {
+    fn from_parts(matrix: [[UdimRepr; 2]; 2], translation: [UdimRepr; 2]) -> Self {
+        Self {
+            matrix,
+            translation,
+            space: PhantomData,
+        }
+    }
+
+    /// The transformation that leaves every point in place.
+    pub fn identity() -> Self {
+        Self::from_parts([[1, 0], [0, 1]], [0, 0])
+    }
+
+    /// Rotation about the origin by `quarter_turns` times 90 degrees.
+    /// Since Y grows downward, positive turns rotate clockwise on the screen.
+    pub fn rotate(quarter_turns: i32) -> Self {
+        let matrix = match quarter_turns.rem_euclid(4) {
+            0 => [[1, 0], [0, 1]],
+            1 => [[0, -1], [1, 0]],
+            2 => [[-1, 0], [0, -1]],
+            _ => [[0, 1], [-1, 0]],
+        };
+        Self::from_parts(matrix, [0, 0])
+    }
+
+    /// Mirrors X coordinates at the Y axis.
+    pub fn flip_x() -> Self {
+        Self::from_parts([[-1, 0], [0, 1]], [0, 0])
+    }
+
+    /// Mirrors Y coordinates at the X axis.
+    pub fn flip_y() -> Self {
+        Self::from_parts([[1, 0], [0, -1]], [0, 0])
+    }
+
+    /// Exchanges the X and Y coordinates.
+    pub fn swap_axes() -> Self {
+        Self::from_parts([[0, 1], [1, 0]], [0, 0])
+    }
+
+    /// Shifts points by x and y.
+    pub fn translate(x: dimX<S>, y: dimY<S>) -> Self {
+        Self::from_parts([[1, 0], [0, 1]], [x.value(), y.value()])
+    }
+
+    /// Mirrors points inside `area` so that its opposite edges map onto each other, in the dimensions selected by the flags.
+    pub fn mirror_within(area: AABB<S>, flip_x: bool, flip_y: bool) -> Self {
+        let mut transform = Self::identity();
+        if flip_x {
+            transform = transform
+                .then(Self::flip_x())
+                .then(Self::translate(area.x1 + area.x2, udim::new(0)));
+        }
+        if flip_y {
+            transform = transform
+                .then(Self::flip_y())
+                .then(Self::translate(udim::new(0), area.y1 + area.y2));
+        }
+        transform
+    }
+
+    /// Composes two transformations, so that `next` is applied after `self`.
+    pub fn then(self, next: Self) -> Self {
+        let (a, b) = (next.matrix, self.matrix);
+        let matrix = [
+            [
+                a[0][0] * b[0][0] + a[0][1] * b[1][0],
+                a[0][0] * b[0][1] + a[0][1] * b[1][1],
+            ],
+            [
+                a[1][0] * b[0][0] + a[1][1] * b[1][0],
+                a[1][0] * b[0][1] + a[1][1] * b[1][1],
+            ],
+        ];
+        let [tx, ty] = next.apply_linear(self.translation);
+        Self::from_parts(
+            matrix,
+            [
+                tx.saturating_add(next.translation[0]),
+                ty.saturating_add(next.translation[1]),
+            ],
+        )
+    }
+
+    /// Applies the linear part, saturating like the arithmetic on [udim].
+    fn apply_linear(&self, [x, y]: [UdimRepr; 2]) -> [UdimRepr; 2] {
+        let dot = |row: [UdimRepr; 2]| {
+            row[0]
+                .saturating_mul(x)
+                .saturating_add(row[1].saturating_mul(y))
+        };
+        [dot(self.matrix[0]), dot(self.matrix[1])]
+    }
+
+    /// Transforms a point.
+    pub fn apply(&self, point: Point2D<S>) -> Point2D<S> {
+        let [x, y] = self.apply_linear([point.x.value(), point.y.value()]);
+        Point2D {
+            x: udim::new(x.saturating_add(self.translation[0])),
+            y: udim::new(y.saturating_add(self.translation[1])),
+        }
+    }
+
+    /// Transforms an AABB. The result is again axis-aligned since we only rotate by multiples of 90 degrees.
+    pub fn apply_aabb(&self, aabb: AABB<S>) -> AABB<S> {
+        let p1 = self.apply(Point2D {
+            x: aabb.x1,
+            y: aabb.y1,
+        });
+        let p2 = self.apply(Point2D {
+            x: aabb.x2,
+            y: aabb.y2,
+        });
+        AABB::new(p1.x, p1.y, p2.x, p2.y)
+    }
+}
+
+impl<S: Space> Default for Transform<S> {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+/// An affine map from points to normalized coordinates, as a row-major 2x3 matrix that is applied to `(x, y, 1)`.
+/// Unlike a [Transform] followed by [AABB::normalize] it also maps panels that are slightly rotated or skewed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Affine {
+    pub matrix: [[f32; 3]; 2],
+}
+
+impl Affine {
+    /// Maps a point.
+    pub fn apply<S: Space>(&self, point: Point2D<S>) -> NormalizedPoint {
+        let (x, y) = (point.x.float(), point.y.float());
+        let [a, b] = self.matrix;
+        NormalizedPoint::new(a[0] * x + a[1] * y + a[2], b[0] * x + b[1] * y + b[2])
+    }
+}
+
+/// The affine map that takes each point of the pairs as close as possible onto its normalized target, in the least-squares sense.
+/// Any number of pairs can be fitted, e.g. from calibrating on 5, 9 or 13 targets, and more pairs average out the noise of single touches.
+/// Returns `None` for fewer than three pairs or if all points lie on a line.
+pub fn fit_affine<S: Space>(points: &[(Point2D<S>, NormalizedPoint)]) -> Option<Affine> {
+    if points.len() < 3 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let coords = || {
+        points.iter().map(|(p, t)| {
+            (
+                p.x.float() as f64,
+                p.y.float() as f64,
+                t.x.value() as f64,
+                t.y.value() as f64,
+            )
+        })
+    };
+    let (mut mx, mut my, mut mu, mut mv) = (0.0, 0.0, 0.0, 0.0);
+    for (x, y, u, v) in coords() {
+        (mx, my, mu, mv) = (mx + x / n, my + y / n, mu + u / n, mv + v / n);
+    }
+
+    // Relative to the means, the normal equations of the translation drop out and only a 2x2 system is left.
+    let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+    let (mut sxu, mut syu, mut sxv, mut syv) = (0.0, 0.0, 0.0, 0.0);
+    for (x, y, u, v) in coords() {
+        let (x, y, u, v) = (x - mx, y - my, u - mu, v - mv);
+        sxx += x * x;
+        sxy += x * y;
+        syy += y * y;
+        sxu += x * u;
+        syu += y * u;
+        sxv += x * v;
+        syv += y * v;
+    }
+    let det = sxx * syy - sxy * sxy;
+    if det <= f64::EPSILON * sxx * syy {
+        return None;
+    }
+
+    let row = |sxt: f64, syt: f64, mt: f64| {
+        let a = (sxt * syy - syt * sxy) / det;
+        let b = (syt * sxx - sxt * sxy) / det;
+        [a as f32, b as f32, (mt - a * mx - b * my) as f32]
+    };
+    Some(Affine {
+        matrix: [row(sxu, syu, mu), row(sxv, syv, mv)],
+    })
+}
+
+impl fmt::Display for Affine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b] = self.matrix;
+        f.write_fmt(format_args!(
+            "[[{}, {}, {}], [{}, {}, {}]]",
+            a[0], a[1], a[2], b[0], b[1], b[2]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type ScreenBox = AABB<ScreenSpace>;
+
+    #[test]
+    fn test_aabb_contains_and_intersect() {
+        let a = ScreenBox::from((0, 0, 100, 50));
+        let b = ScreenBox::from((50, 25, 200, 200));
+
+        assert!(a.contains(&(100, 50).into()));
+        assert!(!a.contains(&(101, 25).into()));
+        assert_eq!(Some(ScreenBox::from((50, 25, 100, 50))), a.intersect(b));
+        assert_eq!(None, a.intersect(ScreenBox::from((150, 0, 200, 50))));
+        assert_eq!(5000, a.area());
+    }
+
+    #[test]
+    fn test_aabb_scale_and_inset() {
+        let a = ScreenBox::from((0, 0, 100, 100));
+
+        assert_eq!(ScreenBox::from((25, 25, 75, 75)), a.scale_about_center(0.5));
+        assert_eq!(
+            ScreenBox::from((10, 20, 90, 80)),
+            a.inset(udim::new(10), udim::new(20))
+        );
+        assert_eq!(
+            ScreenBox::from((50, 0, 50, 100)),
+            a.inset(udim::new(60), udim::new(0))
+        );
+        assert_eq!(
+            ScreenBox::from((-10, -20, 110, 120)),
+            a.outset(udim::new(10), udim::new(20))
+        );
+    }
+
+    #[test]
+    fn test_aabb_normalize() {
+        let touch = AABB::<TouchSpace>::from((300, 300, 3800, 3800));
+        let monitor = ScreenBox::from((1920, 0, 3200, 1024));
+
+        let center = touch.normalize((2050, 2050).into());
+        assert_eq!(NormalizedPoint::new(0.5, 0.5), center);
+        assert_eq!(Point2D::from((2560, 512)), monitor.denormalize(center));
+        assert_eq!(-0.5, touch.xrange().normalize(udim::new(-1450)).value());
+        assert_eq!(
+            Point2D::from((1920, 1024)),
+            monitor.denormalize(NormalizedPoint::new(-0.5, 2.0).clamped())
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_saturates() {
+        let far = Point2D::<ScreenSpace>::from((UdimRepr::MAX, UdimRepr::MIN));
+        let near = Point2D::from((-10, 10));
+
+        assert_eq!(far, far - near);
+        assert!(far.euclidean_distance_to(&near) > 0.0);
+        assert_eq!(
+            UdimRepr::MAX,
+            ScreenBox::from((0, 0, 100, 100))
+                .xrange()
+                .denormalize(Normalized::new(1e30))
+                .value()
+        );
+        assert_eq!(
+            Point2D::from((UdimRepr::MAX, UdimRepr::MIN)),
+            Transform::translate(udim::new(10), udim::new(-10)).apply(far)
+        );
+        assert_eq!(None, udim::<X, TouchSpace>::new(-1).checked_u16());
+        assert_eq!(u16::MAX, udim::<X, TouchSpace>::new(70000).saturating_u16());
+    }
+
+    #[test]
+    fn test_transform_compose() {
+        let p = Point2D::<ScreenSpace>::from((10, 20));
+
+        assert_eq!(Point2D::from((-20, 10)), Transform::rotate(1).apply(p));
+        assert_eq!(p, Transform::rotate(1).then(Transform::rotate(-1)).apply(p));
+        assert_eq!(
+            Transform::<ScreenSpace>::rotate(2),
+            Transform::flip_x().then(Transform::flip_y())
+        );
+
+        let rotate_then_shift =
+            Transform::rotate(1).then(Transform::translate(udim::new(5), udim::new(0)));
+        assert_eq!(Point2D::from((-15, 10)), rotate_then_shift.apply(p));
+
+        let area = ScreenBox::from((0, 0, 100, 50));
+        let mirror = Transform::mirror_within(area, true, true);
+        assert_eq!(Point2D::from((90, 30)), mirror.apply(p));
+        assert_eq!(area, mirror.apply_aabb(area));
+        assert_eq!(
+            ScreenBox::from((-50, 0, 0, 100)),
+            Transform::rotate(1).apply_aabb(area)
+        );
+    }
+
+    #[test]
+    fn test_fit_affine() {
+        type Touch = Point2D<TouchSpace>;
+        let exact = Affine {
+            matrix: [[0.00025, 0.00001, -0.1], [-0.00002, 0.0003, -0.05]],
+        };
+        // A 3x3 grid, where the middle target is touched a bit off.
+        let mut pairs: Vec<(Touch, NormalizedPoint)> = (0..9)
+            .map(|i| {
+                let touch = Touch::from((500 + 1500 * (i % 3), 500 + 1500 * (i / 3)));
+                (touch, exact.apply(touch))
+            })
+            .collect();
+        let fit = fit_affine(&pairs).unwrap();
+        for (touch, target) in &pairs {
+            let mapped = fit.apply(*touch);
+            assert!((mapped.x.value() - target.x.value()).abs() < 1e-4);
+            assert!((mapped.y.value() - target.y.value()).abs() < 1e-4);
+        }
+
+        pairs[4].0 = Touch::from((2090, 2000));
+        let fit = fit_affine(&pairs).unwrap();
+        let center = fit.apply(Touch::from((2000, 2000)));
+        let expected = exact.apply(Touch::from((2000, 2000)));
+        assert!((center.x.value() - expected.x.value()).abs() < 0.01);
+
+        // Too few pairs, and the pairs of the first row lie on a line.
+        assert_eq!(None, fit_affine(&pairs[..2]));
+        assert_eq!(None, fit_affine(&pairs[..3]));
+    }
+}
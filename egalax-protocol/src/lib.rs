@@ -0,0 +1,14 @@
+//! Parsing of the packets of eGalax touchscreens and the geometry to map touches onto a monitor.
+//!
+//! This is the part of egalax-rs that does not need Linux, so that other hosts like microcontroller gateways
+//! can reuse exactly the parsing and calibration math of the driver.
+//! Without the default `std` feature the crate is `no_std` and leaves out the timestamped [packet::USBMessage].
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod calibration;
+pub mod error;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+pub mod geo;
+pub mod packet;
+pub mod units;
@@ -0,0 +1,567 @@
+//! Implements parsing of the packets that are received from the hidraw interface.
+//!
+//! Older controllers that are connected over RS-232 or a USB-serial adapter send the same touch reports in a serial
+//! format instead, which is parsed by [USBPacket::try_parse_serial].
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::{error::ParsePacketError, geo::Point2D, units::*};
+
+/// Length of a raw packet.
+pub const RAW_PACKET_LEN: usize = 6;
+
+/// Bitmasks for fields in the raw packet.
+const TOUCH_STATE_MASK: u8 = 0x01;
+const RESOLUTION_MASK: u8 = 0x06;
+/// Contact id of a multi-touch packet, in the otherwise unused upper bits of the status byte.
+const CONTACT_ID_MASK: u8 = 0x78;
+
+/// Length of a packet of the serial protocol. Packets that report pressure have one more byte, see [serial_packet_len].
+pub const SERIAL_PACKET_LEN: usize = 5;
+/// Only the first byte of a serial packet has the highest bit set, so that the stream can be resynchronized.
+const SERIAL_START_MASK: u8 = 0x80;
+const SERIAL_PRESSURE_MASK: u8 = 0x40;
+const SERIAL_DATA_MASK: u8 = 0x7f;
+/// Pressures are reported from 0 to this by the controllers that report pressure.
+pub const MAX_PRESSURE: u8 = SERIAL_DATA_MASK;
+
+/// Supported resolutions in bits.
+const MIN_RESOLUTION: u8 = 11;
+const MAX_RESOLUTION: u8 = 14;
+
+/// Type of raw packets.
+#[derive(Debug, Clone, Copy)]
+pub struct RawPacket(pub [u8; RAW_PACKET_LEN]);
+
+impl fmt::Display for RawPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "[{:#04x}, {:#04x}, {:#04x}, {:#04x}, {:#04x}, {:#04x}]",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        ))
+    }
+}
+
+/// A boolean indicating if a finger touch is detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchState {
+    IsTouching,
+    NotTouching,
+}
+
+/// Type of packet tags that we currently support.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketTag {
+    TouchEvent = 0x2,
+    /// Sent instead of touch events by multi-touch controllers while more than one finger is down.
+    /// Each contact is reported in its own packet with the layout of a touch event and a contact id in the status byte.
+    MultiTouch = 0x6,
+}
+
+/// A packet of any type that we support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    /// Single-touch report.
+    Touch(USBPacket),
+    /// Report of one contact of a multi-touch report.
+    MultiTouch(Contact),
+}
+
+/// One finger of a multi-touch report. The touch state of the packet tells if this finger is still down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contact {
+    /// Identifies the finger across packets. The first finger that touches has id 0.
+    pub id: u8,
+    pub packet: USBPacket,
+}
+
+impl Packet {
+    /// Parsing logic for all packet types.
+    /// Multi-touch packets are accepted wherever touch events are expected, since controllers switch between them
+    /// depending on the number of fingers.
+    pub fn try_parse(
+        packet: RawPacket,
+        expected_tag: Option<PacketTag>,
+    ) -> Result<Self, ParsePacketError> {
+        let accepts_multi_touch = matches!(
+            expected_tag,
+            None | Some(PacketTag::TouchEvent | PacketTag::MultiTouch)
+        );
+        if packet.0[0] != PacketTag::MultiTouch as u8 || !accepts_multi_touch {
+            return USBPacket::try_parse(packet, expected_tag).map(Packet::Touch);
+        }
+
+        let id = (packet.0[1] & CONTACT_ID_MASK) >> 3;
+        let mut touch_event = packet;
+        touch_event.0[0] = PacketTag::TouchEvent as u8;
+        touch_event.0[1] &= !CONTACT_ID_MASK;
+        let packet = USBPacket::try_parse(touch_event, Some(PacketTag::TouchEvent))?;
+        Ok(Packet::MultiTouch(Contact { id, packet }))
+    }
+
+    /// Encode the packet into the format sent by the touchscreen. This is the inverse of [Packet::try_parse].
+    pub fn encode(&self) -> RawPacket {
+        match self {
+            Packet::Touch(packet) => packet.encode(),
+            Packet::MultiTouch(contact) => {
+                let mut raw = contact.packet.encode();
+                raw.0[0] = PacketTag::MultiTouch as u8;
+                raw.0[1] |= (contact.id << 3) & CONTACT_ID_MASK;
+                raw
+            }
+        }
+    }
+
+    /// Id of the finger that the packet reports, which is 0 for single-touch reports.
+    pub fn contact_id(&self) -> u8 {
+        match self {
+            Packet::Touch(_) => 0,
+            Packet::MultiTouch(contact) => contact.id,
+        }
+    }
+
+    /// The touch state and position of the reported finger.
+    pub fn touch_packet(&self) -> &USBPacket {
+        match self {
+            Packet::Touch(packet) => packet,
+            Packet::MultiTouch(contact) => &contact.packet,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_time(self, time: Instant) -> USBMessage {
+        USBMessage {
+            time,
+            packet: *self.touch_packet(),
+            contact: self.contact_id(),
+        }
+    }
+}
+
+/// A representation of a touch event packet sent over USB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct USBPacket {
+    touch_state: TouchState,
+    position: Point2D<TouchSpace>,
+    resolution: u8,
+    /// Only serial controllers report pressure, and only some of them.
+    pressure: Option<u8>,
+}
+
+impl USBPacket {
+    /// Create a touch event packet.
+    /// Fails if the resolution is not supported or the position does not fit into it.
+    pub fn new(
+        touch_state: TouchState,
+        position: Point2D<TouchSpace>,
+        resolution: u8,
+    ) -> Result<Self, ParsePacketError> {
+        if !(MIN_RESOLUTION..=MAX_RESOLUTION).contains(&resolution) {
+            return Err(ParsePacketError::UnsupportedResolution(resolution));
+        }
+
+        let in_range = |v: UdimRepr| v >= 0 && v >> resolution == 0;
+        if !in_range(position.y.value()) {
+            return Err(ParsePacketError::WrongResolution(DimE::Y));
+        } else if !in_range(position.x.value()) {
+            return Err(ParsePacketError::WrongResolution(DimE::X));
+        }
+
+        Ok(Self {
+            touch_state,
+            position,
+            resolution,
+            pressure: None,
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_time(self, time: Instant) -> USBMessage {
+        Packet::Touch(self).with_time(time)
+    }
+
+    pub fn touch_state(&self) -> TouchState {
+        self.touch_state
+    }
+
+    pub fn position(&self) -> Point2D<TouchSpace> {
+        self.position
+    }
+
+    pub fn resolution(&self) -> u8 {
+        self.resolution
+    }
+
+    /// Pressure of the touch from 0 to [MAX_PRESSURE], if the controller reports it.
+    pub fn pressure(&self) -> Option<u8> {
+        self.pressure
+    }
+
+    /// Parsing logic for a touch event packet.
+    /// Fails if the package is somehow malformed.
+    pub fn try_parse(
+        packet: RawPacket,
+        expected_tag: Option<PacketTag>,
+    ) -> Result<Self, ParsePacketError> {
+        log::trace!("Entering Packet::try_parse.");
+
+        if let Some(expected_tag) = expected_tag {
+            let raw_tag = packet.0[0];
+            if raw_tag != expected_tag as u8 {
+                return Err(ParsePacketError::UnexpectedTag(raw_tag));
+            }
+        }
+
+        // The two resolution bits count up from the minimum resolution.
+        let resolution = MIN_RESOLUTION + ((packet.0[1] & RESOLUTION_MASK) >> 1);
+
+        let touch_state = if (packet.0[1] & TOUCH_STATE_MASK) == 0x01 {
+            TouchState::IsTouching
+        } else {
+            TouchState::NotTouching
+        };
+
+        // X and Y coordinates are stored little-endian.
+        let y = ((packet.0[3] as u16) << 8) | (packet.0[2] as u16);
+        let x = ((packet.0[5] as u16) << 8) | (packet.0[4] as u16);
+
+        if y >> resolution != 0x00 {
+            return Err(ParsePacketError::WrongResolution(DimE::Y));
+        } else if x >> resolution != 0x00 {
+            return Err(ParsePacketError::WrongResolution(DimE::X));
+        }
+
+        let packet = USBPacket {
+            touch_state,
+            position: Point2D {
+                x: udim::new(UdimRepr::from(x)),
+                y: udim::new(UdimRepr::from(y)),
+            },
+            resolution,
+            pressure: None,
+        };
+
+        log::trace!("Leaving Packet::try_parse.");
+        Ok(packet)
+    }
+
+    /// Encode the packet into the format sent by the touchscreen. This is the inverse of [USBPacket::try_parse].
+    pub fn encode(&self) -> RawPacket {
+        let touch_state = match self.touch_state {
+            TouchState::IsTouching => TOUCH_STATE_MASK,
+            TouchState::NotTouching => 0x00,
+        };
+        let resolution = ((self.resolution - MIN_RESOLUTION) << 1) & RESOLUTION_MASK;
+
+        // X and Y coordinates are stored little-endian.
+        let [y_lo, y_hi] = self.position.y.saturating_u16().to_le_bytes();
+        let [x_lo, x_hi] = self.position.x.saturating_u16().to_le_bytes();
+
+        RawPacket([
+            PacketTag::TouchEvent as u8,
+            resolution | touch_state,
+            y_lo,
+            y_hi,
+            x_lo,
+            x_hi,
+        ])
+    }
+
+    /// Parsing logic for a packet of the serial protocol.
+    /// The status byte is laid out like in a touch event packet with the start bit set. It is followed by X and then Y,
+    /// each as 7 high and 7 low bits, of which the resolution says how many are used.
+    /// The pressure byte that follows if [serial_packet_len] says so is not part of `packet`,
+    /// see [USBPacket::with_serial_pressure].
+    pub fn try_parse_serial(packet: [u8; SERIAL_PACKET_LEN]) -> Result<Self, ParsePacketError> {
+        let [status, coordinates @ ..] = packet;
+        if status & SERIAL_START_MASK == 0 {
+            return Err(ParsePacketError::SerialFraming(status));
+        }
+        if let Some(byte) = coordinates.iter().find(|b| *b & SERIAL_START_MASK != 0) {
+            return Err(ParsePacketError::SerialFraming(*byte));
+        }
+
+        let resolution = MIN_RESOLUTION + ((status & RESOLUTION_MASK) >> 1);
+        let touch_state = if (status & TOUCH_STATE_MASK) == 0x01 {
+            TouchState::IsTouching
+        } else {
+            TouchState::NotTouching
+        };
+
+        let [x_hi, x_lo, y_hi, y_lo] = coordinates.map(u16::from);
+        let x = (x_hi << 7) | x_lo;
+        let y = (y_hi << 7) | y_lo;
+        if y >> resolution != 0x00 {
+            return Err(ParsePacketError::WrongResolution(DimE::Y));
+        } else if x >> resolution != 0x00 {
+            return Err(ParsePacketError::WrongResolution(DimE::X));
+        }
+
+        Ok(USBPacket {
+            touch_state,
+            position: Point2D {
+                x: udim::new(UdimRepr::from(x)),
+                y: udim::new(UdimRepr::from(y)),
+            },
+            resolution,
+            pressure: None,
+        })
+    }
+
+    /// Add the pressure byte that follows a serial packet that reports pressure.
+    /// Fails if the byte has the start bit set, since it is then the start of the next packet.
+    pub fn with_serial_pressure(self, pressure: u8) -> Result<Self, ParsePacketError> {
+        if pressure & SERIAL_START_MASK != 0 {
+            return Err(ParsePacketError::SerialFraming(pressure));
+        }
+        Ok(Self {
+            pressure: Some(pressure),
+            ..self
+        })
+    }
+
+    /// Encode the packet into the serial format without pressure. This is the inverse of [USBPacket::try_parse_serial].
+    pub fn encode_serial(&self) -> [u8; SERIAL_PACKET_LEN] {
+        let [_, status, ..] = self.encode().0;
+        let x = self.position.x.saturating_u16();
+        let y = self.position.y.saturating_u16();
+        let data = |v: u16| (v & SERIAL_DATA_MASK as u16) as u8;
+
+        [
+            SERIAL_START_MASK | status,
+            data(x >> 7),
+            data(x),
+            data(y >> 7),
+            data(y),
+        ]
+    }
+}
+
+/// Length of the serial packet that starts with the status byte `status`, depending on whether it reports pressure.
+pub fn serial_packet_len(status: u8) -> usize {
+    if status & SERIAL_PRESSURE_MASK != 0 {
+        SERIAL_PACKET_LEN + 1
+    } else {
+        SERIAL_PACKET_LEN
+    }
+}
+
+impl fmt::Display for USBPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let touch = match self.touch_state {
+            TouchState::IsTouching => "1",
+            TouchState::NotTouching => "0",
+        };
+        // Written directly since this is logged for every packet.
+        f.write_fmt(format_args!("Touch={}, Point={}", touch, self.position))
+    }
+}
+
+/// Messages are timestamped with a monotonic clock when they are read,
+/// so that timing decisions are not affected if the wall clock is changed mid-touch.
+/// Only available with the `std` feature, which provides the clock.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct USBMessage {
+    time: Instant,
+    packet: USBPacket,
+    /// Id of the finger for multi-touch reports, see [Contact].
+    contact: u8,
+}
+
+#[cfg(feature = "std")]
+impl USBMessage {
+    pub fn time(&self) -> Instant {
+        self.time
+    }
+
+    pub fn packet(&self) -> &USBPacket {
+        &self.packet
+    }
+
+    /// Id of the reported finger. Only contact 0 moves the pointer, other fingers are for multi-touch.
+    pub fn contact(&self) -> u8 {
+        self.contact
+    }
+
+    /// Encode the packet as the touchscreen sent it, i.e. as a multi-touch packet for contacts other than 0.
+    pub fn encode(&self) -> RawPacket {
+        let packet = if self.contact == 0 {
+            Packet::Touch(self.packet)
+        } else {
+            Packet::MultiTouch(Contact {
+                id: self.contact,
+                packet: self.packet,
+            })
+        };
+        packet.encode()
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for USBMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Message at {:?}\nContact {}: {}",
+            self.time, self.contact, self.packet
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_touch_upper_left() {
+        let raw_packet: RawPacket = RawPacket([0x02, 0x03, 0x3b, 0x01, 0x32, 0x01]);
+
+        assert_eq!(
+            Ok(USBPacket {
+                touch_state: TouchState::IsTouching,
+                position: (306, 315).into(),
+                resolution: 12,
+                pressure: None,
+            }),
+            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+        );
+    }
+
+    #[test]
+    fn test_parse_release_upper_left() {
+        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x01, 0x39, 0x01]);
+
+        assert_eq!(
+            Ok(USBPacket {
+                touch_state: TouchState::IsTouching,
+                position: (313, 309).into(),
+                resolution: 12,
+                pressure: None,
+            }),
+            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+        );
+    }
+
+    #[test]
+    fn test_malformed_const() {
+        let raw_packet: RawPacket = RawPacket([0xaa, 0x02, 0x35, 0x01, 0x39, 0x01]);
+
+        assert_eq!(
+            Err(ParsePacketError::UnexpectedTag(0xaa)),
+            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+        );
+    }
+
+    #[test]
+    fn test_malformed_res_y() {
+        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x11, 0x39, 0x01]);
+
+        assert_eq!(
+            Err(ParsePacketError::WrongResolution(DimE::Y)),
+            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+        );
+    }
+
+    #[test]
+    fn test_malformed_res_x() {
+        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x01, 0x39, 0x11]);
+
+        assert_eq!(
+            Err(ParsePacketError::WrongResolution(DimE::X)),
+            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+        );
+    }
+
+    #[test]
+    fn test_encode_touch_upper_left() {
+        let packet = USBPacket::new(TouchState::IsTouching, (306, 315).into(), 12).unwrap();
+
+        assert_eq!([0x02, 0x03, 0x3b, 0x01, 0x32, 0x01], packet.encode().0);
+        assert_eq!(
+            Err(ParsePacketError::WrongResolution(DimE::X)),
+            USBPacket::new(TouchState::IsTouching, (4096, 315).into(), 12)
+        );
+        assert_eq!(
+            Err(ParsePacketError::UnsupportedResolution(15)),
+            USBPacket::new(TouchState::IsTouching, (306, 315).into(), 15)
+        );
+    }
+
+    #[test]
+    fn test_parse_serial() {
+        // 12 bits, touching, X 306 and Y 315.
+        let packet = USBPacket::try_parse_serial([0x83, 0x02, 0x32, 0x02, 0x3b]).unwrap();
+
+        assert_eq!(
+            USBPacket::new(TouchState::IsTouching, (306, 315).into(), 12).unwrap(),
+            packet
+        );
+        assert_eq!([0x83, 0x02, 0x32, 0x02, 0x3b], packet.encode_serial());
+        assert_eq!(6, serial_packet_len(0xc3));
+        assert_eq!(
+            Some(0x40),
+            packet.with_serial_pressure(0x40).unwrap().pressure()
+        );
+        assert_eq!(
+            Err(ParsePacketError::SerialFraming(0x83)),
+            packet.with_serial_pressure(0x83)
+        );
+        assert_eq!(
+            Err(ParsePacketError::SerialFraming(0x03)),
+            USBPacket::try_parse_serial([0x03, 0x02, 0x32, 0x02, 0x3b])
+        );
+        assert_eq!(
+            Err(ParsePacketError::SerialFraming(0x81)),
+            USBPacket::try_parse_serial([0x83, 0x02, 0x32, 0x81, 0x3b])
+        );
+        assert_eq!(
+            Err(ParsePacketError::WrongResolution(DimE::X)),
+            USBPacket::try_parse_serial([0x81, 0x7f, 0x00, 0x00, 0x00])
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_serial_roundtrip(touching: bool, resolution in 11u8..=14, x: u16, y: u16) {
+            let touch_state = if touching {
+                TouchState::IsTouching
+            } else {
+                TouchState::NotTouching
+            };
+            let max = 1 << resolution;
+            let position = Point2D::from(((x % max) as UdimRepr, (y % max) as UdimRepr));
+            let packet = USBPacket::new(touch_state, position, resolution).unwrap();
+
+            prop_assert_eq!(Ok(packet), USBPacket::try_parse_serial(packet.encode_serial()));
+        }
+
+        #[test]
+        fn prop_encode_parse_roundtrip(touching: bool, resolution in 11u8..=14, x: u16, y: u16) {
+            let touch_state = if touching {
+                TouchState::IsTouching
+            } else {
+                TouchState::NotTouching
+            };
+            let max = 1 << resolution;
+            let position = Point2D::from(((x % max) as UdimRepr, (y % max) as UdimRepr));
+            let packet = USBPacket::new(touch_state, position, resolution).unwrap();
+
+            let parsed = USBPacket::try_parse(packet.encode(), Some(PacketTag::TouchEvent));
+            prop_assert_eq!(Ok(packet), parsed);
+        }
+
+        #[test]
+        fn prop_parse_encode_roundtrip(flags in 0u8..8, coordinates: [u8; 4]) {
+            let [y_lo, y_hi, x_lo, x_hi] = coordinates;
+            let raw_packet = RawPacket([0x02, flags, y_lo, y_hi, x_lo, x_hi]);
+
+            if let Ok(packet) = USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent)) {
+                prop_assert_eq!(raw_packet.0, packet.encode().0);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Wrapper types for number of different dimensions (x & y).
+//!
+//! To prevent accidentally mixing different dimensions when calculating
+//! with screen geometry we add some wrapper types that restrict the
+//! allowed operations.
+//!
+//! In the same way, every number is tagged with the coordinate space it lives in,
+//! so that raw touch coordinates cannot be mixed up with pixels.
+//! Conversions between spaces only happen through the calibration and monitor areas,
+//! which map coordinates to and from [Normalized] coordinates that are independent of any space.
+//!
+//! Arithmetic on numbers saturates at the bounds of [UdimRepr] instead of wrapping around,
+//! so that a bogus calibration or extrapolation can at worst push the cursor to a screen edge.
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    ops::{Add, Mul, Sub},
+};
+use serde::{Deserialize, Serialize};
+
+/// X dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct X;
+
+/// Y dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Y;
+
+/// Marker trait that represents a dimension.
+/// Effectively, this declares a new kind with two type constructors.
+pub trait Dim: Clone + Copy + Eq + Ord {}
+impl Dim for X {}
+impl Dim for Y {}
+
+/// Raw coordinates as reported by the touchscreen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TouchSpace;
+
+/// Pixels relative to the upper-left corner of a single monitor, e.g. in a window covering it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonitorSpace;
+
+/// Pixels in the total screen space spanned by all monitors, as used by X and our virtual device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ScreenSpace;
+
+/// Marker trait that represents a coordinate space.
+pub trait Space: Clone + Copy + Eq + Ord + fmt::Debug {}
+impl Space for TouchSpace {}
+impl Space for MonitorSpace {}
+impl Space for ScreenSpace {}
+
+/// Integer type of a screen dimension
+pub type UdimRepr = i32;
+
+/// Wrapper which uses PhantomData to statically tell apart numbers of different dimensions and coordinate spaces.
+#[allow(non_camel_case_types)]
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct udim<D: Dim, S: Space>(PhantomData<(D, S)>, UdimRepr);
+
+/// Number in X dimension.
+#[allow(non_camel_case_types)]
+pub type dimX<S> = udim<X, S>;
+
+/// Number in Y dimension.
+#[allow(non_camel_case_types)]
+pub type dimY<S> = udim<Y, S>;
+
+impl<D: Dim, S: Space> udim<D, S> {
+    /// Tags a raw value with its dimension and coordinate space.
+    /// This is for values that are read from a device, a config file or written as literals.
+    /// Values of another space are converted through the calibration and monitor areas instead.
+    pub const fn new(x: UdimRepr) -> Self {
+        udim(PhantomData, x)
+    }
+
+    /// The underlying dimensionless value.
+    pub fn value(self) -> UdimRepr {
+        self.1
+    }
+
+    /// The underlying dimensionless value as an f32.
+    pub fn float(self) -> f32 {
+        self.value() as f32
+    }
+
+    /// Converts a float by rounding to the nearest integer.
+    /// Values beyond the range of [UdimRepr] saturate and NaN becomes 0.
+    pub fn round_from(x: f32) -> Self {
+        // Float to integer casts saturate.
+        Self::new(libm::roundf(x) as UdimRepr)
+    }
+
+    /// Converts to the 16 bit integers used by the touchscreen, or `None` if the value does not fit.
+    pub fn checked_u16(self) -> Option<u16> {
+        u16::try_from(self.1).ok()
+    }
+
+    /// Converts to the 16 bit integers used by the touchscreen, clamping values that do not fit.
+    pub fn saturating_u16(self) -> u16 {
+        self.1.clamp(0, UdimRepr::from(u16::MAX)) as u16
+    }
+}
+
+/// A coordinate relative to some range, where 0 is the range's minimum and 1 its maximum.
+/// Values outside of [0, 1] are allowed and lie outside of the range, e.g. touches beyond the calibration points.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Normalized<D: Dim>(PhantomData<D>, f32);
+
+/// Normalized coordinate in X dimension.
+pub type NormX = Normalized<X>;
+
+/// Normalized coordinate in Y dimension.
+pub type NormY = Normalized<Y>;
+
+impl<D: Dim> Normalized<D> {
+    pub fn new(t: f32) -> Self {
+        Normalized(PhantomData, t)
+    }
+
+    /// The underlying dimensionless value.
+    pub fn value(self) -> f32 {
+        self.1
+    }
+
+    /// Mirrors the coordinate within the range, i.e. 0 becomes 1 and vice versa.
+    pub fn flipped(self) -> Self {
+        Self::new(1.0 - self.1)
+    }
+
+    /// Restricts the coordinate to [0, 1].
+    pub fn clamped(self) -> Self {
+        Self::new(self.1.clamp(0.0, 1.0))
+    }
+}
+
+impl<D: Dim> fmt::Display for Normalized<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.1.fmt(f)
+    }
+}
+
+impl<D: Dim, S: Space> fmt::Display for udim<D, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.1.fmt(f)
+    }
+}
+
+/// Arithmetic instances.
+impl<D: Dim, S: Space> Add for udim<D, S> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.1.saturating_add(rhs.1))
+    }
+}
+
+impl<D: Dim, S: Space> Sub for udim<D, S> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.1.saturating_sub(rhs.1))
+    }
+}
+
+impl<D: Dim, S: Space> Mul<f32> for udim<D, S> {
+    type Output = udim<D, S>;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        // Truncates towards zero, the cast saturates.
+        Self::new((self.1 as f32 * rhs) as UdimRepr)
+    }
+}
+
+/// Serialization instances.
+impl<D: Dim, S: Space> Serialize for udim<D, S> {
+    fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: serde::Serializer,
+    {
+        self.1.serialize(serializer)
+    }
+}
+
+impl<'de, D: Dim, S: Space> Deserialize<'de> for udim<D, S> {
+    fn deserialize<De>(deserializer: De) -> Result<Self, De::Error>
+    where
+        De: serde::Deserializer<'de>,
+    {
+        let x: UdimRepr = UdimRepr::deserialize(deserializer)?;
+        Ok(udim::new(x))
+    }
+}
+
+/// A separate dimension enum to avoid generics in some cases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DimE {
+    X,
+    Y,
+}
+
+impl<S: Space> From<dimX<S>> for DimE {
+    fn from(_: dimX<S>) -> Self {
+        Self::X
+    }
+}
+
+impl<S: Space> From<dimY<S>> for DimE {
+    fn from(_: dimY<S>) -> Self {
+        Self::Y
+    }
+}
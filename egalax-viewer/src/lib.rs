@@ -0,0 +1,243 @@
+//! WebAssembly bindings of the packet parser and calibration math, so that captured packet dumps can be inspected
+//! in a browser with `index.html` without installing the toolchain.
+//!
+//! The functions use the plain C ABI instead of wasm-bindgen,
+//! so the module is built with `cargo build --target wasm32-unknown-unknown` alone and loaded with `WebAssembly.instantiate`.
+//! JavaScript copies a dump into memory from [egalax_alloc], decodes it into [DecodedPacket]s
+//! and reads them back with an `Int32Array`.
+
+use std::{mem, slice};
+
+use egalax_protocol::calibration::Calibration;
+use egalax_protocol::geo::{Affine, Origin, Point2D, Rotation, AABB};
+use egalax_protocol::packet::{Packet, RawPacket, TouchState, RAW_PACKET_LEN};
+use egalax_protocol::units::{ScreenSpace, TouchSpace};
+
+/// A packet of a dump. All fields are 32 bit integers so that JavaScript can read them as an `Int32Array`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodedPacket {
+    /// Offset of the packet in the dump in bytes.
+    pub offset: i32,
+    /// 1 if the packet could be parsed. Otherwise the remaining fields are 0.
+    pub valid: i32,
+    pub contact: i32,
+    /// 1 while the finger touches, 0 when it is lifted.
+    pub touching: i32,
+    pub x: i32,
+    pub y: i32,
+    pub resolution: i32,
+}
+
+impl DecodedPacket {
+    fn decode(offset: usize, raw: &[u8]) -> Self {
+        let mut bytes = [0; RAW_PACKET_LEN];
+        bytes.copy_from_slice(raw);
+        match Packet::try_parse(RawPacket(bytes), None) {
+            Ok(packet) => {
+                let touch = packet.touch_packet();
+                DecodedPacket {
+                    offset: offset as i32,
+                    valid: 1,
+                    contact: i32::from(packet.contact_id()),
+                    touching: i32::from(touch.touch_state() == TouchState::IsTouching),
+                    x: touch.position().x.value(),
+                    y: touch.position().y.value(),
+                    resolution: i32::from(touch.resolution()),
+                }
+            }
+            Err(e) => {
+                log::debug!("Packet at offset {} is malformed: {}", offset, e);
+                DecodedPacket {
+                    offset: offset as i32,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+/// The calibration that maps touch coordinates onto a monitor, laid out like [DecodedPacket] for JavaScript.
+/// The fields mirror the calibration in the config file of the driver.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MonitorMapping {
+    /// Touch coordinates of the monitor edges, after swapping axes.
+    pub calibration_x1: i32,
+    pub calibration_y1: i32,
+    pub calibration_x2: i32,
+    pub calibration_y2: i32,
+    /// 1 if raw X coordinates grow from right to left.
+    pub flips_x: i32,
+    /// 1 if raw Y coordinates grow from bottom to top.
+    pub flips_y: i32,
+    /// 1 if raw X coordinates run along the monitor's Y axis.
+    pub swap_axes: i32,
+    /// Counter-clockwise quarter turns of the monitor.
+    pub quarter_turns: i32,
+    /// Size of the monitor in pixels.
+    pub width: i32,
+    pub height: i32,
+    /// 1 if touches are mapped with `calibration_matrix` instead of the calibration points.
+    pub has_matrix: i32,
+    /// The affine calibration as in the config file, which JavaScript writes with a `Float32Array`.
+    pub calibration_matrix: [[f32; 3]; 2],
+}
+
+impl MonitorMapping {
+    /// The calibration of the driver that these fields describe.
+    pub fn calibration(&self) -> Calibration {
+        Calibration {
+            calibration_points: AABB::from((
+                self.calibration_x1,
+                self.calibration_y1,
+                self.calibration_x2,
+                self.calibration_y2,
+            )),
+            origin: Origin::from_flips(self.flips_x != 0, self.flips_y != 0),
+            swap_axes: self.swap_axes != 0,
+            rotation: Rotation::from_quarter_turns(self.quarter_turns),
+            affine: (self.has_matrix != 0).then_some(Affine {
+                matrix: self.calibration_matrix,
+            }),
+        }
+    }
+
+    /// Map a raw touch position to pixels relative to the upper left corner of the monitor, like the driver does.
+    pub fn map_to(&self, position: Point2D<TouchSpace>) -> Point2D<ScreenSpace> {
+        self.calibration()
+            .map_to(position, AABB::from((0, 0, self.width, self.height)))
+    }
+}
+
+/// Allocate `len` bytes that JavaScript can fill, aligned for [DecodedPacket]s and [MonitorMapping]s.
+#[no_mangle]
+pub extern "C" fn egalax_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<i32>::with_capacity(words(len));
+    let ptr = buffer.as_mut_ptr();
+    mem::forget(buffer);
+    ptr.cast()
+}
+
+/// Release memory from [egalax_alloc].
+///
+/// # Safety
+/// `ptr` and `len` must be the result and argument of a single call of [egalax_alloc].
+#[no_mangle]
+pub unsafe extern "C" fn egalax_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr.cast::<i32>(), 0, words(len)));
+}
+
+/// Decode a dump of consecutive raw packets, e.g. read from the hidraw device with `cat`.
+/// Writes at most `capacity` packets to `output` and returns their number. Trailing bytes of an incomplete packet are ignored.
+///
+/// # Safety
+/// `input` must point to `len` readable bytes and `output` to `capacity` writable [DecodedPacket]s.
+#[no_mangle]
+pub unsafe extern "C" fn egalax_decode(
+    input: *const u8,
+    len: usize,
+    output: *mut DecodedPacket,
+    capacity: usize,
+) -> usize {
+    let input = slice::from_raw_parts(input, len);
+    let output = slice::from_raw_parts_mut(output, capacity);
+    let mut count = 0;
+    for (slot, (index, raw)) in output
+        .iter_mut()
+        .zip(input.chunks_exact(RAW_PACKET_LEN).enumerate())
+    {
+        *slot = DecodedPacket::decode(index * RAW_PACKET_LEN, raw);
+        count += 1;
+    }
+    count
+}
+
+/// Replace the touch coordinates of valid packets with pixels on the monitor.
+///
+/// # Safety
+/// `mapping` must point to a [MonitorMapping] and `packets` to `count` [DecodedPacket]s.
+#[no_mangle]
+pub unsafe extern "C" fn egalax_map_to_monitor(
+    mapping: *const MonitorMapping,
+    packets: *mut DecodedPacket,
+    count: usize,
+) {
+    let mapping = &*mapping;
+    for packet in slice::from_raw_parts_mut(packets, count) {
+        if packet.valid != 0 {
+            let position = mapping.map_to((packet.x, packet.y).into());
+            packet.x = position.x.value();
+            packet.y = position.y.value();
+        }
+    }
+}
+
+/// Number of 32 bit words that hold `len` bytes.
+fn words(len: usize) -> usize {
+    len.div_ceil(mem::size_of::<i32>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egalax_protocol::packet::USBPacket;
+
+    #[test]
+    fn test_decode_and_map() {
+        let packets = [
+            USBPacket::new(TouchState::IsTouching, (400, 3600).into(), 12)
+                .unwrap()
+                .encode(),
+            RawPacket([0xff; RAW_PACKET_LEN]),
+            USBPacket::new(TouchState::NotTouching, (3600, 400).into(), 12)
+                .unwrap()
+                .encode(),
+        ];
+        let mut dump: Vec<u8> = packets.iter().flat_map(|raw| raw.0).collect();
+        // An incomplete packet at the end of the capture.
+        dump.extend_from_slice(&[0x02, 0x03]);
+
+        let mut decoded = [DecodedPacket::default(); 4];
+        let count = unsafe {
+            egalax_decode(
+                dump.as_ptr(),
+                dump.len(),
+                decoded.as_mut_ptr(),
+                decoded.len(),
+            )
+        };
+        assert_eq!(3, count);
+        let first = decoded[0];
+        assert_eq!(
+            (1, 1, 400, 3600),
+            (first.valid, first.touching, first.x, first.y)
+        );
+        assert_eq!(0, decoded[1].valid);
+        assert_eq!(12, decoded[2].offset);
+
+        // A panel with its origin in the lower left corner.
+        let mapping = MonitorMapping {
+            calibration_x2: 4000,
+            calibration_y2: 4000,
+            flips_y: 1,
+            width: 1000,
+            height: 1000,
+            ..Default::default()
+        };
+        unsafe { egalax_map_to_monitor(&mapping, decoded.as_mut_ptr(), count) };
+        assert_eq!((100, 100), (decoded[0].x, decoded[0].y));
+        assert_eq!((900, 900), (decoded[2].x, decoded[2].y));
+
+        // An affine calibration replaces the calibration points and their origin, like in the driver.
+        let mapping = MonitorMapping {
+            has_matrix: 1,
+            calibration_matrix: [[0.00025, 0.0, 0.0], [0.0, 0.00025, 0.0]],
+            ..mapping
+        };
+        assert_eq!(
+            Point2D::from((100, 900)),
+            mapping.map_to((400, 3600).into())
+        );
+    }
+}
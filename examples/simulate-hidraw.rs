@@ -1,9 +1,13 @@
-use egalax_rs::{config::ConfigFile, driver::virtual_mouse};
+use egalax_rs::{
+    config::ConfigFile,
+    driver::virtual_mouse,
+    protocol::PacketDecoder,
+    simulate::{self, Gesture, Playback, SynthConfig, TimedPacket},
+};
 use nix::{sys::stat, unistd::mkfifo};
 use std::{
     error,
     fs::{self, OpenOptions},
-    io::{Cursor, Read, Write},
     os::unix::prelude::OpenOptionsExt,
     path::PathBuf,
     thread,
@@ -12,9 +16,14 @@ use std::{
 use tempdir::TempDir;
 
 const HIDRAW_FILE: &str = "./dumps/hidraw.bin";
+const USAGE: &str = "Usage: simulate-hidraw [--rate HZ] [--seed N] [--speed FACTOR] [--loop] [--start MS] [--end MS] \
+    [replay FILE | line X1 Y1 X2 Y2 MS | circle X Y RADIUS MS | tap X Y JITTER MS | long-press X Y MS | noise MS]";
+
+/// Delay between the packets of a replayed dump, which carries no timing information.
+const REPLAY_INTERVAL: Duration = Duration::from_millis(500);
 
-fn virtual_sender(data: Vec<u8>, path: PathBuf) {
-    // let mut writer = OpenOptions::new().write(true).open(&path).unwrap();
+fn virtual_sender(packets: Vec<TimedPacket>, playback: Playback, path: PathBuf) {
+    // Give the driver some time to set up the virtual device.
     thread::sleep(Duration::from_secs(5));
 
     let mut writer = OpenOptions::new()
@@ -22,23 +31,107 @@ fn virtual_sender(data: Vec<u8>, path: PathBuf) {
         .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
         .open(&path)
         .unwrap();
-    let mut hidraw = Cursor::new(data);
-    let mut buf = [0; 6];
-
-    loop {
-        println!("Sending next raw packet");
-        let res = hidraw.read_exact(&mut buf);
-        if let Ok(()) = res {
-            writer.write_all(&buf).unwrap();
-        } else {
-            break;
-        }
-        thread::sleep(Duration::from_millis(500));
+
+    println!("Sending {} packets", packets.len());
+    simulate::play(&mut writer, &packets, &playback).unwrap();
+}
+
+/// Read the packets of a hidraw dump.
+fn replay(path: &str) -> Result<Vec<TimedPacket>, Box<dyn error::Error>> {
+    let hidraw = fs::read(path)?;
+    let mut packets = Vec::new();
+    for (i, message) in PacketDecoder::new(&hidraw[..]).enumerate() {
+        packets.push(TimedPacket {
+            offset: REPLAY_INTERVAL * i as u32,
+            packet: *message?.packet(),
+        });
     }
+    Ok(packets)
+}
+
+/// Parse the gesture arguments.
+fn generate(
+    gesture: &str,
+    args: &[i32],
+    cfg: &SynthConfig,
+) -> Result<Vec<TimedPacket>, Box<dyn error::Error>> {
+    let ms = |v: i32| Duration::from_millis(v as u64);
+    let gesture = match (gesture, args) {
+        ("line", &[x1, y1, x2, y2, duration]) => Gesture::Line {
+            from: (x1, y1).into(),
+            to: (x2, y2).into(),
+            duration: ms(duration),
+        },
+        ("circle", &[x, y, radius, duration]) => Gesture::Circle {
+            center: (x, y).into(),
+            radius: radius as f32,
+            duration: ms(duration),
+        },
+        ("tap", &[x, y, jitter, duration]) => Gesture::Tap {
+            position: (x, y).into(),
+            jitter: jitter as f32,
+            duration: ms(duration),
+        },
+        ("long-press", &[x, y, duration]) => Gesture::LongPress {
+            position: (x, y).into(),
+            duration: ms(duration),
+        },
+        ("noise", &[duration]) => {
+            let max = (1 << cfg.resolution) - 1;
+            Gesture::Noise {
+                area: (0, 0, max, max).into(),
+                duration: ms(duration),
+            }
+        }
+        _ => return Err(USAGE.into()),
+    };
+    Ok(gesture.generate(cfg)?)
 }
 
 fn main() -> Result<(), Box<dyn error::Error>> {
-    let hidraw = fs::read(HIDRAW_FILE).expect("Cannot read hidraw file");
+    env_logger::init();
+
+    let mut cfg = SynthConfig::default();
+    let mut playback = Playback::default();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let ms = |value: String| -> Result<Duration, Box<dyn error::Error>> {
+        Ok(Duration::from_millis(value.parse()?))
+    };
+
+    if let Some(i) = args.iter().position(|arg| arg == "--loop") {
+        args.remove(i);
+        playback.looped = true;
+    }
+    while let Some(i) = args.iter().position(|arg| arg.starts_with("--")) {
+        let flag = args.remove(i);
+        if i >= args.len() {
+            return Err(USAGE.into());
+        }
+        let value = args.remove(i);
+        match flag.as_str() {
+            "--rate" => cfg.rate = value.parse()?,
+            "--seed" => cfg.seed = value.parse()?,
+            "--speed" => playback.speed = value.parse()?,
+            "--start" => playback.start = ms(value)?,
+            "--end" => playback.end = Some(ms(value)?),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    if playback.speed <= 0.0 || cfg.rate <= 0.0 {
+        return Err("speed and rate must be positive".into());
+    }
+
+    let packets = match args.first().map(String::as_str) {
+        None => replay(HIDRAW_FILE)?,
+        Some("replay") => replay(args.get(1).ok_or(USAGE)?)?,
+        Some(gesture) => {
+            let numbers = args[1..]
+                .iter()
+                .map(|arg| arg.parse())
+                .collect::<Result<Vec<i32>, _>>()?;
+            generate(gesture, &numbers, &cfg)?
+        }
+    };
 
     let tmp_dir = TempDir::new("hidraw").unwrap();
     let path = tmp_dir.path().join("egalax.fifo");
@@ -53,7 +146,7 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     // therefore we need to open the writer in another thread, so that they can unblock each other.
     // we cannot open both reader and writer in the same thread, if writer is blocking we have a deadlock, if write is nonblocking, opening returns an error
 
-    thread::spawn(move || virtual_sender(hidraw, path1));
+    thread::spawn(move || virtual_sender(packets, playback, path1));
     let mut reader = OpenOptions::new().read(true).open(&path).unwrap();
     let monitor_cfg = ConfigFile::default().build()?;
     println!("setup complete");
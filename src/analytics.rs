@@ -0,0 +1,224 @@
+//! Anonymous touch statistics published to an MQTT broker, which digital signage operators use to measure
+//! engagement per region of the screen.
+//!
+//! Only available with the `mqtt` feature. Only counts per interval are published, never the position or time of a touch.
+//! We speak just enough MQTT 3.1.1 to publish with QoS 0, so no client library is needed.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use std::{fmt, process, thread};
+
+use crate::geo::Point2D;
+use crate::units::MonitorSpace;
+use crate::usage::{TouchCounts, Zones};
+
+/// Where and how often touch statistics are published.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TouchAnalytics {
+    /// Address of the broker, e.g. `localhost:1883`.
+    pub broker: String,
+    /// Topic the statistics are published to, e.g. `signage/lobby/touches`.
+    pub topic: String,
+    /// How often the statistics are published. The counts start from zero after each one.
+    pub interval: Duration,
+    #[serde(default)]
+    pub zones: Zones,
+}
+
+impl fmt::Display for TouchAnalytics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "to '{}' at {} every {}s with {} zones",
+            self.topic,
+            self.broker,
+            self.interval.as_secs(),
+            self.zones.len()
+        ))
+    }
+}
+
+/// Handle to the thread that counts touches and publishes the statistics.
+#[derive(Debug)]
+pub struct AnalyticsPublisher {
+    sender: Sender<Point2D<MonitorSpace>>,
+}
+
+impl AnalyticsPublisher {
+    pub fn spawn(analytics: TouchAnalytics) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || analytics_loop(analytics, receiver));
+        Self { sender }
+    }
+
+    /// Count a touch that started at this position.
+    pub fn touched(&self, position: Point2D<MonitorSpace>) {
+        // If the thread is gone there are simply no statistics.
+        self.sender.send(position).ok();
+    }
+}
+
+fn analytics_loop(analytics: TouchAnalytics, receiver: Receiver<Point2D<MonitorSpace>>) {
+    log::trace!("Entering fn analytics_loop");
+
+    let interval = analytics.interval.max(Duration::from_secs(1));
+    let mut counts = TouchCounts::new(&analytics.zones);
+    let mut next_publish = Instant::now() + interval;
+    loop {
+        match receiver.recv_timeout(next_publish.saturating_duration_since(Instant::now())) {
+            Ok(position) => counts.record(&analytics.zones, position),
+            Err(RecvTimeoutError::Timeout) => {
+                // A broker that is down only costs the statistics of this interval.
+                if let Err(e) = publish(&analytics, &counts) {
+                    log::warn!(
+                        "Could not publish touch statistics to {}: {}",
+                        analytics.broker,
+                        e
+                    );
+                }
+                counts = TouchCounts::new(&analytics.zones);
+                next_publish += interval;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    log::trace!("Leaving fn analytics_loop");
+}
+
+/// How long we wait for the broker to accept the connection, the packets or to answer.
+const BROKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connect to the first address of the broker that accepts the connection in time.
+fn connect(broker: &str) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in broker.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, BROKER_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the broker has no address")))
+}
+
+/// Connect to the broker, publish the counts as JSON and disconnect again.
+fn publish(analytics: &TouchAnalytics, counts: &TouchCounts) -> io::Result<()> {
+    let payload = serde_json::to_vec(counts)?;
+    let mut stream = connect(&analytics.broker)?;
+    // A stalled broker must not keep the statistics thread from counting.
+    stream.set_read_timeout(Some(BROKER_TIMEOUT))?;
+    stream.set_write_timeout(Some(BROKER_TIMEOUT))?;
+
+    stream.write_all(&encode_connect(&format!("egalax-rs-{}", process::id()))?)?;
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != CONNACK || connack[3] != 0 {
+        return Err(io::Error::other(format!(
+            "broker refused connection with code {}",
+            connack[3]
+        )));
+    }
+
+    stream.write_all(&encode_publish(&analytics.topic, &payload)?)?;
+    stream.write_all(&[DISCONNECT, 0])?;
+    log::debug!("Published touch statistics {:?}", counts);
+    Ok(())
+}
+
+/// Fixed header bytes of the MQTT packets we use.
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xe0;
+
+/// A CONNECT packet with a clean session and without keep alive.
+fn encode_connect(client_id: &str) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    push_string(&mut body, "MQTT")?;
+    // Protocol level 4 is MQTT 3.1.1, flag 0x02 requests a clean session.
+    body.extend_from_slice(&[4, 0x02, 0, 0]);
+    push_string(&mut body, client_id)?;
+    Ok(with_fixed_header(CONNECT, body))
+}
+
+/// A PUBLISH packet with QoS 0, which needs no acknowledgement.
+fn encode_publish(topic: &str, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    push_string(&mut body, topic)?;
+    body.extend_from_slice(payload);
+    Ok(with_fixed_header(PUBLISH, body))
+}
+
+/// Strings are prefixed with their length as a big-endian u16, so longer ones cannot be sent.
+fn push_string(buf: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{:.32}...' is longer than {} bytes", s, u16::MAX),
+        )
+    })?;
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Prepend the packet type and the remaining length, which is encoded with 7 bits per byte.
+fn with_fixed_header(packet_type: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    let mut remaining = body.len();
+    loop {
+        let byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::AABB;
+
+    #[test]
+    fn test_counts_and_encoding() {
+        let analytics = TouchAnalytics {
+            broker: String::from("localhost:1883"),
+            topic: String::from("signage/lobby"),
+            interval: Duration::from_secs(60),
+            zones: Zones::from([
+                (String::from("banner"), AABB::from((0, 0, 1920, 200))),
+                (String::from("menu"), AABB::from((0, 200, 400, 1080))),
+            ]),
+        };
+        let mut counts = TouchCounts::new(&analytics.zones);
+        for position in [(100, 100), (1000, 150), (100, 500), (1000, 800)] {
+            counts.record(&analytics.zones, position.into());
+        }
+        assert_eq!(
+            r#"{"touches":4,"zones":{"banner":2,"menu":1}}"#,
+            serde_json::to_string(&counts).unwrap()
+        );
+
+        let packet = encode_publish("t", b"{}").unwrap();
+        assert_eq!(vec![PUBLISH, 5, 0, 1, b't', b'{', b'}'], packet);
+        // Remaining lengths above 127 take more than one byte.
+        let packet = encode_publish("t", &[0; 200]).unwrap();
+        assert_eq!([PUBLISH, 0xcb, 0x01], packet[..3]);
+        // The length of a topic must fit into two bytes.
+        assert!(encode_publish(&"t".repeat(usize::from(u16::MAX)), b"{}").is_ok());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            encode_publish(&"t".repeat(usize::from(u16::MAX) + 1), b"{}")
+                .unwrap_err()
+                .kind()
+        );
+    }
+}
@@ -1,16 +1,16 @@
-use std::{fs::OpenOptions, io::Write};
-
 use egalax_rs::config::ConfigFile;
+use egalax_rs::config_reference;
 
-/// Generate a default config
+/// Generate a default config with the documentation of each option as comments,
+/// or with `--markdown` print the reference of all options for `Configuration.md`.
 fn main() -> Result<(), anyhow::Error> {
+    if std::env::args().nth(1).as_deref() == Some("--markdown") {
+        print!("{}", config_reference::markdown());
+        return Ok(());
+    }
+
     let cf = ConfigFile::default();
     println!("{:#?}", cf);
-    let s = toml::to_string(&cf)?;
-    let mut f = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open("./config.toml")?;
-    f.write_all(s.as_bytes())?;
+    cf.save("./config.toml")?;
     Ok(())
 }
@@ -0,0 +1,72 @@
+//! Collection of the touches on the calibration targets.
+//! The calibration itself is computed in [egalax_protocol::calibration], which is shared with the packet viewer.
+
+pub use egalax_protocol::calibration::*;
+
+use crate::geo::{Point2D, AABB};
+use crate::units::*;
+
+/// A collection of touch coordinates that belong to a single calibration target.
+/// The final touch coordinate of that target is computed as the midpoint of the smallest area that contains the whole collection.
+#[derive(Debug, Clone, Default)]
+pub struct TouchCloud {
+    points: Vec<Point2D<TouchSpace>>,
+}
+
+impl TouchCloud {
+    pub fn push(&mut self, p: Point2D<TouchSpace>) {
+        self.points.push(p);
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// The smallest bounding box that contains all points.
+    fn bounding_box(&self) -> Option<AABB<TouchSpace>> {
+        let (first, rest) = self.points.split_first()?;
+        Some(
+            rest.iter()
+                .fold(AABB::new(first.x, first.y, first.x, first.y), |abox, p| {
+                    abox.grow_to_point(p)
+                }),
+        )
+    }
+
+    /// Compute the smallest bounding box that contains all points and then return its midpoint.
+    pub fn compute_touch_coord(&self) -> Option<Point2D<TouchSpace>> {
+        Some(self.bounding_box()?.midpoint())
+    }
+
+    /// How far apart the points are, i.e. the longer side of their bounding box.
+    /// A large spread means the finger slid around while touching.
+    pub fn spread(&self) -> Option<UdimRepr> {
+        let abox = self.bounding_box()?;
+        Some(abox.width().value().max(abox.height().value()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch_cloud_midpoint() {
+        let mut cloud = TouchCloud::default();
+        assert_eq!(None, cloud.compute_touch_coord());
+
+        cloud.push((10, 20).into());
+        cloud.push((30, 10).into());
+        cloud.push((20, 40).into());
+        assert_eq!(Some(Point2D::from((20, 25))), cloud.compute_touch_coord());
+        assert_eq!(Some(30), cloud.spread());
+    }
+}
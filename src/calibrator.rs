@@ -0,0 +1,616 @@
+//! Interactive calibration: targets are shown on the touchscreen monitor one after another
+//! and the touches on them are turned into a [Calibration].
+//! Without a display, [calibrate_headless] asks for the touches on the terminal instead.
+//! [calibrate_config] is what the `calibrate` program and subcommand run.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::calibration::{
+    target_positions, Calibration, TouchCloud, AFFINE_TARGETS_NUM, TARGETS_NUM,
+};
+use crate::config::ConfigFile;
+use crate::devices;
+use crate::error::EgalaxError;
+use crate::geo::{Point2D, AABB};
+use crate::overlay::{Overlay, BLACK, GREEN, RED};
+use crate::protocol::{
+    PacketFormat, PacketTag, RawPacket, TouchState, USBMessage, USBPacket, RAW_PACKET_LEN,
+};
+use crate::pump::PacketPump;
+use crate::serial;
+use crate::units::{ScreenSpace, TouchSpace};
+
+/// The calibration is aborted if nobody touches the screen for this long.
+pub const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the overlay is redrawn while waiting for touches.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const TARGET_RADIUS: i32 = 20;
+/// Number of samples on a target after which the sample meter is full.
+/// About a quarter second of holding still is enough for a stable touch coordinate.
+pub const SAMPLES_GOAL: usize = 25;
+/// A touch is rejected if its samples spread over more than this fraction of the touch range,
+/// i.e. about 80 units at a resolution of 12 bits.
+pub const MAX_SPREAD: f32 = 0.02;
+const INSTRUCTIONS: &str = "Touch and hold the highlighted circle, then lift your finger.";
+const RETRY_INSTRUCTIONS: &str = "The touch was not steady. Please touch the same circle again.";
+const HEADLESS_RETRY_INSTRUCTIONS: &str =
+    "The touch was not steady. Please touch the same corner again.";
+/// Names of the corners of the targets in [crate::calibration::TARGET_FRACTIONS] for the terminal prompts.
+const CORNER_NAMES: [&str; TARGETS_NUM] = ["top-left", "top-right", "bottom-left", "bottom-right"];
+const METER_WIDTH: i32 = 300;
+const METER_HEIGHT: i32 = 16;
+
+/// Which targets are touched and how the touches are turned into a [Calibration].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalibrationMode {
+    /// All four corners, for the calibration points.
+    #[default]
+    Corners,
+    /// Three corners, for an affine matrix that also fits slightly rotated or skewed panels.
+    Affine,
+}
+
+impl CalibrationMode {
+    /// Number of targets that are touched in this mode.
+    pub fn targets_num(self) -> usize {
+        match self {
+            CalibrationMode::Corners => TARGETS_NUM,
+            CalibrationMode::Affine => AFFINE_TARGETS_NUM,
+        }
+    }
+
+    /// Positions of the targets in the order they are shown.
+    pub fn targets(self, area: AABB<ScreenSpace>) -> Vec<Point2D<ScreenSpace>> {
+        target_positions(area)[..self.targets_num()].to_vec()
+    }
+}
+
+/// Progress of a calibration, driven by touch packets.
+/// This is independent of any drawing so that frontends can share it.
+#[derive(Debug, Clone, Default)]
+pub struct CalibrationSession {
+    mode: CalibrationMode,
+    /// Touch coordinates of the completed targets.
+    touches: Vec<Point2D<TouchSpace>>,
+    /// Touches on the current target.
+    cloud: TouchCloud,
+    /// If the last touch on the current target was rejected.
+    retry: bool,
+}
+
+/// What happened to a target after a packet was fed into a [CalibrationSession].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetEvent {
+    /// The target with this index was completed.
+    Completed(usize),
+    /// The touch on the target with this index spread too much and must be repeated.
+    Rejected(usize),
+}
+
+impl CalibrationSession {
+    pub fn new(mode: CalibrationMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    pub fn mode(&self) -> CalibrationMode {
+        self.mode
+    }
+
+    /// Index of the target that should be touched next, or `None` if all targets are done.
+    pub fn current_target(&self) -> Option<usize> {
+        (self.touches.len() < self.mode.targets_num()).then_some(self.touches.len())
+    }
+
+    /// Number of samples collected on the current target so far.
+    pub fn samples(&self) -> usize {
+        self.cloud.len()
+    }
+
+    /// If the last touch on the current target was rejected and the user should be asked to try again.
+    pub fn is_retry(&self) -> bool {
+        self.retry
+    }
+
+    /// Feed a packet into the session. A target is completed when the finger is lifted from it,
+    /// unless the touch spread more than [MAX_SPREAD] in which case it is discarded.
+    pub fn update(&mut self, packet: &USBPacket) -> Option<TargetEvent> {
+        let target = self.current_target()?;
+
+        match packet.touch_state() {
+            TouchState::IsTouching => {
+                self.cloud.push(packet.position());
+                None
+            }
+            TouchState::NotTouching => {
+                let touch = self.cloud.compute_touch_coord()?;
+                let spread = self.cloud.spread()? as f32 / (1 << packet.resolution()) as f32;
+                self.cloud.clear();
+
+                if spread > MAX_SPREAD {
+                    log::warn!(
+                        "Rejected touch on target {} at {}, spread {:.3} is too large",
+                        target + 1,
+                        touch,
+                        spread
+                    );
+                    self.retry = true;
+                    Some(TargetEvent::Rejected(target))
+                } else {
+                    log::info!("Target {} touched at {}", target + 1, touch);
+                    self.touches.push(touch);
+                    self.retry = false;
+                    Some(TargetEvent::Completed(target))
+                }
+            }
+        }
+    }
+
+    /// The calibration computed from all targets, once they are done.
+    /// An affine calibration fails if the touches lie on a line, in which case the targets are touched again.
+    pub fn result(&mut self) -> Option<Calibration> {
+        match self.mode {
+            CalibrationMode::Corners => {
+                let touches: &[Point2D<TouchSpace>; TARGETS_NUM] =
+                    self.touches.as_slice().try_into().ok()?;
+                Some(Calibration::from_touches(touches))
+            }
+            CalibrationMode::Affine => {
+                if self.current_target().is_some() {
+                    return None;
+                }
+                let calibration = Calibration::from_affine_touches(&self.touches);
+                if calibration.is_none() {
+                    log::warn!("Touches lie on a line, starting over");
+                    self.touches.clear();
+                    self.retry = true;
+                }
+                calibration
+            }
+        }
+    }
+}
+
+/// A packet received during calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedPacket {
+    /// Milliseconds since the start of the calibration.
+    pub offset_ms: u64,
+    pub packet: [u8; RAW_PACKET_LEN],
+}
+
+/// Everything that happened during a calibration, so that it can be analyzed offline without redoing it on site.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationCapture {
+    /// The monitor area that was calibrated.
+    pub area: AABB<ScreenSpace>,
+    #[serde(default)]
+    pub mode: CalibrationMode,
+    /// Positions of the targets in the order they were shown.
+    pub targets: Vec<Point2D<ScreenSpace>>,
+    pub packets: Vec<CapturedPacket>,
+    /// The resulting calibration, or `None` if the calibration failed.
+    pub calibration: Option<Calibration>,
+    #[serde(skip, default = "Instant::now")]
+    started: Instant,
+}
+
+impl CalibrationCapture {
+    pub fn new(area: AABB<ScreenSpace>, mode: CalibrationMode) -> Self {
+        Self {
+            area,
+            mode,
+            targets: mode.targets(area),
+            packets: Vec::new(),
+            calibration: None,
+            started: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, message: &USBMessage) {
+        self.packets.push(CapturedPacket {
+            offset_ms: message
+                .time()
+                .saturating_duration_since(self.started)
+                .as_millis() as u64,
+            packet: message.packet().encode().0,
+        });
+    }
+
+    /// Run the recorded packets through a new session, e.g. after the calibration math was improved.
+    pub fn recompute(&self) -> Option<Calibration> {
+        let mut session = CalibrationSession::new(self.mode);
+        for captured in &self.packets {
+            match USBPacket::try_parse(RawPacket(captured.packet), Some(PacketTag::TouchEvent)) {
+                Ok(packet) => {
+                    session.update(&packet);
+                }
+                Err(e) => log::warn!("Skipping invalid captured packet: {}", e),
+            }
+        }
+        session.result()
+    }
+}
+
+/// Draw the current state of the session: instructions, progress, the sample meter and the targets.
+/// Completed targets stay visible in green so that the user sees the progress on the screen itself.
+fn draw(overlay: &Overlay, session: &CalibrationSession, targets: &[Point2D<ScreenSpace>]) {
+    overlay.clear();
+    if let Some(target) = session.current_target() {
+        let y = overlay.area().height().value() / 3;
+        if session.is_retry() {
+            overlay.set_color(RED);
+            overlay.draw_text_centered(RETRY_INSTRUCTIONS, y);
+        } else {
+            overlay.set_color(BLACK);
+            overlay.draw_text_centered(INSTRUCTIONS, y);
+        }
+        overlay.set_color(BLACK);
+        overlay.draw_text_centered(
+            &format!("Target {} of {}", target + 1, session.mode().targets_num()),
+            y + 30,
+        );
+        overlay.draw_progress_bar(
+            y + 50,
+            METER_WIDTH,
+            METER_HEIGHT,
+            session.samples() as f32 / SAMPLES_GOAL as f32,
+        );
+
+        overlay.set_color(GREEN);
+        for done in &targets[..target] {
+            overlay.draw_target(*done, TARGET_RADIUS / 2);
+        }
+        overlay.set_color(RED);
+        overlay.draw_target(targets[target], TARGET_RADIUS);
+    }
+    overlay.flush();
+}
+
+/// Show the calibration targets on the monitor area and compute a calibration from the touches read from `stream`,
+/// whose packets are in `format`. All packets and the result are recorded into `capture` if given.
+/// Fails if the stream ends or nobody touches the screen for [CALIBRATION_TIMEOUT].
+pub fn calibrate<T>(
+    stream: T,
+    format: PacketFormat,
+    area: AABB<ScreenSpace>,
+    mode: CalibrationMode,
+    capture: Option<&mut CalibrationCapture>,
+) -> Result<Calibration, EgalaxError>
+where
+    T: io::Read + AsRawFd + Send + 'static,
+{
+    log::trace!("Entering fn calibrate");
+
+    let overlay = Overlay::open(area)?;
+    let targets = mode.targets(area);
+    let calibration = run_session(stream, format, mode, capture, |session, event| {
+        if let Some(TargetEvent::Rejected(_)) = event {
+            overlay.bell();
+        }
+        draw(&overlay, session, &targets);
+    })?;
+
+    log::trace!("Leaving fn calibrate");
+    Ok(calibration)
+}
+
+/// Like [calibrate] but without a display: the targets are described on `out`, e.g. the terminal,
+/// and the user touches the corners of the screen where the targets would have been shown.
+pub fn calibrate_headless<T, W>(
+    stream: T,
+    format: PacketFormat,
+    mode: CalibrationMode,
+    capture: Option<&mut CalibrationCapture>,
+    out: &mut W,
+) -> Result<Calibration, EgalaxError>
+where
+    T: io::Read + AsRawFd + Send + 'static,
+    W: Write,
+{
+    log::trace!("Entering fn calibrate_headless");
+
+    let mut prompted = None;
+    let mut result = Ok(());
+    let calibration = run_session(stream, format, mode, capture, |session, _| {
+        let Some(target) = session.current_target() else {
+            return;
+        };
+        // Each prompt is only printed once, not on every redraw.
+        if prompted == Some((target, session.is_retry())) || result.is_err() {
+            return;
+        }
+        prompted = Some((target, session.is_retry()));
+        result = prompt(out, session, target);
+    })?;
+    result?;
+
+    log::trace!("Leaving fn calibrate_headless");
+    Ok(calibration)
+}
+
+/// How to calibrate from the command line, see [calibrate_config].
+#[derive(Debug, Clone, Default)]
+pub struct CalibrateArgs {
+    /// The device node. By default the serial port of the config, or else the detected eGalax touchscreen.
+    pub node: Option<PathBuf>,
+    pub mode: CalibrationMode,
+    /// Describe the targets on the terminal instead of showing them, see [calibrate_headless].
+    pub headless: bool,
+    /// Save all packets of the session as JSON to this file, even if the calibration fails.
+    pub record: Option<PathBuf>,
+}
+
+/// Calibrate the touchscreen and save the calibration to the config file at `config_path`.
+/// A missing config file is created with the default settings.
+pub fn calibrate_config(
+    config_path: &Path,
+    args: &CalibrateArgs,
+) -> Result<Calibration, EgalaxError> {
+    log::trace!("Entering fn calibrate_config");
+
+    let mut config_file = match ConfigFile::from_file(config_path) {
+        Ok(config_file) => config_file,
+        Err(EgalaxError::IO(e)) if e.kind() == io::ErrorKind::NotFound => ConfigFile::default(),
+        Err(e) => return Err(e),
+    };
+    let serial = config_file.serial().cloned();
+    let node_path = match (&args.node, &serial) {
+        (Some(node_path), _) => node_path.clone(),
+        (None, Some(serial)) => serial.path.clone(),
+        (None, None) => devices::find_egalax_device()?.node,
+    };
+    // Without a display the monitors usually cannot be queried, but the calibration itself does not need them.
+    let monitor_area = match config_file.clone().build() {
+        Ok(monitor_cfg) => Some(monitor_cfg.monitor_area),
+        Err(e) if args.headless => {
+            log::warn!("Calibrating without the monitor area: {}", e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    let device_node = serial::open_device(&node_path, serial.as_ref())?;
+    let format = serial::packet_format(serial.as_ref());
+    let mut capture = CalibrationCapture::new(monitor_area.unwrap_or_default(), args.mode);
+    let capture_ref = args.record.as_ref().map(|_| &mut capture);
+    let calibration = match monitor_area {
+        Some(monitor_area) if !args.headless => {
+            calibrate(device_node, format, monitor_area, args.mode, capture_ref)
+        }
+        _ => calibrate_headless(
+            device_node,
+            format,
+            args.mode,
+            capture_ref,
+            &mut io::stdout(),
+        ),
+    };
+    if let Some(record_path) = &args.record {
+        let contents =
+            serde_json::to_string_pretty(&capture).map_err(|e| EgalaxError::Generic(e.into()))?;
+        fs::write(record_path, contents)?;
+        log::info!("Saved calibration session to {}.", record_path.display());
+    }
+    let calibration = calibration?;
+
+    // Keep the values in pixels if the file came from a unit with another resolution.
+    // Any warnings about that were already logged when building the config.
+    if let Some(monitor_area) = monitor_area {
+        config_file.transfer_to(monitor_area);
+    }
+    config_file.set_calibration(calibration);
+    config_file.set_calibrated_at(SystemTime::now());
+    config_file.save(config_path)?;
+
+    log::trace!("Leaving fn calibrate_config");
+    Ok(calibration)
+}
+
+/// Ask on `out` for a touch on a target.
+fn prompt<W: Write>(out: &mut W, session: &CalibrationSession, target: usize) -> io::Result<()> {
+    if session.is_retry() {
+        writeln!(out, "{}", HEADLESS_RETRY_INSTRUCTIONS)?;
+    }
+    writeln!(
+        out,
+        "[{}/{}] Touch the {} corner of the screen now, about a tenth of the screen in from both edges, \
+        hold still and lift your finger.",
+        target + 1,
+        session.mode().targets_num(),
+        CORNER_NAMES[target]
+    )?;
+    out.flush()
+}
+
+/// Feed the packets read from `stream` into a new session until it has a result.
+/// `show` is called whenever the frontend should present the progress, and with the event of each touch on a target.
+fn run_session<T, F>(
+    stream: T,
+    format: PacketFormat,
+    mode: CalibrationMode,
+    mut capture: Option<&mut CalibrationCapture>,
+    mut show: F,
+) -> Result<Calibration, EgalaxError>
+where
+    T: io::Read + AsRawFd + Send + 'static,
+    F: FnMut(&CalibrationSession, Option<TargetEvent>),
+{
+    let pump = PacketPump::spawn(stream, format);
+    let mut session = CalibrationSession::new(mode);
+    let mut last_activity = Instant::now();
+
+    let calibration = loop {
+        if let Some(calibration) = session.result() {
+            break calibration;
+        }
+        show(&session, None);
+
+        let Some(message) = pump.recv_timeout(REDRAW_INTERVAL)? else {
+            if last_activity.elapsed() > CALIBRATION_TIMEOUT {
+                return Err(EgalaxError::Timeout(CALIBRATION_TIMEOUT));
+            }
+            continue;
+        };
+        last_activity = message.time();
+        // Additional fingers are not part of the calibration.
+        if message.contact() != 0 {
+            continue;
+        }
+        if let Some(capture) = capture.as_deref_mut() {
+            capture.record(&message);
+        }
+        if let Some(event) = session.update(message.packet()) {
+            show(&session, Some(event));
+        }
+    };
+
+    if let Some(capture) = capture {
+        capture.calibration = Some(calibration);
+    }
+    log::info!(
+        "Calibration finished: {}, origin {}, swap axes {}",
+        calibration.calibration_points,
+        calibration.origin,
+        calibration.swap_axes
+    );
+    if let Some(affine) = calibration.affine {
+        log::info!("Calibration matrix: {}", affine);
+    }
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_completes_targets_on_release() {
+        let mut session = CalibrationSession::new(CalibrationMode::Corners);
+        let touches = [(400, 400), (3600, 400), (400, 3600), (3600, 3600)];
+
+        for (i, position) in touches.into_iter().enumerate() {
+            assert_eq!(Some(i), session.current_target());
+            for state in [TouchState::IsTouching, TouchState::NotTouching] {
+                session.update(&USBPacket::new(state, position.into(), 12).unwrap());
+            }
+        }
+
+        assert_eq!(None, session.current_target());
+        let calibration = session.result().unwrap();
+        assert_eq!(
+            AABB::from((0, 0, 4000, 4000)),
+            calibration.calibration_points
+        );
+    }
+
+    #[test]
+    fn test_session_rejects_spread_touch() {
+        let mut session = CalibrationSession::default();
+        for position in [(400, 400), (600, 400)] {
+            session.update(&USBPacket::new(TouchState::IsTouching, position.into(), 12).unwrap());
+        }
+        let release = USBPacket::new(TouchState::NotTouching, (600, 400).into(), 12).unwrap();
+        assert_eq!(Some(TargetEvent::Rejected(0)), session.update(&release));
+        assert!(session.is_retry());
+        assert_eq!(Some(0), session.current_target());
+
+        session.update(&USBPacket::new(TouchState::IsTouching, (410, 400).into(), 12).unwrap());
+        assert_eq!(Some(TargetEvent::Completed(0)), session.update(&release));
+        assert!(!session.is_retry());
+        assert_eq!(Some(1), session.current_target());
+    }
+
+    #[test]
+    fn test_session_affine() {
+        let mut session = CalibrationSession::new(CalibrationMode::Affine);
+        let touch = |session: &mut CalibrationSession, position: (i32, i32)| {
+            for state in [TouchState::IsTouching, TouchState::NotTouching] {
+                session.update(&USBPacket::new(state, position.into(), 12).unwrap());
+            }
+        };
+
+        // Touches on a line give no matrix, so all three targets are repeated.
+        for position in [(400, 400), (2000, 2000), (3600, 3600)] {
+            touch(&mut session, position);
+        }
+        assert_eq!(None, session.result());
+        assert_eq!(Some(0), session.current_target());
+        assert!(session.is_retry());
+
+        for position in [(400, 400), (3600, 400), (400, 3600)] {
+            touch(&mut session, position);
+        }
+        assert_eq!(None, session.current_target());
+        let calibration = session.result().unwrap();
+        assert!(calibration.affine.is_some());
+        assert_eq!(
+            AABB::from((0, 0, 4000, 4000)),
+            calibration.calibration_points
+        );
+    }
+
+    #[test]
+    fn test_calibrate_headless() {
+        let path = std::env::temp_dir().join(format!("egalax-rs-headless-{}", std::process::id()));
+        let mut bytes = Vec::new();
+        for position in [(400, 400), (3600, 400), (400, 3600), (3600, 3600)] {
+            for state in [TouchState::IsTouching, TouchState::NotTouching] {
+                let packet = USBPacket::new(state, position.into(), 12).unwrap();
+                bytes.extend(packet.encode().0);
+            }
+        }
+        std::fs::write(&path, bytes).unwrap();
+
+        let stream = std::fs::File::open(&path).unwrap();
+        let mut out = Vec::new();
+        let calibration = calibrate_headless(
+            stream,
+            PacketFormat::Hidraw,
+            CalibrationMode::Corners,
+            None,
+            &mut out,
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            AABB::from((0, 0, 4000, 4000)),
+            calibration.calibration_points
+        );
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(4, out.lines().count());
+        assert!(out.starts_with("[1/4] Touch the top-left corner"));
+        assert!(out.contains("[4/4] Touch the bottom-right corner"));
+    }
+
+    #[test]
+    fn test_capture_recompute() {
+        let mut capture =
+            CalibrationCapture::new(AABB::from((0, 0, 1000, 1000)), CalibrationMode::Corners);
+        let touches = [(400, 400), (3600, 400), (400, 3600), (3600, 3600)];
+        let start = Instant::now();
+        for position in touches {
+            for state in [TouchState::IsTouching, TouchState::NotTouching] {
+                let packet = USBPacket::new(state, position.into(), 12).unwrap();
+                capture.record(&packet.with_time(start));
+            }
+        }
+
+        let json = serde_json::to_string(&capture).unwrap();
+        let capture: CalibrationCapture = serde_json::from_str(&json).unwrap();
+        assert_eq!(8, capture.packets.len());
+        assert_eq!(
+            AABB::from((0, 0, 4000, 4000)),
+            capture.recompute().unwrap().calibration_points
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Where the driver takes the current time from.
+//!
+//! Packets carry the time they were read, so the timers of the state machine, e.g. the right-click wait, already
+//! run on packet time. What is left, e.g. the startup grace period and predicted positions between packets, asks the
+//! clock instead of calling [Instant::now] so that replays and tests can drive the time deterministically.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The system clock, or a virtual clock that only moves when it is told to.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    /// Clones share the same time, so a test can keep a handle to the clock that it passed to the driver.
+    Virtual(Arc<Mutex<Instant>>),
+}
+
+impl Clock {
+    /// A virtual clock that starts at `start`.
+    pub fn starting_at(start: Instant) -> Self {
+        Clock::Virtual(Arc::new(Mutex::new(start)))
+    }
+
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Virtual(now) => *now.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// Move a virtual clock forward to `time`. It never goes backwards and the system clock is not affected.
+    pub fn advance_to(&self, time: Instant) {
+        if let Clock::Virtual(now) = self {
+            let mut now = now.lock().unwrap_or_else(|e| e.into_inner());
+            *now = (*now).max(time);
+        }
+    }
+
+    /// Move a virtual clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.advance_to(self.now() + duration);
+    }
+}
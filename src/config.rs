@@ -1,13 +1,43 @@
 use anyhow::anyhow;
 use evdev_rs::enums::EV_KEY;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use nix::unistd;
 use serde::{Deserialize, Serialize};
-use std::fs::OpenOptions;
-use std::path::Path;
-use std::time::Duration;
-use std::{fmt, io::Read};
-use xrandr::{Monitor, XHandle};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{error::EgalaxError, geo::AABB};
+use crate::{
+    calibration::Calibration,
+    config_reference,
+    devices::{self, HidrawDevice, MonitorInfo},
+    drift::DriftEstimation,
+    edge_swipe::EdgeSwipe,
+    error::{ApplyConfigError, EgalaxError},
+    geo::{Affine, Origin, Point2D, AABB},
+    noise::AdaptiveNoise,
+    palm::PalmRejection,
+    pinch::PinchZoom,
+    pressure::PressureCurve,
+    rotation::Rotation,
+    scroll::TwoFingerScroll,
+    serial::SerialPort,
+    smoothing::Smoothing,
+    units::{MonitorSpace, ScreenSpace, TouchSpace},
+    usage::UsageStatistics,
+};
+
+#[cfg(feature = "mqtt")]
+use crate::analytics::TouchAnalytics;
+#[cfg(feature = "audio")]
+use crate::feedback::ClickFeedback;
 
 /// Parameters needed to translate the touch event coordinates coming from the monitor to coordinates in X's screen space.
 ///
@@ -22,18 +52,24 @@ use crate::{error::EgalaxError, geo::AABB};
 /// +-----+ +----+      +-----+----+
 ///    |      |
 ///   _+_    _+_
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Config {
     /// Total virtual screen space in pixels. the union of all screen spaces of connected displays.
-    pub screen_space: AABB,
+    pub screen_space: AABB<ScreenSpace>,
     /// Screen space of the target monitor in absolute pixels.
-    pub monitor_area: AABB,
+    pub monitor_area: AABB<ScreenSpace>,
     /// Common config options.
     common: ConfigCommon,
+    /// The click bindings, either from the config file or from the legacy click options.
+    click_bindings: Vec<ClickBinding>,
+    /// The click bindings of each window rule, by window class.
+    window_bindings: BTreeMap<String, Vec<ClickBinding>>,
+    /// The window rule for the focused window, if any.
+    active_rule: Option<String>,
 }
 
 impl Config {
-    pub fn calibration_points(&self) -> AABB {
+    pub fn calibration_points(&self) -> AABB<TouchSpace> {
         self.common.calibration_points
     }
 
@@ -52,6 +88,260 @@ impl Config {
     pub fn ev_right_click(&self) -> EV_KEY {
         self.common.ev_right_click
     }
+
+    /// BTN_TOUCH is always emitted by a touchscreen.
+    pub fn emit_btn_touch(&self) -> bool {
+        self.common.emit_btn_touch || self.common.output_mode == OutputMode::Touchscreen
+    }
+
+    pub fn gestures(&self) -> GestureSwitches {
+        self.common.gestures
+    }
+
+    pub fn startup_splash(&self) -> Option<Duration> {
+        self.common.startup_splash
+    }
+
+    pub fn origin(&self) -> Origin {
+        self.common.origin
+    }
+
+    pub fn swap_axes(&self) -> bool {
+        self.common.swap_axes
+    }
+
+    pub fn invert_x(&self) -> bool {
+        self.common.invert_x
+    }
+
+    pub fn invert_y(&self) -> bool {
+        self.common.invert_y
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.common.rotation
+    }
+
+    pub fn interpolation(&self) -> Option<Interpolation> {
+        self.common.interpolation
+    }
+
+    pub fn hide_cursor(&self) -> bool {
+        self.common.hide_cursor
+    }
+
+    pub fn tap_to_click(&self) -> Option<TapToClick> {
+        self.common.tap_to_click
+    }
+
+    pub fn drag_hold(&self) -> Option<Duration> {
+        self.common.drag_hold
+    }
+
+    pub fn grab_kernel_input(&self) -> bool {
+        self.common.grab_kernel_input
+    }
+
+    pub fn startup_grace(&self) -> Option<Duration> {
+        self.common.startup_grace
+    }
+
+    /// Everything needed to map touch coordinates onto the monitor.
+    pub fn calibration(&self) -> Calibration {
+        self.common.calibration()
+    }
+
+    /// Replace the calibration, e.g. with one from the control interface.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.common.set_calibration(calibration);
+    }
+
+    pub fn calibrated_at(&self) -> Option<SystemTime> {
+        self.common.calibrated_at()
+    }
+
+    pub fn calibration_max_age(&self) -> Option<Duration> {
+        self.common.calibration_max_age
+    }
+
+    pub fn drift_estimation(&self) -> Option<DriftEstimation> {
+        self.common.drift_estimation
+    }
+
+    pub fn sticky_drag(&self) -> bool {
+        self.common.sticky_drag
+    }
+
+    pub fn busy_poll_burns_cpu(&self) -> bool {
+        self.common.busy_poll_burns_cpu
+    }
+
+    pub fn realtime_priority(&self) -> Option<u8> {
+        self.common.realtime_priority
+    }
+
+    pub fn cpu_affinity(&self) -> &[usize] {
+        &self.common.cpu_affinity
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        self.common.output_mode
+    }
+
+    pub fn software_cursor(&self) -> bool {
+        self.common.software_cursor
+    }
+
+    pub fn usage_statistics(&self) -> Option<&UsageStatistics> {
+        self.common.usage_statistics.as_ref()
+    }
+
+    pub fn second_finger(&self) -> &SecondFinger {
+        &self.common.second_finger
+    }
+
+    pub fn adaptive_noise(&self) -> Option<AdaptiveNoise> {
+        self.common.adaptive_noise
+    }
+
+    pub fn smoothing(&self) -> Option<Smoothing> {
+        self.common.smoothing
+    }
+
+    pub fn palm_rejection(&self) -> Option<PalmRejection> {
+        self.common.palm_rejection
+    }
+
+    pub fn two_finger_scroll(&self) -> Option<TwoFingerScroll> {
+        self.common.two_finger_scroll
+    }
+
+    pub fn pinch_zoom(&self) -> Option<&PinchZoom> {
+        self.common.pinch_zoom.as_ref()
+    }
+
+    pub fn double_tap(&self) -> Option<DoubleTap> {
+        self.common.double_tap
+    }
+
+    pub fn edge_swipe(&self) -> Option<&EdgeSwipe> {
+        self.common.edge_swipe.as_ref()
+    }
+
+    pub fn edge_click_guard(&self) -> Option<EdgeClickGuard> {
+        self.common.edge_click_guard
+    }
+
+    pub fn maintenance_tap(&self) -> Option<&MaintenanceTap> {
+        self.common.maintenance_tap.as_ref()
+    }
+
+    pub fn pressure_curve(&self) -> Option<&PressureCurve> {
+        self.common.pressure_curve.as_ref()
+    }
+
+    pub fn serial(&self) -> Option<&SerialPort> {
+        self.common.serial.as_ref()
+    }
+
+    #[cfg(feature = "audio")]
+    pub fn click_feedback(&self) -> Option<&ClickFeedback> {
+        self.common.click_feedback.as_ref()
+    }
+
+    #[cfg(feature = "mqtt")]
+    pub fn touch_analytics(&self) -> Option<&TouchAnalytics> {
+        self.common.touch_analytics.as_ref()
+    }
+
+    /// See [ConfigFile::recalibration_due].
+    pub fn recalibration_due(&self, now: SystemTime) -> bool {
+        self.common.recalibration_due(now)
+    }
+
+    /// The click bindings of the active window rule, or else the common ones.
+    pub fn click_bindings(&self) -> &[ClickBinding] {
+        self.active_rule
+            .as_ref()
+            .and_then(|rule| self.window_bindings.get(rule))
+            .unwrap_or(&self.click_bindings)
+    }
+
+    /// The click bindings of all window rules and the common ones, e.g. to know which buttons can be emitted.
+    pub fn all_click_bindings(&self) -> impl Iterator<Item = &ClickBinding> {
+        self.click_bindings
+            .iter()
+            .chain(self.window_bindings.values().flatten())
+    }
+
+    pub fn has_window_rules(&self) -> bool {
+        !self.window_bindings.is_empty()
+    }
+
+    /// Activate the window rule for the class of the focused window, ignoring case.
+    /// Returns the name of the rule if there is one.
+    pub fn set_window_class(&mut self, class: Option<&str>) -> Option<&str> {
+        self.active_rule = class.and_then(|class| {
+            self.window_bindings
+                .keys()
+                .find(|rule| rule.eq_ignore_ascii_case(class))
+                .cloned()
+        });
+        self.active_rule.as_deref()
+    }
+
+    /// The binding that fires when a touch at a position of the monitor ends after `held`.
+    /// Bindings with a hold duration only apply if the finger stayed `still`.
+    /// If several bindings apply the one with the longest hold wins, and among equal holds the first one in the table.
+    pub fn click_binding(
+        &self,
+        held: Duration,
+        still: bool,
+        position: Point2D<MonitorSpace>,
+    ) -> Option<&ClickBinding> {
+        self.click_bindings()
+            .iter()
+            .rev()
+            .filter(|binding| binding.applies_at(position))
+            .filter(|binding| binding.hold <= held && (still || binding.hold.is_zero()))
+            .max_by_key(|binding| binding.hold)
+    }
+
+    /// The binding with the longest hold at a position of the monitor, if it has a hold duration.
+    /// Since no other binding can replace it, it fires as soon as the finger was held still for that long instead of on release.
+    pub fn longest_hold_binding(&self, position: Point2D<MonitorSpace>) -> Option<&ClickBinding> {
+        self.click_bindings()
+            .iter()
+            .rev()
+            .filter(|binding| binding.applies_at(position))
+            .max_by_key(|binding| binding.hold)
+            .filter(|binding| !binding.hold.is_zero())
+    }
+
+    /// The value of a common option, or `None` if it is not set.
+    pub fn option(&self, key: &str) -> Result<Option<toml::Value>, EgalaxError> {
+        check_common_option(key)?;
+        let common = toml::Table::try_from(&self.common).map_err(|e| anyhow!(e))?;
+        Ok(common.get(key).cloned())
+    }
+
+    /// A copy of the config with a common option set to a value in TOML syntax, e.g. to tune a threshold while
+    /// the driver is running. The click bindings of window rules are kept as they are.
+    pub fn with_option(&self, key: &str, value: &str) -> Result<Config, EgalaxError> {
+        check_common_option(key)?;
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .map_err(|e| anyhow!("invalid value: {}", e))?
+            .remove("value")
+            .expect("the value was parsed");
+        let mut common = toml::Table::try_from(&self.common).map_err(|e| anyhow!(e))?;
+        common.insert(key.to_string(), value);
+        let common: ConfigCommon = common.try_into().map_err(|e| anyhow!(e))?;
+        Ok(Config {
+            click_bindings: common.click_bindings(),
+            common,
+            ..self.clone()
+        })
+    }
 }
 
 impl fmt::Display for Config {
@@ -66,10 +356,31 @@ impl fmt::Display for Config {
 }
 
 /// Common config options that are taken verbatim from the config file.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ConfigCommon {
     /// The coordinates of the calibration points in the coordinate system of the touch screen (appears to be physically in units of 0.1mm).
-    calibration_points: AABB,
+    calibration_points: AABB<TouchSpace>,
+    /// If set, map raw touch positions to fractions of the monitor with this affine matrix, e.g. from a 3-point calibration.
+    /// Unlike the calibration points it also maps panels that are slightly rotated or skewed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    calibration_matrix: Option<Affine>,
+    /// The corner of the panel where the touchscreen reports its minimum coordinates.
+    #[serde(default)]
+    origin: Origin,
+    /// If raw X coordinates run along the monitor's Y axis, e.g. because the foil is rotated.
+    #[serde(default)]
+    swap_axes: bool,
+    /// Mirror raw X coordinates within the calibration points, on top of what `origin` does.
+    /// For panels that report X mirrored relative to the display, without having to figure out the origin.
+    #[serde(default)]
+    invert_x: bool,
+    /// Mirror raw Y coordinates within the calibration points, like `invert_x`.
+    #[serde(default)]
+    invert_y: bool,
+    /// Rotation of the monitor relative to the calibration points, e.g. for a panel mounted in portrait orientation.
+    /// Calibrating again resets it, since the calibrator shows its targets in the current rotation.
+    #[serde(default)]
+    rotation: Rotation,
     /// How long you have to keep pressing to trigger a right-click.
     right_click_wait: Duration,
     /// Threshold to filter noise of consecutive touch events happening close to each other.
@@ -78,28 +389,835 @@ struct ConfigCommon {
     ev_left_click: EV_KEY,
     /// Key code for right-click.
     ev_right_click: EV_KEY,
+    /// Also emit BTN_TOUCH while a finger is on the screen, which some applications (e.g. Qt) expect even from pointer devices.
+    #[serde(default)]
+    emit_btn_touch: bool,
+    /// Switches to turn individual gestures on or off.
+    #[serde(default)]
+    gestures: GestureSwitches,
+    /// If set, show a splash screen on the monitor for this long after startup to verify the driver is running.
+    #[serde(default)]
+    startup_splash: Option<Duration>,
+    /// If set, predict cursor positions between packets for controllers with a low report rate.
+    #[serde(default)]
+    interpolation: Option<Interpolation>,
+    /// Hide the cursor while the touchscreen was the last device to move the pointer, e.g. on kiosks.
+    #[serde(default)]
+    hide_cursor: bool,
+    /// If set, only emit a left-click on release if the touch was a short tap that did not move.
+    #[serde(default)]
+    tap_to_click: Option<TapToClick>,
+    /// If set, moving the finger after holding it still for this long drags with the left button held down.
+    /// Should be shorter than `right_click_wait`, since holding still for that long triggers a right-click instead.
+    #[serde(default)]
+    drag_hold: Option<Duration>,
+    /// Grab the input devices that the kernel creates for the touchscreen, so that touches are not reported twice.
+    #[serde(default)]
+    grab_kernel_input: bool,
+    /// If set, ignore all touches for this long after startup or after the device was reopened,
+    /// e.g. to filter EMI bursts while a kiosk powers up.
+    #[serde(default)]
+    startup_grace: Option<Duration>,
+    /// Time of the last calibration in seconds since the Unix epoch, set by the calibrator.
+    #[serde(default)]
+    calibrated_at: Option<u64>,
+    /// If set, warn that the touchscreen should be recalibrated once the calibration is older than this.
+    /// Resistive panels drift as they age, so kiosks should be recalibrated regularly.
+    #[serde(default)]
+    calibration_max_age: Option<Duration>,
+    /// If set, estimate calibration drift from touches that are quickly corrected and report it on the control interface.
+    #[serde(default)]
+    drift_estimation: Option<DriftEstimation>,
+    /// Actions for touches depending on how long they are held and where they are.
+    /// If empty, the bindings are made from `ev_left_click`, `ev_right_click` and `right_click_wait`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    click_bindings: Vec<ClickBinding>,
+    /// A tap presses the left button and keeps it latched while following the finger across lifts,
+    /// and the next tap releases it. For users who cannot keep the finger on the screen while dragging.
+    #[serde(default)]
+    sticky_drag: bool,
+    /// Spin on the device instead of sleeping until a packet arrives, which notices packets slightly earlier,
+    /// e.g. for rhythm games. As the name says, this keeps a CPU core busy all the time.
+    #[serde(default)]
+    busy_poll_burns_cpu: bool,
+    /// If set, run the driver thread with this SCHED_FIFO priority (1 to 99) when permitted,
+    /// e.g. with CAP_SYS_NICE or an RLIMIT_RTPRIO. Other processes on a busy core may then be starved,
+    /// especially together with `busy_poll_burns_cpu`.
+    #[serde(default)]
+    realtime_priority: Option<u8>,
+    /// If not empty, pin the driver thread to these CPUs, e.g. to keep it away from the cores of a heavyweight browser.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cpu_affinity: Vec<usize>,
+    /// If the virtual device is a mouse or a multi-touch touchscreen.
+    #[serde(default)]
+    output_mode: OutputMode,
+    /// Draw a crosshair where the pointer was moved, for compositors that draw no cursor for the virtual device.
+    #[serde(default)]
+    software_cursor: bool,
+    /// If set, count touches per day and zone and save the counts, e.g. to schedule maintenance by usage.
+    #[serde(default)]
+    usage_statistics: Option<UsageStatistics>,
+    /// What a second finger does while the first one is held for a hold binding, e.g. a hand resting on the bezel.
+    #[serde(default)]
+    second_finger: SecondFinger,
+    /// If set, raise the has-moved threshold and the tap slop while the touches are noisy, e.g. outdoors or near EMI sources.
+    #[serde(default)]
+    adaptive_noise: Option<AdaptiveNoise>,
+    /// If set, smooth the positions of touches against cursor jitter before any events are generated.
+    #[serde(default)]
+    smoothing: Option<Smoothing>,
+    /// If set, discard touches that jump or scatter like a palm resting on the panel.
+    #[serde(default)]
+    palm_rejection: Option<PalmRejection>,
+    /// If set, moving two fingers scrolls with wheel events instead of moving the pointer.
+    #[serde(default)]
+    two_finger_scroll: Option<TwoFingerScroll>,
+    /// If set, spreading or closing two fingers zooms, by default with Ctrl + wheel.
+    #[serde(default)]
+    pinch_zoom: Option<PinchZoom>,
+    /// If set, a second tap shortly after the first one at about the same place fires an action instead of its click,
+    /// e.g. to get reliable double-clicks from a panel whose taps land a bit off.
+    #[serde(default)]
+    double_tap: Option<DoubleTap>,
+    /// If set, swiping in from an edge of the monitor presses a key combination, e.g. KEY_BACK from the left edge.
+    #[serde(default)]
+    edge_swipe: Option<EdgeSwipe>,
+    /// If set, suppress clicks of short touches near the edges of the monitor,
+    /// e.g. the spurious touches of a badly seated overlay pressing on the bezel.
+    #[serde(default)]
+    edge_click_guard: Option<EdgeClickGuard>,
+    /// If set, a series of quick taps in a zone, e.g. a corner, fires a hidden action such as opening a maintenance menu.
+    #[serde(default)]
+    maintenance_tap: Option<MaintenanceTap>,
+    /// If set, map the pressure of pressure capable variants with this curve before it is emitted,
+    /// e.g. so that drawing feels the same on stiffer overlays.
+    #[serde(default)]
+    pressure_curve: Option<PressureCurve>,
+    /// If set, the touchscreen is an older one with a serial controller on this port instead of a hidraw device.
+    #[serde(default)]
+    serial: Option<SerialPort>,
+    /// If set, make emitted clicks audible. Only available with the `audio` feature.
+    #[cfg(feature = "audio")]
+    #[serde(default)]
+    click_feedback: Option<ClickFeedback>,
+    /// If set, publish anonymous touch counts to an MQTT broker. Only available with the `mqtt` feature.
+    #[cfg(feature = "mqtt")]
+    #[serde(default)]
+    touch_analytics: Option<TouchAnalytics>,
+}
+
+/// Fails unless `key` is a documented common option.
+fn check_common_option(key: &str) -> Result<(), EgalaxError> {
+    let key = format!("common.{}", key);
+    match config_reference::lookup(&key) {
+        Some(doc) if doc.key == key => Ok(()),
+        _ => Err(anyhow!("unknown option '{}'", &key["common.".len()..]).into()),
+    }
+}
+
+impl ConfigCommon {
+    /// The inversions are folded into the origin, which mirrors the coordinates in the same way.
+    fn calibration(&self) -> Calibration {
+        Calibration {
+            calibration_points: self.calibration_points,
+            origin: self.origin.mirrored(self.invert_x, self.invert_y),
+            swap_axes: self.swap_axes,
+            rotation: self.rotation,
+            affine: self.calibration_matrix,
+        }
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration_points = calibration.calibration_points;
+        self.origin = calibration.origin;
+        self.swap_axes = calibration.swap_axes;
+        // The origin of a calibration already includes any inversions.
+        self.invert_x = false;
+        self.invert_y = false;
+        self.rotation = calibration.rotation;
+        self.calibration_matrix = calibration.affine;
+    }
+
+    /// The configured click bindings, or else the left-click on a tap and the right-click on a long hold of earlier versions.
+    /// A touchscreen has no left-click on a tap since toolkits already turn touches into clicks.
+    fn click_bindings(&self) -> Vec<ClickBinding> {
+        if !self.click_bindings.is_empty() {
+            return self.click_bindings.clone();
+        }
+
+        let mut bindings = Vec::new();
+        if self.output_mode == OutputMode::Mouse {
+            bindings.push(ClickBinding {
+                hold: Duration::ZERO,
+                action: ClickAction::Button(self.ev_left_click),
+                zone: None,
+            });
+        }
+        if self.gestures.right_click {
+            bindings.push(ClickBinding {
+                hold: self.right_click_wait,
+                action: ClickAction::Button(self.ev_right_click),
+                zone: None,
+            });
+        }
+        bindings
+    }
+
+    /// The click bindings while a window rule is active.
+    fn click_bindings_for(&self, rule: &WindowRule) -> Vec<ClickBinding> {
+        let mut common = self.clone();
+        if let Some(gestures) = rule.gestures {
+            common.gestures = gestures;
+        }
+        if let Some(click_bindings) = &rule.click_bindings {
+            common.click_bindings = click_bindings.clone();
+        }
+        common.click_bindings()
+    }
+
+    fn click_feedback_description(&self) -> String {
+        #[cfg(feature = "audio")]
+        return self
+            .click_feedback
+            .as_ref()
+            .map_or(String::from("off"), ClickFeedback::to_string);
+        #[cfg(not(feature = "audio"))]
+        return String::from("not compiled in");
+    }
+
+    fn touch_analytics_description(&self) -> String {
+        #[cfg(feature = "mqtt")]
+        return self
+            .touch_analytics
+            .as_ref()
+            .map_or(String::from("off"), TouchAnalytics::to_string);
+        #[cfg(not(feature = "mqtt"))]
+        return String::from("not compiled in");
+    }
+
+    /// Scale the options in pixels by `x` horizontally and `y` vertically, e.g. for a monitor with another resolution.
+    /// Distances without a direction are scaled by the geometric mean of both factors.
+    fn rescale(&mut self, x: f32, y: f32) {
+        let distance = (x * y).sqrt();
+        for binding in &mut self.click_bindings {
+            binding.zone = binding.zone.map(|zone| zone.scale(x, y));
+        }
+        if let Some(drift) = &mut self.drift_estimation {
+            drift.min_correction *= distance;
+            drift.max_correction *= distance;
+        }
+        if let Some(smoothing) = &mut self.smoothing {
+            let mut y_filter = smoothing.y.unwrap_or(smoothing.filter);
+            smoothing.filter.scale(x);
+            y_filter.scale(y);
+            // If the Y axis scales differently, it needs a filter of its own.
+            if y_filter != smoothing.filter {
+                smoothing.y = Some(y_filter);
+            }
+            if let Some(speed) = &mut smoothing.bypass_speed {
+                *speed *= distance;
+            }
+        }
+        if let Some(scroll) = &mut self.two_finger_scroll {
+            scroll.speed /= distance;
+        }
+        if let Some(pinch) = &mut self.pinch_zoom {
+            pinch.step *= distance;
+        }
+        if let Some(swipe) = &mut self.edge_swipe {
+            swipe.edge_width *= distance;
+            swipe.min_travel *= distance;
+        }
+        if let Some(guard) = &mut self.edge_click_guard {
+            guard.edge_width *= distance;
+        }
+        if let Some(maintenance) = &mut self.maintenance_tap {
+            maintenance.zone = maintenance.zone.scale(x, y);
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(analytics) = &mut self.touch_analytics {
+            for zone in analytics.zones.values_mut() {
+                *zone = zone.scale(x, y);
+            }
+        }
+    }
+
+    fn calibrated_at(&self) -> Option<SystemTime> {
+        self.calibrated_at
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn recalibration_due(&self, now: SystemTime) -> bool {
+        let Some(max_age) = self.calibration_max_age else {
+            return false;
+        };
+        // A calibration without a timestamp predates the calibrator that sets it, so it is considered old.
+        self.calibrated_at()
+            .is_none_or(|time| now.duration_since(time).unwrap_or_default() > max_age)
+    }
 }
 
 impl fmt::Display for ConfigCommon {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_fmt(format_args!(
             "Calibration points of touchscreen: {}.\n\
+            Calibration matrix: {}.\n\
+            Origin of touchscreen: {}.\n\
+            Swap axes: {}.\n\
+            Invert X: {}.\n\
+            Invert Y: {}.\n\
+            Rotation: {}.\n\
             Right-click wait duration: {}ms.\n\
-            Has-moved threshold: {}mm.",
+            Has-moved threshold: {}mm.\n\
+            Emit BTN_TOUCH: {}.\n\
+            Gestures: {}.\n\
+            Startup splash: {}.\n\
+            Interpolation: {}.\n\
+            Hide cursor: {}.\n\
+            Tap-to-click: {}.\n\
+            Drag hold duration: {}.\n\
+            Grab kernel input devices: {}.\n\
+            Startup grace period: {}.\n\
+            Calibrated at: {}.\n\
+            Calibration max age: {}.\n\
+            Drift estimation: {}.\n\
+            Click bindings: {}.\n\
+            Sticky drag: {}.\n\
+            Busy poll: {}.\n\
+            Realtime priority: {}.\n\
+            CPU affinity: {}.\n\
+            Output mode: {}.\n\
+            Software cursor: {}.\n\
+            Usage statistics: {}.\n\
+            Second finger: {}.\n\
+            Adaptive noise thresholds: {}.\n\
+            Smoothing: {}.\n\
+            Palm rejection: {}.\n\
+            Two-finger scroll: {}.\n\
+            Pinch to zoom: {}.\n\
+            Double tap: {}.\n\
+            Edge swipe: {}.\n\
+            Edge click guard: {}.\n\
+            Maintenance tap: {}.\n\
+            Pressure curve: {}.\n\
+            Serial port: {}.\n\
+            Click feedback: {}.\n\
+            Touch analytics: {}.",
             self.calibration_points,
+            self.calibration_matrix
+                .map_or(String::from("off"), |m| m.to_string()),
+            self.origin,
+            self.swap_axes,
+            self.invert_x,
+            self.invert_y,
+            self.rotation,
             self.right_click_wait.as_millis(),
             self.has_moved_threshold * 0.1,
+            self.emit_btn_touch,
+            self.gestures,
+            self.startup_splash
+                .map_or(String::from("off"), |d| format!("{}ms", d.as_millis())),
+            self.interpolation
+                .map_or(String::from("off"), |i| i.to_string()),
+            self.hide_cursor,
+            self.tap_to_click
+                .map_or(String::from("off"), |t| t.to_string()),
+            self.drag_hold
+                .map_or(String::from("off"), |d| format!("{}ms", d.as_millis())),
+            self.grab_kernel_input,
+            self.startup_grace
+                .map_or(String::from("off"), |d| format!("{}ms", d.as_millis())),
+            self.calibrated_at
+                .map_or(String::from("unknown"), |secs| format!(
+                    "{}s since epoch",
+                    secs
+                )),
+            self.calibration_max_age
+                .map_or(String::from("off"), |d| format!("{}s", d.as_secs())),
+            self.drift_estimation
+                .map_or(String::from("off"), |d| d.to_string()),
+            self.click_bindings()
+                .iter()
+                .map(ClickBinding::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.sticky_drag,
+            self.busy_poll_burns_cpu,
+            self.realtime_priority
+                .map_or(String::from("off"), |p| p.to_string()),
+            if self.cpu_affinity.is_empty() {
+                String::from("any")
+            } else {
+                self.cpu_affinity
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            self.output_mode,
+            self.software_cursor,
+            self.usage_statistics
+                .as_ref()
+                .map_or(String::from("off"), UsageStatistics::to_string),
+            self.second_finger,
+            self.adaptive_noise
+                .map_or(String::from("off"), |a| a.to_string()),
+            self.smoothing
+                .map_or(String::from("off"), |s| s.to_string()),
+            self.palm_rejection
+                .map_or(String::from("off"), |p| p.to_string()),
+            self.two_finger_scroll
+                .map_or(String::from("off"), |s| s.to_string()),
+            self.pinch_zoom
+                .as_ref()
+                .map_or(String::from("off"), PinchZoom::to_string),
+            self.double_tap
+                .map_or(String::from("off"), |d| d.to_string()),
+            self.edge_swipe
+                .as_ref()
+                .map_or(String::from("off"), EdgeSwipe::to_string),
+            self.edge_click_guard
+                .map_or(String::from("off"), |g| g.to_string()),
+            self.maintenance_tap
+                .as_ref()
+                .map_or(String::from("off"), MaintenanceTap::to_string),
+            self.pressure_curve
+                .as_ref()
+                .map_or(String::from("off"), PressureCurve::to_string),
+            self.serial
+                .as_ref()
+                .map_or(String::from("off"), SerialPort::to_string),
+            self.click_feedback_description(),
+            self.touch_analytics_description(),
+        ))
+    }
+}
+
+/// Parameters for predicting positions between packets.
+/// Positions are extrapolated from the velocity between the last two packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interpolation {
+    /// Interval at which predicted positions are emitted while waiting for the next packet.
+    pub interval: Duration,
+    /// Positions are never predicted further than this after the last packet.
+    pub max_extrapolation: Duration,
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "every {}ms for at most {}ms",
+            self.interval.as_millis(),
+            self.max_extrapolation.as_millis()
+        ))
+    }
+}
+
+/// Limits within which a touch counts as a tap that emits a left-click on release.
+/// Touches outside these limits, e.g. when dragging to scroll content, do not click.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TapToClick {
+    /// Maximum duration of a tap.
+    pub max_duration: Duration,
+    /// Maximum distance the finger may move during a tap, in touchscreen units.
+    pub slop: f32,
+}
+
+impl fmt::Display for TapToClick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "at most {}ms and {}mm",
+            self.max_duration.as_millis(),
+            self.slop * 0.1
+        ))
+    }
+}
+
+/// Where and how long a touch near the edges of the monitor must be held for its click to fire.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EdgeClickGuard {
+    /// Width of the band along the edges of the monitor in pixels.
+    pub edge_width: f32,
+    /// Clicks of touches in the band are only suppressed if the touch was shorter than this.
+    /// If not set, all clicks in the band are suppressed.
+    #[serde(default)]
+    pub min_duration: Option<Duration>,
+}
+
+impl EdgeClickGuard {
+    /// If a click of a touch of the given duration that ended at `position` is suppressed on a monitor of the given size.
+    pub fn suppresses(
+        &self,
+        position: Point2D<MonitorSpace>,
+        width: f32,
+        height: f32,
+        duration: Duration,
+    ) -> bool {
+        let (x, y) = (position.x.float(), position.y.float());
+        let edge_distance = x.min(y).min(width - x).min(height - y);
+        edge_distance < self.edge_width && self.min_duration.is_none_or(|min| duration < min)
+    }
+}
+
+impl fmt::Display for EdgeClickGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min_duration {
+            Some(min) => f.write_fmt(format_args!(
+                "touches shorter than {}ms within {}px of the edges",
+                min.as_millis(),
+                self.edge_width
+            )),
+            None => f.write_fmt(format_args!(
+                "all touches within {}px of the edges",
+                self.edge_width
+            )),
+        }
+    }
+}
+
+/// A series of quick taps in a zone that fires a hidden action, e.g. so that kiosk technicians can open a maintenance
+/// menu without a keyboard.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceTap {
+    /// Number of taps.
+    pub taps: usize,
+    /// Maximum time from the release of the first tap to the release of the last.
+    pub window: Duration,
+    /// Area of the monitor in pixels relative to its upper left corner where the taps must land.
+    pub zone: AABB<MonitorSpace>,
+    pub action: MaintenanceAction,
+}
+
+impl fmt::Display for MaintenanceTap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} after {} taps within {}ms in {}",
+            self.action,
+            self.taps,
+            self.window.as_millis(),
+            self.zone
+        ))
+    }
+}
+
+/// What a [MaintenanceTap] does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceAction {
+    /// Run a command with `sh -c`.
+    Command(String),
+    /// Press a key combination.
+    Keys(Vec<EV_KEY>),
+}
+
+impl fmt::Display for MaintenanceAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceAction::Command(command) => f.write_fmt(format_args!("`{}`", command)),
+            MaintenanceAction::Keys(keys) => f.write_str(
+                &keys
+                    .iter()
+                    .map(|key| format!("{:?}", key))
+                    .collect::<Vec<_>>()
+                    .join("+"),
+            ),
+        }
+    }
+}
+
+/// Limits within which two taps make a double tap, and what it does.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DoubleTap {
+    /// Maximum time from the release of the first tap to the release of the second.
+    pub window: Duration,
+    /// Maximum distance between both taps, in touchscreen units.
+    pub distance: f32,
+    #[serde(default)]
+    pub action: DoubleTapAction,
+}
+
+impl fmt::Display for DoubleTap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} within {}ms and {}mm",
+            self.action,
+            self.window.as_millis(),
+            self.distance * 0.1
         ))
     }
 }
 
+/// What the second tap of a [DoubleTap] does instead of its click.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DoubleTapAction {
+    /// Click the left button where the first tap was, so that both taps make a double-click
+    /// even if the second one landed a bit off.
+    #[default]
+    DoubleClick,
+    /// Click this button or key.
+    Key(EV_KEY),
+}
+
+impl fmt::Display for DoubleTapAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoubleTapAction::DoubleClick => f.write_str("double-click"),
+            DoubleTapAction::Key(key) => f.write_fmt(format_args!("{:?}", key)),
+        }
+    }
+}
+
+/// What happens when a [ClickBinding] fires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClickAction {
+    /// Click a button of the virtual mouse.
+    Button(EV_KEY),
+    /// Run a command with `sh -c`, e.g. to open an admin menu on a kiosk.
+    Command(String),
+}
+
+impl fmt::Display for ClickAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClickAction::Button(button) => f.write_fmt(format_args!("{:?}", button)),
+            ClickAction::Command(command) => f.write_fmt(format_args!("`{}`", command)),
+        }
+    }
+}
+
+/// Binds an action to touches that are held for at least `hold`, optionally only within a zone of the monitor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClickBinding {
+    /// Minimum duration of the touch. A binding without a hold duration fires for taps.
+    #[serde(default)]
+    pub hold: Duration,
+    pub action: ClickAction,
+    /// Area of the monitor in pixels relative to its upper left corner where the binding applies, or everywhere if unset.
+    #[serde(default)]
+    pub zone: Option<AABB<MonitorSpace>>,
+}
+
+impl ClickBinding {
+    fn applies_at(&self, position: Point2D<MonitorSpace>) -> bool {
+        self.zone.is_none_or(|zone| zone.contains(&position))
+    }
+}
+
+impl fmt::Display for ClickBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} after {}ms",
+            self.action,
+            self.hold.as_millis()
+        ))?;
+        if let Some(zone) = &self.zone {
+            f.write_fmt(format_args!(" in {}", zone))?;
+        }
+        Ok(())
+    }
+}
+
+/// A named calibration that replaces the common one, e.g. for another orientation of the display.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub calibration_points: AABB<TouchSpace>,
+    #[serde(default)]
+    pub origin: Origin,
+    #[serde(default)]
+    pub swap_axes: bool,
+    /// The profile is chosen automatically when the monitor has this rotation.
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    /// Like the common `calibration_matrix`, from a 3-point calibration in this orientation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibration_matrix: Option<Affine>,
+}
+
+impl Profile {
+    /// Profiles are calibrated in the rotation they are used for, so no rotation is applied on top.
+    fn calibration(&self) -> Calibration {
+        Calibration {
+            calibration_points: self.calibration_points,
+            origin: self.origin,
+            swap_axes: self.swap_axes,
+            rotation: Rotation::Normal,
+            affine: self.calibration_matrix,
+        }
+    }
+}
+
+/// Overrides of the click behavior while a window of some application is focused.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowRule {
+    /// Replaces the common gesture switches. Like those, they only matter without `click_bindings`.
+    #[serde(default)]
+    pub gestures: Option<GestureSwitches>,
+    /// Replaces the common click bindings.
+    #[serde(default)]
+    pub click_bindings: Option<Vec<ClickBinding>>,
+}
+
+/// Settings of one touchscreen, matched by its USB serial number or its vendor and product ID, so that several panels
+/// share a config file and each keeps its settings on any port or machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    /// USB serial number, see `list-devices --json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_id: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<u16>,
+    /// Replaces the common calibration points, origin and swap of axes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibration_points: Option<AABB<TouchSpace>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_axes: Option<bool>,
+    /// Replaces the monitor that touches are mapped to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor_designator: Option<MonitorDesignator>,
+    /// Replaces the common gesture switches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gestures: Option<GestureSwitches>,
+    /// Replace the common gesture settings, e.g. to enable scrolling only on the panel that is large enough for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub two_finger_scroll: Option<TwoFingerScroll>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinch_zoom: Option<PinchZoom>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edge_swipe: Option<EdgeSwipe>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double_tap: Option<DoubleTap>,
+}
+
+impl DeviceProfile {
+    /// If the profile names a serial number or an ID and all that it names match the device.
+    fn matches(&self, device: &HidrawDevice) -> bool {
+        (self.serial.is_some() || self.vendor_id.is_some() || self.product_id.is_some())
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|serial| device.serial.as_ref() == Some(serial))
+            && self.vendor_id.is_none_or(|id| id == device.vendor_id)
+            && self.product_id.is_none_or(|id| id == device.product_id)
+    }
+}
+
+impl fmt::Display for DeviceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.serial {
+            Some(serial) => f.write_fmt(format_args!("serial {}", serial)),
+            None => f.write_fmt(format_args!(
+                "{}:{}",
+                self.vendor_id
+                    .map_or(String::from("*"), |id| format!("{:04x}", id)),
+                self.product_id
+                    .map_or(String::from("*"), |id| format!("{:04x}", id))
+            )),
+        }
+    }
+}
+
+/// What kind of input device the driver creates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// An absolute pointer with mouse buttons, which works everywhere.
+    #[default]
+    Mouse,
+    /// A direct multi-touch device with slots, so that toolkits like GTK and Qt use their touch handling.
+    /// The pointer still follows the first finger.
+    Touchscreen,
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            OutputMode::Mouse => "mouse",
+            OutputMode::Touchscreen => "touchscreen",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Switches to enable or disable each gesture independently.
+/// With everything enabled the driver behaves as it did before gestures were configurable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureSwitches {
+    /// Emit a right-click when the finger is held still for `right_click_wait`.
+    /// Only used if no `click_bindings` are configured.
+    pub right_click: bool,
+}
+
+impl Default for GestureSwitches {
+    fn default() -> Self {
+        Self { right_click: true }
+    }
+}
+
+impl fmt::Display for GestureSwitches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("right-click={}", self.right_click))
+    }
+}
+
+/// What happens when a second finger lands while the first one is held still and a hold binding is pending.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecondFinger {
+    /// The second finger is not taken into account.
+    #[default]
+    Ignore,
+    /// Hold bindings (by default the right-click) do not fire for the rest of the touch. Taps still click.
+    Cancel,
+    /// Fire this action instead, after which nothing else fires for the touch.
+    Fire(ClickAction),
+}
+
+impl fmt::Display for SecondFinger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecondFinger::Ignore => f.write_str("ignore"),
+            SecondFinger::Cancel => f.write_str("cancel hold bindings"),
+            SecondFinger::Fire(action) => f.write_fmt(format_args!("fire {}", action)),
+        }
+    }
+}
+
 /// Representation of config file which can be used to build a [MonitorConfig]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigFile {
     /// Name of the xrandr output of the monitor on which touch events will be interpreted.
     monitor_designator: MonitorDesignator,
+    /// Which monitor to use if the designated monitor is not connected.
+    #[serde(default)]
+    fallback: MonitorFallback,
+    /// If set, the area of the monitor in pixels instead of querying the monitors, e.g. in containers or without X.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monitor_area: Option<AABB<ScreenSpace>>,
+    /// If set, the area of all monitors together in pixels. Defaults to the monitor area if that is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    screen_space: Option<AABB<ScreenSpace>>,
+    /// Width and height in pixels of the monitor that the pixel values in this file were set up for, written by the calibrator.
+    /// On a monitor with another resolution, e.g. after copying the file to another unit, the values are rescaled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resolution: Option<(i32, i32)>,
     /// Common config options.
     common: ConfigCommon,
+    /// Alternative calibrations by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+    /// Overrides of the click behavior by the class of the focused window (see `xprop WM_CLASS`), ignoring case.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    window_rules: BTreeMap<String, WindowRule>,
+    /// Settings of single touchscreens, written as `[[device]]` tables.
+    #[serde(default, rename = "device", skip_serializing_if = "Vec::is_empty")]
+    devices: Vec<DeviceProfile>,
 }
 
 impl ConfigFile {
@@ -120,44 +1238,256 @@ impl ConfigFile {
         Ok(config_file)
     }
 
-    /// Query info from Xrandr to build a [MonitorConfig].
+    /// Save config to file with the documentation of each option as comments, see [write_atomically].
+    pub fn save<P>(&self, path: P) -> Result<(), EgalaxError>
+    where
+        P: AsRef<Path>,
+    {
+        log::trace!("Entering ConfigFile::save");
+
+        let contents = toml::to_string_pretty(self).map_err(|e| anyhow!(e))?;
+        let contents = config_reference::annotate(&contents);
+        write_atomically(path.as_ref(), contents.as_bytes())?;
+        log::info!("Saved config file '{}'", path.as_ref().display());
+
+        log::trace!("Leaving ConfigFile::save");
+        Ok(())
+    }
+
+    /// Replace the calibration, e.g. with the result of a calibrator run.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.common.set_calibration(calibration);
+    }
+
+    /// Rescale the options in pixels from the recorded resolution to the one of `monitor_area` and record that instead.
+    /// Returns warnings about what does not transfer exactly. Nothing is rescaled if no resolution was recorded yet.
+    pub fn transfer_to(&mut self, monitor_area: AABB<ScreenSpace>) -> Vec<String> {
+        let to = (monitor_area.width().value(), monitor_area.height().value());
+        let from = match self.resolution.replace(to) {
+            Some(from) if from != to && from.0 > 0 && from.1 > 0 => from,
+            _ => return Vec::new(),
+        };
+        log::info!(
+            "Rescaling pixel values from {}x{} to {}x{}",
+            from.0,
+            from.1,
+            to.0,
+            to.1
+        );
+
+        let x = to.0 as f32 / from.0 as f32;
+        let y = to.1 as f32 / from.1 as f32;
+        self.common.rescale(x, y);
+        for rule in self.window_rules.values_mut() {
+            for binding in rule.click_bindings.iter_mut().flatten() {
+                binding.zone = binding.zone.map(|zone| zone.scale(x, y));
+            }
+        }
+
+        let mut warnings = Vec::new();
+        if (x / y - 1.0).abs() > 0.01 {
+            warnings.push(format!(
+                "The aspect ratio changed from {}x{} to {}x{}. Zones are stretched, other distances in pixels are scaled by {:.2}, \
+                and the touchscreen is probably a different model that should be recalibrated.",
+                from.0,
+                from.1,
+                to.0,
+                to.1,
+                (x * y).sqrt()
+            ));
+        }
+        if self.monitor_area.is_some() || self.screen_space.is_some() {
+            warnings.push(String::from(
+                "The monitor area and screen space in the config file are not rescaled, check that they fit this unit.",
+            ));
+        }
+        warnings
+    }
+
+    /// Record when the touchscreen was calibrated, so that the driver can warn once the calibration is too old.
+    pub fn set_calibrated_at(&mut self, time: SystemTime) {
+        self.common.calibrated_at = time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+    }
+
+    /// If the calibration is older than the configured maximum age.
+    /// Always false if no maximum age is configured.
+    pub fn recalibration_due(&self, now: SystemTime) -> bool {
+        self.common.recalibration_due(now)
+    }
+
+    pub fn monitor_designator(&self) -> &MonitorDesignator {
+        &self.monitor_designator
+    }
+
+    pub fn grab_kernel_input(&self) -> bool {
+        self.common.grab_kernel_input
+    }
+
+    pub fn usage_statistics(&self) -> Option<&UsageStatistics> {
+        self.common.usage_statistics.as_ref()
+    }
+
+    pub fn serial(&self) -> Option<&SerialPort> {
+        self.common.serial.as_ref()
+    }
+
+    /// Apply the first `[[device]]` profile that matches a touchscreen, preferring profiles with a serial number
+    /// since they match only a single panel. Without a device or a matching profile the config stays as it is.
+    pub fn for_device(mut self, device: Option<&HidrawDevice>) -> Self {
+        let Some(device) = device else {
+            return self;
+        };
+        let Some(profile) = self
+            .devices
+            .iter()
+            .find(|profile| profile.serial.is_some() && profile.matches(device))
+            .or_else(|| self.devices.iter().find(|profile| profile.matches(device)))
+            .cloned()
+        else {
+            return self;
+        };
+
+        log::info!("Using the device profile for {}.", profile);
+        if let Some(calibration_points) = profile.calibration_points {
+            self.common.calibration_points = calibration_points;
+        }
+        if let Some(origin) = profile.origin {
+            self.common.origin = origin;
+        }
+        if let Some(swap_axes) = profile.swap_axes {
+            self.common.swap_axes = swap_axes;
+        }
+        if let Some(monitor_designator) = profile.monitor_designator {
+            self.monitor_designator = monitor_designator;
+        }
+        if let Some(gestures) = profile.gestures {
+            self.common.gestures = gestures;
+        }
+        if let Some(two_finger_scroll) = profile.two_finger_scroll {
+            self.common.two_finger_scroll = Some(two_finger_scroll);
+        }
+        if let Some(pinch_zoom) = profile.pinch_zoom {
+            self.common.pinch_zoom = Some(pinch_zoom);
+        }
+        if let Some(edge_swipe) = profile.edge_swipe {
+            self.common.edge_swipe = Some(edge_swipe);
+        }
+        if let Some(double_tap) = profile.double_tap {
+            self.common.double_tap = Some(double_tap);
+        }
+        self
+    }
+
+    /// Choose the profile for a rotation of the monitor.
+    /// A profile that names the rotation explicitly is preferred, then one named after the rotation (e.g. "left"),
+    /// and finally one named "portrait" or "landscape".
+    pub fn profile_for_rotation(&self, rotation: Rotation) -> Option<&str> {
+        let orientation = if rotation.is_portrait() {
+            "portrait"
+        } else {
+            "landscape"
+        };
+
+        self.profiles
+            .iter()
+            .find(|(_, profile)| profile.rotation == Some(rotation))
+            .or_else(|| self.profiles.get_key_value(rotation.to_string().as_str()))
+            .or_else(|| self.profiles.get_key_value(orientation))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns a copy of the config file that uses the calibration of the named profile, if it exists.
+    pub fn with_profile(&self, name: &str) -> Option<ConfigFile> {
+        let profile = self.profiles.get(name)?;
+        let mut config_file = self.clone();
+        config_file.set_calibration(profile.calibration());
+        Some(config_file)
+    }
+
+    /// Query the monitors from Xrandr, or from the compositor in a Wayland session, to build a [Config].
+    /// See [devices::list_monitors]. The monitors are not queried if the config file sets the monitor area.
     pub fn build(self) -> Result<Config, EgalaxError> {
         log::trace!("Entering MonitorConfigBuilder::build");
 
-        let monitors = XHandle::open()?.monitors()?;
-        let screen_space = self.compute_screen_space(&monitors);
-        let monitor_area = self.get_monitor_area(&monitors)?;
-
-        let config = Config {
-            screen_space: screen_space,
-            monitor_area: monitor_area,
-            common: self.common,
+        let config = if let Some(monitor_area) = self.monitor_area {
+            log::info!("Using monitor area {} from the config file", monitor_area);
+            let screen_space = self.screen_space.unwrap_or(monitor_area);
+            self.build_for_area(screen_space, monitor_area)
+        } else {
+            let monitors = devices::list_monitors()?;
+            let screen_space = self
+                .screen_space
+                .unwrap_or_else(|| self.compute_screen_space(&monitors));
+            let monitor_area = self.get_monitor_area(&monitors)?;
+            self.build_for_area(screen_space, monitor_area)
         };
+
         log::trace!("Leaving MonitorConfigBuilder::build");
         Ok(config)
     }
 
+    /// Build a [Config] for a known screen layout without querying the monitors, e.g. in tests.
+    pub fn build_for_area(
+        mut self,
+        screen_space: AABB<ScreenSpace>,
+        monitor_area: AABB<ScreenSpace>,
+    ) -> Config {
+        for warning in self.transfer_to(monitor_area) {
+            log::warn!("{}", warning);
+        }
+        Config {
+            screen_space,
+            monitor_area,
+            click_bindings: self.common.click_bindings(),
+            window_bindings: self
+                .window_rules
+                .iter()
+                .map(|(class, rule)| (class.clone(), self.common.click_bindings_for(rule)))
+                .collect(),
+            active_rule: None,
+            common: self.common,
+        }
+    }
+
     /// Union screen spaces of all monitors to get total screen space used by X.
-    fn compute_screen_space(&self, monitors: &[Monitor]) -> AABB {
+    fn compute_screen_space(&self, monitors: &[MonitorInfo]) -> AABB<ScreenSpace> {
         monitors
             .iter()
-            .map(AABB::from)
+            .map(|monitor| monitor.area)
             .fold(AABB::default(), AABB::union)
     }
 
     /// Get only the screen space of the touchscreen monitor.
-    fn get_monitor_area(&self, monitors: &[Monitor]) -> Result<AABB, EgalaxError> {
+    /// If the designated monitor is not connected, the fallback policy decides which monitor to use instead.
+    fn get_monitor_area(&self, monitors: &[MonitorInfo]) -> Result<AABB<ScreenSpace>, EgalaxError> {
+        let primary = || monitors.iter().find(|monitor| monitor.is_primary);
         let monitor = match &self.monitor_designator {
-            MonitorDesignator::Primary => monitors.iter().find(|monitor| monitor.is_primary),
+            MonitorDesignator::Primary => primary(),
             MonitorDesignator::Named(monitor_name) => monitors
                 .iter()
                 .find(|monitor| monitor.name == *monitor_name),
-        }
-        .ok_or(EgalaxError::MonitorNotFound(
-            self.monitor_designator.to_string(),
-        ))?;
+        };
 
-        let area = AABB::from(monitor);
+        let monitor = match monitor {
+            Some(monitor) => monitor,
+            None => {
+                let fallback =
+                    self.fallback
+                        .select(monitors)
+                        .ok_or(EgalaxError::MonitorNotFound(
+                            self.monitor_designator.to_string(),
+                        ))?;
+                log::warn!(
+                    "Monitor {} not found, falling back to the {} monitor {}",
+                    self.monitor_designator,
+                    self.fallback,
+                    fallback.name
+                );
+                fallback
+            }
+        };
+
+        let area = monitor.area;
         log::info!("Using uncalibrated monitor's total dimensions {}", area);
         Ok(area)
     }
@@ -167,22 +1497,113 @@ impl Default for ConfigFile {
     fn default() -> Self {
         Self {
             monitor_designator: MonitorDesignator::Named("HDMI-A-0".to_string()),
+            fallback: MonitorFallback::default(),
+            monitor_area: None,
+            screen_space: None,
+            resolution: None,
             common: ConfigCommon {
                 calibration_points: AABB::from((300, 300, 3800, 3800)),
+                calibration_matrix: None,
+                origin: Origin::default(),
+                swap_axes: false,
+                invert_x: false,
+                invert_y: false,
+                rotation: Rotation::Normal,
                 right_click_wait: Duration::from_millis(1500),
                 has_moved_threshold: 30.0,
                 ev_left_click: EV_KEY::BTN_LEFT,
                 ev_right_click: EV_KEY::BTN_RIGHT,
+                emit_btn_touch: false,
+                gestures: GestureSwitches::default(),
+                startup_splash: None,
+                interpolation: None,
+                hide_cursor: false,
+                tap_to_click: None,
+                drag_hold: None,
+                grab_kernel_input: false,
+                startup_grace: None,
+                calibrated_at: None,
+                calibration_max_age: None,
+                drift_estimation: None,
+                click_bindings: Vec::new(),
+                sticky_drag: false,
+                busy_poll_burns_cpu: false,
+                realtime_priority: None,
+                cpu_affinity: Vec::new(),
+                output_mode: OutputMode::default(),
+                software_cursor: false,
+                usage_statistics: None,
+                second_finger: SecondFinger::default(),
+                adaptive_noise: None,
+                smoothing: None,
+                palm_rejection: None,
+                two_finger_scroll: None,
+                pinch_zoom: None,
+                double_tap: None,
+                edge_swipe: None,
+                edge_click_guard: None,
+                maintenance_tap: None,
+                pressure_curve: None,
+                serial: None,
+                #[cfg(feature = "audio")]
+                click_feedback: None,
+                #[cfg(feature = "mqtt")]
+                touch_analytics: None,
             },
+            profiles: BTreeMap::new(),
+            window_rules: BTreeMap::new(),
+            devices: Vec::new(),
         }
     }
 }
 
 impl fmt::Display for ConfigFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let profiles = if self.profiles.is_empty() {
+            String::from("none")
+        } else {
+            self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+        };
+        let window_rules = if self.window_rules.is_empty() {
+            String::from("none")
+        } else {
+            self.window_rules
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let monitor_area = self
+            .monitor_area
+            .map_or(String::from("queried"), |area| area.to_string());
+        let screen_space = self
+            .screen_space
+            .map_or(String::from("queried"), |area| area.to_string());
+        let resolution = self
+            .resolution
+            .map_or(String::from("unknown"), |(width, height)| {
+                format!("{}x{}", width, height)
+            });
+        let devices = if self.devices.is_empty() {
+            String::from("none")
+        } else {
+            self.devices
+                .iter()
+                .map(DeviceProfile::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
         let description = format!(
-            "Name of XRandR Output: {}.\n{}",
-            self.monitor_designator, self.common
+            "Name of XRandR Output: {}.\nFallback monitor: {}.\nMonitor area: {}.\nScreen space: {}.\nResolution: {}.\n{}\nProfiles: {}.\nWindow rules: {}.\nDevice profiles: {}.",
+            self.monitor_designator,
+            self.fallback,
+            monitor_area,
+            screen_space,
+            resolution,
+            self.common,
+            profiles,
+            window_rules,
+            devices
         );
 
         f.write_str(&description)
@@ -204,3 +1625,707 @@ impl fmt::Display for MonitorDesignator {
         f.write_str(&description)
     }
 }
+
+/// What to do when the designated monitor is not connected, e.g. after a display was swapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MonitorFallback {
+    /// Use the primary monitor.
+    Primary,
+    /// Use the monitor with the largest area.
+    Largest,
+    /// Fail to start.
+    #[default]
+    Fail,
+}
+
+impl MonitorFallback {
+    /// Select the monitor to use instead of the designated one, or `None` if there is none or the policy is to fail.
+    pub fn select(self, monitors: &[MonitorInfo]) -> Option<&MonitorInfo> {
+        match self {
+            MonitorFallback::Fail => None,
+            MonitorFallback::Primary => monitors.iter().find(|monitor| monitor.is_primary),
+            MonitorFallback::Largest => monitors.iter().max_by_key(|monitor| monitor.area.area()),
+        }
+    }
+}
+
+impl fmt::Display for MonitorFallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            MonitorFallback::Primary => "primary",
+            MonitorFallback::Largest => "largest",
+            MonitorFallback::Fail => "fail",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Where the effective value of a config option comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ValueSource {
+    /// The option is missing from the config file and the driver uses its default.
+    Default,
+    /// The value is taken from the config file.
+    File,
+    /// The option is in the config file but the driver does not know it, e.g. because of a typo.
+    Ignored,
+    /// The value is given as an argument to the driver.
+    CommandLine,
+    /// The value is taken from an environment variable.
+    Environment,
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ValueSource::Default => "default",
+            ValueSource::File => "config file",
+            ValueSource::Ignored => "ignored",
+            ValueSource::CommandLine => "command line",
+            ValueSource::Environment => "environment",
+        };
+        f.write_str(description)
+    }
+}
+
+/// A single config option as the driver sees it, with dotted keys for nested options.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveValue {
+    pub key: String,
+    pub value: toml::Value,
+    pub source: ValueSource,
+}
+
+impl fmt::Display for EffectiveValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} = {}  # {}",
+            self.key, self.value, self.source
+        ))
+    }
+}
+
+/// Collect the leaves of a table by their dotted keys.
+fn flatten_table(prefix: &str, table: &toml::Table, values: &mut BTreeMap<String, toml::Value>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            toml::Value::Table(table) => flatten_table(&key, table, values),
+            value => {
+                values.insert(key, value.clone());
+            }
+        }
+    }
+}
+
+/// The effective configuration for the contents of a config file, with the source of each value.
+/// Options that are unset and have no default, like an optional gesture, are left out.
+pub fn effective_values(contents: &str) -> Result<Vec<EffectiveValue>, EgalaxError> {
+    let config_file: ConfigFile = toml::from_str(contents).map_err(|e| anyhow!(e))?;
+    let raw: toml::Table = contents.parse().map_err(|e| anyhow!("{}", e))?;
+    let effective = toml::Table::try_from(&config_file).map_err(|e| anyhow!(e))?;
+
+    let mut raw_values = BTreeMap::new();
+    flatten_table("", &raw, &mut raw_values);
+    let mut effective_values = BTreeMap::new();
+    flatten_table("", &effective, &mut effective_values);
+
+    let mut values: BTreeMap<String, EffectiveValue> = effective_values
+        .into_iter()
+        .map(|(key, value)| {
+            let source = if raw_values.contains_key(&key) {
+                ValueSource::File
+            } else {
+                ValueSource::Default
+            };
+            (key.clone(), EffectiveValue { key, value, source })
+        })
+        .collect();
+    for (key, value) in raw_values {
+        values.entry(key.clone()).or_insert(EffectiveValue {
+            key,
+            value,
+            source: ValueSource::Ignored,
+        });
+    }
+    Ok(values.into_values().collect())
+}
+
+/// Locations that the privileged `apply-config` helper may write to.
+pub const SYSTEM_CONFIG_LOCATIONS: &[&str] = &["/etc/egalax-rs/config.toml"];
+
+/// Largest config that the `apply-config` helper accepts. Real configs are a few hundred bytes.
+const MAX_APPLIED_CONFIG_LEN: usize = 64 * 1024;
+
+/// Validate a config and write it to a system location, as done by the helper that runs with elevated privileges.
+///
+/// Since the caller is not trusted, only the [SYSTEM_CONFIG_LOCATIONS] are allowed as targets and they must not be symlinks.
+/// The contents must be a valid config file, which is written in a normalized form.
+pub fn apply_config(target: &Path, contents: &str) -> Result<(), ApplyConfigError> {
+    log::trace!("Entering fn apply_config");
+
+    let forbidden = || ApplyConfigError::ForbiddenPath(target.display().to_string());
+    if !SYSTEM_CONFIG_LOCATIONS
+        .iter()
+        .any(|location| Path::new(location) == target)
+    {
+        return Err(forbidden());
+    }
+    if fs::symlink_metadata(target).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return Err(forbidden());
+    }
+
+    if contents.len() > MAX_APPLIED_CONFIG_LEN {
+        return Err(ApplyConfigError::TooLarge(contents.len()));
+    }
+    let config_file: ConfigFile =
+        toml::from_str(contents).map_err(|e| ApplyConfigError::Invalid(e.to_string()))?;
+
+    let write_error = |e: EgalaxError| ApplyConfigError::Write(e.to_string());
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|e| write_error(e.into()))?;
+    }
+    config_file.save(target).map_err(write_error)?;
+
+    log::trace!("Leaving fn apply_config");
+    Ok(())
+}
+
+/// Appends a suffix to the file name of a path, e.g. `config.toml` becomes `config.toml.bak`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map_or_else(OsString::new, OsString::from);
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Replace a file such that it contains either the old or the new contents even if we lose power midway.
+///
+/// The contents are written and synced to a temporary file next to `path` which is then renamed over it.
+/// The temporary file has a unique name, so concurrent writers, e.g. the driver and a calibration, cannot mix their
+/// contents, and it gets the permissions of the file it replaces. The previous version of the file is kept with a
+/// `.bak` suffix.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let tmp_path = with_suffix(
+        path,
+        &format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+    let tmp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    let result = replace_with(path, tmp, &tmp_path, contents);
+    if result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    result
+}
+
+/// Fill the temporary file of [write_atomically] and rename it over `path`.
+fn replace_with(path: &Path, mut tmp: File, tmp_path: &Path, contents: &[u8]) -> io::Result<()> {
+    tmp.write_all(contents)?;
+    match fs::metadata(path) {
+        Ok(metadata) => tmp.set_permissions(metadata.permissions())?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    tmp.sync_all()?;
+    drop(tmp);
+
+    match fs::copy(path, with_suffix(path, ".bak")) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    fs::rename(tmp_path, path)?;
+
+    // The rename is only durable once the directory is synced as well.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Reports when the config file was written, so that the driver can reload it.
+///
+/// The directory is watched instead of the file because editors and [write_atomically] replace the file
+/// by renaming a new one over it, which a watch on the old file would not notice.
+#[derive(Debug)]
+pub struct ConfigWatch {
+    inotify: Inotify,
+    file_name: OsString,
+}
+
+impl ConfigWatch {
+    pub fn new(path: &Path) -> Result<Self, EgalaxError> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("'{}' does not name a file", path.display()))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .map_err(io::Error::from)?;
+        let watch = Self { inotify, file_name };
+        watch
+            .inotify
+            .add_watch(
+                dir,
+                AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+            )
+            .map_err(io::Error::from)?;
+        Ok(watch)
+    }
+
+    /// Returns true if the config file was written or replaced since the last check. Does not block.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        loop {
+            match self.inotify.read_events() {
+                Ok(events) => {
+                    changed |= events
+                        .iter()
+                        .any(|event| event.name.as_ref() == Some(&self.file_name));
+                }
+                Err(Errno::EAGAIN) => break,
+                Err(e) => {
+                    log::warn!("Could not watch the config file: {}", e);
+                    break;
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl Drop for ConfigWatch {
+    fn drop(&mut self) {
+        // nix does not close the inotify instance for us.
+        unistd::close(self.inotify.as_raw_fd()).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!("egalax-rs-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        let mut config_file = ConfigFile::default();
+        config_file.save(&path).unwrap();
+        assert!(!with_suffix(&path, ".bak").exists());
+
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        config_file.common.swap_axes = true;
+        config_file.save(&path).unwrap();
+        let backup = ConfigFile::from_file(with_suffix(&path, ".bak")).unwrap();
+        assert!(!backup.common.swap_axes);
+        assert!(ConfigFile::from_file(&path).unwrap().common.swap_axes);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        // No temporary file is left behind.
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["config.toml", "config.toml.bak"], names);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_monitor_fallback() {
+        let monitor = |name: &str, is_primary, area: (i32, i32, i32, i32)| MonitorInfo {
+            name: name.to_string(),
+            is_primary,
+            area: AABB::from(area),
+        };
+        let monitors = [
+            monitor("DP-1", false, (0, 0, 1920, 1080)),
+            monitor("HDMI-A-0", true, (1920, 0, 3200, 1024)),
+        ];
+        let name = |fallback: MonitorFallback, monitors| {
+            fallback
+                .select(monitors)
+                .map(|monitor: &MonitorInfo| monitor.name.clone())
+        };
+
+        assert_eq!(None, name(MonitorFallback::Fail, &monitors));
+        assert_eq!(
+            Some("HDMI-A-0".to_string()),
+            name(MonitorFallback::Primary, &monitors)
+        );
+        assert_eq!(
+            Some("DP-1".to_string()),
+            name(MonitorFallback::Largest, &monitors)
+        );
+
+        // Without a primary monitor only the largest one can be used.
+        let monitors = [
+            monitors[0].clone(),
+            monitor("HDMI-A-0", false, (1920, 0, 3200, 1024)),
+        ];
+        assert_eq!(None, name(MonitorFallback::Primary, &monitors));
+        assert_eq!(
+            Some("DP-1".to_string()),
+            name(MonitorFallback::Largest, &monitors)
+        );
+        assert_eq!(None, name(MonitorFallback::Largest, &[]));
+    }
+
+    #[test]
+    fn test_invert_axes() {
+        let mut config_file = ConfigFile::default();
+        config_file.common.origin = Origin::BottomRight;
+        config_file.common.invert_x = true;
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = config_file.clone().build_for_area(area, area);
+        assert_eq!(Origin::BottomLeft, config.calibration().origin);
+        assert_eq!(
+            Point2D::from((100, 900)),
+            config.calibration().map_to((650, 650).into(), area)
+        );
+
+        config_file.set_calibration(config.calibration());
+        assert!(!config_file.common.invert_x);
+        assert_eq!(Origin::BottomLeft, config_file.common.origin);
+    }
+
+    #[test]
+    fn test_config_watch() {
+        let dir = std::env::temp_dir().join(format!("egalax-rs-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "").unwrap();
+
+        let watch = ConfigWatch::new(&path).unwrap();
+        assert!(!watch.changed());
+        fs::write(dir.join("other.toml"), "").unwrap();
+        assert!(!watch.changed());
+        ConfigFile::default().save(&path).unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_effective_values() {
+        let config = r#"
+            monitor_designator = "Primary"
+
+            [common]
+            calibration_points = { x1 = 0, y1 = 0, x2 = 4000, y2 = 4000 }
+            right_click_wait = { secs = 1, nanos = 0 }
+            has_moved_threshold = 5.0
+            ev_left_click = "BTN_LEFT"
+            ev_right_click = "BTN_RIGHT"
+            hide_cursr = true
+        "#;
+        let values = effective_values(config).unwrap();
+        let source = |key: &str| {
+            values
+                .iter()
+                .find(|value| value.key == key)
+                .map(|value| value.source)
+        };
+
+        assert_eq!(
+            Some(ValueSource::File),
+            source("common.calibration_points.x2")
+        );
+        assert_eq!(Some(ValueSource::Default), source("common.swap_axes"));
+        assert_eq!(Some(ValueSource::Default), source("fallback"));
+        assert_eq!(Some(ValueSource::Ignored), source("common.hide_cursr"));
+        assert_eq!(None, source("common.startup_splash"));
+    }
+
+    #[test]
+    fn test_apply_config_rejects_untrusted_input() {
+        let config = toml::to_string(&ConfigFile::default()).unwrap();
+        assert_eq!(
+            Err(ApplyConfigError::ForbiddenPath("/etc/passwd".to_string())),
+            apply_config(Path::new("/etc/passwd"), &config)
+        );
+        assert!(matches!(
+            apply_config(
+                Path::new(SYSTEM_CONFIG_LOCATIONS[0]),
+                "monitor_designator = 1"
+            ),
+            Err(ApplyConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_monitor_area_override() {
+        let config_file = ConfigFile {
+            monitor_area: Some(AABB::from((1920, 0, 3840, 1080))),
+            ..Default::default()
+        };
+        let contents = toml::to_string(&config_file).unwrap();
+        let config_file: ConfigFile = toml::from_str(&contents).unwrap();
+
+        // Without an X server in the tests, building only succeeds if no monitors are queried.
+        let config = config_file.build().unwrap();
+        assert_eq!(AABB::from((1920, 0, 3840, 1080)), config.monitor_area);
+        assert_eq!(config.monitor_area, config.screen_space);
+    }
+
+    #[test]
+    fn test_profile_for_rotation() {
+        let mut config_file = ConfigFile::default();
+        let profile = Profile {
+            calibration_points: AABB::from((3800, 300, 300, 3800)),
+            origin: Origin::default(),
+            swap_axes: true,
+            rotation: None,
+            calibration_matrix: None,
+        };
+        config_file.profiles.insert("portrait".to_string(), profile);
+        config_file.profiles.insert(
+            "upside-down".to_string(),
+            Profile {
+                rotation: Some(Rotation::Inverted),
+                ..profile
+            },
+        );
+
+        assert_eq!(None, config_file.profile_for_rotation(Rotation::Normal));
+        assert_eq!(
+            Some("portrait"),
+            config_file.profile_for_rotation(Rotation::Left)
+        );
+        assert_eq!(
+            Some("upside-down"),
+            config_file.profile_for_rotation(Rotation::Inverted)
+        );
+
+        let portrait = config_file.with_profile("portrait").unwrap();
+        assert!(portrait.common.swap_axes);
+        assert!(config_file.with_profile("landscape").is_none());
+    }
+
+    #[test]
+    fn test_click_bindings() {
+        let mut config_file = ConfigFile::default();
+        let legacy = config_file.common.click_bindings();
+        assert_eq!(2, legacy.len());
+        assert_eq!(ClickAction::Button(EV_KEY::BTN_RIGHT), legacy[1].action);
+
+        let bindings = r#"
+            [[common.click_bindings]]
+            action = { button = "BTN_LEFT" }
+
+            [[common.click_bindings]]
+            hold = { secs = 3, nanos = 0 }
+            action = { command = "notify-send admin" }
+            zone = { x1 = 0, y1 = 0, x2 = 100, y2 = 100 }
+
+            [[common.click_bindings]]
+            hold = { secs = 3, nanos = 0 }
+            action = { button = "BTN_MIDDLE" }
+        "#;
+        let config = toml::to_string(&config_file).unwrap() + bindings;
+        config_file = toml::from_str(&config).unwrap();
+        let config = Config {
+            screen_space: AABB::default(),
+            monitor_area: AABB::default(),
+            click_bindings: config_file.common.click_bindings(),
+            window_bindings: BTreeMap::new(),
+            active_rule: None,
+            common: config_file.common,
+        };
+
+        let (corner, center) = (Point2D::from((50, 50)), Point2D::from((500, 500)));
+        let secs = Duration::from_secs;
+        let action = |held, still, position| {
+            config
+                .click_binding(held, still, position)
+                .map(|binding| binding.action.clone())
+        };
+        assert_eq!(
+            Some(ClickAction::Button(EV_KEY::BTN_LEFT)),
+            action(secs(1), true, corner)
+        );
+        assert_eq!(
+            Some(ClickAction::Button(EV_KEY::BTN_LEFT)),
+            action(secs(4), false, corner)
+        );
+        assert_eq!(
+            Some(ClickAction::Command("notify-send admin".to_string())),
+            action(secs(4), true, corner)
+        );
+        assert_eq!(
+            Some(ClickAction::Button(EV_KEY::BTN_MIDDLE)),
+            action(secs(4), true, center)
+        );
+        assert_eq!(secs(3), config.longest_hold_binding(center).unwrap().hold);
+    }
+
+    #[test]
+    fn test_window_rules() {
+        let config = toml::to_string(&ConfigFile::default()).unwrap()
+            + r#"
+            [window_rules.Krita.gestures]
+            right_click = false
+        "#;
+        let config_file: ConfigFile = toml::from_str(&config).unwrap();
+        let rule = &config_file.window_rules["Krita"];
+        let mut config = Config {
+            screen_space: AABB::default(),
+            monitor_area: AABB::default(),
+            click_bindings: config_file.common.click_bindings(),
+            window_bindings: BTreeMap::from([(
+                "Krita".to_string(),
+                config_file.common.click_bindings_for(rule),
+            )]),
+            active_rule: None,
+            common: config_file.common.clone(),
+        };
+
+        assert_eq!(2, config.click_bindings().len());
+        assert_eq!(Some("Krita"), config.set_window_class(Some("krita")));
+        assert_eq!(1, config.click_bindings().len());
+        assert_eq!(3, config.all_click_bindings().count());
+        assert_eq!(None, config.set_window_class(Some("firefox")));
+        assert_eq!(2, config.click_bindings().len());
+    }
+
+    #[test]
+    fn test_device_profiles() {
+        let config = toml::to_string(&ConfigFile::default()).unwrap()
+            + r#"
+            [[device]]
+            vendor_id = 0x0eef
+            product_id = 0x0001
+            monitor_designator = "Primary"
+
+            [[device]]
+            serial = "RIGHT-PANEL"
+            calibration_points = { x1 = 100, y1 = 200, x2 = 3900, y2 = 3800 }
+            gestures = { right_click = false }
+            two_finger_scroll = { speed = 2.5 }
+            double_tap = { window = { secs = 0, nanos = 300000000 }, distance = 40.0 }
+        "#;
+        let config_file: ConfigFile = toml::from_str(&config).unwrap();
+        let device = |serial: Option<&str>| HidrawDevice {
+            node: PathBuf::from("/dev/hidraw3"),
+            name: String::new(),
+            vendor_id: 0x0eef,
+            product_id: 0x0001,
+            serial: serial.map(String::from),
+            driver: None,
+        };
+
+        // The profile with the serial number wins even though it comes later.
+        let right = config_file
+            .clone()
+            .for_device(Some(&device(Some("RIGHT-PANEL"))));
+        assert_eq!(
+            AABB::from((100, 200, 3900, 3800)),
+            right.common.calibration_points
+        );
+        assert!(!right.common.gestures.right_click);
+        assert_eq!(
+            Some(2.5),
+            right.common.two_finger_scroll.map(|scroll| scroll.speed)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(300)),
+            right.common.double_tap.map(|double_tap| double_tap.window)
+        );
+        assert_eq!(config_file.common.pinch_zoom, right.common.pinch_zoom);
+        assert_eq!(config_file.monitor_designator, right.monitor_designator);
+
+        let left = config_file.clone().for_device(Some(&device(Some("LEFT"))));
+        assert_eq!(MonitorDesignator::Primary, left.monitor_designator);
+        assert_eq!(None, left.common.two_finger_scroll);
+        assert_eq!(
+            config_file.common.calibration_points,
+            left.common.calibration_points
+        );
+        let unknown = config_file.clone().for_device(None);
+        assert_eq!(config_file.monitor_designator, unknown.monitor_designator);
+    }
+
+    #[test]
+    fn test_recalibration_due() {
+        let day = Duration::from_secs(86400);
+        let calibrated = UNIX_EPOCH + day * 1000;
+        let mut config_file = ConfigFile::default();
+        assert!(!config_file.recalibration_due(calibrated));
+
+        config_file.common.calibration_max_age = Some(day * 90);
+        assert!(config_file.recalibration_due(calibrated));
+
+        config_file.set_calibrated_at(calibrated);
+        assert!(!config_file.recalibration_due(calibrated + day * 90));
+        assert!(config_file.recalibration_due(calibrated + day * 91));
+    }
+
+    #[test]
+    fn test_transfer_to() {
+        let mut config_file = ConfigFile::default();
+        config_file.common.click_bindings = vec![ClickBinding {
+            hold: Duration::ZERO,
+            action: ClickAction::Button(EV_KEY::BTN_RIGHT),
+            zone: Some(AABB::from((0, 0, 200, 100))),
+        }];
+        config_file.common.pinch_zoom = Some(PinchZoom {
+            step: 40.0,
+            emit: Default::default(),
+        });
+
+        // Without a recorded resolution nothing is rescaled, but the resolution is recorded.
+        assert!(config_file
+            .transfer_to(AABB::from((0, 0, 1920, 1080)))
+            .is_empty());
+        assert_eq!(Some((1920, 1080)), config_file.resolution);
+        assert_eq!(
+            Some(AABB::from((0, 0, 200, 100))),
+            config_file.common.click_bindings[0].zone
+        );
+
+        assert!(config_file
+            .transfer_to(AABB::from((1920, 0, 5760, 2160)))
+            .is_empty());
+        assert_eq!(Some((3840, 2160)), config_file.resolution);
+        assert_eq!(
+            Some(AABB::from((0, 0, 400, 200))),
+            config_file.common.click_bindings[0].zone
+        );
+        assert_eq!(80.0, config_file.common.pinch_zoom.as_ref().unwrap().step);
+
+        // A different aspect ratio cannot be transferred exactly.
+        assert_eq!(
+            1,
+            config_file
+                .transfer_to(AABB::from((0, 0, 1280, 1024)))
+                .len()
+        );
+        assert_eq!(
+            Some(AABB::from((0, 0, 133, 94))),
+            config_file.common.click_bindings[0].zone
+        );
+    }
+}
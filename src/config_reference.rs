@@ -0,0 +1,567 @@
+//! Reference documentation of every config option, the single source for the comments in generated config files
+//! and for `Configuration.md`, so that a config file edited on-site explains itself.
+//!
+//! Regenerate the Markdown reference with `gen-config --markdown > Configuration.md` after changing [OPTIONS].
+
+use std::collections::BTreeSet;
+
+/// Documentation of a single config option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionDoc {
+    /// Dotted path of the option in the config file, e.g. `common.has_moved_threshold`.
+    pub key: &'static str,
+    /// Unit of the value, or empty if it has none.
+    pub unit: &'static str,
+    /// Allowed values.
+    pub range: &'static str,
+    pub description: &'static str,
+    /// For options that are unset by default, a value in TOML syntax that is shown commented out.
+    pub example: Option<&'static str>,
+}
+
+const DURATION: &str = "duration as `{ secs, nanos }`";
+
+/// All documented options in the order of the config file.
+pub const OPTIONS: &[OptionDoc] = &[
+    OptionDoc {
+        key: "fallback",
+        unit: "",
+        range: "`fail`, `primary` or `largest`",
+        description: "Which monitor to use if the designated monitor is not connected, e.g. after a display was swapped.",
+        example: None,
+    },
+    OptionDoc {
+        key: "monitor_designator",
+        unit: "",
+        range: "`\"Primary\"` or `Named = \"<output>\"` with an output from `egalax-rs list-monitors`",
+        description: "The monitor that the touchscreen is mounted on.",
+        example: None,
+    },
+    OptionDoc {
+        key: "monitor_area",
+        unit: "pixels",
+        range: "`x1`, `y1`, `x2`, `y2` of the monitor within the screen space",
+        description: "If set, use this monitor area instead of querying the monitors, e.g. in containers or without X. Replaces `monitor_designator` and `fallback`.",
+        example: Some("{ x1 = 0, y1 = 0, x2 = 1920, y2 = 1080 }"),
+    },
+    OptionDoc {
+        key: "screen_space",
+        unit: "pixels",
+        range: "`x1`, `y1`, `x2`, `y2` of all monitors together",
+        description: "If set, use this screen space instead of querying the monitors. Defaults to `monitor_area` if that is set.",
+        example: Some("{ x1 = 0, y1 = 0, x2 = 1920, y2 = 1080 }"),
+    },
+    OptionDoc {
+        key: "resolution",
+        unit: "pixels",
+        range: "`[width, height]`",
+        description: "Resolution of the monitor that the values in pixels were set up for. Written by `calibrate`. On a monitor with another resolution they are rescaled.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common",
+        unit: "",
+        range: "",
+        description: "Options that apply to every profile and window rule.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.calibration_points",
+        unit: "touchscreen units (about 0.1mm)",
+        range: "`x1`, `y1`, `x2`, `y2` between 0 and 16383",
+        description: "Touch coordinates of the monitor edges. Written by `calibrate`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.calibration_matrix",
+        unit: "fractions of the monitor per touchscreen unit",
+        range: "two rows of three numbers, or unset",
+        description: "Affine matrix that maps raw touch coordinates to the monitor instead of the calibration points, which also fits slightly rotated or skewed panels. Written by `calibrate --affine`.",
+        example: Some("[[0.00025, 0.0, -0.1], [0.0, 0.00025, -0.1]]"),
+    },
+    OptionDoc {
+        key: "common.origin",
+        unit: "",
+        range: "`top-left`, `top-right`, `bottom-left` or `bottom-right`",
+        description: "The corner of the panel where the touchscreen reports its minimum coordinates. Written by `calibrate`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.swap_axes",
+        unit: "",
+        range: "`true` or `false`",
+        description: "If raw X coordinates run along the monitor's Y axis, e.g. because the foil is rotated. Written by `calibrate`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.invert_x",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Mirror raw X coordinates within the calibration points, on top of what `origin` does.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.invert_y",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Mirror raw Y coordinates within the calibration points, on top of what `origin` does.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.rotation",
+        unit: "",
+        range: "`normal`, `left`, `inverted` or `right`",
+        description: "Rotation of the monitor relative to the calibration points, e.g. for a panel mounted in portrait orientation.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.right_click_wait",
+        unit: DURATION,
+        range: "usually 0.5s to 2s",
+        description: "How long the finger has to be held still to right-click.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.has_moved_threshold",
+        unit: "touchscreen units (about 0.1mm)",
+        range: "0 or more, usually 10 to 50",
+        description: "A touch that moves further than this is a movement and no longer a hold.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.ev_left_click",
+        unit: "",
+        range: "an evdev key code, e.g. `BTN_LEFT`",
+        description: "Button that is clicked on a tap.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.ev_right_click",
+        unit: "",
+        range: "an evdev key code, e.g. `BTN_RIGHT`",
+        description: "Button that is clicked on a long hold.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.emit_btn_touch",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Also emit BTN_TOUCH while a finger is on the screen, which some applications (e.g. Qt) expect.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.gestures",
+        unit: "",
+        range: "`right_click = true` or `false`",
+        description: "Switches to turn individual gestures on or off. Only used without `click_bindings`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.startup_splash",
+        unit: DURATION,
+        range: "a few seconds",
+        description: "If set, show a splash screen for this long after startup to verify the driver is running.",
+        example: Some("{ secs = 3, nanos = 0 }"),
+    },
+    OptionDoc {
+        key: "common.interpolation",
+        unit: DURATION,
+        range: "`interval` and `max_extrapolation`, usually a few milliseconds",
+        description: "If set, predict cursor positions between packets for controllers with a low report rate.",
+        example: Some("{ interval = { secs = 0, nanos = 4000000 }, max_extrapolation = { secs = 0, nanos = 20000000 } }"),
+    },
+    OptionDoc {
+        key: "common.hide_cursor",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Hide the cursor while the touchscreen was the last device to move the pointer, e.g. on kiosks.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.tap_to_click",
+        unit: "duration and touchscreen units (about 0.1mm)",
+        range: "`max_duration` shorter than `right_click_wait`, `slop` smaller than the panel",
+        description: "If set, only click on release if the touch was a short tap that did not move further than the slop.",
+        example: Some("{ max_duration = { secs = 0, nanos = 300000000 }, slop = 50.0 }"),
+    },
+    OptionDoc {
+        key: "common.drag_hold",
+        unit: DURATION,
+        range: "shorter than `right_click_wait`",
+        description: "If set, moving the finger after holding it still for this long drags with the left button held down.",
+        example: Some("{ secs = 0, nanos = 500000000 }"),
+    },
+    OptionDoc {
+        key: "common.grab_kernel_input",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Grab the input devices that the kernel creates for the touchscreen, so that touches are not reported twice.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.startup_grace",
+        unit: DURATION,
+        range: "a few seconds",
+        description: "If set, ignore all touches for this long after startup, e.g. to filter EMI bursts while a kiosk powers up.",
+        example: Some("{ secs = 2, nanos = 0 }"),
+    },
+    OptionDoc {
+        key: "common.calibrated_at",
+        unit: "seconds since the Unix epoch",
+        range: "",
+        description: "Time of the last calibration. Written by `calibrate`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.calibration_max_age",
+        unit: DURATION,
+        range: "usually months",
+        description: "If set, warn that the touchscreen should be recalibrated once the calibration is older than this.",
+        example: Some("{ secs = 15552000, nanos = 0 }"),
+    },
+    OptionDoc {
+        key: "common.drift_estimation",
+        unit: "duration and pixels",
+        range: "`min_correction` below `max_correction`",
+        description: "If set, estimate calibration drift from touches that are quickly corrected.",
+        example: Some("{ window = { secs = 1, nanos = 0 }, min_correction = 5.0, max_correction = 60.0 }"),
+    },
+    OptionDoc {
+        key: "common.click_bindings",
+        unit: "",
+        range: "tables with `hold`, `action` and an optional `zone` in pixels of the monitor",
+        description: "Actions for touches depending on how long they are held and where. If empty, they are made from `ev_left_click`, `ev_right_click` and `right_click_wait`.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.sticky_drag",
+        unit: "",
+        range: "`true` or `false`",
+        description: "A tap latches the left button down until the next tap, for users who cannot keep the finger on the screen while dragging.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.busy_poll_burns_cpu",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Spin on the device instead of sleeping until a packet arrives. Keeps a CPU core busy all the time.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.realtime_priority",
+        unit: "",
+        range: "1 to 99",
+        description: "If set, run the driver thread with this SCHED_FIFO priority when permitted.",
+        example: Some("10"),
+    },
+    OptionDoc {
+        key: "common.cpu_affinity",
+        unit: "",
+        range: "CPU numbers",
+        description: "If not empty, pin the driver thread to these CPUs.",
+        example: Some("[3]"),
+    },
+    OptionDoc {
+        key: "common.output_mode",
+        unit: "",
+        range: "`mouse` or `touchscreen`",
+        description: "If the virtual device is a mouse or a multi-touch touchscreen.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.software_cursor",
+        unit: "",
+        range: "`true` or `false`",
+        description: "Draw a crosshair where the pointer was moved, for compositors that draw no cursor for the virtual device.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.usage_statistics",
+        unit: "",
+        range: "`path`, `save_interval` and optional `zones` in pixels of the monitor",
+        description: "If set, count touches per day and zone and save the counts, e.g. to schedule maintenance by usage. `egalax-rs stats export` writes them as CSV.",
+        example: Some("{ path = \"/var/lib/egalax-rs/usage.json\", save_interval = { secs = 300, nanos = 0 } }"),
+    },
+    OptionDoc {
+        key: "common.second_finger",
+        unit: "",
+        range: "`ignore`, `cancel` or `{ fire = <action> }`",
+        description: "What a second finger does while the first one is held for a hold binding.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.adaptive_noise",
+        unit: "touchscreen units (about 0.1mm)",
+        range: "`multiplier` around 3, `max_threshold` above `has_moved_threshold`",
+        description: "If set, raise the has-moved threshold and the tap slop while the touches are noisy.",
+        example: Some("{ multiplier = 3.0, max_threshold = 100.0 }"),
+    },
+    OptionDoc {
+        key: "common.smoothing",
+        unit: "Hz and pixels per second",
+        range: "`ema` with `alpha` in (0, 1], or `one-euro` with positive `min_cutoff`, `beta` and `d_cutoff`; optionally another filter `y` for the Y axis and a positive `bypass_speed`",
+        description: "If set, smooth the positions of touches against cursor jitter. Above `bypass_speed` the positions are passed through unchanged.",
+        example: Some("{ filter = \"one-euro\", min_cutoff = 1.0, beta = 0.007 }"),
+    },
+    OptionDoc {
+        key: "common.palm_rejection",
+        unit: "touchscreen units (about 0.1mm)",
+        range: "`max_jump` and `max_spread`, usually a few hundred",
+        description: "If set, discard touches that jump or scatter like a palm resting on the panel.",
+        example: Some("{ max_jump = 400.0, max_spread = 200.0 }"),
+    },
+    OptionDoc {
+        key: "common.two_finger_scroll",
+        unit: "wheel clicks per 100 pixels",
+        range: "`speed` usually 1 to 5, `natural` `true` or `false`",
+        description: "If set, moving two fingers scrolls with wheel events instead of moving the pointer.",
+        example: Some("{ speed = 2.0, natural = true }"),
+    },
+    OptionDoc {
+        key: "common.pinch_zoom",
+        unit: "pixels",
+        range: "`step` usually 20 to 100, `emit` either `{ wheel = [<modifier keys>] }` or `{ keys = [[<zoom in keys>], [<zoom out keys>]] }`",
+        description: "If set, spreading or closing two fingers zooms, by default with Ctrl + wheel.",
+        example: Some("{ step = 40.0, emit = { wheel = [\"KEY_LEFTCTRL\"] } }"),
+    },
+    OptionDoc {
+        key: "common.double_tap",
+        unit: "duration and touchscreen units (about 0.1mm)",
+        range: "`window` usually below a second, `distance` usually a few hundred, `action` either `\"double_click\"` or `{ key = \"<key>\" }`",
+        description: "If set, a second tap shortly after the first one at about the same place fires an action instead of its click, by default completing a double-click where the first tap was.",
+        example: Some("{ window = { secs = 0, nanos = 400000000 }, distance = 150.0, action = \"double_click\" }"),
+    },
+    OptionDoc {
+        key: "common.edge_swipe",
+        unit: "pixels",
+        range: "`edge_width` usually 10 to 50, `min_travel` usually 100 to 300, `keys` a table from `left`, `right`, `top` or `bottom` to key combinations",
+        description: "If set, swiping in from an edge of the monitor presses a key combination, e.g. KEY_BACK from the left edge.",
+        example: Some("{ edge_width = 30.0, min_travel = 150.0, keys = { left = [\"KEY_BACK\"], right = [\"KEY_FORWARD\"] } }"),
+    },
+    OptionDoc {
+        key: "common.edge_click_guard",
+        unit: "pixels and duration",
+        range: "`edge_width` usually 5 to 30, `min_duration` about 100ms or unset to suppress all clicks near the edges",
+        description: "If set, suppress clicks of touches near the edges of the monitor that are shorter than `min_duration`, e.g. spurious touches of a badly seated overlay.",
+        example: Some("{ edge_width = 15.0, min_duration = { secs = 0, nanos = 120000000 } }"),
+    },
+    OptionDoc {
+        key: "common.maintenance_tap",
+        unit: "pixels and duration",
+        range: "`taps` usually 4 or more, `window` a few seconds, `zone` a small area such as a corner, `action` either `{ command = \"...\" }` or `{ keys = [<keys>] }`",
+        description: "If set, a series of quick taps in a zone fires a hidden action, e.g. so that kiosk technicians can open a maintenance menu without a keyboard. Only the last tap is consumed, the others click as usual.",
+        example: Some("{ taps = 5, window = { secs = 3, nanos = 0 }, zone = { x1 = 0, y1 = 0, x2 = 80, y2 = 80 }, action = { command = \"xdg-open http://localhost:8080/maintenance\" } }"),
+    },
+    OptionDoc {
+        key: "common.pressure_curve",
+        unit: "normalized pressure from 0 to 1",
+        range: "`\"linear\"`, `\"soft\"`, `\"hard\"` or `{ points = [[<input>, <output>], ...] }` with ascending inputs",
+        description: "If set, map the pressure of pressure capable variants with this curve before it is emitted, e.g. so that drawing feels the same on stiffer overlays. Only serial controllers report pressure.",
+        example: Some("{ points = [[0.0, 0.0], [0.3, 0.5], [1.0, 1.0]] }"),
+    },
+    OptionDoc {
+        key: "common.serial",
+        unit: "baud",
+        range: "`path` of the serial port and optional `baud_rate`, one of 1200 to 115200, by default 9600",
+        description: "If set, the touchscreen is an older one with a serial controller, connected over RS-232 or a USB-serial adapter. The driver and the calibration read the port instead of a hidraw node, which is also the device when none is given on the command line.",
+        example: Some("{ path = \"/dev/ttyUSB0\", baud_rate = 9600 }"),
+    },
+    OptionDoc {
+        key: "common.click_feedback",
+        unit: "",
+        range: "`{ bell = <percent> }` or `{ command = \"...\" }`",
+        description: "If set, make emitted clicks audible. Only available with the `audio` feature.",
+        example: None,
+    },
+    OptionDoc {
+        key: "common.touch_analytics",
+        unit: "",
+        range: "`broker`, `topic`, `interval` and optional `zones`",
+        description: "If set, publish anonymous touch counts to an MQTT broker. Only available with the `mqtt` feature.",
+        example: None,
+    },
+    OptionDoc {
+        key: "profiles",
+        unit: "",
+        range: "tables with `calibration_points`, `origin`, `swap_axes` and an optional `rotation` and `calibration_matrix`",
+        description: "Alternative calibrations by name, chosen automatically by the rotation of the monitor.",
+        example: None,
+    },
+    OptionDoc {
+        key: "window_rules",
+        unit: "",
+        range: "tables with optional `gestures` and `click_bindings`",
+        description: "Overrides of the click behavior by the class of the focused window, ignoring case.",
+        example: None,
+    },
+    OptionDoc {
+        key: "device",
+        unit: "",
+        range: "`[[device]]` tables with `serial` or `vendor_id` and `product_id`, and optional `calibration_points`, `origin`, `swap_axes`, `monitor_designator`, `gestures`, `two_finger_scroll`, `pinch_zoom`, `edge_swipe` and `double_tap`",
+        description: "Settings of single touchscreens, matched by USB serial number or else by vendor and product ID, so that a panel keeps its calibration and monitor on any port or machine.",
+        example: None,
+    },
+];
+
+/// The documentation of an option, or else of the closest enclosing table, e.g. `profiles` for `profiles.portrait`.
+pub fn lookup(key: &str) -> Option<&'static OptionDoc> {
+    let mut key = key;
+    loop {
+        if let Some(doc) = OPTIONS.iter().find(|doc| doc.key == key) {
+            return Some(doc);
+        }
+        key = &key[..key.rfind('.')?];
+    }
+}
+
+/// The documentation as TOML comments.
+fn comment(doc: &OptionDoc) -> String {
+    let mut comment = format!("# {}\n", doc.description);
+    match (doc.unit.is_empty(), doc.range.is_empty()) {
+        (false, false) => comment += &format!("# Unit: {}. Range: {}.\n", doc.unit, doc.range),
+        (false, true) => comment += &format!("# Unit: {}.\n", doc.unit),
+        (true, false) => comment += &format!("# Values: {}.\n", doc.range),
+        (true, true) => {}
+    }
+    comment
+}
+
+/// Add the documentation of each option as comments to a serialized config file.
+/// Unset options with an example are added commented out to their table.
+pub fn annotate(contents: &str) -> String {
+    let lines = parse_lines(contents);
+    let present: BTreeSet<&str> = lines.iter().filter_map(|(key, _)| key.as_deref()).collect();
+    let mut annotated = String::new();
+    let mut documented = BTreeSet::new();
+    let mut table = "";
+
+    for (line, (key, is_header)) in contents.lines().zip(&lines) {
+        if line.is_empty() {
+            continue;
+        }
+        if *is_header {
+            add_examples(&mut annotated, table, &present);
+            table = key.as_deref().unwrap_or_default();
+        }
+        let doc = key
+            .as_deref()
+            .and_then(lookup)
+            .filter(|doc| documented.insert(doc.key));
+        if doc.is_some() || *is_header {
+            separate(&mut annotated);
+        }
+        if let Some(doc) = doc {
+            annotated += &comment(doc);
+        }
+        annotated += line;
+        annotated.push('\n');
+    }
+    add_examples(&mut annotated, table, &present);
+    annotated
+}
+
+/// The dotted key of each line of a serialized config file, if it has one, and if the line is a table header.
+fn parse_lines(contents: &str) -> Vec<(Option<String>, bool)> {
+    let mut table = String::new();
+    contents
+        .lines()
+        .map(|line| {
+            if let Some(header) = line.strip_prefix('[') {
+                table = header.trim_matches(|c| c == '[' || c == ']').to_string();
+                (Some(table.clone()), true)
+            } else if line.starts_with([' ', '#']) {
+                (None, false)
+            } else {
+                let key = line.split_once(" = ").map(|(key, _)| join(&table, key));
+                (key, false)
+            }
+        })
+        .collect()
+}
+
+/// Add the unset options of a table that have an example, commented out.
+fn add_examples(annotated: &mut String, table: &str, present: &BTreeSet<&str>) {
+    for doc in OPTIONS {
+        let Some(example) = doc.example else {
+            continue;
+        };
+        let (parent, name) = doc.key.rsplit_once('.').unwrap_or(("", doc.key));
+        if parent == table && !present.contains(doc.key) {
+            separate(annotated);
+            *annotated += &comment(doc);
+            *annotated += &format!("# {} = {}\n", name, example);
+        }
+    }
+}
+
+/// Start a new paragraph unless we are at the start of the file or of a table.
+fn separate(annotated: &mut String) {
+    let last_line = annotated.lines().last().unwrap_or_default();
+    if !last_line.is_empty() && !last_line.starts_with('[') {
+        annotated.push('\n');
+    }
+}
+
+fn join(table: &str, key: &str) -> String {
+    if table.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", table, key)
+    }
+}
+
+/// The reference of all options as a Markdown table.
+pub fn markdown() -> String {
+    let mut markdown = String::from(
+        "# Configuration\n\n\
+        Generated from `src/config_reference.rs` with `gen-config --markdown`.\n\n\
+        | Option | Unit | Values | Description |\n\
+        | --- | --- | --- | --- |\n",
+    );
+    for doc in OPTIONS {
+        markdown += &format!(
+            "| `{}` | {} | {} | {} |\n",
+            doc.key,
+            doc.unit,
+            doc.range.replace('|', "\\|"),
+            doc.description
+        );
+    }
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+
+    #[test]
+    fn test_annotate() {
+        let contents = toml::to_string(&ConfigFile::default()).unwrap();
+        let annotated = annotate(&contents);
+
+        // Every option that is written is documented, so the reference cannot fall behind the config.
+        // Values within the table of an option, like the coordinates of the calibration points, are covered by it.
+        for (key, _) in parse_lines(&contents) {
+            let Some(key) = key else {
+                continue;
+            };
+            let documented = OPTIONS.iter().any(|doc| {
+                doc.key == key
+                    || key
+                        .rsplit_once('.')
+                        .is_some_and(|(table, _)| doc.key == table && table != "common")
+            });
+            assert!(documented, "{} is not documented", key);
+        }
+
+        assert!(annotated.contains("# Values: `true` or `false`.\nswap_axes = false\n"));
+        assert!(annotated.contains(&format!(
+            "# smoothing = {}\n",
+            lookup("common.smoothing").unwrap().example.unwrap()
+        )));
+        // The comments and the commented out examples do not change the config.
+        let parsed: ConfigFile = toml::from_str(&annotated).unwrap();
+        assert_eq!(contents, toml::to_string(&parsed).unwrap());
+    }
+}
@@ -0,0 +1,960 @@
+//! A control interface on a Unix socket to query the running driver.
+//!
+//! Clients connect to the socket and send one command per line. Each command is answered with a single line of JSON.
+//! The commands are:
+//! - `status` reports statistics, e.g. `echo status | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/egalax-rs.sock`.
+//! - `subscribe` keeps the connection open and writes one line of JSON per [DriverEvent], so that kiosk applications
+//!   can react to touches, clicks and gestures directly instead of interpreting pointer events.
+//! - `record start [DIR]` starts recording packets and emitted events to a new file in `DIR`, by default the working
+//!   directory of the driver, and `record stop` finishes the recording. See [crate::recorder].
+//! - `pause` temporarily drops all touches, ending the current one without clicking, and `resume` accepts them again.
+//! - `reload` reads the config file of this touchscreen again. SIGHUP instead reloads the configs of all touchscreens
+//!   that the process drives.
+//! - `set-calibration CALIBRATION` replaces the calibration with one in JSON, e.g. from a settings GUI, until the
+//!   config is reloaded. Like a reloaded config it takes effect between touches.
+//! - `debug COMMAND` is passed to the packet loop of the driver, e.g. to inspect its state, inject packets or tune
+//!   options while it runs. Only available with the `debug` feature, see `egalax-rs debug` for a REPL.
+//!
+//! Scripts may also send a command as a JSON object with its name in `command`, e.g. `{"command": "pause"}` or
+//! `{"command": "set-calibration", "calibration": {...}}`.
+
+use anyhow::anyhow;
+use evdev_rs::InputEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, fs};
+
+use crate::calibration::Calibration;
+use crate::config::ClickAction;
+use crate::drift::DriftEstimate;
+use crate::edge_swipe::Edge;
+use crate::error::EgalaxError;
+use crate::geo::Point2D;
+use crate::noise::AdaptiveThresholds;
+use crate::palm::PalmCounters;
+use crate::protocol::{TouchState, USBMessage, USBPacket};
+use crate::recorder::{RecordingSummary, SessionRecorder};
+use crate::units::ScreenSpace;
+use crate::usage::{DailyCounts, TouchCounts};
+
+/// Name of the control socket within the runtime directory.
+const SOCKET_NAME: &str = "egalax-rs.sock";
+
+/// Number of events that are queued for a subscriber before it is dropped for not keeping up.
+const SUBSCRIBER_QUEUE: usize = 256;
+
+/// Length of the window over which the packet rate is measured.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How long to wait for the driver to answer a debug command. The packet loop looks for commands at least every second.
+#[cfg(feature = "debug")]
+const DEBUG_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The default location of the control socket, in `$XDG_RUNTIME_DIR` or else in `/run` for root, e.g. for a system
+/// service, and in `/tmp` for everyone else.
+pub fn default_socket_path() -> PathBuf {
+    let fallback = if nix::unistd::Uid::effective().is_root() {
+        "/run"
+    } else {
+        "/tmp"
+    };
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map_or_else(|| PathBuf::from(fallback), PathBuf::from)
+        .join(SOCKET_NAME)
+}
+
+/// The control socket of the touchscreen with the given index when one driver runs several of them.
+/// The first one uses the [default_socket_path], the others get their index appended.
+pub fn device_socket_path(index: usize) -> PathBuf {
+    let path = default_socket_path();
+    match index {
+        0 => path,
+        _ => path.with_file_name(format!("egalax-rs-{}.sock", index)),
+    }
+}
+
+/// Statistics about the running driver which are updated by the driver and read by the control interface.
+#[derive(Debug)]
+struct Statistics {
+    started: Instant,
+    device: Option<PathBuf>,
+    profile: Option<String>,
+    calibration: Option<Calibration>,
+    calibrated_at: Option<SystemTime>,
+    calibration_max_age: Option<Duration>,
+    drift: Option<DriftEstimate>,
+    noise: Option<AdaptiveThresholds>,
+    palm: Option<PalmCounters>,
+    usage: Option<UsageReport>,
+    touch_state: TouchState,
+    /// Resolution in bits of the last packet.
+    resolution: Option<u8>,
+    /// Number of skipped packets per unknown tag.
+    unknown_tags: BTreeMap<u8, u64>,
+    /// How often the resolution changed between packets.
+    resolution_changes: u32,
+    last_packet: Option<Instant>,
+    /// Start of the current rate window and the number of packets received in it.
+    window: (Instant, u32),
+    packets_per_sec: f32,
+    /// Connections that receive driver events.
+    subscribers: Vec<SyncSender<DriverEvent>>,
+    /// The session recording in progress.
+    recorder: Option<SessionRecorder>,
+    /// If touch input was temporarily disabled, e.g. over D-Bus.
+    input_disabled: bool,
+    /// A calibration from the control interface that the driver has not picked up yet.
+    requested_calibration: Option<Calibration>,
+    /// If a reload of the config was requested that the driver has not picked up yet.
+    reload_requested: bool,
+    /// Passes debug commands to the packet loop.
+    #[cfg(feature = "debug")]
+    debug: Option<mpsc::Sender<DebugRequest>>,
+}
+
+/// A debug command for the packet loop of the driver and where to send its JSON answer.
+#[cfg(feature = "debug")]
+#[derive(Debug)]
+pub struct DebugRequest {
+    pub command: String,
+    pub reply: mpsc::Sender<String>,
+}
+
+/// Something the driver recognized, published to subscribers of the control socket.
+/// Positions are in screen coordinates.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DriverEvent {
+    TouchDown {
+        position: Point2D<ScreenSpace>,
+    },
+    TouchUp {
+        position: Point2D<ScreenSpace>,
+    },
+    /// A click binding fired when the finger was lifted.
+    Click {
+        action: ClickAction,
+        position: Point2D<ScreenSpace>,
+    },
+    /// A click binding fired while the finger was held down.
+    LongPress {
+        action: ClickAction,
+        position: Point2D<ScreenSpace>,
+    },
+    Gesture {
+        gesture: Gesture,
+        position: Point2D<ScreenSpace>,
+    },
+}
+
+/// Gestures reported in [DriverEvent::Gesture].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gesture {
+    DragStart,
+    DragEnd,
+    /// A tap latched the left button in sticky drag mode.
+    StickyDragLatch,
+    /// A tap released the latched left button in sticky drag mode.
+    StickyDragRelease,
+    /// A second tap shortly after the first one fired the double tap action.
+    DoubleTap,
+    /// The finger swiped in from an edge of the monitor.
+    EdgeSwipe {
+        edge: Edge,
+    },
+    /// A series of taps fired the maintenance action.
+    MaintenanceTap,
+}
+
+/// Shared handle to the statistics of a running driver.
+#[derive(Debug, Clone)]
+pub struct DriverStatus(Arc<Mutex<Statistics>>);
+
+impl DriverStatus {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self(Arc::new(Mutex::new(Statistics {
+            started: now,
+            device: None,
+            profile: None,
+            calibration: None,
+            calibrated_at: None,
+            calibration_max_age: None,
+            drift: None,
+            noise: None,
+            palm: None,
+            usage: None,
+            touch_state: TouchState::NotTouching,
+            resolution: None,
+            unknown_tags: BTreeMap::new(),
+            resolution_changes: 0,
+            last_packet: None,
+            window: (now, 0),
+            packets_per_sec: 0.0,
+            subscribers: Vec::new(),
+            recorder: None,
+            input_disabled: false,
+            requested_calibration: None,
+            reload_requested: false,
+            #[cfg(feature = "debug")]
+            debug: None,
+        })))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Statistics> {
+        // The statistics stay consistent even if a thread panicked while holding the lock.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set the device node that the driver reads from.
+    pub fn set_device(&self, device: &Path) {
+        self.lock().device = Some(device.to_path_buf());
+    }
+
+    /// Set the name of the active configuration profile.
+    pub fn set_profile(&self, profile: &str) {
+        self.lock().profile = Some(profile.to_string());
+    }
+
+    /// Temporarily disable or enable touch input. While disabled the driver drops all packets.
+    pub fn set_input_enabled(&self, enabled: bool) {
+        self.lock().input_disabled = !enabled;
+    }
+
+    pub fn input_enabled(&self) -> bool {
+        !self.lock().input_disabled
+    }
+
+    /// The calibration that the driver currently uses.
+    pub fn calibration(&self) -> Option<Calibration> {
+        self.lock().calibration
+    }
+
+    /// Ask the driver to replace its calibration, see [DriverStatus::take_requested_calibration].
+    pub fn request_calibration(&self, calibration: Calibration) {
+        self.lock().requested_calibration = Some(calibration);
+    }
+
+    /// The calibration requested since the last call, which the driver should switch to.
+    pub fn take_requested_calibration(&self) -> Option<Calibration> {
+        self.lock().requested_calibration.take()
+    }
+
+    /// Ask the driver to read its config file again, see [DriverStatus::take_reload_request].
+    pub fn request_reload(&self) {
+        self.lock().reload_requested = true;
+    }
+
+    /// If a reload was requested since the last call.
+    pub fn take_reload_request(&self) -> bool {
+        std::mem::take(&mut self.lock().reload_requested)
+    }
+
+    /// Set the calibration that the driver currently uses.
+    pub fn set_calibration(&self, calibration: Calibration) {
+        self.lock().calibration = Some(calibration);
+    }
+
+    /// Set when the calibration was made and how old it may get before the touchscreen should be recalibrated.
+    pub fn set_calibration_age(
+        &self,
+        calibrated_at: Option<SystemTime>,
+        max_age: Option<Duration>,
+    ) {
+        let mut stats = self.lock();
+        stats.calibrated_at = calibrated_at;
+        stats.calibration_max_age = max_age;
+    }
+
+    /// Set the current estimate of the calibration drift.
+    pub fn set_drift(&self, drift: Option<DriftEstimate>) {
+        self.lock().drift = drift;
+    }
+
+    /// Set the measured noise and the thresholds that are raised because of it.
+    pub fn set_noise(&self, noise: AdaptiveThresholds) {
+        self.lock().noise = Some(noise);
+    }
+
+    /// Set how many touches were rejected as palms.
+    pub fn set_palm(&self, counters: PalmCounters) {
+        self.lock().palm = Some(counters);
+    }
+
+    /// Set the counted touches if usage statistics are enabled.
+    pub fn set_usage(&self, counts: &DailyCounts, now: SystemTime) {
+        self.lock().usage = Some(UsageReport {
+            today: counts.day(now),
+            total: counts.total(),
+        });
+    }
+
+    /// Record that a packet was received.
+    /// A change of the resolution is logged, since the controller should always report the same one.
+    /// It hints at a firmware problem or at packets that were not framed correctly.
+    pub fn record_packet(&self, packet: &USBPacket) {
+        let now = Instant::now();
+        let mut stats = self.lock();
+        stats.touch_state = packet.touch_state();
+        stats.last_packet = Some(now);
+
+        let resolution = packet.resolution();
+        if let Some(previous) = stats.resolution.filter(|&previous| previous != resolution) {
+            log::warn!(
+                "Resolution changed from {} to {} bits. The controller firmware or the packet framing may be broken.",
+                previous,
+                resolution
+            );
+            stats.resolution_changes += 1;
+        }
+        stats.resolution = Some(resolution);
+
+        let (window_start, count) = &mut stats.window;
+        *count += 1;
+        let elapsed = now.duration_since(*window_start);
+        if elapsed >= RATE_WINDOW {
+            let rate = *count as f32 / elapsed.as_secs_f32();
+            stats.window = (now, 0);
+            stats.packets_per_sec = rate;
+        }
+    }
+
+    /// Add to the number of skipped packets per unknown tag.
+    pub fn record_unknown_tags(&self, unknown_tags: BTreeMap<u8, u64>) {
+        if unknown_tags.is_empty() {
+            return;
+        }
+        let mut stats = self.lock();
+        for (tag, count) in unknown_tags {
+            *stats.unknown_tags.entry(tag).or_default() += count;
+        }
+    }
+
+    /// Start receiving driver events. At most [SUBSCRIBER_QUEUE] events are queued, see [DriverStatus::publish].
+    pub fn subscribe(&self) -> Receiver<DriverEvent> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        self.lock().subscribers.push(sender);
+        receiver
+    }
+
+    /// Send an event to all subscribers without blocking.
+    /// Subscribers that disconnected are removed, and so are those whose queue is full because they stopped reading.
+    pub fn publish(&self, event: DriverEvent) {
+        let mut stats = self.lock();
+        if stats.subscribers.is_empty() {
+            return;
+        }
+        stats
+            .subscribers
+            .retain(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("Dropping an event subscriber that does not keep up.");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    /// Start receiving debug commands. Only the last receiver gets them.
+    #[cfg(feature = "debug")]
+    pub fn debug_requests(&self) -> Receiver<DebugRequest> {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().debug = Some(sender);
+        receiver
+    }
+
+    /// Pass a debug command to the driver and wait for its JSON answer.
+    #[cfg(feature = "debug")]
+    fn debug(&self, command: String) -> Result<String, String> {
+        let sender = self
+            .lock()
+            .debug
+            .clone()
+            .ok_or_else(|| String::from("driver is not running"))?;
+        let (reply, answer) = mpsc::channel();
+        sender
+            .send(DebugRequest { command, reply })
+            .map_err(|_| String::from("driver is not running"))?;
+        answer
+            .recv_timeout(DEBUG_TIMEOUT)
+            .map_err(|_| String::from("driver did not answer"))
+    }
+
+    /// Start recording packets and emitted events to a new file in `dir` and return its path.
+    pub fn start_recording(&self, dir: &Path) -> Result<PathBuf, EgalaxError> {
+        let mut stats = self.lock();
+        if let Some(recorder) = &stats.recorder {
+            return Err(EgalaxError::Generic(anyhow!(
+                "already recording to '{}'",
+                recorder.path().display()
+            )));
+        }
+        let recorder = SessionRecorder::create(dir)?;
+        let path = recorder.path().to_path_buf();
+        stats.recorder = Some(recorder);
+        Ok(path)
+    }
+
+    /// Finish the recording in progress.
+    pub fn stop_recording(&self) -> Result<RecordingSummary, EgalaxError> {
+        match self.lock().recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Err(EgalaxError::Generic(anyhow!("not recording"))),
+        }
+    }
+
+    /// Add a packet and the events emitted for it to the recording, if one is in progress.
+    /// A recording that fails to write is stopped.
+    pub fn record_session(&self, message: Option<&USBMessage>, events: &[InputEvent]) {
+        let mut stats = self.lock();
+        let Some(recorder) = &mut stats.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(message, events) {
+            log::warn!(
+                "Stopping session recording to '{}': {}",
+                recorder.path().display(),
+                e
+            );
+            stats.recorder = None;
+        }
+    }
+
+    /// Take a snapshot of the current statistics.
+    pub fn report(&self) -> StatusReport {
+        let now = Instant::now();
+        let stats = self.lock();
+
+        // If no packet arrived for a whole window the rate from the previous window is outdated.
+        let packets_per_sec = if now.duration_since(stats.window.0) >= RATE_WINDOW * 2 {
+            0.0
+        } else {
+            stats.packets_per_sec
+        };
+
+        let calibration_age = stats
+            .calibrated_at
+            .map(|time| SystemTime::now().duration_since(time).unwrap_or_default());
+        let recalibration_due = stats
+            .calibration_max_age
+            .is_some_and(|max_age| calibration_age.is_none_or(|age| age > max_age));
+
+        StatusReport {
+            uptime_secs: now.duration_since(stats.started).as_secs(),
+            device: stats.device.as_ref().map(|d| d.display().to_string()),
+            packets_per_sec,
+            last_packet_age_ms: stats
+                .last_packet
+                .map(|time| now.duration_since(time).as_millis() as u64),
+            touching: stats.touch_state == TouchState::IsTouching,
+            input_disabled: stats.input_disabled,
+            resolution: stats.resolution,
+            resolution_changes: stats.resolution_changes,
+            unknown_tags: stats.unknown_tags.clone(),
+            profile: stats.profile.clone(),
+            calibration: stats.calibration,
+            calibration_age_secs: calibration_age.map(|age| age.as_secs()),
+            recalibration_due,
+            drift: stats.drift,
+            noise: stats.noise,
+            palm: stats.palm,
+            usage: stats.usage.clone(),
+        }
+    }
+}
+
+impl Default for DriverStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answer to the `status` command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub uptime_secs: u64,
+    pub device: Option<String>,
+    pub packets_per_sec: f32,
+    /// Milliseconds since the last packet, or `None` if no packet was received yet.
+    pub last_packet_age_ms: Option<u64>,
+    pub touching: bool,
+    /// If touch input is temporarily disabled.
+    #[serde(default)]
+    pub input_disabled: bool,
+    /// Resolution in bits of the last packet.
+    pub resolution: Option<u8>,
+    /// How often the resolution changed between packets, which should never happen.
+    pub resolution_changes: u32,
+    /// Number of skipped packets per unknown tag, e.g. vendor packets.
+    pub unknown_tags: BTreeMap<u8, u64>,
+    pub profile: Option<String>,
+    pub calibration: Option<Calibration>,
+    /// Seconds since the calibration was made, or `None` if that is unknown.
+    pub calibration_age_secs: Option<u64>,
+    /// If the calibration is older than the configured maximum age.
+    pub recalibration_due: bool,
+    /// Estimated calibration drift if drift estimation is enabled and corrections were observed.
+    pub drift: Option<DriftEstimate>,
+    /// Measured noise and adapted thresholds if adaptive noise thresholds are enabled and a touch ended.
+    pub noise: Option<AdaptiveThresholds>,
+    /// Touches rejected as palms if palm rejection is enabled and a palm was rejected.
+    pub palm: Option<PalmCounters>,
+    /// Counted touches if usage statistics are enabled.
+    pub usage: Option<UsageReport>,
+}
+
+/// Touches counted by the usage statistics.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageReport {
+    /// Touches of the current day in UTC.
+    pub today: TouchCounts,
+    /// Touches of all days in the statistics file.
+    pub total: TouchCounts,
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let last_packet = self
+            .last_packet_age_ms
+            .map_or(String::from("never"), |age| format!("{}ms ago", age));
+        f.write_fmt(format_args!(
+            "Uptime: {}s.\n\
+            Device: {}.\n\
+            Packets per second: {:.1}.\n\
+            Last packet: {}.\n\
+            Touching: {}.\n\
+            Resolution: {}.\n\
+            Profile: {}.",
+            self.uptime_secs,
+            self.device.as_deref().unwrap_or("none"),
+            self.packets_per_sec,
+            last_packet,
+            self.touching,
+            self.resolution.map_or(String::from("unknown"), |r| format!(
+                "{} bits ({} changes)",
+                r, self.resolution_changes
+            )),
+            self.profile.as_deref().unwrap_or("none"),
+        ))?;
+        if self.input_disabled {
+            f.write_str("\nTouch input is disabled.")?;
+        }
+        if !self.unknown_tags.is_empty() {
+            let unknown_tags = self
+                .unknown_tags
+                .iter()
+                .map(|(tag, count)| format!("{:#04x}: {}", tag, count))
+                .collect::<Vec<_>>();
+            f.write_fmt(format_args!(
+                "\nSkipped packets with unknown tags: {}.",
+                unknown_tags.join(", ")
+            ))?;
+        }
+        if let Some(calibration) = &self.calibration {
+            f.write_fmt(format_args!(
+                "\nCalibration: {}, origin {}, swap axes {}, rotation {}.",
+                calibration.calibration_points,
+                calibration.origin,
+                calibration.swap_axes,
+                calibration.rotation
+            ))?;
+        }
+        if let Some(age) = self.calibration_age_secs {
+            f.write_fmt(format_args!("\nCalibration age: {} days.", age / 86400))?;
+        }
+        if self.recalibration_due {
+            f.write_str("\nRecalibration is due.")?;
+        }
+        if let Some(drift) = &self.drift {
+            f.write_fmt(format_args!("\nEstimated drift: {}.", drift))?;
+        }
+        if let Some(noise) = &self.noise {
+            f.write_fmt(format_args!("\nAdaptive thresholds: {}.", noise))?;
+        }
+        if let Some(palm) = &self.palm {
+            f.write_fmt(format_args!("\nRejected palms: {}.", palm))?;
+        }
+        if let Some(usage) = &self.usage {
+            f.write_fmt(format_args!(
+                "\nTouches today: {}. Touches in total: {}.",
+                usage.today.touches, usage.total.touches
+            ))?;
+            for (zone, count) in &usage.total.zones {
+                f.write_fmt(format_args!(
+                    "\n  Zone {}: {} today, {} in total.",
+                    zone,
+                    usage.today.zones.get(zone).copied().unwrap_or_default(),
+                    count
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Answer to a command that could not be executed.
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    error: String,
+}
+
+/// Send a command to a running driver and return its JSON response.
+pub fn query(path: &Path, command: &str) -> Result<String, EgalaxError> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", command)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(response.trim_end().to_string())
+}
+
+/// Listens on the control socket in a background thread. The socket file is removed when the server is dropped.
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Bind the control socket and start answering commands.
+    /// A stale socket file from a previous run is replaced, but the socket of a running driver is left alone.
+    pub fn spawn(path: &Path, status: DriverStatus) -> Result<Self, EgalaxError> {
+        match UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(EgalaxError::Generic(anyhow!(
+                    "control socket '{}' is in use by another driver",
+                    path.display()
+                )))
+            }
+            // Nobody listens anymore, so the socket is left over from a driver that did not exit cleanly.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        log::info!("Listening for control commands on '{}'", path.display());
+
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                match client {
+                    Ok(client) => {
+                        let status = status.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve_client(client, &status) {
+                                log::warn!("Control connection failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::warn!("Could not accept control connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Answer commands from a single client until it disconnects.
+fn serve_client(client: UnixStream, status: &DriverStatus) -> Result<(), EgalaxError> {
+    let mut writer = client.try_clone()?;
+    for line in BufReader::new(client).lines() {
+        let line = line?;
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        log::debug!("Received control command '{}'", command);
+        if command == "subscribe" {
+            return stream_events(writer, status);
+        }
+        let response = execute(command, status)?;
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+/// Write every driver event to the client until it disconnects.
+fn stream_events(mut writer: UnixStream, status: &DriverStatus) -> Result<(), EgalaxError> {
+    for event in status.subscribe() {
+        let line = serde_json::to_string(&event).map_err(|e| EgalaxError::Generic(e.into()))?;
+        match writeln!(writer, "{}", line) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    log::debug!("Event subscriber disconnected.");
+    Ok(())
+}
+
+/// Answer to `record start`.
+#[derive(Debug, Serialize)]
+struct RecordingStarted {
+    recording: PathBuf,
+}
+
+/// Answer to `pause` and `resume`.
+#[derive(Debug, Serialize)]
+struct InputState {
+    input_enabled: bool,
+}
+
+/// Answer to `reload` and `set-calibration`, which the driver only picks up later.
+#[derive(Debug, Serialize)]
+struct Requested {
+    requested: &'static str,
+}
+
+/// A command sent as a JSON object instead of a line of words.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum JsonCommand {
+    Status,
+    Pause,
+    Resume,
+    Reload,
+    SetCalibration { calibration: Calibration },
+}
+
+impl JsonCommand {
+    /// The command as a line of words.
+    fn to_line(&self) -> Result<String, serde_json::Error> {
+        Ok(match self {
+            JsonCommand::Status => String::from("status"),
+            JsonCommand::Pause => String::from("pause"),
+            JsonCommand::Resume => String::from("resume"),
+            JsonCommand::Reload => String::from("reload"),
+            JsonCommand::SetCalibration { calibration } => {
+                format!("set-calibration {}", serde_json::to_string(calibration)?)
+            }
+        })
+    }
+}
+
+/// Execute a command and return the JSON response.
+fn execute(command: &str, status: &DriverStatus) -> Result<String, EgalaxError> {
+    let error = |error: String| serde_json::to_string(&ErrorReport { error });
+    if command.starts_with('{') {
+        let line = serde_json::from_str::<JsonCommand>(command).and_then(|json| json.to_line());
+        return match line {
+            Ok(line) => execute(&line, status),
+            Err(e) => {
+                error(format!("invalid command: {}", e)).map_err(|e| EgalaxError::Generic(e.into()))
+            }
+        };
+    }
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let response = match words.as_slice() {
+        ["status"] => serde_json::to_string(&status.report()),
+        [verb @ ("pause" | "resume")] => {
+            let input_enabled = *verb == "resume";
+            log::info!(
+                "Touch input {} from the control interface.",
+                if input_enabled { "resumed" } else { "paused" }
+            );
+            status.set_input_enabled(input_enabled);
+            serde_json::to_string(&InputState { input_enabled })
+        }
+        ["reload"] => {
+            status.request_reload();
+            serde_json::to_string(&Requested {
+                requested: "reload",
+            })
+        }
+        // The calibration is the rest of the line, since JSON may contain spaces.
+        ["set-calibration", _, ..] => {
+            match serde_json::from_str(command["set-calibration".len()..].trim()) {
+                Ok(calibration) => {
+                    status.request_calibration(calibration);
+                    serde_json::to_string(&Requested {
+                        requested: "set-calibration",
+                    })
+                }
+                Err(e) => error(format!("invalid calibration: {}", e)),
+            }
+        }
+        ["record", "start", dir @ ..] if dir.len() <= 1 => {
+            let dir = dir.first().map_or(Path::new("."), Path::new);
+            match status.start_recording(dir) {
+                Ok(recording) => serde_json::to_string(&RecordingStarted { recording }),
+                Err(e) => error(e.to_string()),
+            }
+        }
+        ["record", "stop"] => match status.stop_recording() {
+            Ok(summary) => serde_json::to_string(&summary),
+            Err(e) => error(e.to_string()),
+        },
+        #[cfg(feature = "debug")]
+        // The rest of the line is passed as it is, since values may contain spaces.
+        ["debug", _, ..] => match status.debug(command["debug".len()..].trim().to_string()) {
+            Ok(answer) => Ok(answer),
+            Err(e) => error(e),
+        },
+        _ => error(format!("unknown command '{}'", command)),
+    };
+    response.map_err(|e| EgalaxError::Generic(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_report() {
+        let status = DriverStatus::new();
+        status.set_device(Path::new("/dev/hidraw.egalax"));
+        let packet = |resolution| {
+            USBPacket::new(TouchState::IsTouching, (100, 100).into(), resolution).unwrap()
+        };
+        status.record_packet(&packet(12));
+
+        let report = status.report();
+        assert_eq!(Some("/dev/hidraw.egalax".to_string()), report.device);
+        assert!(report.touching);
+        assert_eq!(
+            (Some(12), 0),
+            (report.resolution, report.resolution_changes)
+        );
+        status.record_packet(&packet(11));
+        assert_eq!(1, status.report().resolution_changes);
+        assert!(report.last_packet_age_ms.is_some());
+
+        let response = execute("status", &status).unwrap();
+        assert!(response.starts_with("{\"uptime_secs\":0,"));
+        let response = execute("frobnicate", &status).unwrap();
+        assert_eq!("{\"error\":\"unknown command 'frobnicate'\"}", response);
+    }
+
+    #[test]
+    fn test_subscribe_events() {
+        let status = DriverStatus::new();
+        let position = Point2D::from((10, 20));
+        // Without subscribers events are dropped.
+        status.publish(DriverEvent::TouchDown { position });
+
+        let receiver = status.subscribe();
+        let dropped = status.subscribe();
+        drop(dropped);
+        status.publish(DriverEvent::Gesture {
+            gesture: Gesture::DragStart,
+            position,
+        });
+        assert_eq!(1, status.lock().subscribers.len());
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            "{\"event\":\"gesture\",\"gesture\":\"drag_start\",\"position\":{\"x\":10,\"y\":20}}",
+            serde_json::to_string(&event).unwrap()
+        );
+        assert!(receiver.try_recv().is_err());
+
+        // A subscriber that stops reading is dropped once its queue is full.
+        for _ in 0..SUBSCRIBER_QUEUE {
+            status.publish(DriverEvent::TouchDown { position });
+        }
+        assert_eq!(1, status.lock().subscribers.len());
+        status.publish(DriverEvent::TouchDown { position });
+        assert!(status.lock().subscribers.is_empty());
+        assert_eq!(SUBSCRIBER_QUEUE, receiver.try_iter().count());
+    }
+
+    #[test]
+    fn test_record_commands() {
+        let status = DriverStatus::new();
+        assert_eq!(
+            "{\"error\":\"not recording\"}",
+            execute("record stop", &status).unwrap()
+        );
+
+        let dir = std::env::temp_dir().join(format!("egalax-rs-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let response = execute(&format!("record start {}", dir.display()), &status).unwrap();
+        assert!(response.starts_with("{\"recording\":"));
+        let packet = USBPacket::new(TouchState::IsTouching, (100, 100).into(), 12).unwrap();
+        status.record_session(Some(&packet.with_time(Instant::now())), &[]);
+
+        let summary = status.stop_recording().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(1, summary.packets);
+    }
+
+    #[test]
+    fn test_socket_in_use() {
+        let dir = std::env::temp_dir().join(format!("egalax-rs-socket-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        // A socket file without a listener is replaced.
+        drop(UnixListener::bind(&path).unwrap());
+        let server = ControlServer::spawn(&path, DriverStatus::new()).unwrap();
+        // The socket of a running server is not taken over.
+        assert!(ControlServer::spawn(&path, DriverStatus::new()).is_err());
+        assert!(query(&path, "status")
+            .unwrap()
+            .starts_with("{\"uptime_secs\":"));
+
+        drop(server);
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_runtime_commands() {
+        let status = DriverStatus::new();
+        assert_eq!(
+            "{\"input_enabled\":false}",
+            execute("pause", &status).unwrap()
+        );
+        assert!(!status.input_enabled());
+        assert_eq!(
+            "{\"input_enabled\":true}",
+            execute("{\"command\": \"resume\"}", &status).unwrap()
+        );
+        assert!(status.input_enabled());
+
+        let area = crate::geo::AABB::from((0, 0, 1000, 1000));
+        let mut calibration = crate::config::ConfigFile::default()
+            .build_for_area(area, area)
+            .calibration();
+        calibration.swap_axes = !calibration.swap_axes;
+        let command =
+            serde_json::json!({ "command": "set-calibration", "calibration": calibration });
+        assert_eq!(
+            "{\"requested\":\"set-calibration\"}",
+            execute(&command.to_string(), &status).unwrap()
+        );
+        assert_eq!(Some(calibration), status.take_requested_calibration());
+        assert_eq!(
+            "{\"requested\":\"reload\"}",
+            execute("reload", &status).unwrap()
+        );
+        assert!(status.take_reload_request());
+        assert!(!status.take_reload_request());
+        assert!(execute("set-calibration {}", &status)
+            .unwrap()
+            .starts_with("{\"error\":\"invalid calibration"));
+        assert!(execute("{\"command\": \"frobnicate\"}", &status)
+            .unwrap()
+            .starts_with("{\"error\":\"invalid command"));
+    }
+}
@@ -0,0 +1,264 @@
+//! Hiding the X cursor while the touchscreen is in use, or drawing one ourselves.
+//!
+//! On kiosks an arrow cursor under the finger is unwanted. We hide the cursor with XFixes when a touch happens
+//! and show it again as soon as the pointer is moved by another device, e.g. a mouse.
+//!
+//! Conversely, some embedded compositors draw no cursor for our virtual device at all.
+//! For debugging and demos a [SoftwareCursor] draws a crosshair at the touch position instead.
+
+use std::os::raw::{c_int, c_uint, c_ulong};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use std::{mem, ptr};
+use x11::{xfixes, xlib};
+
+use crate::error::EgalaxError;
+use crate::geo::Point2D;
+use crate::overlay::RED;
+use crate::units::ScreenSpace;
+use crate::x11::XConnection;
+
+/// How often we check whether another device moved the pointer while the cursor is hidden.
+const POINTER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Pointer movements up to this many pixels away from the last touch are attributed to the touchscreen.
+const POINTER_SLOP: f32 = 2.0;
+/// Width and height of the software cursor's crosshair in pixels. Odd so that there is a center pixel.
+const CROSSHAIR_SIZE: i32 = 31;
+/// Thickness of the crosshair's lines in pixels.
+const CROSSHAIR_THICKNESS: i32 = 3;
+/// Kinds of window shapes, from X11/extensions/shape.h.
+const SHAPE_BOUNDING: c_int = 0;
+const SHAPE_INPUT: c_int = 2;
+
+/// Handle to a thread that hides the cursor while the touchscreen was the last device to move the pointer.
+/// The cursor is shown again when the handle is dropped.
+#[derive(Debug)]
+pub struct CursorHider {
+    sender: Sender<Point2D<ScreenSpace>>,
+}
+
+impl CursorHider {
+    /// Connect to the X server and start the thread.
+    pub fn spawn() -> Result<Self, EgalaxError> {
+        let display = CursorConnection::open()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || hide_cursor_loop(display, receiver));
+        Ok(Self { sender })
+    }
+
+    /// Notify that the touchscreen moved the pointer to the given position in screen space.
+    pub fn touched(&self, position: Point2D<ScreenSpace>) {
+        // If the thread is gone the cursor simply stays visible.
+        self.sender.send(position).ok();
+    }
+}
+
+/// Handle to a thread that draws a crosshair where the touchscreen moved the pointer.
+/// The crosshair disappears when the handle is dropped.
+#[derive(Debug)]
+pub struct SoftwareCursor {
+    sender: Sender<Point2D<ScreenSpace>>,
+}
+
+impl SoftwareCursor {
+    /// Connect to the X server and start the thread.
+    pub fn spawn() -> Result<Self, EgalaxError> {
+        let display = CursorConnection::open()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || software_cursor_loop(display, receiver));
+        Ok(Self { sender })
+    }
+
+    /// Notify that the driver moved the pointer to the given position in screen space.
+    pub fn moved(&self, position: Point2D<ScreenSpace>) {
+        // If the thread is gone there is simply no crosshair.
+        self.sender.send(position).ok();
+    }
+}
+
+/// Connection to the X server that shows, hides or draws the cursor.
+struct CursorConnection {
+    connection: XConnection,
+}
+
+impl CursorConnection {
+    fn open() -> Result<Self, EgalaxError> {
+        Ok(Self {
+            connection: XConnection::open()?,
+        })
+    }
+
+    fn hide_cursor(&self) {
+        // SAFETY: display and root window are valid for the lifetime of the connection.
+        unsafe {
+            xfixes::XFixesHideCursor(self.connection.display(), self.connection.root());
+            xlib::XFlush(self.connection.display());
+        }
+    }
+
+    fn show_cursor(&self) {
+        // SAFETY: display and root window are valid for the lifetime of the connection.
+        unsafe {
+            xfixes::XFixesShowCursor(self.connection.display(), self.connection.root());
+            xlib::XFlush(self.connection.display());
+        }
+    }
+
+    /// Create an unmapped window in the shape of a crosshair that lets all input through to the windows below.
+    fn create_crosshair(&self) -> xlib::Window {
+        let offset = (CROSSHAIR_SIZE - CROSSHAIR_THICKNESS) / 2;
+        let mut rectangles = [
+            xlib::XRectangle {
+                x: 0,
+                y: offset as i16,
+                width: CROSSHAIR_SIZE as u16,
+                height: CROSSHAIR_THICKNESS as u16,
+            },
+            xlib::XRectangle {
+                x: offset as i16,
+                y: 0,
+                width: CROSSHAIR_THICKNESS as u16,
+                height: CROSSHAIR_SIZE as u16,
+            },
+        ];
+
+        // SAFETY: display and root window are valid for the lifetime of the connection,
+        // all other pointers point to live locals and the regions are destroyed exactly once.
+        unsafe {
+            let mut attributes: xlib::XSetWindowAttributes = mem::zeroed();
+            attributes.override_redirect = xlib::True;
+            attributes.background_pixel = RED as c_ulong;
+            let window = xlib::XCreateWindow(
+                self.connection.display(),
+                self.connection.root(),
+                0,
+                0,
+                CROSSHAIR_SIZE as c_uint,
+                CROSSHAIR_SIZE as c_uint,
+                0,
+                xlib::CopyFromParent,
+                xlib::InputOutput as c_uint,
+                ptr::null_mut(),
+                xlib::CWOverrideRedirect | xlib::CWBackPixel,
+                &mut attributes,
+            );
+
+            let bounding = xfixes::XFixesCreateRegion(
+                self.connection.display(),
+                rectangles.as_mut_ptr(),
+                rectangles.len() as c_int,
+            );
+            xfixes::XFixesSetWindowShapeRegion(
+                self.connection.display(),
+                window,
+                SHAPE_BOUNDING,
+                0,
+                0,
+                bounding,
+            );
+            xfixes::XFixesDestroyRegion(self.connection.display(), bounding);
+            // Without an input shape the crosshair would catch the clicks of the pointer it marks.
+            let input = xfixes::XFixesCreateRegion(self.connection.display(), ptr::null_mut(), 0);
+            xfixes::XFixesSetWindowShapeRegion(
+                self.connection.display(),
+                window,
+                SHAPE_INPUT,
+                0,
+                0,
+                input,
+            );
+            xfixes::XFixesDestroyRegion(self.connection.display(), input);
+            window
+        }
+    }
+
+    /// Center the crosshair on a position in screen space and keep it above other windows.
+    fn move_crosshair(&self, window: xlib::Window, position: Point2D<ScreenSpace>) {
+        let half = CROSSHAIR_SIZE / 2;
+        // SAFETY: the window was created on this connection.
+        unsafe {
+            xlib::XMoveWindow(
+                self.connection.display(),
+                window,
+                position.x.value() - half,
+                position.y.value() - half,
+            );
+            xlib::XMapRaised(self.connection.display(), window);
+            xlib::XFlush(self.connection.display());
+        }
+    }
+
+    /// Current position of the pointer in screen space.
+    fn pointer_position(&self) -> Option<Point2D<ScreenSpace>> {
+        let (mut root, mut child) = (0, 0);
+        let (mut x, mut y, mut win_x, mut win_y) = (0, 0, 0, 0);
+        let mut mask = 0;
+        // SAFETY: all out-pointers point to live locals.
+        let on_screen = unsafe {
+            xlib::XQueryPointer(
+                self.connection.display(),
+                self.connection.root(),
+                &mut root,
+                &mut child,
+                &mut x,
+                &mut y,
+                &mut win_x,
+                &mut win_y,
+                &mut mask,
+            )
+        };
+        (on_screen == xlib::True).then(|| Point2D::from((x, y)))
+    }
+}
+
+/// Hide the cursor on every touch and show it again once the pointer leaves the last touch position.
+fn hide_cursor_loop(display: CursorConnection, receiver: Receiver<Point2D<ScreenSpace>>) {
+    log::trace!("Entering fn hide_cursor_loop");
+
+    let mut last_touch = None;
+    loop {
+        match receiver.recv_timeout(POINTER_CHECK_INTERVAL) {
+            Ok(position) => {
+                if last_touch.is_none() {
+                    log::debug!("Hiding cursor.");
+                    display.hide_cursor();
+                }
+                last_touch = Some(position);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let Some(touch) = last_touch else {
+                    continue;
+                };
+                let moved_elsewhere = display
+                    .pointer_position()
+                    .is_some_and(|pointer| pointer.euclidean_distance_to(&touch) > POINTER_SLOP);
+                if moved_elsewhere {
+                    log::debug!("Pointer moved by another device, showing cursor.");
+                    display.show_cursor();
+                    last_touch = None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    if last_touch.is_some() {
+        display.show_cursor();
+    }
+    log::trace!("Leaving fn hide_cursor_loop");
+}
+
+/// Move the crosshair to every position we are told about.
+fn software_cursor_loop(display: CursorConnection, receiver: Receiver<Point2D<ScreenSpace>>) {
+    log::trace!("Entering fn software_cursor_loop");
+
+    let window = display.create_crosshair();
+    while let Ok(position) = receiver.recv() {
+        // Only the latest position matters if we fell behind.
+        let position = receiver.try_iter().last().unwrap_or(position);
+        display.move_crosshair(window, position);
+    }
+
+    log::trace!("Leaving fn software_cursor_loop");
+}
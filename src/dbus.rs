@@ -0,0 +1,93 @@
+//! A D-Bus service of the running driver, so that desktop applets and the calibration GUI can query and control it
+//! without restarting it. Only available with the `dbus` feature.
+//!
+//! The service `org.egalax.Driver` serves the interface of the same name at `/org/egalax/Driver` with
+//! - `Status()`, which returns the status report as JSON like the `status` command of the control socket,
+//! - `Calibration()`, which returns the calibration that the driver currently uses as JSON,
+//! - `ReloadConfig()`, which reloads the config file like the `reload` command of the control socket, and
+//! - the read-write property `InputEnabled`, which temporarily disables touch input while `false`.
+
+use anyhow::anyhow;
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+
+use crate::control::DriverStatus;
+use crate::error::EgalaxError;
+
+/// Well-known name of the service.
+pub const BUS_NAME: &str = "org.egalax.Driver";
+/// Path of the object that implements the interface.
+pub const OBJECT_PATH: &str = "/org/egalax/Driver";
+
+/// The object served at [OBJECT_PATH].
+#[derive(Debug)]
+struct DriverObject {
+    status: DriverStatus,
+}
+
+#[zbus::interface(name = "org.egalax.Driver")]
+impl DriverObject {
+    /// The status report of the driver as JSON.
+    fn status(&self) -> fdo::Result<String> {
+        serde_json::to_string(&self.status.report()).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// The calibration that the driver currently uses as JSON.
+    fn calibration(&self) -> fdo::Result<String> {
+        let calibration = self
+            .status
+            .calibration()
+            .ok_or_else(|| fdo::Error::Failed(String::from("no calibration loaded yet")))?;
+        serde_json::to_string(&calibration).map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Read the config file again. The new config replaces the current one between touches.
+    fn reload_config(&self) {
+        log::info!("Reload of the config requested over D-Bus.");
+        self.status.request_reload();
+    }
+
+    #[zbus(property)]
+    fn input_enabled(&self) -> bool {
+        self.status.input_enabled()
+    }
+
+    #[zbus(property)]
+    fn set_input_enabled(&mut self, enabled: bool) {
+        log::info!(
+            "Touch input {} over D-Bus.",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        self.status.set_input_enabled(enabled);
+    }
+}
+
+/// The D-Bus service of the driver, which is served on its own thread until this is dropped.
+#[derive(Debug)]
+pub struct DbusService {
+    _connection: Connection,
+}
+
+impl DbusService {
+    /// Claim [BUS_NAME] on the system bus, e.g. when running as a daemon, or on the session bus.
+    pub fn spawn(status: DriverStatus, system_bus: bool) -> Result<Self, EgalaxError> {
+        let builder = if system_bus {
+            connection::Builder::system()
+        } else {
+            connection::Builder::session()
+        };
+        let connection = builder
+            .and_then(|builder| builder.name(BUS_NAME))
+            .and_then(|builder| builder.serve_at(OBJECT_PATH, DriverObject { status }))
+            .and_then(|builder| builder.build())
+            .map_err(|e| EgalaxError::Generic(anyhow!("could not serve '{}': {}", BUS_NAME, e)))?;
+        log::info!(
+            "Serving '{}' on the {} bus",
+            BUS_NAME,
+            if system_bus { "system" } else { "session" }
+        );
+        Ok(Self {
+            _connection: connection,
+        })
+    }
+}
@@ -0,0 +1,389 @@
+//! Listing of the hidraw devices and monitors that the driver could use.
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use xrandr::XHandle;
+
+use crate::error::EgalaxError;
+use crate::geo::AABB;
+use crate::systemd::{self, Notifier};
+use crate::units::{udim, ScreenSpace};
+
+/// Where the kernel exposes hidraw devices in sysfs.
+pub(crate) const SYSFS_HIDRAW: &str = "/sys/class/hidraw";
+
+/// USB vendor ID of eGalax (D-WAV Scientific).
+pub const EGALAX_VENDOR_ID: u16 = 0x0eef;
+
+/// How often to retry opening a device node while waiting for it, in case an inotify event was missed.
+const WAIT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A hidraw device as described by sysfs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HidrawDevice {
+    /// Device node, e.g. `/dev/hidraw3`.
+    pub node: PathBuf,
+    /// Name the device reports for itself.
+    pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// USB serial number, if the device reports one. It tells identical panels apart on any port.
+    #[serde(default)]
+    pub serial: Option<String>,
+    /// Kernel driver bound to the HID device, e.g. `hid-generic`.
+    pub driver: Option<String>,
+}
+
+impl HidrawDevice {
+    /// Checks if the device is made by eGalax.
+    pub fn is_egalax(&self) -> bool {
+        self.vendor_id == EGALAX_VENDOR_ID
+    }
+
+    /// Read the description of a hidraw device from the `uevent` file of its HID device.
+    fn from_uevent(node: PathBuf, uevent: &str) -> Option<Self> {
+        let mut device = HidrawDevice {
+            node,
+            name: String::new(),
+            vendor_id: 0,
+            product_id: 0,
+            serial: None,
+            driver: None,
+        };
+
+        for line in uevent.lines() {
+            match line.split_once('=') {
+                Some(("HID_NAME", name)) => device.name = name.to_string(),
+                // For USB devices the unique ID is the serial number, which is empty if there is none.
+                Some(("HID_UNIQ", serial)) if !serial.is_empty() => {
+                    device.serial = Some(serial.to_string())
+                }
+                Some(("DRIVER", driver)) => device.driver = Some(driver.to_string()),
+                // The ID has the format bus:vendor:product with 32-bit hex numbers.
+                Some(("HID_ID", id)) => {
+                    let mut parts = id.split(':').skip(1);
+                    device.vendor_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+                    device.product_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+                }
+                _ => {}
+            }
+        }
+
+        Some(device)
+    }
+}
+
+/// List all hidraw devices, sorted by their device node.
+pub fn list_hidraw_devices() -> Result<Vec<HidrawDevice>, EgalaxError> {
+    let mut devices = Vec::new();
+    for entry in fs::read_dir(SYSFS_HIDRAW)? {
+        let entry = entry?;
+        let uevent = match fs::read_to_string(entry.path().join("device").join("uevent")) {
+            Ok(uevent) => uevent,
+            Err(e) => {
+                log::warn!("Cannot read uevent of {:?}: {}", entry.file_name(), e);
+                continue;
+            }
+        };
+
+        let node = Path::new("/dev").join(entry.file_name());
+        if let Some(device) = HidrawDevice::from_uevent(node, &uevent) {
+            devices.push(device);
+        }
+    }
+
+    devices.sort_by(|a, b| a.node.cmp(&b.node));
+    Ok(devices)
+}
+
+/// Find the hidraw node of the eGalax touchscreen, so that it does not have to be configured.
+/// Fails if there is no eGalax device or more than one.
+pub fn find_egalax_device() -> Result<HidrawDevice, EgalaxError> {
+    select_egalax_device(list_hidraw_devices()?)
+}
+
+fn select_egalax_device(devices: Vec<HidrawDevice>) -> Result<HidrawDevice, EgalaxError> {
+    let mut candidates: Vec<_> = devices
+        .into_iter()
+        .filter(HidrawDevice::is_egalax)
+        .collect();
+    match candidates.len() {
+        0 => Err(EgalaxError::NoEgalaxDevice),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(EgalaxError::AmbiguousEgalaxDevice(
+            candidates
+                .iter()
+                .map(|device| device.node.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+    }
+}
+
+/// Find the hidraw device behind a device node, which may also be a symlink like `/dev/hidraw.egalax`.
+/// Returns `None` if the node is not a hidraw device, e.g. a pipe during testing.
+pub fn identify_device(node_path: &Path) -> Option<HidrawDevice> {
+    let node = fs::canonicalize(node_path).ok()?;
+    list_hidraw_devices()
+        .ok()?
+        .into_iter()
+        .find(|device| device.node == node)
+}
+
+/// A monitor as reported by xrandr.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub is_primary: bool,
+    /// Area of the monitor in screen space.
+    pub area: AABB<ScreenSpace>,
+}
+
+/// List all monitors that xrandr knows about.
+/// In a Wayland session the compositor is asked instead if the `wayland` feature is enabled.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, EgalaxError> {
+    #[cfg(feature = "wayland")]
+    if crate::wayland::is_wayland_session() {
+        return crate::wayland::list_monitors();
+    }
+
+    let monitors = XHandle::open()?.monitors()?;
+    Ok(monitors
+        .iter()
+        .map(|monitor| MonitorInfo {
+            name: monitor.name.clone(),
+            is_primary: monitor.is_primary,
+            area: AABB::new_wh(
+                udim::new(monitor.x),
+                udim::new(monitor.y),
+                udim::new(monitor.width_px),
+                udim::new(monitor.height_px),
+            ),
+        })
+        .collect())
+}
+
+/// Open a device node, waiting until it appears if it does not exist yet.
+///
+/// udev creates the node (or the symlink to it) once the USB device was enumerated, which at boot can happen after the driver started.
+/// We watch the directory of the node with inotify and also retry periodically. Missing permissions are retried as well,
+/// since udev may create the node before it applies the permission rules.
+/// Returns `None` if the driver should stop instead, see [wait_until_open].
+pub fn wait_for_device(
+    node_path: &Path,
+    notifier: Option<&mut Notifier>,
+) -> Result<Option<File>, EgalaxError> {
+    log::trace!("Entering fn wait_for_device");
+
+    let dir = match node_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let device_node = wait_until_open(dir, &node_path.display().to_string(), notifier, || {
+        Ok(OpenOptions::new().read(true).open(node_path)?)
+    })?;
+    if device_node.is_some() {
+        log::info!("Device node '{}' appeared", node_path.display());
+    }
+
+    log::trace!("Leaving fn wait_for_device");
+    Ok(device_node)
+}
+
+/// Wait until a device with the same vendor and product ID, and serial number if it has one, is plugged in again
+/// after `device` was unplugged, and open its node. The kernel may assign a different hidraw node after replugging, so the node is looked up in sysfs.
+/// Returns `None` if the driver should stop instead, see [wait_until_open].
+pub fn wait_for_replug(
+    device: &HidrawDevice,
+    notifier: Option<&mut Notifier>,
+) -> Result<Option<(File, PathBuf)>, EgalaxError> {
+    log::trace!("Entering fn wait_for_replug");
+
+    let description = format!("device {:04x}:{:04x}", device.vendor_id, device.product_id);
+    let replugged = wait_until_open(Path::new("/dev"), &description, notifier, || {
+        let node = list_hidraw_devices()?
+            .into_iter()
+            .find(|d| {
+                (d.vendor_id, d.product_id) == (device.vendor_id, device.product_id)
+                    && (device.serial.is_none() || d.serial == device.serial)
+            })
+            .map(|d| d.node)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        let device_node = OpenOptions::new().read(true).open(&node)?;
+        Ok((device_node, node))
+    })?;
+    if let Some((_, node)) = &replugged {
+        log::info!(
+            "Device {} was plugged in again as '{}'",
+            description,
+            node.display()
+        );
+    }
+
+    log::trace!("Leaving fn wait_for_replug");
+    Ok(replugged)
+}
+
+/// Call `open` until it succeeds, retrying whenever something changes in `dir` and periodically.
+/// Missing nodes and permissions are retried as they are expected while udev sets up a device.
+/// The watchdog of `notifier` is pinged while waiting, since waiting for a device is not a hang.
+/// Returns `None` once [systemd::handle_termination] caught a signal, so that the driver can stop as usual.
+fn wait_until_open<T>(
+    dir: &Path,
+    description: &str,
+    mut notifier: Option<&mut Notifier>,
+    mut open: impl FnMut() -> Result<T, EgalaxError>,
+) -> Result<Option<T>, EgalaxError> {
+    let inotify =
+        Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).map_err(io::Error::from)?;
+    inotify
+        .add_watch(
+            dir,
+            AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_ATTRIB,
+        )
+        .map_err(io::Error::from)?;
+
+    // Wake up often enough to ping the watchdog.
+    let timeout = notifier
+        .as_ref()
+        .and_then(|notifier| notifier.watchdog_interval())
+        .map_or(WAIT_RETRY_INTERVAL, |interval| {
+            interval.min(WAIT_RETRY_INTERVAL)
+        });
+    let mut logged = false;
+    loop {
+        if systemd::termination_requested() {
+            log::info!(
+                "Stopping to wait for '{}' after a termination signal.",
+                description
+            );
+            return Ok(None);
+        }
+        if let Some(notifier) = notifier.as_deref_mut() {
+            notifier.ping_watchdog();
+        }
+
+        match open() {
+            Ok(opened) => return Ok(Some(opened)),
+            Err(EgalaxError::IO(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                if !logged {
+                    log::info!("Waiting for '{}' to appear: {}", description, e);
+                    logged = true;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+
+        let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+        // We only need to know that something changed, so drain the events.
+        match inotify.read_events() {
+            Ok(_) | Err(Errno::EAGAIN) => {}
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::{env, process};
+
+    #[test]
+    fn test_parse_uevent() {
+        let uevent = "DRIVER=hid-generic\n\
+            HID_ID=0003:00000EEF:00000001\n\
+            HID_NAME=eGalax Inc. USB TouchController\n\
+            HID_PHYS=usb-0000:00:14.0-1/input0\n\
+            HID_UNIQ=A1B2C3\n";
+        let device = HidrawDevice::from_uevent(PathBuf::from("/dev/hidraw3"), uevent).unwrap();
+
+        assert!(device.is_egalax());
+        assert_eq!(0x0001, device.product_id);
+        assert_eq!("eGalax Inc. USB TouchController", device.name);
+        assert_eq!(Some("hid-generic".to_string()), device.driver);
+        assert_eq!(Some("A1B2C3".to_string()), device.serial);
+    }
+
+    #[test]
+    fn test_select_egalax_device() {
+        let device = |node: &str, vendor_id| HidrawDevice {
+            node: PathBuf::from(node),
+            name: String::new(),
+            vendor_id,
+            product_id: 0x0001,
+            serial: None,
+            driver: None,
+        };
+        let keyboard = device("/dev/hidraw0", 0x046d);
+        let touchscreen = device("/dev/hidraw1", EGALAX_VENDOR_ID);
+
+        assert!(matches!(
+            select_egalax_device(vec![keyboard.clone()]),
+            Err(EgalaxError::NoEgalaxDevice)
+        ));
+        assert_eq!(
+            touchscreen,
+            select_egalax_device(vec![keyboard, touchscreen.clone()]).unwrap()
+        );
+        let error = select_egalax_device(vec![
+            touchscreen.clone(),
+            device("/dev/hidraw2", EGALAX_VENDOR_ID),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            "Multiple eGalax touchscreens found (/dev/hidraw1, /dev/hidraw2), pass one of their hidraw device nodes explicitly",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_wait_pings_watchdog() {
+        let path = env::temp_dir().join(format!("egalax-rs-wait-{}.sock", process::id()));
+        fs::remove_file(&path).ok();
+        let manager = UnixDatagram::bind(&path).unwrap();
+        manager.set_nonblocking(true).unwrap();
+        let mut notifier = Notifier::new(
+            SocketAddr::from_pathname(&path).unwrap(),
+            Some(Duration::ZERO),
+        )
+        .unwrap();
+
+        // The node appears on the third attempt, and the watchdog is pinged before each of them.
+        let mut attempts = 0;
+        let opened = wait_until_open(&env::temp_dir(), "device", Some(&mut notifier), || {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(io::Error::from(io::ErrorKind::NotFound).into());
+            }
+            Ok(attempts)
+        })
+        .unwrap();
+        assert_eq!(Some(3), opened);
+
+        let mut buf = [0; 64];
+        let mut pings = 0;
+        while let Ok(len) = manager.recv(&mut buf) {
+            assert_eq!(b"WATCHDOG=1", &buf[..len]);
+            pings += 1;
+        }
+        fs::remove_file(&path).ok();
+        assert_eq!(3, pings);
+    }
+}
@@ -0,0 +1,253 @@
+//! Diagnosis of common setup problems, used by the `doctor` subcommand and at driver startup.
+//!
+//! The most common problem is duplicate input: the kernel's HID driver usually also creates an input device for the
+//! touchscreen, and if nothing grabs it every touch reaches X twice, once from the kernel and once from us.
+
+use nix::errno::Errno;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fmt, io};
+
+use crate::config::ConfigFile;
+use crate::devices::SYSFS_HIDRAW;
+use crate::error::EgalaxError;
+use crate::lint;
+
+nix::ioctl_write_int!(eviocgrab, b'E', 0x90);
+
+/// An input device that the kernel created for the same HID device as our hidraw node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelInputDevice {
+    /// Name of the input device as reported by the kernel.
+    pub name: String,
+    /// The evdev node of the input device, e.g. `/dev/input/event5`.
+    pub event_node: PathBuf,
+    /// If some other program grabbed the device, or `None` if we lack the permissions to find out.
+    pub grabbed: Option<bool>,
+}
+
+impl fmt::Display for KernelInputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let grabbed = match self.grabbed {
+            Some(true) => "grabbed",
+            Some(false) => "not grabbed",
+            None => "unknown if grabbed",
+        };
+        f.write_fmt(format_args!(
+            "'{}' at {} ({})",
+            self.name,
+            self.event_node.display(),
+            grabbed
+        ))
+    }
+}
+
+/// Find the kernel input devices that belong to the same HID device as the given hidraw node.
+pub fn kernel_input_devices(hidraw_node: &Path) -> Result<Vec<KernelInputDevice>, EgalaxError> {
+    // The node is often a symlink created by udev, e.g. /dev/hidraw.egalax.
+    let hidraw_node = fs::canonicalize(hidraw_node)?;
+    let hidraw_name = hidraw_node
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a device node"))?;
+    let hid_device = Path::new(SYSFS_HIDRAW).join(hidraw_name).join("device");
+
+    let mut devices = Vec::new();
+    let inputs = match fs::read_dir(hid_device.join("input")) {
+        Ok(inputs) => inputs,
+        // No kernel driver created input devices.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(devices),
+        Err(e) => return Err(e.into()),
+    };
+
+    for input in inputs {
+        let input = input?.path();
+        let name = fs::read_to_string(input.join("name"))
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+
+        for handler in fs::read_dir(&input)? {
+            let handler = handler?.file_name();
+            let handler = handler.to_string_lossy();
+            if handler.starts_with("event") {
+                let event_node = Path::new("/dev/input").join(handler.as_ref());
+                devices.push(KernelInputDevice {
+                    name: name.clone(),
+                    grabbed: is_grabbed(&event_node),
+                    event_node,
+                });
+            }
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Check if an evdev device is grabbed by trying to grab it ourselves.
+fn is_grabbed(event_node: &Path) -> Option<bool> {
+    let device = OpenOptions::new().read(true).open(event_node).ok()?;
+    // SAFETY: EVIOCGRAB takes an integer argument and the fd is valid while `device` lives.
+    match unsafe { eviocgrab(device.as_raw_fd(), 1) } {
+        Ok(_) => {
+            // SAFETY: as above.
+            unsafe { eviocgrab(device.as_raw_fd(), 0) }.ok();
+            Some(false)
+        }
+        Err(Errno::EBUSY) => Some(true),
+        Err(_) => None,
+    }
+}
+
+/// Grab the kernel input devices of the touchscreen so that their events do not reach other programs.
+/// The grab lasts as long as the returned files are open.
+pub fn grab_kernel_input(hidraw_node: &Path) -> Result<Vec<File>, EgalaxError> {
+    let mut grabs = Vec::new();
+    for device in kernel_input_devices(hidraw_node)? {
+        let file = OpenOptions::new().read(true).open(&device.event_node)?;
+        // SAFETY: EVIOCGRAB takes an integer argument and the fd is valid while `file` lives.
+        unsafe { eviocgrab(file.as_raw_fd(), 1) }.map_err(io::Error::from)?;
+        log::info!("Grabbed kernel input device {}", device);
+        grabs.push(file);
+    }
+    Ok(grabs)
+}
+
+/// How serious a finding is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Result of a single check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    pub(crate) fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("[{}] {}", self.severity, self.message))
+    }
+}
+
+/// Warn about kernel input devices that would duplicate our input.
+pub fn check_duplicate_input(hidraw_node: &Path, grab_enabled: bool) -> Vec<Finding> {
+    let devices = match kernel_input_devices(hidraw_node) {
+        Ok(devices) => devices,
+        Err(e) => {
+            return vec![Finding::new(
+                Severity::Warning,
+                format!("Could not look up kernel input devices: {}", e),
+            )]
+        }
+    };
+
+    if devices.is_empty() {
+        return vec![Finding::new(
+            Severity::Ok,
+            "No kernel input device duplicates the touchscreen.",
+        )];
+    }
+
+    devices
+        .into_iter()
+        .map(|device| match device.grabbed {
+            Some(true) => Finding::new(
+                Severity::Ok,
+                format!("Kernel input device {} does not reach X.", device),
+            ),
+            _ if grab_enabled => Finding::new(
+                Severity::Ok,
+                format!(
+                    "Kernel input device {} will be grabbed by the driver.",
+                    device
+                ),
+            ),
+            _ => Finding::new(
+                Severity::Warning,
+                format!(
+                    "Kernel input device {} is active, touches will be reported twice. \
+                    Set `grab_kernel_input = true` in the config to let the driver grab it.",
+                    device
+                ),
+            ),
+        })
+        .collect()
+}
+
+/// Run all checks for a device node and config file.
+pub fn diagnose(hidraw_node: &Path, config_path: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    match OpenOptions::new().read(true).open(hidraw_node) {
+        Ok(_) => findings.push(Finding::new(
+            Severity::Ok,
+            format!("Device node {} is readable.", hidraw_node.display()),
+        )),
+        Err(e) => findings.push(Finding::new(
+            Severity::Error,
+            format!("Cannot open device node {}: {}", hidraw_node.display(), e),
+        )),
+    }
+
+    let config_file = match ConfigFile::from_file(config_path) {
+        Ok(config_file) => {
+            findings.push(Finding::new(
+                Severity::Ok,
+                format!("Config file {} is valid.", config_path.display()),
+            ));
+            Some(config_file)
+        }
+        Err(e) => {
+            findings.push(Finding::new(
+                Severity::Error,
+                format!("Cannot load config file {}: {}", config_path.display(), e),
+            ));
+            None
+        }
+    };
+
+    if let Some(config_file) = &config_file {
+        findings.extend(lint::lint_config_file(config_file.clone()));
+    }
+
+    if config_file
+        .as_ref()
+        .is_some_and(|config_file| config_file.recalibration_due(SystemTime::now()))
+    {
+        findings.push(Finding::new(
+            Severity::Warning,
+            "The calibration is older than `calibration_max_age`. Run `calibrate` to recalibrate the touchscreen.",
+        ));
+    }
+
+    let grab_enabled = config_file.is_some_and(|config_file| config_file.grab_kernel_input());
+    findings.extend(check_duplicate_input(hidraw_node, grab_enabled));
+    findings
+}
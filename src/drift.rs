@@ -0,0 +1,161 @@
+//! Estimation of calibration drift from how people use the touchscreen.
+//!
+//! When the calibration drifts, touches land slightly off from where the user aimed, so they quickly touch again
+//! a little further away. The [DriftEstimator] treats such micro-corrections as samples of the drift
+//! and averages them. The result is only a soft signal for maintenance, since corrections also happen for other reasons.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::geo::Point2D;
+use crate::units::ScreenSpace;
+
+/// Weight of a new correction in the running average.
+const SMOOTHING: f32 = 0.1;
+
+/// Parameters that decide which pairs of touches count as a correction.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DriftEstimation {
+    /// The second touch must start within this time after the first one ended.
+    pub window: Duration,
+    /// Touches that are closer than this in pixels are a double tap and not a correction.
+    pub min_correction: f32,
+    /// Touches that are further apart than this in pixels aimed at different things.
+    pub max_correction: f32,
+}
+
+impl fmt::Display for DriftEstimation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "corrections of {}px to {}px within {}ms",
+            self.min_correction,
+            self.max_correction,
+            self.window.as_millis()
+        ))
+    }
+}
+
+/// Estimated offset between where touches land and where the user meant to touch, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DriftEstimate {
+    pub x: f32,
+    pub y: f32,
+    /// Number of corrections the estimate is based on.
+    pub corrections: u32,
+}
+
+impl DriftEstimate {
+    /// Length of the estimated offset in pixels.
+    pub fn magnitude(&self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+}
+
+impl fmt::Display for DriftEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "({:.1}, {:.1})px from {} corrections",
+            self.x, self.y, self.corrections
+        ))
+    }
+}
+
+/// Watches the start and end of touches for micro-corrections.
+#[derive(Debug, Clone)]
+pub struct DriftEstimator {
+    params: DriftEstimation,
+    /// Where the current touch landed.
+    current: Option<Point2D<ScreenSpace>>,
+    /// Where the previous touch landed and when it ended.
+    previous: Option<(Point2D<ScreenSpace>, Instant)>,
+    estimate: Option<DriftEstimate>,
+}
+
+impl DriftEstimator {
+    pub fn new(params: DriftEstimation) -> Self {
+        Self {
+            params,
+            current: None,
+            previous: None,
+            estimate: None,
+        }
+    }
+
+    /// Record where a touch landed. Returns true if it was counted as a correction of the previous touch.
+    pub fn touch_started(&mut self, now: Instant, position: Point2D<ScreenSpace>) -> bool {
+        self.current = Some(position);
+        let Some((previous, ended)) = self.previous.take() else {
+            return false;
+        };
+
+        let distance = position.euclidean_distance_to(&previous);
+        if now.saturating_duration_since(ended) > self.params.window
+            || distance < self.params.min_correction
+            || distance > self.params.max_correction
+        {
+            return false;
+        }
+
+        // The drift moved the first touch away from the target, so it points from the correction to the first touch.
+        let offset = previous - position;
+        let (x, y) = (offset.x.float(), offset.y.float());
+        self.estimate = Some(match self.estimate {
+            None => DriftEstimate {
+                x,
+                y,
+                corrections: 1,
+            },
+            Some(estimate) => DriftEstimate {
+                x: estimate.x + SMOOTHING * (x - estimate.x),
+                y: estimate.y + SMOOTHING * (y - estimate.y),
+                corrections: estimate.corrections + 1,
+            },
+        });
+        log::debug!(
+            "Touch corrected by {}, drift estimate {:?}",
+            offset,
+            self.estimate
+        );
+        true
+    }
+
+    /// Record that the current touch ended.
+    pub fn touch_ended(&mut self, now: Instant) {
+        self.previous = self.current.take().map(|position| (position, now));
+    }
+
+    pub fn estimate(&self) -> Option<DriftEstimate> {
+        self.estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drift_estimator() {
+        let mut estimator = DriftEstimator::new(DriftEstimation {
+            window: Duration::from_secs(1),
+            min_correction: 5.0,
+            max_correction: 50.0,
+        });
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        // A double tap, a correction, and a touch on something else.
+        for (time, position) in [(0, (100, 100)), (200, (102, 100)), (400, (112, 94))] {
+            estimator.touch_started(ms(time), position.into());
+            estimator.touch_ended(ms(time + 100));
+        }
+        assert!(!estimator.touch_started(ms(600), (300, 300).into()));
+        // Too late for a correction.
+        estimator.touch_ended(ms(700));
+        assert!(!estimator.touch_started(ms(2000), (310, 300).into()));
+
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(1, estimate.corrections);
+        assert_eq!((-10.0, 6.0), (estimate.x, estimate.y));
+    }
+}
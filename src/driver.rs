@@ -1,65 +1,266 @@
-use evdev_rs::enums::{BusType, EventCode, EventType, InputProp, EV_ABS, EV_KEY, EV_SYN};
+use evdev_rs::enums::{BusType, EventCode, EventType, InputProp, EV_ABS, EV_KEY, EV_REL, EV_SYN};
 use evdev_rs::{
     AbsInfo, DeviceWrapper, EnableCodeData, InputEvent, TimeVal, UInputDevice, UninitDevice,
 };
+use nix::errno::Errno;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::time::{clock_gettime, ClockId};
+use nix::unistd::Pid;
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::{Duration, Instant, SystemTime};
-use std::{io, thread};
+use std::{io, mem, process, thread};
 
-use crate::config::Config;
+#[cfg(feature = "mqtt")]
+use crate::analytics::AnalyticsPublisher;
+use crate::calibration::Calibration;
+use crate::clock::Clock;
+use crate::config::{
+    ClickAction, Config, ConfigFile, ConfigWatch, DoubleTapAction, MaintenanceAction, OutputMode,
+    SecondFinger,
+};
+#[cfg(feature = "debug")]
+use crate::control::DebugRequest;
+use crate::control::{DriverEvent, DriverStatus, Gesture};
+use crate::cursor::{CursorHider, SoftwareCursor};
+use crate::devices::{self, HidrawDevice};
+use crate::doctor::{self, Severity};
+use crate::drift::DriftEstimator;
+use crate::dump::DumpReader;
 use crate::error::EgalaxError;
+#[cfg(feature = "audio")]
+use crate::feedback::FeedbackPlayer;
 use crate::geo::Point2D;
-use crate::protocol::{PacketTag, RawPacket, TouchState, USBMessage, USBPacket, RAW_PACKET_LEN};
+use crate::kinematics::Kinematics;
+use crate::noise::{AdaptiveThresholds, NoiseEstimator};
+use crate::overlay;
+use crate::palm::{PalmDetector, PalmVerdict};
+use crate::pinch::{PinchAction, Zoom};
+use crate::protocol::{
+    monotonic_timeval, Packet, PacketDecoder, PacketFormat, PacketTag, TouchState, USBMessage,
+    MAX_PRESSURE,
+};
+use crate::rotation::{Rotation, RotationWatch};
+use crate::scroll::{ScrollDelta, WHEEL_HI_RES_PER_CLICK};
+use crate::serial::{self, SerialPort};
+use crate::smoothing::SmoothingFilter;
+use crate::systemd::{self, Notifier};
+use crate::units::{udim, ScreenSpace, TouchSpace};
+use crate::usage::UsageTracker;
+use crate::window::WindowWatch;
 
-/// Touchstate of the driver that also keeps track of when & where the touch started.
-#[derive(Debug, Clone, Copy)]
-enum DriverTouchState {
-    IsTouching {
-        /// The start time of the current touch.
-        touch_start_time: Instant,
-        /// The initial touch point.
-        touch_origin: Point2D,
-    },
-    NotTouching,
-}
+use gestures::{GestureAction, GestureContext, GestureRegistry, TouchFrame, TouchPhase};
+
+#[cfg(feature = "debug")]
+mod debug;
+mod gestures;
+
+/// How long to wait for packets before checking whether the system was suspended.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// If the boot clock advanced this much more than the monotonic clock, we assume the system was suspended.
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Number of multi-touch slots of the virtual touchscreen, one for each contact id a packet can carry.
+const MT_SLOTS: usize = 16;
+
+/// Tracking ids are assigned from 0 up to this value and then wrap around.
+const MAX_TRACKING_ID: i32 = 0xffff;
+
+/// Name of the active profile reported to the control interface when the common calibration is used.
+const DEFAULT_PROFILE: &str = "default";
+
+/// How often and how long to wait between attempts to reopen the device node after a resume.
+const REOPEN_ATTEMPTS: u32 = 10;
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
 
 /// Internal state of the driver.
 #[derive(Debug)]
 struct DriverState {
     /// If someone is pressing on the touchscreen.
-    touch_state: DriverTouchState,
-    /// If we are emitting a right-click.
-    is_right_click: bool,
+    touch_state: TouchState,
+    /// Motion of the current touch in touchscreen coordinates.
+    kinematics: Kinematics<TouchSpace>,
+    /// If a click binding already fired while holding, so that nothing fires on release.
+    hold_fired: bool,
     /// If true, finger has moved too much so we don't emit a right-click.
     has_moved: bool,
-}
-
-impl DriverState {
-    pub fn touch_state(&self) -> DriverTouchState {
-        self.touch_state
-    }
+    /// If a second finger cancelled the hold bindings of this touch.
+    hold_cancelled: bool,
+    /// If the left button is held down for a drag.
+    is_dragging: bool,
+    /// If a second finger scrolled during this touch, so that nothing fires on release.
+    has_scrolled: bool,
+    /// If two fingers zoomed during this touch, so that they no longer scroll and nothing fires on release.
+    has_pinched: bool,
+    /// If the finger swiped in from an edge, so that nothing fires on release.
+    has_swiped: bool,
 }
 
 impl Default for DriverState {
     fn default() -> Self {
         DriverState {
-            touch_state: DriverTouchState::NotTouching,
-            is_right_click: false,
+            touch_state: TouchState::NotTouching,
+            kinematics: Kinematics::default(),
+            hold_fired: false,
             has_moved: false,
+            hold_cancelled: false,
+            is_dragging: false,
+            has_scrolled: false,
+            has_pinched: false,
+            has_swiped: false,
         }
     }
 }
 
+/// Detects system suspend by comparing the monotonic clock, which stops during suspend, with the boot clock, which does not.
+#[derive(Debug)]
+struct SuspendWatch {
+    monotonic: Instant,
+    boottime: Duration,
+}
+
+impl SuspendWatch {
+    fn new() -> Result<Self, EgalaxError> {
+        Ok(Self {
+            monotonic: Instant::now(),
+            boottime: boottime()?,
+        })
+    }
+
+    /// Returns true if the system was suspended since the last check.
+    fn check(&mut self) -> Result<bool, EgalaxError> {
+        let monotonic = Instant::now();
+        let boottime = boottime()?;
+
+        let awake = monotonic.duration_since(self.monotonic);
+        let elapsed = boottime.saturating_sub(self.boottime);
+        self.monotonic = monotonic;
+        self.boottime = boottime;
+
+        let suspended = elapsed.saturating_sub(awake);
+        if suspended > SUSPEND_THRESHOLD {
+            log::info!("System was suspended for {:?}.", suspended);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Time since boot including time spent in suspend.
+fn boottime() -> Result<Duration, EgalaxError> {
+    let time = clock_gettime(ClockId::CLOCK_BOOTTIME).map_err(io::Error::from)?;
+    Ok(Duration::from(time))
+}
+
+/// Why the driver stopped reading from a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamEnd {
+    /// The stream has ended.
+    Closed,
+    /// The system resumed from suspend so the stream is probably stale.
+    Resumed,
+    /// The monitor was rotated so the config must be rebuilt.
+    Rotated(Rotation),
+    /// The device was unplugged.
+    Unplugged,
+    /// The config file was changed or a reload was requested with SIGHUP.
+    /// If the config file could be reloaded, the new config is pending in the driver.
+    ConfigChanged,
+}
+
+/// The result of reading from the device in [read_message].
+#[derive(Debug)]
+enum ReadOutcome {
+    Message(USBMessage),
+    /// There was nothing to read after all, e.g. EAGAIN after a spurious wakeup, so we should poll again.
+    Retry,
+    End(StreamEnd),
+}
+
+/// Read the next message from the device and sort out errors that are not fatal.
+/// Interrupted reads are retried by the decoder. If the device is gone we report that it was unplugged,
+/// so that it is reconnected. hidraw reports this with EIO, some kernels use ENODEV, ENXIO or ESHUTDOWN during a replug.
+fn read_message<R: io::Read>(decoder: &mut PacketDecoder<R>) -> Result<ReadOutcome, EgalaxError> {
+    let error = match decoder.next_message() {
+        Ok(Some(message)) => return Ok(ReadOutcome::Message(message)),
+        Ok(None) => return Ok(ReadOutcome::End(StreamEnd::Closed)),
+        Err(error) => error,
+    };
+    let EgalaxError::IO(e) = &error else {
+        return Err(error);
+    };
+    match (e.kind(), e.raw_os_error()) {
+        (io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted, _) => {
+            log::debug!("Nothing to read: {}", e);
+            Ok(ReadOutcome::Retry)
+        }
+        (_, Some(libc::EIO | libc::ENODEV | libc::ENXIO | libc::ESHUTDOWN)) => {
+            log::warn!("Could not read from the device: {}", e);
+            Ok(ReadOutcome::End(StreamEnd::Unplugged))
+        }
+        _ => Err(error),
+    }
+}
+
+/// Predicts monitor positions between packets by extrapolating the velocity between the last two packets.
+#[derive(Debug, Default)]
+struct Predictor {
+    /// Motion of the current touch in screen space.
+    motion: Kinematics<ScreenSpace>,
+}
+
+impl Predictor {
+    /// Record the monitor position of a packet.
+    fn observe(&mut self, time: Instant, position: Point2D<ScreenSpace>) {
+        self.motion.update(time, position);
+    }
+
+    /// Forget all observations, e.g. when the finger is lifted.
+    fn reset(&mut self) {
+        self.motion.reset();
+    }
+
+    /// If we are allowed to predict a position at the given time.
+    fn can_predict(&self, now: Instant, max_extrapolation: Duration) -> bool {
+        self.motion.samples() >= 2
+            && self
+                .motion
+                .last_time()
+                .is_some_and(|time| now.duration_since(time) <= max_extrapolation)
+    }
+
+    /// Extrapolate the position at the given time.
+    fn predict(&self, now: Instant, max_extrapolation: Duration) -> Option<Point2D<ScreenSpace>> {
+        if !self.can_predict(now, max_extrapolation) {
+            return None;
+        }
+        let position = self.motion.position()?;
+        let velocity = self.motion.velocity();
+
+        let dt = now.duration_since(self.motion.last_time()?).as_secs_f32();
+        Some(Point2D {
+            x: position.x + udim::round_from(velocity.x * dt),
+            y: position.y + udim::round_from(velocity.y * dt),
+        })
+    }
+}
+
 struct EventGen {
     time: TimeVal,
     events: Vec<InputEvent>,
 }
 
 impl EventGen {
-    fn new(time: TimeVal) -> Self {
-        Self {
-            time,
-            events: Vec::new(),
-        }
+    /// Generate events into `events`, which should be empty.
+    /// Reusing the buffer of the last packet avoids allocating for every packet.
+    fn new(time: TimeVal, events: Vec<InputEvent>) -> Self {
+        Self { time, events }
     }
 
     fn add_btn_click(&mut self, btn: EV_KEY) {
@@ -78,34 +279,62 @@ impl EventGen {
             .push(InputEvent::new(&self.time, &EventCode::EV_KEY(btn), 0));
     }
 
-    fn add_move_position(&mut self, position: Point2D, monitor_cfg: &Config) {
-        let x_scale = monitor_cfg
-            .calibration_points()
-            .xrange()
-            .linear_factor(position.x);
-        let x_monitor = monitor_cfg.monitor_area.xrange().lerp(x_scale);
-
-        let y_scale = monitor_cfg
-            .calibration_points()
-            .yrange()
-            .linear_factor(position.y);
-        let y_monitor = monitor_cfg.monitor_area.yrange().lerp(y_scale);
-
-        log::info!("Moving to x {}", x_monitor.value());
-        log::info!("Moving to y {}", y_monitor.value());
+    /// Move to a position in screen space.
+    fn add_monitor_position(&mut self, monitor_position: Point2D<ScreenSpace>) {
+        log::trace!("Moving to {}", monitor_position);
 
         self.events.push(InputEvent::new(
             &self.time,
             &EventCode::EV_ABS(EV_ABS::ABS_X),
-            x_monitor.value(),
+            monitor_position.x.value(),
         ));
         self.events.push(InputEvent::new(
             &self.time,
             &EventCode::EV_ABS(EV_ABS::ABS_Y),
-            y_monitor.value(),
+            monitor_position.y.value(),
         ));
     }
 
+    /// Turn the wheels, with the high-resolution events first like the kernel does for real mice.
+    fn add_scroll(&mut self, delta: ScrollDelta) {
+        let codes = [
+            (EV_REL::REL_WHEEL_HI_RES, EV_REL::REL_WHEEL),
+            (EV_REL::REL_HWHEEL_HI_RES, EV_REL::REL_HWHEEL),
+        ];
+        for (axis, (hi_res_code, click_code)) in codes.into_iter().enumerate() {
+            if delta.hi_res[axis] != 0 {
+                self.events.push(InputEvent::new(
+                    &self.time,
+                    &EventCode::EV_REL(hi_res_code),
+                    delta.hi_res[axis],
+                ));
+            }
+            if delta.clicks[axis] != 0 {
+                self.events.push(InputEvent::new(
+                    &self.time,
+                    &EventCode::EV_REL(click_code),
+                    delta.clicks[axis],
+                ));
+            }
+        }
+    }
+
+    /// Press keys together and release them in reverse order.
+    fn add_key_combination(&mut self, keys: &[EV_KEY]) {
+        for key in keys {
+            self.add_btn_press(*key);
+        }
+        self.add_syn();
+        for key in keys.iter().rev() {
+            self.add_btn_release(*key);
+        }
+    }
+
+    fn add_abs(&mut self, code: EV_ABS, value: i32) {
+        self.events
+            .push(InputEvent::new(&self.time, &EventCode::EV_ABS(code), value));
+    }
+
     fn add_syn(&mut self) {
         self.events.push(InputEvent::new(
             &self.time,
@@ -120,85 +349,712 @@ impl EventGen {
     }
 }
 
+/// The fingers in the multi-touch slots of the virtual touchscreen, see the kernel's multi-touch protocol type B.
+#[derive(Debug, Default)]
+struct Slots {
+    /// Tracking id of the finger in each slot, or `None` if the slot is free.
+    tracking_ids: [Option<i32>; MT_SLOTS],
+    next_tracking_id: i32,
+}
+
+impl Slots {
+    /// Generate the events for a contact: a new tracking id when it lands, its position while it is down,
+    /// and a tracking id of -1 when it is lifted.
+    fn update(
+        &mut self,
+        events: &mut EventGen,
+        contact: u8,
+        touch_state: TouchState,
+        position: Point2D<ScreenSpace>,
+    ) {
+        let Some(tracking_id) = self.tracking_ids.get_mut(usize::from(contact)) else {
+            return;
+        };
+        match touch_state {
+            TouchState::IsTouching => {
+                events.add_abs(EV_ABS::ABS_MT_SLOT, i32::from(contact));
+                if tracking_id.is_none() {
+                    let id = self.next_tracking_id;
+                    self.next_tracking_id = (id + 1) % (MAX_TRACKING_ID + 1);
+                    *tracking_id = Some(id);
+                    events.add_abs(EV_ABS::ABS_MT_TRACKING_ID, id);
+                }
+                events.add_abs(EV_ABS::ABS_MT_POSITION_X, position.x.value());
+                events.add_abs(EV_ABS::ABS_MT_POSITION_Y, position.y.value());
+            }
+            TouchState::NotTouching => {
+                if tracking_id.take().is_some() {
+                    events.add_abs(EV_ABS::ABS_MT_SLOT, i32::from(contact));
+                    events.add_abs(EV_ABS::ABS_MT_TRACKING_ID, -1);
+                }
+            }
+        }
+    }
+
+    /// Lift all fingers, e.g. after a resume.
+    fn release_all(&mut self, events: &mut EventGen) {
+        for (slot, tracking_id) in self.tracking_ids.iter_mut().enumerate() {
+            if tracking_id.take().is_some() {
+                events.add_abs(EV_ABS::ABS_MT_SLOT, slot as i32);
+                events.add_abs(EV_ABS::ABS_MT_TRACKING_ID, -1);
+            }
+        }
+    }
+}
+
+/// Report the calibration of a config to the control interface and warn if it is too old.
+fn publish_calibration(monitor_cfg: &Config, status: &DriverStatus) {
+    status.set_calibration(monitor_cfg.calibration());
+    status.set_calibration_age(
+        monitor_cfg.calibrated_at(),
+        monitor_cfg.calibration_max_age(),
+    );
+    if monitor_cfg.recalibration_due(SystemTime::now()) {
+        log::warn!("The calibration is older than the configured maximum age. Please recalibrate the touchscreen.");
+    }
+}
+
 /// Driver contains its current state and config used for processing touchscreen packets.
 #[derive(Debug)]
 struct Driver {
     state: DriverState,
     config: Config,
+    /// Channel to the startup splash screen while it is shown.
+    splash: Option<Sender<Point2D<ScreenSpace>>>,
+    /// Predicts positions between packets if interpolation is enabled.
+    predictor: Predictor,
+    /// Statistics for the control interface.
+    status: DriverStatus,
+    /// Hides the cursor during touches if enabled.
+    cursor: Option<CursorHider>,
+    /// Draws a crosshair at the pointer position if enabled.
+    software_cursor: Option<SoftwareCursor>,
+    /// Estimates calibration drift if enabled.
+    drift: Option<DriftEstimator>,
+    /// Raises thresholds while touches are noisy if enabled.
+    noise: Option<NoiseEstimator>,
+    /// Smooths the positions of the first finger if enabled.
+    smoothing: Option<SmoothingFilter>,
+    /// Rejects touches of palms if enabled.
+    palm: Option<PalmDetector>,
+    /// Recognizes the gestures that the config enables.
+    gestures: GestureRegistry,
+    /// Counts touches per day and zone if enabled.
+    usage: Option<UsageTracker>,
+    /// If the left button is latched down by a tap in sticky drag mode. Unlike the drag state this persists across touches.
+    sticky_latched: bool,
+    /// Makes clicks audible if enabled.
+    #[cfg(feature = "audio")]
+    feedback: Option<FeedbackPlayer>,
+    /// Counts touches for the touch analytics if enabled.
+    #[cfg(feature = "mqtt")]
+    analytics: Option<AnalyticsPublisher>,
+    /// Reports rotations of the monitor if we can rebuild the config.
+    rotation: Option<RotationWatch>,
+    /// Reports the class of the focused window if there are window rules.
+    window: Option<WindowWatch>,
+    /// Reports changes of the config file if we can reload it.
+    config_watch: Option<ConfigWatch>,
+    /// Reloads the config file without blocking the packet loop.
+    config_loader: Option<ConfigLoader>,
+    /// A reloaded config, until it replaces the current one between touches.
+    pending_config: Option<Config>,
+    /// Class of the focused window, so that the window rule can be applied again after the config was replaced.
+    window_class: Option<String>,
+    /// Notifies the service manager in daemon mode.
+    notifier: Option<Notifier>,
+    /// Buffer for the generated events that is reused across packets.
+    events: Vec<InputEvent>,
+    /// Fingers on the virtual touchscreen in touchscreen output mode.
+    slots: Slots,
+    /// All packets are ignored until this time during the startup grace period.
+    ignore_until: Option<Instant>,
+    /// Time for everything that does not run on the time of packets. Replays and tests use a virtual clock.
+    clock: Clock,
+    /// The reload requests seen so far, see [systemd::reload_generation].
+    reload_generation: u64,
+    /// Debug commands from the control interface.
+    #[cfg(feature = "debug")]
+    debug: Option<Receiver<DebugRequest>>,
 }
 
 impl Driver {
     /// Create a new driver with default initial state from a config.
-    fn new(monitor_cfg: Config) -> Self {
+    fn new(monitor_cfg: Config, status: DriverStatus) -> Self {
+        publish_calibration(&monitor_cfg, &status);
+        let usage = monitor_cfg.usage_statistics().cloned().and_then(|config| {
+            UsageTracker::load(config)
+                .map_err(|e| log::warn!("Could not load usage statistics: {}", e))
+                .ok()
+        });
+        if let Some(usage) = &usage {
+            status.set_usage(usage.counts(), SystemTime::now());
+        }
         Self {
             state: DriverState::default(),
+            drift: monitor_cfg.drift_estimation().map(DriftEstimator::new),
+            noise: monitor_cfg.adaptive_noise().map(NoiseEstimator::new),
+            smoothing: monitor_cfg.smoothing().map(SmoothingFilter::new),
+            palm: monitor_cfg.palm_rejection().map(PalmDetector::new),
+            gestures: GestureRegistry::from_config(&monitor_cfg),
+            usage,
+            sticky_latched: false,
+            #[cfg(feature = "audio")]
+            feedback: monitor_cfg
+                .click_feedback()
+                .cloned()
+                .map(FeedbackPlayer::spawn),
+            #[cfg(feature = "mqtt")]
+            analytics: monitor_cfg
+                .touch_analytics()
+                .cloned()
+                .map(AnalyticsPublisher::spawn),
             config: monitor_cfg,
+            splash: None,
+            predictor: Predictor::default(),
+            status,
+            cursor: None,
+            software_cursor: None,
+            rotation: None,
+            window: None,
+            config_watch: None,
+            config_loader: None,
+            pending_config: None,
+            notifier: None,
+            window_class: None,
+            events: Vec::new(),
+            slots: Slots::default(),
+            ignore_until: None,
+            clock: Clock::default(),
+            reload_generation: systemd::reload_generation(),
+            #[cfg(feature = "debug")]
+            debug: None,
         }
     }
 
     /// Update the internal state of the driver and return any evdev events that should be emitted.
     /// Linux' input subsystem already filters out duplicate events so we always emit moves to x & y.
-    fn update(&mut self, message: USBMessage) -> Vec<InputEvent> {
+    fn update(&mut self, message: USBMessage) -> Result<Vec<InputEvent>, EgalaxError> {
         log::trace!("Entering Driver::update");
 
-        log::info!("Processing message: {}", message);
+        log::trace!("Processing message: {}", message);
+
+        // Only the first finger moves the pointer, the others are only reported by a touchscreen,
+        // scroll or pinch together with the first finger, or cancel a pending hold binding of the first finger.
+        let touchscreen = self.config.output_mode() == OutputMode::Touchscreen;
+        let second_finger = message.contact() != 0
+            && message.packet().touch_state() == TouchState::IsTouching
+            && *self.config.second_finger() != SecondFinger::Ignore
+            && self.hold_pending();
+        if message.contact() != 0 && !touchscreen && !second_finger && !self.gestures.multi_finger()
+        {
+            log::debug!("Ignoring secondary contact {}.", message.contact());
+            return Ok(Vec::new());
+        }
 
-        let mut events = EventGen::new(message.time());
         let packet = message.packet();
+        let now = message.time();
+        if message.contact() == 0 {
+            self.status.record_packet(packet);
+        }
+
+        if self.ignore_until.is_some_and(|until| now < until) {
+            log::debug!("Ignoring packet during startup grace period.");
+            return Ok(Vec::new());
+        }
+        let mut events = EventGen::new(
+            monotonic_timeval(message.time())?,
+            mem::take(&mut self.events),
+        );
 
-        match (self.state.touch_state(), packet.touch_state()) {
-            (DriverTouchState::NotTouching, TouchState::NotTouching) => {
-                // No touch previously and now.
+        // A touch in progress when input is disabled ends without firing anything.
+        if !self.status.input_enabled() {
+            if self.state.touch_state == TouchState::IsTouching {
+                log::info!("Touch input is disabled. Cancelling the current touch.");
+                self.cancel_touch(&mut events);
             }
-            (DriverTouchState::IsTouching { .. }, TouchState::NotTouching) => {
-                // User stopped touching.
+            return Ok(events.finish());
+        }
 
-                if !self.state.is_right_click {
-                    log::info!("Releasing left-click.");
-                    events.add_btn_click(self.config.ev_left_click());
+        if message.contact() == 0 {
+            if let Some(palm) = &mut self.palm {
+                let position =
+                    (packet.touch_state() == TouchState::IsTouching).then(|| packet.position());
+                match palm.observe(position) {
+                    PalmVerdict::Accept => {}
+                    PalmVerdict::Reject => {
+                        self.status.set_palm(palm.counters());
+                        self.cancel_touch(&mut events);
+                        return Ok(events.finish());
+                    }
+                    PalmVerdict::Ignore => return Ok(events.finish()),
+                }
+            }
+        }
+
+        let mut monitor_position = self
+            .config
+            .calibration()
+            .map_to(packet.position(), self.config.monitor_area);
+        if message.contact() == 0 {
+            if let Some(smoothing) = &mut self.smoothing {
+                monitor_position = smoothing.filter(now, monitor_position);
+                if packet.touch_state() == TouchState::NotTouching {
+                    smoothing.reset();
+                }
+            }
+        }
+        let local_position = self.config.monitor_area.to_monitor(monitor_position);
+
+        // Secondary contacts are not tracked, so all their frames while touching are moves.
+        let previous_touch_state = self.state.touch_state;
+        let touching = packet.touch_state() == TouchState::IsTouching;
+        let phase = match (message.contact(), previous_touch_state, touching) {
+            (0, TouchState::NotTouching, false) => None,
+            (0, TouchState::NotTouching, true) => Some(TouchPhase::Down),
+            (_, _, true) => Some(TouchPhase::Move),
+            (_, _, false) => Some(TouchPhase::Up),
+        };
+        if message.contact() == 0 {
+            match phase {
+                Some(TouchPhase::Down) => {
+                    // User started touching.
+                    log::info!("left-click");
+                    if self.config.emit_btn_touch() {
+                        events.add_btn_press(EV_KEY::BTN_TOUCH);
+                    }
+                    self.state.touch_state = TouchState::IsTouching;
+                    self.state.kinematics.update(now, packet.position());
+                    self.status.publish(DriverEvent::TouchDown {
+                        position: monitor_position,
+                    });
+                    #[cfg(feature = "mqtt")]
+                    if let Some(analytics) = &self.analytics {
+                        analytics.touched(local_position);
+                    }
+                    if let Some(usage) = &mut self.usage {
+                        usage.record(local_position);
+                        self.status.set_usage(usage.counts(), SystemTime::now());
+                    }
                 }
+                Some(TouchPhase::Move) => self.state.kinematics.update(now, packet.position()),
+                Some(TouchPhase::Up) | None => {}
+            }
+        }
+        if let Some(phase) = phase {
+            let frame = TouchFrame {
+                contact: message.contact(),
+                phase,
+                time: now,
+                position: monitor_position,
+                local_position,
+            };
+            self.recognize_gestures(&mut events, &frame);
+        }
 
-                self.state = DriverState::default();
+        if touchscreen {
+            self.slots.update(
+                &mut events,
+                message.contact(),
+                packet.touch_state(),
+                monitor_position,
+            );
+        }
+        if message.contact() != 0 {
+            if second_finger {
+                self.second_finger_landed(&mut events, monitor_position);
             }
-            (DriverTouchState::NotTouching, TouchState::IsTouching) => {
-                // User started touching.
-                log::info!("left-click");
-                self.state.touch_state = DriverTouchState::IsTouching {
-                    touch_start_time: Instant::now(),
-                    touch_origin: packet.position(),
+            return Ok(events.finish());
+        }
+        // While two fingers scroll or pinch the pointer stays where they started.
+        if self.gestures.holds_pointer() {
+            return Ok(events.finish());
+        }
+
+        if phase == Some(TouchPhase::Up) {
+            // User stopped touching.
+            if self.config.emit_btn_touch() {
+                events.add_btn_release(EV_KEY::BTN_TOUCH);
+            }
+            self.status.publish(DriverEvent::TouchUp {
+                position: monitor_position,
+            });
+
+            self.state = DriverState::default();
+            self.predictor.reset();
+        }
+
+        events.add_monitor_position(monitor_position);
+        if let Some(pressure) = packet.pressure() {
+            // The pressure drops to zero when the touch ends, whatever the last packet reported.
+            let pressure = if touching { self.pressure(pressure) } else { 0 };
+            events.add_abs(EV_ABS::ABS_PRESSURE, pressure);
+        }
+        if let Some(software_cursor) = &self.software_cursor {
+            software_cursor.moved(monitor_position);
+        }
+        if let Some(drift) = &mut self.drift {
+            match (previous_touch_state, packet.touch_state()) {
+                (TouchState::NotTouching, TouchState::IsTouching)
+                    if drift.touch_started(now, monitor_position) =>
+                {
+                    self.status.set_drift(drift.estimate());
+                }
+                (TouchState::IsTouching, TouchState::NotTouching) => drift.touch_ended(now),
+                _ => {}
+            }
+        }
+        if let Some(noise) = &mut self.noise {
+            match packet.touch_state() {
+                TouchState::IsTouching => noise.observe(packet.position()),
+                TouchState::NotTouching if previous_touch_state == TouchState::IsTouching => {
+                    noise.touch_ended();
+                    let thresholds = AdaptiveThresholds {
+                        noise: noise.noise(),
+                        has_moved: noise.raise(self.config.has_moved_threshold()),
+                        tap_slop: self.config.tap_to_click().map(|tap| noise.raise(tap.slop)),
+                    };
+                    self.status.set_noise(thresholds);
+                }
+                TouchState::NotTouching => {}
+            }
+        }
+        if packet.touch_state() == TouchState::IsTouching {
+            self.predictor.observe(now, monitor_position);
+            if let Some(cursor) = &self.cursor {
+                cursor.touched(monitor_position);
+            }
+        }
+        // Once the splash screen is gone the receiver is dropped and we stop sending.
+        if self
+            .splash
+            .as_ref()
+            .is_some_and(|splash| splash.send(monitor_position).is_err())
+        {
+            self.splash = None;
+        }
+
+        Ok(events.finish())
+    }
+
+    /// Map the raw pressure of a packet with the configured pressure curve, or emit it as it is without one.
+    fn pressure(&self, raw: u8) -> i32 {
+        let max = MAX_PRESSURE.into();
+        match self.config.pressure_curve() {
+            Some(curve) => curve.apply_raw(raw.into(), max),
+            None => raw.into(),
+        }
+    }
+
+    /// Start ignoring touches for the configured grace period, e.g. after the device was opened.
+    fn start_grace_period(&mut self) {
+        self.ignore_until = self
+            .config
+            .startup_grace()
+            .map(|grace| self.clock.now() + grace);
+    }
+
+    /// Replace the config, e.g. after the monitor was rotated or the config file was reloaded.
+    /// Returns true if the virtual device must be recreated because the screen space, the output mode,
+    /// or the key and wheel codes it emits changed.
+    fn set_config(&mut self, mut monitor_cfg: Config) -> bool {
+        let device_changed = monitor_cfg.screen_space != self.config.screen_space
+            || monitor_cfg.output_mode() != self.config.output_mode()
+            || virtual_device_keys(&monitor_cfg) != virtual_device_keys(&self.config)
+            || virtual_device_wheels(&monitor_cfg) != virtual_device_wheels(&self.config);
+        monitor_cfg.set_window_class(self.window_class.as_deref());
+        publish_calibration(&monitor_cfg, &self.status);
+        self.gestures = GestureRegistry::from_config(&monitor_cfg);
+        self.config = monitor_cfg;
+        device_changed
+    }
+
+    /// Replace the calibration of the current config until the config is built again.
+    fn set_calibration(&mut self, calibration: Calibration) {
+        log::info!("Switching to the calibration from the control interface.");
+        let mut config = self.config.clone();
+        config.set_calibration(calibration);
+        self.set_config(config);
+    }
+
+    /// Switch to the window rule for the class of the newly focused window.
+    fn set_window_class(&mut self, class: Option<String>) {
+        match self.config.set_window_class(class.as_deref()) {
+            Some(rule) => log::info!("Using window rule '{}'.", rule),
+            None => log::debug!("No window rule for window class {:?}.", class),
+        }
+        self.window_class = class;
+    }
+
+    /// Forget the current touch, e.g. after a resume when we cannot know if the finger is still on the screen.
+    /// Returns events to release everything that is still pressed.
+    fn reset(&mut self) -> Result<Vec<InputEvent>, EgalaxError> {
+        let mut events = EventGen::new(
+            monotonic_timeval(self.clock.now())?,
+            mem::take(&mut self.events),
+        );
+        if self.state.is_dragging || self.sticky_latched {
+            events.add_btn_release(self.config.ev_left_click());
+        }
+        self.sticky_latched = false;
+        if self.state.touch_state == TouchState::IsTouching && self.config.emit_btn_touch() {
+            events.add_btn_release(EV_KEY::BTN_TOUCH);
+        }
+        self.slots.release_all(&mut events);
+        self.gestures.reset();
+        // The next touch must not be smoothed towards or compared with the forgotten one.
+        if let Some(smoothing) = &mut self.smoothing {
+            smoothing.reset();
+        }
+        if let Some(noise) = &mut self.noise {
+            noise.touch_ended();
+        }
+
+        self.state = DriverState::default();
+        self.predictor.reset();
+        Ok(events.finish())
+    }
+
+    /// If no finger is on the screen, so that the config can be replaced without cutting a touch short.
+    fn is_idle(&self) -> bool {
+        self.state.touch_state == TouchState::NotTouching
+            && self.slots.tracking_ids.iter().all(Option::is_none)
+    }
+
+    /// If the first finger is held still so that a hold binding may still fire.
+    fn hold_pending(&self) -> bool {
+        self.state.touch_state == TouchState::IsTouching
+            && !self.state.hold_fired
+            && !self.state.has_moved
+            && !self.state.hold_cancelled
+            && !self.sticky_latched
+    }
+
+    /// End the current touch without firing anything, e.g. because it turned out to be a palm.
+    /// Pressed buttons are released where the pointer last was.
+    fn cancel_touch(&mut self, events: &mut EventGen) {
+        let position =
+            self.state
+                .kinematics
+                .position()
+                .map_or(self.config.monitor_area.midpoint(), |p| {
+                    self.config
+                        .calibration()
+                        .map_to(p, self.config.monitor_area)
+                });
+        if self.state.is_dragging {
+            events.add_btn_release(self.config.ev_left_click());
+            self.publish_gesture(Gesture::DragEnd, position);
+        }
+        if self.config.emit_btn_touch() && self.state.touch_state == TouchState::IsTouching {
+            events.add_btn_release(EV_KEY::BTN_TOUCH);
+        }
+        if self.config.output_mode() == OutputMode::Touchscreen {
+            self.slots
+                .update(events, 0, TouchState::NotTouching, position);
+        }
+        if self.state.touch_state == TouchState::IsTouching {
+            self.status.publish(DriverEvent::TouchUp { position });
+        }
+        if let Some(smoothing) = &mut self.smoothing {
+            smoothing.reset();
+        }
+        if let Some(noise) = &mut self.noise {
+            noise.touch_ended();
+        }
+        self.gestures.reset();
+        self.state = DriverState::default();
+        self.predictor.reset();
+    }
+
+    /// A second finger landed while a hold binding was pending, see [SecondFinger].
+    fn second_finger_landed(&mut self, events: &mut EventGen, position: Point2D<ScreenSpace>) {
+        match self.config.second_finger().clone() {
+            SecondFinger::Ignore => {}
+            SecondFinger::Cancel => {
+                log::info!("Second finger landed. Cancelling hold bindings.");
+                self.state.hold_cancelled = true;
+            }
+            SecondFinger::Fire(action) => {
+                log::info!("Second finger landed. Firing {}.", action);
+                self.state.hold_fired = true;
+                self.fire(events, &action);
+                self.status.publish(DriverEvent::Click { action, position });
+            }
+        }
+    }
+
+    /// Pass a frame to the gesture recognizers and perform the actions they recognized.
+    fn recognize_gestures(&mut self, events: &mut EventGen, frame: &TouchFrame) {
+        let mut gestures = mem::take(&mut self.gestures);
+        gestures.update(
+            frame,
+            &mut GestureContext {
+                config: &self.config,
+                touch: &mut self.state,
+                sticky_latched: &mut self.sticky_latched,
+                noise: self.noise.as_ref(),
+            },
+        );
+        for action in gestures.take_actions() {
+            self.perform(events, action, frame.position);
+        }
+        self.gestures = gestures;
+    }
+
+    /// Perform the action of a recognized gesture, see [GestureAction].
+    fn perform(
+        &self,
+        events: &mut EventGen,
+        action: GestureAction,
+        position: Point2D<ScreenSpace>,
+    ) {
+        match action {
+            GestureAction::Press(button, gesture) => {
+                // Press before moving so that a drag starts where the finger was held.
+                events.add_btn_press(button);
+                events.add_syn();
+                self.publish_gesture(gesture, position);
+            }
+            GestureAction::Release(button, gesture) => {
+                events.add_btn_release(button);
+                self.publish_gesture(gesture, position);
+            }
+            GestureAction::Click(action) => {
+                self.fire(events, &action);
+                self.status.publish(DriverEvent::Click { action, position });
+            }
+            GestureAction::LongPress(action) => {
+                self.fire(events, &action);
+                self.status
+                    .publish(DriverEvent::LongPress { action, position });
+            }
+            GestureAction::EdgeSwipe(edge, keys) => {
+                events.add_key_combination(&keys);
+                self.publish_gesture(Gesture::EdgeSwipe { edge }, position);
+            }
+            GestureAction::DoubleTap(action, first_position) => {
+                match action {
+                    DoubleTapAction::DoubleClick => {
+                        events.add_monitor_position(first_position);
+                        events.add_syn();
+                        events.add_btn_click(self.config.ev_left_click());
+                    }
+                    DoubleTapAction::Key(key) => events.add_btn_click(key),
+                }
+                self.publish_gesture(Gesture::DoubleTap, position);
+            }
+            GestureAction::Scroll(delta) => events.add_scroll(delta),
+            GestureAction::Zoom(zoom) => self.zoom(events, zoom),
+            GestureAction::SuppressClick => {}
+            GestureAction::Maintenance(action) => {
+                match action {
+                    MaintenanceAction::Command(command) => {
+                        self.fire(events, &ClickAction::Command(command))
+                    }
+                    MaintenanceAction::Keys(keys) => events.add_key_combination(&keys),
+                }
+                self.publish_gesture(Gesture::MaintenanceTap, position);
+            }
+        }
+    }
+
+    /// Emit a zoom step of a pinch, see [PinchAction].
+    fn zoom(&self, events: &mut EventGen, zoom: Zoom) {
+        let Some(pinch) = self.config.pinch_zoom() else {
+            return;
+        };
+        log::info!("Pinch zoom {:?}.", zoom);
+        match &pinch.emit {
+            PinchAction::Wheel(modifiers) => {
+                let clicks = match zoom {
+                    Zoom::In => 1,
+                    Zoom::Out => -1,
                 };
+                for key in modifiers {
+                    events.add_btn_press(*key);
+                }
+                events.add_syn();
+                events.add_scroll(ScrollDelta {
+                    clicks: [clicks, 0],
+                    hi_res: [clicks * WHEEL_HI_RES_PER_CLICK, 0],
+                });
+                events.add_syn();
+                for key in modifiers.iter().rev() {
+                    events.add_btn_release(*key);
+                }
             }
-            (
-                DriverTouchState::IsTouching {
-                    touch_start_time,
-                    touch_origin,
-                },
-                TouchState::IsTouching,
-            ) => {
-                // User continues touching.
-                // During a continued touch we check whether the finger moved too far and if so we disable right-clicks.
-                // And otherwise we perform a right-click if the user pressed long enough.
-                if !self.state.is_right_click && !self.state.has_moved {
-                    let touch_distance = touch_origin.euclidean_distance_to(&packet.position());
-
-                    if touch_distance > self.config.has_moved_threshold() {
-                        log::info!("Finger has moved while touching. Disabling right-click.");
-                        self.state.has_moved = true;
-                    } else {
-                        let time_touching = Instant::now().duration_since(touch_start_time);
-
-                        if time_touching > self.config.right_click_wait() {
-                            log::info!("right-click");
-                            self.state.is_right_click = true;
-                            events.add_btn_click(self.config.ev_right_click());
-                        }
+            PinchAction::Keys(zoom_in, zoom_out) => match zoom {
+                Zoom::In => events.add_key_combination(zoom_in),
+                Zoom::Out => events.add_key_combination(zoom_out),
+            },
+        }
+    }
+
+    /// Perform the action of a click binding.
+    fn fire(&self, events: &mut EventGen, action: &ClickAction) {
+        match action {
+            ClickAction::Button(button) => {
+                events.add_btn_click(*button);
+                #[cfg(feature = "audio")]
+                if let Some(feedback) = &self.feedback {
+                    feedback.clicked(*button);
+                }
+            }
+            ClickAction::Command(command) => {
+                match process::Command::new("sh").arg("-c").arg(command).spawn() {
+                    // Wait for the command in the background so that it does not become a zombie.
+                    Ok(mut child) => {
+                        thread::spawn(move || child.wait());
                     }
+                    Err(e) => log::warn!("Could not run command `{}`: {}", command, e),
                 }
             }
         }
+    }
+
+    fn publish_gesture(&self, gesture: Gesture, position: Point2D<ScreenSpace>) {
+        self.status
+            .publish(DriverEvent::Gesture { gesture, position });
+    }
 
-        events.add_move_position(packet.position(), &self.config);
-        events.finish()
+    /// If positions should currently be predicted, returns how long to wait for the next packet before predicting one.
+    fn prediction_timeout(&self) -> Option<Duration> {
+        let interpolation = self.config.interpolation()?;
+        self.predictor
+            .can_predict(self.clock.now(), interpolation.max_extrapolation)
+            .then_some(interpolation.interval)
+    }
+
+    /// Return events that move to a predicted position, if there is one.
+    /// The prediction is clamped to the monitor area so that it can never overshoot the screen edges.
+    fn predict(&mut self) -> Result<Vec<InputEvent>, EgalaxError> {
+        let Some(interpolation) = self.config.interpolation() else {
+            return Ok(Vec::new());
+        };
+        let Some(position) = self
+            .predictor
+            .predict(self.clock.now(), interpolation.max_extrapolation)
+        else {
+            return Ok(Vec::new());
+        };
+
+        let area = self.config.monitor_area;
+        let position = Point2D {
+            x: position.x.clamp(area.xrange().min(), area.xrange().max()),
+            y: position.y.clamp(area.yrange().min(), area.yrange().max()),
+        };
+        log::debug!("Predicted position {}", position);
+
+        let mut events = EventGen::new(
+            monotonic_timeval(self.clock.now())?,
+            mem::take(&mut self.events),
+        );
+        events.add_monitor_position(position);
+        if let Some(software_cursor) = &self.software_cursor {
+            software_cursor.moved(position);
+        }
+        Ok(events.finish())
     }
 
     /// Setup the virtual device with uinput
@@ -220,8 +1076,9 @@ impl Driver {
 
         log::info!("Set events that will be generated for virtual device.");
         u.enable_event_type(&EventType::EV_KEY)?;
-        u.enable_event_code(&EventCode::EV_KEY(self.config.ev_left_click()), None)?;
-        u.enable_event_code(&EventCode::EV_KEY(self.config.ev_right_click()), None)?;
+        for key in virtual_device_keys(&self.config) {
+            u.enable_event_code(&EventCode::EV_KEY(key), None)?;
+        }
 
         // For the minimum and maximum values we must specify the whole virtual screen space
         // to establish a frame of reference. Later, we will always send cursor movements
@@ -255,6 +1112,52 @@ impl Driver {
             Some(EnableCodeData::AbsInfo(abs_info_y)),
         )?;
 
+        if self.config.output_mode() == OutputMode::Touchscreen {
+            let abs_info = |minimum, maximum| AbsInfo {
+                value: 0,
+                minimum,
+                maximum,
+                fuzz: 0,
+                flat: 0,
+                resolution: 0,
+            };
+            let mt_axes = [
+                (EV_ABS::ABS_MT_SLOT, abs_info(0, MT_SLOTS as i32 - 1)),
+                (EV_ABS::ABS_MT_TRACKING_ID, abs_info(0, MAX_TRACKING_ID)),
+                (EV_ABS::ABS_MT_POSITION_X, abs_info_x),
+                (EV_ABS::ABS_MT_POSITION_Y, abs_info_y),
+            ];
+            for (code, abs_info) in mt_axes {
+                u.enable_event_code(
+                    &EventCode::EV_ABS(code),
+                    Some(EnableCodeData::AbsInfo(abs_info)),
+                )?;
+            }
+        }
+
+        if emits_pressure(&self.config) {
+            let abs_info_pressure = AbsInfo {
+                value: 0,
+                minimum: 0,
+                maximum: MAX_PRESSURE.into(),
+                fuzz: 0,
+                flat: 0,
+                resolution: 0,
+            };
+            u.enable_event_code(
+                &EventCode::EV_ABS(EV_ABS::ABS_PRESSURE),
+                Some(EnableCodeData::AbsInfo(abs_info_pressure)),
+            )?;
+        }
+
+        let wheels = virtual_device_wheels(&self.config);
+        if !wheels.is_empty() {
+            u.enable_event_type(&EventType::EV_REL)?;
+            for code in wheels {
+                u.enable_event_code(&EventCode::EV_REL(code), None)?;
+            }
+        }
+
         // TODO do we need MSC_SCAN which is present in recording.txt?
         u.enable_event_code(&EventCode::EV_SYN(EV_SYN::SYN_REPORT), None)?;
 
@@ -269,13 +1172,25 @@ impl Driver {
         Ok(vm)
     }
 
+    /// Keep the buffer of sent events for the next packet.
+    fn recycle_events(&mut self, mut events: Vec<InputEvent>) {
+        events.clear();
+        self.events = events;
+    }
+
     /// Send the generated events to the uinput virtual device.
-    fn send_events(&self, vm: &UInputDevice, events: &[InputEvent]) -> Result<(), EgalaxError> {
+    /// The buffer is kept for the events of the next packet.
+    fn send_events(
+        &mut self,
+        vm: &UInputDevice,
+        events: Vec<InputEvent>,
+    ) -> Result<(), EgalaxError> {
         log::trace!("Entering Driver::send_events.");
 
-        for event in events {
+        for event in &events {
             vm.write_event(event)?;
         }
+        self.recycle_events(events);
 
         log::trace!("Leaving Driver::send_events.");
         Ok(())
@@ -288,30 +1203,528 @@ where
     T: io::Read,
     F: FnMut(USBMessage) -> Result<(), EgalaxError>,
 {
-    let mut raw_packet = RawPacket([0; RAW_PACKET_LEN]);
+    let mut decoder = PacketDecoder::new(stream);
 
-    loop {
-        match stream.read_exact(&mut raw_packet.0) {
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
-            res => res?,
-        };
-        log::info!("Read raw packet: {}", raw_packet);
-
-        let time = TimeVal::try_from(SystemTime::now())?;
-        let packet = USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))?;
-        f(packet.with_time(time))?;
+    while let Some(message) = decoder.next_message()? {
+        f(message)?;
     }
+
+    Ok(())
 }
 
 /// Create a virtual mouse using uinput and then continuously transform packets from the touchscreen into
 /// evdev events that move the mouse.
+///
+/// After a resume from suspend the driver state is reset but reading continues on the same stream.
+/// Use [virtual_mouse_device] to also reopen the device node.
 pub fn virtual_mouse<T>(stream: &mut T, monitor_cfg: Config) -> Result<(), EgalaxError>
 where
-    T: io::Read,
+    T: io::Read + AsRawFd,
 {
     log::trace!("Entering fn virtual_mouse");
 
-    let mut driver = Driver::new(monitor_cfg);
+    let (mut driver, vm) = setup_driver(monitor_cfg, DriverStatus::default())?;
+
+    loop {
+        match drive(&mut driver, &vm, stream, PacketFormat::Hidraw)? {
+            StreamEnd::Closed => break,
+            StreamEnd::Unplugged => return Err(io::Error::from(io::ErrorKind::NotConnected).into()),
+            StreamEnd::Resumed | StreamEnd::Rotated(_) => {
+                let events = driver.reset()?;
+                driver.send_events(&vm, events)?;
+            }
+            StreamEnd::ConfigChanged => {
+                log::warn!("Cannot reload the config without a config file, ignoring.")
+            }
+        }
+    }
+
+    log::trace!("Leaving fn virtual_mouse");
+    Ok(())
+}
+
+/// Feed the packets of a dump through a driver without creating a virtual device and call `sink` with each message
+/// and the events that the driver would emit for it. Returns the number of messages.
+///
+/// The messages are stamped with the offsets of the dump instead of the time they are processed, and the driver runs on
+/// a virtual clock that follows them, so the timing of the recording is reproduced exactly and the replay runs as fast
+/// as possible. Malformed packets are skipped. Predicted positions between packets are not replayed.
+pub fn replay_dry_run<R, F>(
+    dump: DumpReader<R>,
+    monitor_cfg: Config,
+    mut sink: F,
+) -> Result<u64, EgalaxError>
+where
+    R: io::Read,
+    F: FnMut(&USBMessage, &[InputEvent]),
+{
+    log::trace!("Entering fn replay_dry_run");
+
+    let start = Instant::now();
+    let mut driver = Driver::new(monitor_cfg, DriverStatus::default());
+    driver.clock = Clock::starting_at(start);
+    let mut messages = 0;
+    for frame in dump {
+        let frame = frame?;
+        let packet = match Packet::try_parse(frame.packet, Some(PacketTag::TouchEvent)) {
+            Ok(packet) => packet,
+            Err(e) => {
+                log::warn!("Skipping packet {}: {}", frame.packet, e);
+                continue;
+            }
+        };
+        let message = packet.with_time(start + frame.offset);
+        driver.clock.advance_to(message.time());
+        let events = driver.update(message)?;
+        sink(&message, &events);
+        driver.recycle_events(events);
+        messages += 1;
+    }
+
+    log::trace!("Leaving fn replay_dry_run");
+    Ok(messages)
+}
+
+/// Like [virtual_mouse] but reads from a device node, which is reopened after a resume from suspend
+/// because the old file descriptor is often stale by then.
+/// The config is rebuilt from the config file when the monitor is rotated, using a matching profile if there is one.
+/// When the config file changes or SIGHUP is caught after [systemd::handle_reload], the config file is read again
+/// by a [ConfigLoader] and the new calibration, timings and key codes are used once no finger is on the screen.
+/// If it cannot be read, the old config is kept.
+/// The virtual device is only recreated if it must support different events.
+/// Statistics about the driver are reported to `status`.
+/// With `wait_for_device` a missing device node is waited for instead of failing, see [devices::wait_for_device].
+/// When the device is unplugged we wait until it is plugged in again, see [reconnect]. The same happens if it cannot
+/// be reopened after a resume, e.g. because it was unplugged during the suspend.
+/// If a `notifier` is given, the service manager is told when the virtual device is ready and when we stop,
+/// and its watchdog is pinged while reading packets or waiting for the device. Both end after
+/// [systemd::handle_termination] caught a signal.
+/// If the config has a serial port, the node is read as that port, see [crate::serial].
+pub fn virtual_mouse_device(
+    node_path: &Path,
+    config_path: &Path,
+    status: DriverStatus,
+    wait_for_device: bool,
+    mut notifier: Option<Notifier>,
+) -> Result<(), EgalaxError> {
+    log::trace!("Entering fn virtual_mouse_device");
+
+    let device_node = if wait_for_device {
+        let Some(device_node) = devices::wait_for_device(node_path, notifier.as_mut())? else {
+            if let Some(notifier) = &notifier {
+                notifier.stopping();
+            }
+            return Ok(());
+        };
+        device_node
+    } else {
+        OpenOptions::new().read(true).open(node_path)?
+    };
+    log::info!("Opened device node '{}'", node_path.display());
+
+    // Remember which device this is so that we find it again when it is replugged, and to pick its device profile.
+    let device = devices::identify_device(node_path);
+    let config_file = ConfigFile::from_file(config_path)?.for_device(device.as_ref());
+    // The config is built for the current rotation of the monitor, so the watch only needs to report changes.
+    let rotation = RotationWatch::spawn(config_file.monitor_designator().clone())
+        .map_err(|e| log::warn!("Could not watch monitor rotation: {}", e))
+        .ok();
+    let initial_rotation = rotation.as_ref().and_then(RotationWatch::initial);
+    let monitor_cfg = match initial_rotation {
+        Some(rotation) => build_for_rotation(&config_file, rotation, &status)?,
+        None => config_file.clone().build()?,
+    };
+    log::info!("Using monitor config:\n{}", monitor_cfg);
+
+    // A serial port is configured once and again whenever it is reopened. Changing it needs a restart.
+    let serial = config_file.serial().cloned();
+    let mut device_node = serial::prepare_device(device_node, serial.as_ref())?;
+    let format = serial::packet_format(serial.as_ref());
+    // The node of the device, which changes if it is replugged.
+    let mut current_path = node_path.to_path_buf();
+    // The grabs are released when the files are dropped at the end of this function.
+    // A serial touchscreen has no input devices of the kernel that could be grabbed.
+    let mut _grabs = match serial {
+        Some(_) => Vec::new(),
+        None => grab_kernel_input(node_path, monitor_cfg.grab_kernel_input()),
+    };
+
+    status.set_device(node_path);
+    let (mut driver, mut vm) = setup_driver(monitor_cfg, status)?;
+    driver.rotation = rotation;
+    if driver.config.has_window_rules() {
+        driver.window = WindowWatch::spawn()
+            .map_err(|e| log::warn!("Could not watch the focused window: {}", e))
+            .ok();
+    }
+    driver.config_watch = ConfigWatch::new(config_path)
+        .map_err(|e| log::warn!("Could not watch the config file: {}", e))
+        .ok();
+    let mut config_loader = ConfigLoader::spawn(
+        config_path.to_path_buf(),
+        device.clone(),
+        driver.status.clone(),
+    );
+    if let Some(rotation) = initial_rotation {
+        config_loader.set_rotation(rotation);
+    }
+    driver.config_loader = Some(config_loader);
+    driver.notifier = notifier;
+    if let Some(notifier) = &driver.notifier {
+        notifier.ready(&format!("Reading from {}", node_path.display()));
+    }
+    loop {
+        match drive(&mut driver, &vm, &mut device_node, format)? {
+            StreamEnd::Closed => break,
+            StreamEnd::Resumed => {
+                let events = driver.reset()?;
+                driver.send_events(&vm, events)?;
+                match reopen_device(&current_path) {
+                    Ok(reopened) => {
+                        device_node = serial::prepare_device(reopened, serial.as_ref())?
+                    }
+                    Err(e) => {
+                        log::warn!("Could not reopen device after resume: {}", e);
+                        let Some(reconnected) = reconnect(
+                            &mut driver,
+                            &current_path,
+                            device.as_ref(),
+                            serial.as_ref(),
+                            &mut _grabs,
+                        )?
+                        else {
+                            break;
+                        };
+                        (device_node, current_path) = reconnected;
+                    }
+                }
+                driver.start_grace_period();
+            }
+            StreamEnd::Unplugged => {
+                let events = driver.reset()?;
+                driver.send_events(&vm, events)?;
+                let Some(reconnected) = reconnect(
+                    &mut driver,
+                    &current_path,
+                    device.as_ref(),
+                    serial.as_ref(),
+                    &mut _grabs,
+                )?
+                else {
+                    break;
+                };
+                (device_node, current_path) = reconnected;
+                driver.start_grace_period();
+            }
+            StreamEnd::Rotated(new_rotation) => {
+                // Building the config queries the monitors, so it is done by the loader. The config for the
+                // new rotation then arrives like a reloaded one, and the old one is kept if it cannot be built.
+                if let Some(loader) = &mut driver.config_loader {
+                    loader.set_rotation(new_rotation);
+                    loader.request();
+                }
+            }
+            StreamEnd::ConfigChanged => {
+                let Some(monitor_cfg) = driver.pending_config.take() else {
+                    continue;
+                };
+                let events = driver.reset()?;
+                driver.send_events(&vm, events)?;
+                log::info!("Reloaded monitor config:\n{}", monitor_cfg);
+                if driver.set_config(monitor_cfg) {
+                    log::info!("Virtual device changed, recreating it.");
+                    vm = driver.get_virtual_device()?;
+                }
+            }
+        }
+    }
+    if let Some(notifier) = &driver.notifier {
+        notifier.stopping();
+    }
+
+    log::trace!("Leaving fn virtual_mouse_device");
+    Ok(())
+}
+
+/// Wait until the device is back after it was unplugged and open it again.
+/// Only the packet source is re-established. The virtual device is kept, so that applications do not see
+/// the pointer disappear and reappear. If the device could not be identified, its node is waited for instead.
+/// Returns the device node and its path, which changes if the kernel assigns another node to the replugged device.
+/// A serial port is configured again, e.g. after a USB-serial adapter was replugged.
+/// Returns `None` if a termination signal arrived while waiting.
+fn reconnect(
+    driver: &mut Driver,
+    node_path: &Path,
+    device: Option<&HidrawDevice>,
+    serial: Option<&SerialPort>,
+    grabs: &mut Vec<File>,
+) -> Result<Option<(File, PathBuf)>, EgalaxError> {
+    let Some(device) = device else {
+        log::warn!("Device node '{}' is gone.", node_path.display());
+        let Some(device_node) = devices::wait_for_device(node_path, driver.notifier.as_mut())?
+        else {
+            return Ok(None);
+        };
+        return Ok(Some((
+            serial::prepare_device(device_node, serial)?,
+            node_path.to_path_buf(),
+        )));
+    };
+    log::warn!("Device {} was unplugged.", device.node.display());
+    if let Some(notifier) = &driver.notifier {
+        notifier.status("Waiting for the device to be plugged in again");
+    }
+    // Release the grabs of the old input devices before the new ones appear.
+    grabs.clear();
+    let Some((replugged, replugged_path)) =
+        devices::wait_for_replug(device, driver.notifier.as_mut())?
+    else {
+        return Ok(None);
+    };
+    *grabs = grab_kernel_input(&replugged_path, driver.config.grab_kernel_input());
+    driver.status.set_device(&replugged_path);
+    if let Some(notifier) = &driver.notifier {
+        notifier.status(&format!("Reading from {}", replugged_path.display()));
+    }
+    Ok(Some((replugged, replugged_path)))
+}
+
+/// The key codes the virtual device must support for a config.
+fn virtual_device_keys(config: &Config) -> Vec<EV_KEY> {
+    // The left button is always needed for dragging.
+    let mut keys = vec![config.ev_left_click()];
+    for binding in config.all_click_bindings() {
+        if let ClickAction::Button(button) = binding.action {
+            keys.push(button);
+        }
+    }
+    if config.emit_btn_touch() {
+        // Together with INPUT_PROP_DIRECT this makes toolkits treat the device as a touchscreen.
+        keys.push(EV_KEY::BTN_TOUCH);
+    }
+    if let Some(pinch) = config.pinch_zoom() {
+        keys.extend(pinch.emit.keys());
+    }
+    if let Some(edge_swipe) = config.edge_swipe() {
+        keys.extend(edge_swipe.keys());
+    }
+    if let Some(DoubleTapAction::Key(key)) = config.double_tap().map(|double_tap| double_tap.action)
+    {
+        keys.push(key);
+    }
+    if let Some(MaintenanceAction::Keys(maintenance_keys)) = config
+        .maintenance_tap()
+        .map(|maintenance| &maintenance.action)
+    {
+        keys.extend(maintenance_keys);
+    }
+    keys
+}
+
+/// Whether the virtual device must support pressure for a config.
+/// Only serial controllers report it, but a pressure curve also says that the touchscreen does.
+fn emits_pressure(config: &Config) -> bool {
+    config.serial().is_some() || config.pressure_curve().is_some()
+}
+
+/// The wheels the virtual device must support for a config.
+fn virtual_device_wheels(config: &Config) -> Vec<EV_REL> {
+    let zoom_wheel = config
+        .pinch_zoom()
+        .is_some_and(|pinch| matches!(pinch.emit, PinchAction::Wheel(_)));
+    if config.two_finger_scroll().is_some() || zoom_wheel {
+        vec![
+            EV_REL::REL_WHEEL,
+            EV_REL::REL_HWHEEL,
+            EV_REL::REL_WHEEL_HI_RES,
+            EV_REL::REL_HWHEEL_HI_RES,
+        ]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Grab the kernel input devices of the touchscreen if `grab` is set, or else warn if they would duplicate input.
+fn grab_kernel_input(node_path: &Path, grab: bool) -> Vec<File> {
+    if grab {
+        return doctor::grab_kernel_input(node_path)
+            .map_err(|e| log::warn!("Could not grab kernel input devices: {}", e))
+            .unwrap_or_default();
+    }
+    for finding in doctor::check_duplicate_input(node_path, false) {
+        if finding.severity != Severity::Ok {
+            log::warn!("{}", finding.message);
+        }
+    }
+    Vec::new()
+}
+
+/// Reads and builds the config file on its own thread, so that a slow reload or a rebuild after the monitor was rotated,
+/// e.g. while the monitors are queried, never stalls the packet loop. The loop picks up loaded configs from a channel without blocking.
+#[derive(Debug)]
+struct ConfigLoader {
+    requests: Sender<Option<Rotation>>,
+    loaded: Receiver<Config>,
+    /// The last rotation of the monitor, so that a reloaded config is built for it as well.
+    rotation: Option<Rotation>,
+}
+
+impl ConfigLoader {
+    fn spawn(config_path: PathBuf, device: Option<HidrawDevice>, status: DriverStatus) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<Option<Rotation>>();
+        let (loaded_sender, loaded) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(rotation) = request_receiver.recv() {
+                // Requests that piled up while loading are served by a single reload.
+                let rotation = request_receiver.try_iter().last().unwrap_or(rotation);
+                // A half-written or invalid file must not stop the driver, we just wait for the next change.
+                let reloaded = ConfigFile::from_file(&config_path).and_then(|new_file| {
+                    let new_file = new_file.for_device(device.as_ref());
+                    match rotation {
+                        Some(rotation) => build_for_rotation(&new_file, rotation, &status),
+                        None => new_file.build(),
+                    }
+                });
+                match reloaded {
+                    Ok(reloaded) => {
+                        if loaded_sender.send(reloaded).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => log::warn!(
+                        "Keeping the old config, could not build it from '{}': {}",
+                        config_path.display(),
+                        e
+                    ),
+                }
+            }
+        });
+        Self {
+            requests,
+            loaded,
+            rotation: None,
+        }
+    }
+
+    fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = Some(rotation);
+    }
+
+    /// Ask for the config file to be read again. Does not block.
+    fn request(&self) {
+        self.requests.send(self.rotation).ok();
+    }
+
+    /// The most recently loaded config, if one arrived since the last call. Does not block.
+    fn take(&self) -> Option<Config> {
+        self.loaded.try_iter().last()
+    }
+}
+
+/// Build the config for a rotation of the monitor, using the matching profile if there is one.
+fn build_for_rotation(
+    config_file: &ConfigFile,
+    rotation: Rotation,
+    status: &DriverStatus,
+) -> Result<Config, EgalaxError> {
+    match config_file
+        .profile_for_rotation(rotation)
+        .and_then(|name| Some((name, config_file.with_profile(name)?)))
+    {
+        Some((name, profile_file)) => {
+            log::info!("Switching to profile '{}' for rotation {}.", name, rotation);
+            status.set_profile(name);
+            profile_file.build()
+        }
+        None => {
+            log::info!(
+                "No profile for rotation {}, using default calibration.",
+                rotation
+            );
+            status.set_profile(DEFAULT_PROFILE);
+            config_file.clone().build()
+        }
+    }
+}
+
+/// Try to reopen a device node. After a resume the device may take a moment to reappear.
+fn reopen_device(node_path: &Path) -> Result<File, EgalaxError> {
+    let mut attempt = 1;
+    loop {
+        match OpenOptions::new().read(true).open(node_path) {
+            Ok(device_node) => {
+                log::info!("Reopened device node '{}'", node_path.display());
+                return Ok(device_node);
+            }
+            Err(e) if attempt < REOPEN_ATTEMPTS => {
+                log::warn!(
+                    "Could not reopen device node '{}' (attempt {}): {}",
+                    node_path.display(),
+                    attempt,
+                    e
+                );
+                attempt += 1;
+                thread::sleep(REOPEN_DELAY);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Run the calling thread with a SCHED_FIFO priority, which needs CAP_SYS_NICE or a suitable RLIMIT_RTPRIO.
+fn set_realtime_priority(priority: u8) -> io::Result<()> {
+    let param = libc::sched_param {
+        sched_priority: libc::c_int::from(priority.clamp(1, 99)),
+    };
+    // SAFETY: the parameter is a valid sched_param that outlives the call. Pid 0 is the calling thread.
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Pin the calling thread to the given CPUs.
+fn set_cpu_affinity(cpus: &[usize]) -> nix::Result<()> {
+    let mut cpu_set = CpuSet::new();
+    for &cpu in cpus {
+        cpu_set.set(cpu)?;
+    }
+    // Pid 0 is the calling thread.
+    sched_setaffinity(Pid::from_raw(0), &cpu_set)
+}
+
+/// Wait until the file descriptor is readable or the timeout passed and return if it is readable.
+/// With `busy` we spin on a non-blocking poll instead of sleeping in the kernel, which trades CPU time for latency.
+fn poll_readable(fd: RawFd, timeout: Duration, busy: bool) -> nix::Result<bool> {
+    if !busy {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        return Ok(poll(&mut fds, timeout.as_millis().max(1) as i32)? > 0);
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        if poll(&mut fds, 0)? > 0 {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::hint::spin_loop();
+    }
+}
+
+/// Create the driver and its virtual device.
+fn setup_driver(
+    monitor_cfg: Config,
+    status: DriverStatus,
+) -> Result<(Driver, UInputDevice), EgalaxError> {
+    let mut driver = Driver::new(monitor_cfg, status);
+    driver.start_grace_period();
+    #[cfg(feature = "debug")]
+    {
+        driver.debug = Some(driver.status.debug_requests());
+    }
     let vm = driver.get_virtual_device()?;
 
     log::info!(
@@ -319,12 +1732,700 @@ where
         vm.devnode().unwrap_or("<unknown>")
     );
 
-    let process_packet = |message| {
-        let events = driver.update(message);
-        driver.send_events(&vm, &events)
-    };
-    process_packets(stream, process_packet)?;
+    if let Some(duration) = driver.config.startup_splash() {
+        let (tx, rx) = mpsc::channel();
+        let monitor_area = driver.config.monitor_area;
+        thread::spawn(move || {
+            if let Err(e) = overlay::show_splash(monitor_area, duration, rx) {
+                log::warn!("Could not show startup splash: {}", e);
+            }
+        });
+        driver.splash = Some(tx);
+    }
 
-    log::trace!("Leaving fn virtual_mouse");
-    Ok(())
+    if driver.config.hide_cursor() {
+        match CursorHider::spawn() {
+            Ok(cursor) => driver.cursor = Some(cursor),
+            Err(e) => log::warn!("Could not set up cursor hiding: {}", e),
+        }
+    }
+    if driver.config.software_cursor() {
+        match SoftwareCursor::spawn() {
+            Ok(software_cursor) => driver.software_cursor = Some(software_cursor),
+            Err(e) => log::warn!("Could not set up software cursor: {}", e),
+        }
+    }
+
+    if let Some(priority) = driver.config.realtime_priority() {
+        match set_realtime_priority(priority) {
+            Ok(()) => log::info!("Running with realtime priority {}.", priority),
+            Err(e) => log::warn!("Could not set realtime priority {}: {}", priority, e),
+        }
+    }
+    let cpus = driver.config.cpu_affinity();
+    if !cpus.is_empty() {
+        match set_cpu_affinity(cpus) {
+            Ok(()) => log::info!("Pinned driver thread to CPUs {:?}.", cpus),
+            Err(e) => log::warn!("Could not pin driver thread to CPUs {:?}: {}", cpus, e),
+        }
+    }
+
+    Ok((driver, vm))
+}
+
+/// Transform packets from the stream into events until it ends or the system resumes from suspend.
+/// We never block on the stream indefinitely so that a suspend is noticed even without new packets.
+fn drive<T>(
+    driver: &mut Driver,
+    vm: &UInputDevice,
+    stream: &mut T,
+    format: PacketFormat,
+) -> Result<StreamEnd, EgalaxError>
+where
+    T: io::Read + AsRawFd,
+{
+    let fd = stream.as_raw_fd();
+    let mut decoder = PacketDecoder::with_format(stream, format);
+    let mut suspend_watch = SuspendWatch::new()?;
+
+    loop {
+        if systemd::termination_requested() {
+            log::info!("Stopping after a termination signal.");
+            return Ok(StreamEnd::Closed);
+        }
+        if let Some(notifier) = &mut driver.notifier {
+            notifier.ping_watchdog();
+        }
+
+        if !decoder.has_buffered_packet() {
+            // While predicting positions we wait for the next packet with the shorter prediction interval.
+            let prediction_timeout = driver.prediction_timeout();
+            let timeout = prediction_timeout.unwrap_or(WATCHDOG_INTERVAL);
+
+            let ready = match poll_readable(fd, timeout, driver.config.busy_poll_burns_cpu()) {
+                Ok(ready) => ready,
+                Err(Errno::EINTR | Errno::EAGAIN) => continue,
+                Err(e) => return Err(io::Error::from(e).into()),
+            };
+
+            if suspend_watch.check()? {
+                return Ok(StreamEnd::Resumed);
+            }
+            if let Some(rotation) = driver.rotation.as_ref().and_then(RotationWatch::changed) {
+                return Ok(StreamEnd::Rotated(rotation));
+            }
+            if let Some(class) = driver.window.as_ref().and_then(WindowWatch::changed) {
+                driver.set_window_class(class);
+            }
+            #[cfg(feature = "debug")]
+            driver.serve_debug_requests(vm)?;
+            let reload_generation = systemd::reload_generation();
+            // Unlike SIGHUP, a reload from the control interface only concerns this touchscreen.
+            let reload_requested = driver.status.take_reload_request()
+                | (mem::replace(&mut driver.reload_generation, reload_generation)
+                    != reload_generation);
+            if reload_requested
+                || driver
+                    .config_watch
+                    .as_ref()
+                    .is_some_and(ConfigWatch::changed)
+            {
+                match &driver.config_loader {
+                    Some(loader) => loader.request(),
+                    None => return Ok(StreamEnd::ConfigChanged),
+                }
+            }
+            // A reloaded config or calibration only replaces the current one between touches.
+            if driver.is_idle() {
+                if let Some(calibration) = driver.status.take_requested_calibration() {
+                    driver.set_calibration(calibration);
+                }
+                if let Some(loaded) = driver.config_loader.as_ref().and_then(ConfigLoader::take) {
+                    driver.pending_config = Some(loaded);
+                    return Ok(StreamEnd::ConfigChanged);
+                }
+            }
+
+            if !ready {
+                if prediction_timeout.is_some() {
+                    let events = driver.predict()?;
+                    driver.status.record_session(None, &events);
+                    driver.send_events(vm, events)?;
+                }
+                continue;
+            }
+        }
+
+        let outcome = read_message(&mut decoder)?;
+        let unknown_tags = decoder.take_unknown_tags();
+        if let Some(usage) = &mut driver.usage {
+            usage.record_errors(unknown_tags.values().sum());
+        }
+        driver.status.record_unknown_tags(unknown_tags);
+        match outcome {
+            ReadOutcome::Message(message) => {
+                let events = driver.update(message)?;
+                driver.status.record_session(Some(&message), &events);
+                driver.send_events(vm, events)?;
+            }
+            ReadOutcome::Retry => {}
+            ReadOutcome::End(end) => return Ok(end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Interpolation, TapToClick};
+    use crate::dump::DumpWriter;
+    use crate::geo::AABB;
+    use crate::protocol::{Contact, USBPacket};
+    use egalax_protocol::fixtures::{self, Canned};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts the allocations of each thread, so that tests running in parallel do not disturb each other.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS
+                .try_with(|count| count.set(count.get() + 1))
+                .ok();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn test_slots_track_fingers() {
+        let mut slots = Slots::default();
+        let mut events = EventGen::new(TimeVal::new(0, 0), Vec::new());
+        let position = Point2D::from((10, 20));
+
+        slots.update(&mut events, 0, TouchState::IsTouching, position);
+        slots.update(&mut events, 1, TouchState::IsTouching, position);
+        slots.update(&mut events, 0, TouchState::IsTouching, position);
+        slots.update(&mut events, 0, TouchState::NotTouching, position);
+        slots.update(&mut events, 0, TouchState::IsTouching, position);
+        slots.release_all(&mut events);
+
+        let tracking_ids: Vec<i32> = events
+            .finish()
+            .iter()
+            .filter(|event| event.event_code == EventCode::EV_ABS(EV_ABS::ABS_MT_TRACKING_ID))
+            .map(|event| event.value)
+            .collect();
+        assert_eq!(vec![0, 1, -1, 2, -1, -1], tracking_ids);
+    }
+
+    #[test]
+    fn test_second_finger_cancels_right_click() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"]
+            .as_table_mut()
+            .unwrap()
+            .insert(String::from("second_finger"), "cancel".into());
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            config_file.build_for_area(area, area),
+            DriverStatus::default(),
+        );
+        let start = Instant::now();
+
+        // The first finger is held longer than the right-click wait while a second finger rests on the screen.
+        let packet = |state| USBPacket::new(state, (2000, 2000).into(), 12).unwrap();
+        let mut events = Vec::new();
+        for (offset_ms, packet) in [
+            (0, Packet::Touch(packet(TouchState::IsTouching))),
+            (
+                100,
+                Packet::MultiTouch(Contact {
+                    id: 1,
+                    packet: packet(TouchState::IsTouching),
+                }),
+            ),
+            (2000, Packet::Touch(packet(TouchState::IsTouching))),
+            (2100, Packet::Touch(packet(TouchState::NotTouching))),
+        ] {
+            let message = packet.with_time(start + Duration::from_millis(offset_ms));
+            events.extend(driver.update(message).unwrap());
+        }
+        assert!(!events
+            .iter()
+            .any(|event| event.event_code == EventCode::EV_KEY(EV_KEY::BTN_RIGHT)));
+    }
+
+    #[test]
+    fn test_disabled_input() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let status = DriverStatus::default();
+        let mut driver = Driver::new(
+            ConfigFile::default().build_for_area(area, area),
+            status.clone(),
+        );
+        let start = Instant::now();
+        let packet = |state| Packet::Touch(USBPacket::new(state, (2000, 2000).into(), 12).unwrap());
+        let mut tap = |offset_ms| {
+            let mut events = Vec::new();
+            for (offset_ms, state) in [
+                (offset_ms, TouchState::IsTouching),
+                (offset_ms + 50, TouchState::IsTouching),
+                (offset_ms + 100, TouchState::NotTouching),
+            ] {
+                let message = packet(state).with_time(start + Duration::from_millis(offset_ms));
+                events.extend(driver.update(message).unwrap());
+                // Disabling the input in the middle of a touch cancels it.
+                if offset_ms == 50 {
+                    status.set_input_enabled(false);
+                }
+            }
+            events
+                .iter()
+                .any(|event| event.event_code == EventCode::EV_KEY(EV_KEY::BTN_LEFT))
+        };
+
+        assert!(!tap(0));
+        assert!(!status.input_enabled());
+        assert!(status.report().input_disabled);
+        assert!(!tap(1000));
+        status.set_input_enabled(true);
+        assert!(tap(2000));
+    }
+
+    #[test]
+    fn test_reset_forgets_touch() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        let common = config_file["common"].as_table_mut().unwrap();
+        common.insert(
+            String::from("smoothing"),
+            toml::from_str("filter = \"ema\"\nalpha = 0.5").unwrap(),
+        );
+        common.insert(
+            String::from("adaptive_noise"),
+            toml::from_str("multiplier = 3.0\nmax_threshold = 100.0").unwrap(),
+        );
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            config_file.build_for_area(area, area),
+            DriverStatus::default(),
+        );
+        let start = Instant::now();
+        let touch = |position: (i32, i32), offset_ms| {
+            Packet::Touch(USBPacket::new(TouchState::IsTouching, position.into(), 12).unwrap())
+                .with_time(start + Duration::from_millis(offset_ms))
+        };
+
+        // The finger is still on the screen when the driver is reset, e.g. on a resume.
+        driver.update(touch((400, 400), 0)).unwrap();
+        driver.update(touch((400, 400), 10)).unwrap();
+        driver.reset().unwrap();
+        let events = driver.update(touch((3600, 3600), 20)).unwrap();
+
+        // The new touch is neither smoothed towards the old position nor taken as a jump of the old touch.
+        let expected = driver
+            .config
+            .calibration()
+            .map_to((3600, 3600).into(), area);
+        assert!(events
+            .iter()
+            .any(|event| event.event_code == EventCode::EV_ABS(EV_ABS::ABS_X)
+                && event.value == expected.x.value()));
+        assert_eq!(0.0, driver.noise.as_ref().unwrap().noise());
+    }
+
+    #[test]
+    fn test_grace_period_on_virtual_clock() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"].as_table_mut().unwrap().insert(
+            String::from("startup_grace"),
+            toml::Value::try_from(Duration::from_secs(2)).unwrap(),
+        );
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            config_file.build_for_area(area, area),
+            DriverStatus::default(),
+        );
+        let start = Instant::now();
+        let clock = Clock::starting_at(start);
+        driver.clock = clock.clone();
+        driver.start_grace_period();
+
+        // Time only passes when the test says so, however long the test takes.
+        let packet = USBPacket::new(TouchState::IsTouching, (2000, 2000).into(), 12).unwrap();
+        clock.advance(Duration::from_secs(1));
+        let message = Packet::Touch(packet).with_time(clock.now());
+        assert!(driver.update(message).unwrap().is_empty());
+        clock.advance(Duration::from_secs(1));
+        let message = Packet::Touch(packet).with_time(clock.now());
+        assert!(!driver.update(message).unwrap().is_empty());
+        assert_eq!(start + Duration::from_secs(2), clock.now());
+        clock.advance_to(start);
+        assert_eq!(start + Duration::from_secs(2), driver.clock.now());
+    }
+
+    /// Feed a canned fixture through the driver and return the keys that were pressed (1) and released (0).
+    fn key_trace(driver: &mut Driver, fixture: &[Canned]) -> Vec<(EV_KEY, i32)> {
+        let start = Instant::now();
+        let mut keys = Vec::new();
+        for canned in fixture {
+            let time = start + Duration::from_millis(canned.offset_ms);
+            let events = driver.update(canned.decoded().with_time(time)).unwrap();
+            keys.extend(events.iter().filter_map(|event| match event.event_code {
+                EventCode::EV_KEY(key) => Some((key, event.value)),
+                _ => None,
+            }));
+        }
+        keys
+    }
+
+    #[test]
+    fn test_fixture_traces() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            ConfigFile::default().build_for_area(area, area),
+            DriverStatus::default(),
+        );
+        // With the default config the first finger holds the left button until it is lifted,
+        // and the second finger does not press anything.
+        let click = vec![(EV_KEY::BTN_LEFT, 1), (EV_KEY::BTN_LEFT, 0)];
+        for fixture in [fixtures::TAP, fixtures::DRAG, fixtures::TWO_FINGERS] {
+            assert_eq!(click, key_trace(&mut driver, fixture));
+        }
+    }
+
+    /// A driver for the fixtures with `option` of the common config set to `value`.
+    fn driver_with(option: &str, value: toml::Value) -> Driver {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"]
+            .as_table_mut()
+            .unwrap()
+            .insert(String::from(option), value);
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        Driver::new(
+            config_file.build_for_area(area, area),
+            DriverStatus::default(),
+        )
+    }
+
+    #[test]
+    fn test_tap_to_click_traces() {
+        let mut driver = driver_with(
+            "tap_to_click",
+            toml::Value::try_from(TapToClick {
+                max_duration: Duration::from_millis(200),
+                slop: 50.0,
+            })
+            .unwrap(),
+        );
+        // The tap stays within the slop and clicks on release, while the drag does not click at all.
+        assert_eq!(
+            vec![(EV_KEY::BTN_LEFT, 1), (EV_KEY::BTN_LEFT, 0)],
+            key_trace(&mut driver, fixtures::TAP)
+        );
+        assert_eq!(
+            Vec::<(EV_KEY, i32)>::new(),
+            key_trace(&mut driver, fixtures::DRAG)
+        );
+    }
+
+    #[test]
+    fn test_drag_hold_traces() {
+        let mut driver = driver_with(
+            "drag_hold",
+            toml::Value::try_from(Duration::from_millis(300)).unwrap(),
+        );
+        // The finger rests where the drag starts for longer than the drag hold, then drags.
+        let mut hold_then_drag: Vec<Canned> = [0, 200, 400]
+            .map(|offset_ms| Canned {
+                offset_ms,
+                ..fixtures::DRAG[0]
+            })
+            .to_vec();
+        hold_then_drag.extend(fixtures::DRAG[1..].iter().map(|canned| Canned {
+            offset_ms: canned.offset_ms + 400,
+            ..*canned
+        }));
+        // The left button goes down once the finger moves after the hold and stays down until it is lifted.
+        let (moves, lift) = hold_then_drag.split_at(hold_then_drag.len() - 1);
+        assert_eq!(vec![(EV_KEY::BTN_LEFT, 1)], key_trace(&mut driver, moves));
+        assert_eq!(vec![(EV_KEY::BTN_LEFT, 0)], key_trace(&mut driver, lift));
+
+        // Without the hold the drag only clicks when the finger is lifted, like a tap.
+        let (moves, lift) = fixtures::DRAG.split_at(fixtures::DRAG.len() - 1);
+        assert_eq!(Vec::<(EV_KEY, i32)>::new(), key_trace(&mut driver, moves));
+        assert_eq!(
+            vec![(EV_KEY::BTN_LEFT, 1), (EV_KEY::BTN_LEFT, 0)],
+            key_trace(&mut driver, lift)
+        );
+    }
+
+    #[test]
+    fn test_capped_prediction_traces() {
+        let mut driver = driver_with(
+            "interpolation",
+            toml::Value::try_from(Interpolation {
+                interval: Duration::from_millis(5),
+                max_extrapolation: Duration::from_millis(10),
+            })
+            .unwrap(),
+        );
+        let start = Instant::now();
+        let clock = Clock::starting_at(start);
+        driver.clock = clock.clone();
+
+        // Between the packets, which are 20ms apart, positions are predicted at the interval.
+        let mut keys = Vec::new();
+        let mut predicted = Vec::new();
+        for canned in fixtures::DRAG {
+            clock.advance_to(start + Duration::from_millis(canned.offset_ms));
+            let events = driver
+                .update(canned.decoded().with_time(clock.now()))
+                .unwrap();
+            keys.extend(events.iter().filter_map(|event| match event.event_code {
+                EventCode::EV_KEY(key) => Some((key, event.value)),
+                _ => None,
+            }));
+            for _ in 0..3 {
+                clock.advance(Duration::from_millis(5));
+                let events = driver.predict().unwrap();
+                assert!(!events
+                    .iter()
+                    .any(|event| matches!(event.event_code, EventCode::EV_KEY(_))));
+                predicted.push(
+                    events
+                        .iter()
+                        .any(|event| event.event_code == EventCode::EV_ABS(EV_ABS::ABS_X)),
+                );
+            }
+        }
+
+        // Predictions never press anything, so the drag clicks as usual.
+        assert_eq!(vec![(EV_KEY::BTN_LEFT, 1), (EV_KEY::BTN_LEFT, 0)], keys);
+        // Predicting needs two packets, stops after the maximum extrapolation and never continues after the lift.
+        let expected: Vec<bool> = fixtures::DRAG
+            .iter()
+            .enumerate()
+            .flat_map(|(i, canned)| {
+                let predicts = i > 0 && canned.touch_state == TouchState::IsTouching;
+                [predicts, predicts, false]
+            })
+            .collect();
+        assert_eq!(expected, predicted);
+    }
+
+    #[test]
+    fn test_sticky_drag_traces() {
+        let mut driver = driver_with("sticky_drag", true.into());
+        // The first tap latches the left button, a drag only moves the pointer and the next tap releases it.
+        assert_eq!(
+            vec![(EV_KEY::BTN_LEFT, 1)],
+            key_trace(&mut driver, fixtures::TAP)
+        );
+        assert_eq!(
+            Vec::<(EV_KEY, i32)>::new(),
+            key_trace(&mut driver, fixtures::DRAG)
+        );
+        assert_eq!(
+            vec![(EV_KEY::BTN_LEFT, 0)],
+            key_trace(&mut driver, fixtures::TAP)
+        );
+    }
+
+    #[test]
+    fn test_pressure_curve() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"]
+            .as_table_mut()
+            .unwrap()
+            .insert(String::from("pressure_curve"), "hard".into());
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            config_file.build_for_area(area, area),
+            DriverStatus::default(),
+        );
+        assert!(emits_pressure(&driver.config));
+        let start = Instant::now();
+
+        let packet = |state, pressure| {
+            USBPacket::new(state, (2000, 2000).into(), 12)
+                .unwrap()
+                .with_serial_pressure(pressure)
+                .unwrap()
+        };
+        let mut pressures = Vec::new();
+        for (offset_ms, packet) in [
+            (0, packet(TouchState::IsTouching, MAX_PRESSURE)),
+            (50, packet(TouchState::IsTouching, 64)),
+            (100, packet(TouchState::NotTouching, 64)),
+        ] {
+            let message = Packet::Touch(packet).with_time(start + Duration::from_millis(offset_ms));
+            let events = driver.update(message).unwrap();
+            pressures.extend(
+                events
+                    .iter()
+                    .filter(|event| event.event_code == EventCode::EV_ABS(EV_ABS::ABS_PRESSURE))
+                    .map(|event| event.value),
+            );
+        }
+        // The hard curve squares the normalized pressure, so that light strokes count less.
+        assert_eq!(vec![127, 32, 0], pressures);
+    }
+
+    #[test]
+    fn test_replay_dry_run() {
+        let mut dump = DumpWriter::new(Vec::new()).unwrap();
+        let start = Instant::now();
+        for canned in fixtures::TAP {
+            dump.write(start + Duration::from_millis(canned.offset_ms), canned.raw)
+                .unwrap();
+        }
+        dump.write(start, fixtures::MALFORMED[0].0).unwrap();
+        let bytes = dump.finish().unwrap();
+
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut offsets = Vec::new();
+        let mut keys = Vec::new();
+        let messages = replay_dry_run(
+            DumpReader::new(&bytes[..]).unwrap(),
+            ConfigFile::default().build_for_area(area, area),
+            |message, events| {
+                offsets.push(message.time());
+                keys.extend(events.iter().filter_map(|event| match event.event_code {
+                    EventCode::EV_KEY(key) => Some((key, event.value)),
+                    _ => None,
+                }));
+            },
+        )
+        .unwrap();
+
+        // The malformed packet is skipped and the others keep their original spacing.
+        assert_eq!(fixtures::TAP.len() as u64, messages);
+        assert_eq!(Duration::from_millis(80), offsets[2] - offsets[0]);
+        assert_eq!(vec![(EV_KEY::BTN_LEFT, 1), (EV_KEY::BTN_LEFT, 0)], keys);
+    }
+
+    #[test]
+    fn test_config_loader() {
+        let dir = std::env::temp_dir().join(format!("egalax-rs-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "not a config").unwrap();
+        let loader = ConfigLoader::spawn(path.clone(), None, DriverStatus::default());
+        let wait = || {
+            for _ in 0..100 {
+                if let Some(loaded) = loader.take() {
+                    return Some(loaded);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            None
+        };
+
+        // An invalid file keeps the old config, a valid one is handed over.
+        assert!(loader.take().is_none());
+        loader.request();
+        assert!(wait().is_none());
+        let config = "monitor_area = { x1 = 0, y1 = 0, x2 = 800, y2 = 600 }\n".to_string()
+            + &toml::to_string(&ConfigFile::default()).unwrap();
+        std::fs::write(&path, config).unwrap();
+        loader.request();
+        let monitor_cfg = wait().unwrap();
+        assert_eq!(AABB::from((0, 0, 800, 600)), monitor_cfg.monitor_area);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A stream that returns scripted results for each read, e.g. to simulate errors of the device.
+    struct Scripted(Vec<io::Result<Vec<u8>>>);
+
+    impl io::Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let bytes = self.0.remove(0)?;
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            Ok(bytes.len())
+        }
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let packet = [0x02, 0x03, 0x3b, 0x01, 0x32, 0x01];
+        let errno = |errno| Err(io::Error::from_raw_os_error(errno));
+        let mut decoder = PacketDecoder::new(Scripted(vec![
+            errno(libc::EINTR),
+            Ok(packet[..2].to_vec()),
+            errno(libc::EAGAIN),
+            Ok(packet[2..].to_vec()),
+            errno(libc::ENODEV),
+            errno(libc::EACCES),
+        ]));
+
+        // Interrupted reads are retried right away, and a read that would block is retried after polling again.
+        assert!(matches!(read_message(&mut decoder), Ok(ReadOutcome::Retry)));
+        assert!(matches!(
+            read_message(&mut decoder),
+            Ok(ReadOutcome::Message(_))
+        ));
+        assert!(matches!(
+            read_message(&mut decoder),
+            Ok(ReadOutcome::End(StreamEnd::Unplugged))
+        ));
+        assert!(read_message(&mut decoder).is_err());
+        assert!(matches!(
+            read_message(&mut decoder),
+            Ok(ReadOutcome::End(StreamEnd::Closed))
+        ));
+    }
+
+    #[test]
+    fn test_steady_state_does_not_allocate() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = ConfigFile::default().build_for_area(area, area);
+        let mut driver = Driver::new(config, DriverStatus::default());
+        let start = Instant::now();
+
+        // A touch that moves across the screen and ends with a click.
+        let touch = |driver: &mut Driver, offset_ms: u64| {
+            for i in 0..50 {
+                let packet =
+                    USBPacket::new(TouchState::IsTouching, (1000 + i * 20, 2000).into(), 12);
+                let time = start + Duration::from_millis(offset_ms + i as u64 * 10);
+                let events = driver.update(packet.unwrap().with_time(time)).unwrap();
+                driver.recycle_events(events);
+            }
+            let packet = USBPacket::new(TouchState::NotTouching, (2000, 2000).into(), 12);
+            let time = start + Duration::from_millis(offset_ms + 500);
+            let events = driver.update(packet.unwrap().with_time(time)).unwrap();
+            driver.recycle_events(events);
+        };
+
+        // The first touch grows the event buffer to its steady size.
+        touch(&mut driver, 0);
+        let before = allocations();
+        touch(&mut driver, 1000);
+        assert_eq!(0, allocations() - before);
+    }
 }
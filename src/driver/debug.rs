@@ -0,0 +1,181 @@
+//! Debug commands that the control interface passes to the packet loop, so that the state of the driver can be
+//! inspected and its options tuned while it runs. Only compiled with the `debug` feature.
+//!
+//! The commands are:
+//! - `state` reports the state of the current touch and the enabled gestures.
+//! - `get OPTION` reports the value of a common option.
+//! - `set OPTION VALUE` sets a common option to a value in TOML syntax, e.g. `set has_moved_threshold 80.0`.
+//!   Options that change the virtual device, e.g. the key codes, cannot be set.
+//! - `inject touch|release X Y [CONTACT]` feeds a packet at 12 bit touchscreen coordinates through the driver.
+
+use evdev_rs::{InputEvent, UInputDevice};
+use serde_json::{json, Map, Value};
+use std::sync::mpsc::TryRecvError;
+
+use super::Driver;
+use crate::error::EgalaxError;
+use crate::protocol::{Contact, Packet, TouchState, USBPacket};
+use crate::smoothing::SmoothingFilter;
+use crate::units::UdimRepr;
+
+/// Resolution of injected packets in bits.
+const INJECT_RESOLUTION: u8 = 12;
+
+impl Driver {
+    /// Execute the pending debug commands and send the events of injected packets to the virtual device.
+    pub(super) fn serve_debug_requests(&mut self, vm: &UInputDevice) -> Result<(), EgalaxError> {
+        loop {
+            let request = match self.debug.as_ref().map(|debug| debug.try_recv()) {
+                Some(Ok(request)) => request,
+                Some(Err(TryRecvError::Disconnected)) => {
+                    self.debug = None;
+                    return Ok(());
+                }
+                Some(Err(TryRecvError::Empty)) | None => return Ok(()),
+            };
+            log::debug!("Executing debug command '{}'", request.command);
+            let answer = match self.execute_debug(&request.command) {
+                Ok((answer, events)) => {
+                    self.send_events(vm, events)?;
+                    answer
+                }
+                Err(e) => json!({ "error": e }),
+            };
+            // The client may have given up waiting.
+            request.reply.send(answer.to_string()).ok();
+        }
+    }
+
+    /// Execute a debug command and return its answer and the events to emit.
+    pub(super) fn execute_debug(
+        &mut self,
+        command: &str,
+    ) -> Result<(Value, Vec<InputEvent>), String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["state"] => Ok((self.debug_state(), Vec::new())),
+            ["get", key] => Ok((self.option_answer(key)?, Vec::new())),
+            ["set", key, ..] => {
+                // The value is the rest of the line, since tables contain spaces.
+                let value = command.trim_start()["set".len()..].trim_start()[key.len()..].trim();
+                let config = self
+                    .config
+                    .with_option(key, value)
+                    .map_err(|e| e.to_string())?;
+                let old_config = self.config.clone();
+                if self.set_config(config) {
+                    self.set_config(old_config);
+                    return Err(format!("'{}' cannot be changed without a restart", key));
+                }
+                // The filters keep state across touches, so they only pick up new options when created again.
+                self.smoothing = self.config.smoothing().map(SmoothingFilter::new);
+                log::info!("Set {} to {} from the debug interface.", key, value);
+                Ok((self.option_answer(key)?, Vec::new()))
+            }
+            ["inject", state, x, y, contact @ ..] if contact.len() <= 1 => {
+                let touch_state = match *state {
+                    "touch" => TouchState::IsTouching,
+                    "release" => TouchState::NotTouching,
+                    _ => return Err(format!("unknown touch state '{}'", state)),
+                };
+                let parse = |word: &str| {
+                    word.parse::<UdimRepr>()
+                        .map_err(|_| format!("invalid number '{}'", word))
+                };
+                let position = (parse(x)?, parse(y)?).into();
+                let packet = USBPacket::new(touch_state, position, INJECT_RESOLUTION)
+                    .map_err(|e| e.to_string())?;
+                let packet = match contact.first() {
+                    None => Packet::Touch(packet),
+                    Some(id) => Packet::MultiTouch(Contact {
+                        id: id
+                            .parse()
+                            .map_err(|_| format!("invalid contact '{}'", id))?,
+                        packet,
+                    }),
+                };
+                let message = packet.with_time(self.clock.now());
+                let events = self.update(message).map_err(|e| e.to_string())?;
+                self.status.record_session(Some(&message), &events);
+                let answer = events
+                    .iter()
+                    .map(|event| Value::from(format!("{:?} {}", event.event_code, event.value)))
+                    .collect();
+                Ok((Value::Array(answer), events))
+            }
+            _ => Err(format!("unknown debug command '{}'", command)),
+        }
+    }
+
+    /// An object with the value of a common option.
+    fn option_answer(&self, key: &str) -> Result<Value, String> {
+        let value = self.config.option(key).map_err(|e| e.to_string())?;
+        let mut answer = Map::new();
+        answer.insert(key.to_string(), json!(value));
+        Ok(Value::Object(answer))
+    }
+
+    /// The state of the current touch and the enabled gestures.
+    fn debug_state(&self) -> Value {
+        let state = &self.state;
+        json!({
+            "touching": state.touch_state == TouchState::IsTouching,
+            "position": state.kinematics.position(),
+            "hold_fired": state.hold_fired,
+            "has_moved": state.has_moved,
+            "hold_cancelled": state.hold_cancelled,
+            "is_dragging": state.is_dragging,
+            "has_scrolled": state.has_scrolled,
+            "has_pinched": state.has_pinched,
+            "has_swiped": state.has_swiped,
+            "sticky_latched": self.sticky_latched,
+            "idle": self.is_idle(),
+            "gestures": self.gestures.names(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+    use crate::control::DriverStatus;
+    use crate::geo::AABB;
+    use evdev_rs::enums::EV_KEY;
+
+    #[test]
+    fn test_debug_commands() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let mut driver = Driver::new(
+            ConfigFile::default().build_for_area(area, area),
+            DriverStatus::default(),
+        );
+
+        let (answer, _) = driver
+            .execute_debug("set has_moved_threshold 80.0")
+            .unwrap();
+        assert_eq!(json!({ "has_moved_threshold": 80.0 }), answer);
+        assert_eq!(80.0, driver.config.has_moved_threshold());
+        let (answer, _) = driver
+            .execute_debug("set smoothing { filter = \"ema\", alpha = 0.5 }")
+            .unwrap();
+        assert_eq!("ema", answer["smoothing"]["filter"]);
+        assert!(driver.smoothing.is_some());
+        // The virtual device would need other keys, and unknown options are rejected.
+        assert!(driver
+            .execute_debug("set ev_left_click \"BTN_MIDDLE\"")
+            .is_err());
+        assert_eq!(EV_KEY::BTN_LEFT, driver.config.ev_left_click());
+        assert!(driver.execute_debug("get frobnicate").is_err());
+
+        driver.execute_debug("inject touch 2048 2048").unwrap();
+        let (answer, _) = driver.execute_debug("state").unwrap();
+        assert_eq!(json!(true), answer["touching"]);
+        let (answer, _) = driver.execute_debug("inject release 2048 2048").unwrap();
+        assert!(answer
+            .as_array()
+            .unwrap()
+            .contains(&json!("EV_KEY(BTN_LEFT) 1")));
+        assert!(driver.execute_debug("inject hover 1 2").is_err());
+    }
+}
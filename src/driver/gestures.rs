@@ -0,0 +1,874 @@
+//! Recognition of gestures from the touches of the driver.
+//!
+//! Each gesture is a [GestureRecognizer] that looks at the frames of a touch and the state shared by all gestures,
+//! and returns what it recognized as a [GestureAction] that the driver performs. The [GestureRegistry] holds the
+//! recognizers that are enabled by the config. Recognizers of several fingers see the frames of all contacts.
+//! Recognizers of the first finger are asked in order and the first one that recognizes something consumes the frame,
+//! e.g. so that lifting the finger at the end of a drag does not click as well.
+
+use evdev_rs::enums::EV_KEY;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Instant;
+
+use super::DriverState;
+use crate::config::{self, ClickAction, Config, DoubleTapAction, MaintenanceAction};
+use crate::control::Gesture;
+use crate::edge_swipe::{Edge, EdgeSwipeRecognizer};
+use crate::geo::Point2D;
+use crate::noise::NoiseEstimator;
+use crate::pinch::{PinchRecognizer, Zoom};
+use crate::scroll::{ScrollDelta, ScrollRecognizer};
+use crate::units::{MonitorSpace, ScreenSpace, TouchSpace};
+
+/// Where a finger is in its touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TouchPhase {
+    /// The finger landed.
+    Down,
+    /// The finger is still on the panel. The driver does not track when secondary contacts land,
+    /// so all their frames while touching are moves.
+    Move,
+    /// The finger was lifted.
+    Up,
+}
+
+/// A packet of one contact as seen by the recognizers.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TouchFrame {
+    pub contact: u8,
+    pub phase: TouchPhase,
+    pub time: Instant,
+    /// Position in screen space, after smoothing.
+    pub position: Point2D<ScreenSpace>,
+    /// Position relative to the monitor, to look up zones.
+    pub local_position: Point2D<MonitorSpace>,
+}
+
+impl TouchFrame {
+    /// The position while the finger is on the panel.
+    fn touching_position(&self) -> Option<Point2D<ScreenSpace>> {
+        (self.phase != TouchPhase::Up).then_some(self.position)
+    }
+}
+
+/// What the driver should do for a recognized gesture.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum GestureAction {
+    /// Press a button, e.g. at the start of a drag.
+    Press(EV_KEY, Gesture),
+    /// Release a button, e.g. at the end of a drag.
+    Release(EV_KEY, Gesture),
+    /// Fire a click binding when the finger was lifted.
+    Click(ClickAction),
+    /// Fire a click binding while the finger is held down.
+    LongPress(ClickAction),
+    /// Press a key combination for a swipe in from an edge.
+    EdgeSwipe(Edge, Vec<EV_KEY>),
+    /// Fire the double tap action, with the position of the first tap.
+    DoubleTap(DoubleTapAction, Point2D<ScreenSpace>),
+    Scroll(ScrollDelta),
+    Zoom(Zoom),
+    /// Fire nothing for a touch whose click is suppressed.
+    SuppressClick,
+    /// Fire the action of the maintenance taps.
+    Maintenance(MaintenanceAction),
+}
+
+/// What recognizers know about the driver besides the frame.
+pub(super) struct GestureContext<'a> {
+    pub config: &'a Config,
+    /// State of the current touch of the first finger, which recognizers update to tell each other what the touch did.
+    pub touch: &'a mut DriverState,
+    /// If the left button is latched down by a tap in sticky drag mode.
+    pub sticky_latched: &'a mut bool,
+    pub noise: Option<&'a NoiseEstimator>,
+}
+
+impl GestureContext<'_> {
+    /// If the touch did nothing yet that decides what it is, so that it may still start a drag or fire a hold binding.
+    /// While a sticky drag is latched, touches only move the pointer.
+    fn undecided(&self) -> bool {
+        !self.touch.hold_fired
+            && !self.touch.has_moved
+            && !self.made_gesture()
+            && !*self.sticky_latched
+    }
+
+    /// If the touch scrolled, zoomed or swiped, so that nothing else fires for it.
+    fn made_gesture(&self) -> bool {
+        self.touch.has_scrolled || self.touch.has_pinched || self.touch.has_swiped
+    }
+
+    /// Raise a threshold of the config if the touches are noisy, see [NoiseEstimator].
+    fn raise_threshold(&self, threshold: f32) -> f32 {
+        self.noise.map_or(threshold, |noise| noise.raise(threshold))
+    }
+
+    /// If the finger moved further than the has-moved threshold since it landed.
+    fn has_moved(&self) -> bool {
+        self.touch.kinematics.max_distance()
+            > self.raise_threshold(self.config.has_moved_threshold())
+    }
+
+    /// Checks if the current touch may emit a left-click on release.
+    /// Without tap-to-click every touch clicks, otherwise only short touches that stayed in place.
+    fn is_tap(&self, now: Instant) -> bool {
+        let Some(tap) = self.config.tap_to_click() else {
+            return true;
+        };
+
+        let kinematics = &self.touch.kinematics;
+        let is_tap = kinematics.duration(now) <= tap.max_duration
+            && kinematics.max_distance() <= self.raise_threshold(tap.slop);
+        if !is_tap {
+            log::info!("Touch was not a tap. Suppressing left-click.");
+        }
+        is_tap
+    }
+}
+
+/// Recognizes a gesture from the frames of touches.
+pub(super) trait GestureRecognizer: fmt::Debug {
+    /// Name of the gesture for the logs.
+    fn name(&self) -> &'static str;
+
+    /// Look at a frame and return the action for a gesture, if one was recognized.
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction>;
+
+    /// If the gesture involves several fingers, so that it must see the frames of all contacts.
+    fn multi_finger(&self) -> bool {
+        false
+    }
+
+    /// If several fingers are currently making the gesture, so that the first one must not move the pointer.
+    fn holds_pointer(&self) -> bool {
+        false
+    }
+
+    /// Forget the current touch, e.g. after a resume or when it was rejected as a palm.
+    fn reset(&mut self) {}
+}
+
+/// Moving the finger after holding it still for `drag_hold` drags with the left button held down.
+#[derive(Debug)]
+struct Drag;
+
+impl GestureRecognizer for Drag {
+    fn name(&self) -> &'static str {
+        "drag"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        match frame.phase {
+            TouchPhase::Move
+                if ctx.undecided()
+                    && ctx.has_moved()
+                    && ctx
+                        .config
+                        .drag_hold()
+                        .is_some_and(|hold| ctx.touch.kinematics.duration(frame.time) >= hold) =>
+            {
+                log::info!("Finger has moved after holding. Starting drag.");
+                ctx.touch.has_moved = true;
+                ctx.touch.is_dragging = true;
+                Some(GestureAction::Press(
+                    ctx.config.ev_left_click(),
+                    Gesture::DragStart,
+                ))
+            }
+            TouchPhase::Up if ctx.touch.is_dragging => {
+                log::info!("Ending drag.");
+                Some(GestureAction::Release(
+                    ctx.config.ev_left_click(),
+                    Gesture::DragEnd,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A finger that moves further than the has-moved threshold disables hold bindings.
+#[derive(Debug)]
+struct Movement;
+
+impl GestureRecognizer for Movement {
+    fn name(&self) -> &'static str {
+        "movement"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase == TouchPhase::Move && ctx.undecided() && ctx.has_moved() {
+            log::info!("Finger has moved while touching. Disabling hold bindings.");
+            ctx.touch.has_moved = true;
+        }
+        None
+    }
+}
+
+/// Fires the longest hold binding (by default a right-click) once the finger was held still long enough.
+#[derive(Debug)]
+struct LongPress;
+
+impl GestureRecognizer for LongPress {
+    fn name(&self) -> &'static str {
+        "long-press"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase != TouchPhase::Move || !ctx.undecided() || ctx.touch.hold_cancelled {
+            return None;
+        }
+        let held = ctx.touch.kinematics.duration(frame.time);
+        let binding = ctx
+            .config
+            .longest_hold_binding(frame.local_position)
+            .filter(|binding| held > binding.hold)?;
+
+        log::info!("Holding {}.", binding);
+        ctx.touch.hold_fired = true;
+        Some(GestureAction::LongPress(binding.action.clone()))
+    }
+}
+
+/// A series of quick taps in a zone fires the maintenance action instead of the click of the last tap,
+/// see [config::MaintenanceTap].
+#[derive(Debug)]
+struct MaintenanceTaps {
+    params: config::MaintenanceTap,
+    /// Release times of the taps in the zone that may still be part of the series.
+    releases: VecDeque<Instant>,
+}
+
+impl GestureRecognizer for MaintenanceTaps {
+    fn name(&self) -> &'static str {
+        "maintenance-tap"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase != TouchPhase::Up {
+            return None;
+        }
+        // Any other touch breaks the series.
+        if ctx.touch.hold_fired
+            || ctx.touch.has_moved
+            || ctx.made_gesture()
+            || !self.params.zone.contains(&frame.local_position)
+            || !ctx.is_tap(frame.time)
+        {
+            self.releases.clear();
+            return None;
+        }
+
+        while self
+            .releases
+            .front()
+            .is_some_and(|first| frame.time.duration_since(*first) > self.params.window)
+        {
+            self.releases.pop_front();
+        }
+        self.releases.push_back(frame.time);
+        if self.releases.len() < self.params.taps {
+            return None;
+        }
+
+        log::info!("Maintenance taps. Firing {}.", self.params.action);
+        self.releases.clear();
+        Some(GestureAction::Maintenance(self.params.action.clone()))
+    }
+
+    fn reset(&mut self) {
+        self.releases.clear();
+    }
+}
+
+/// Suppresses the clicks of short touches near the edges of the monitor, see [config::EdgeClickGuard].
+#[derive(Debug)]
+struct EdgeGuard {
+    params: config::EdgeClickGuard,
+    width: f32,
+    height: f32,
+}
+
+impl GestureRecognizer for EdgeGuard {
+    fn name(&self) -> &'static str {
+        "edge-guard"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase != TouchPhase::Up || ctx.touch.hold_fired || ctx.made_gesture() {
+            return None;
+        }
+        let held = ctx.touch.kinematics.duration(frame.time);
+        if !self
+            .params
+            .suppresses(frame.local_position, self.width, self.height, held)
+        {
+            return None;
+        }
+
+        log::info!("Touch near the edge. Suppressing clicks.");
+        Some(GestureAction::SuppressClick)
+    }
+}
+
+/// With sticky drag a tap toggles the left button, so that dragging needs no sustained pressure.
+#[derive(Debug)]
+struct StickyDrag;
+
+impl GestureRecognizer for StickyDrag {
+    fn name(&self) -> &'static str {
+        "sticky-drag"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        // While latched the movement is not recorded in the touch state, so it is checked here,
+        // otherwise the end of a latched drag would release the button.
+        if frame.phase != TouchPhase::Up
+            || ctx.touch.hold_fired
+            || ctx.touch.has_moved
+            || ctx.has_moved()
+            || ctx.made_gesture()
+            || !ctx.is_tap(frame.time)
+        {
+            return None;
+        }
+
+        *ctx.sticky_latched = !*ctx.sticky_latched;
+        let left_click = ctx.config.ev_left_click();
+        if *ctx.sticky_latched {
+            log::info!("Latching sticky drag.");
+            Some(GestureAction::Press(left_click, Gesture::StickyDragLatch))
+        } else {
+            log::info!("Releasing sticky drag.");
+            Some(GestureAction::Release(
+                left_click,
+                Gesture::StickyDragRelease,
+            ))
+        }
+    }
+}
+
+/// A second tap shortly after the first one at about the same place fires the double tap action instead of its click.
+#[derive(Debug)]
+struct DoubleTap {
+    params: config::DoubleTap,
+    /// Time of the release and positions of the last tap, until it is too old to start a double tap.
+    last_tap: Option<(Instant, Point2D<TouchSpace>, Point2D<ScreenSpace>)>,
+}
+
+impl GestureRecognizer for DoubleTap {
+    fn name(&self) -> &'static str {
+        "double-tap"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase != TouchPhase::Up {
+            return None;
+        }
+        let last_tap = self.last_tap.take();
+        let position = ctx.touch.kinematics.position()?;
+        if ctx.touch.hold_fired
+            || ctx.touch.has_moved
+            || ctx.touch.hold_cancelled
+            || ctx.made_gesture()
+            || *ctx.sticky_latched
+            || !ctx.is_tap(frame.time)
+        {
+            return None;
+        }
+
+        match last_tap {
+            Some((time, first, first_position))
+                if frame.time.duration_since(time) <= self.params.window
+                    && position.euclidean_distance_to(&first) <= self.params.distance =>
+            {
+                log::info!("Double tap. Firing {}.", self.params.action);
+                Some(GestureAction::DoubleTap(self.params.action, first_position))
+            }
+            _ => {
+                self.last_tap = Some((frame.time, position, frame.position));
+                None
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last_tap = None;
+    }
+}
+
+/// Fires the click binding for how long the finger was held when it is lifted, by default a left-click on a tap.
+#[derive(Debug)]
+struct Tap;
+
+impl GestureRecognizer for Tap {
+    fn name(&self) -> &'static str {
+        "tap"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        if frame.phase != TouchPhase::Up {
+            return None;
+        }
+        if ctx.made_gesture() {
+            log::info!("Touch scrolled, zoomed or swiped. Suppressing clicks.");
+            return None;
+        }
+        if ctx.touch.hold_fired || *ctx.sticky_latched {
+            return None;
+        }
+
+        let held = ctx.touch.kinematics.duration(frame.time);
+        let still = !ctx.touch.has_moved && !ctx.touch.hold_cancelled;
+        // Taps are subject to tap-to-click, bindings with a hold duration are not.
+        let binding = ctx
+            .config
+            .click_binding(held, still, frame.local_position)
+            .filter(|binding| !binding.hold.is_zero() || ctx.is_tap(frame.time))?;
+
+        log::info!("Releasing {}.", binding);
+        Some(GestureAction::Click(binding.action.clone()))
+    }
+}
+
+impl GestureRecognizer for EdgeSwipeRecognizer {
+    fn name(&self) -> &'static str {
+        "edge-swipe"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        match frame.phase {
+            // While a sticky drag is latched, touches only move the pointer.
+            TouchPhase::Down if *ctx.sticky_latched => self.reset(),
+            TouchPhase::Down => self.touch_down(frame.local_position),
+            TouchPhase::Move if !ctx.touch.hold_fired && !ctx.touch.is_dragging => {
+                let (edge, keys) = self.touch_moved(frame.local_position)?;
+                log::info!("Swiped in from the {} edge.", edge);
+                ctx.touch.has_swiped = true;
+                return Some(GestureAction::EdgeSwipe(edge, keys.to_vec()));
+            }
+            TouchPhase::Move | TouchPhase::Up => {}
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        EdgeSwipeRecognizer::reset(self);
+    }
+}
+
+impl GestureRecognizer for PinchRecognizer {
+    fn name(&self) -> &'static str {
+        "pinch"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        let zoom = self.update(frame.contact, frame.touching_position())?;
+        ctx.touch.has_pinched = true;
+        Some(GestureAction::Zoom(zoom))
+    }
+
+    fn multi_finger(&self) -> bool {
+        true
+    }
+
+    fn holds_pointer(&self) -> bool {
+        self.is_pinching()
+    }
+
+    fn reset(&mut self) {
+        PinchRecognizer::reset(self);
+    }
+}
+
+impl GestureRecognizer for ScrollRecognizer {
+    fn name(&self) -> &'static str {
+        "scroll"
+    }
+
+    fn recognize(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) -> Option<GestureAction> {
+        let delta = self.update(frame.contact, frame.touching_position())?;
+        // Once the fingers zoomed they do not scroll anymore, since a pinch also moves the point between them.
+        if ctx.touch.has_pinched {
+            return None;
+        }
+        ctx.touch.has_scrolled = true;
+        Some(GestureAction::Scroll(delta))
+    }
+
+    fn multi_finger(&self) -> bool {
+        true
+    }
+
+    fn holds_pointer(&self) -> bool {
+        self.is_scrolling()
+    }
+
+    fn reset(&mut self) {
+        ScrollRecognizer::reset(self);
+    }
+}
+
+/// The recognizers enabled by a config and the actions they recognized in the current frame.
+#[derive(Debug, Default)]
+pub(super) struct GestureRegistry {
+    recognizers: Vec<Box<dyn GestureRecognizer>>,
+    /// Buffer for the actions of a frame that is reused, so that recognizing gestures does not allocate.
+    actions: Vec<GestureAction>,
+}
+
+impl GestureRegistry {
+    /// Create the recognizers for the gestures that a config enables.
+    /// Recognizers of several fingers come first so that they can hold the pointer before the first finger is looked at.
+    pub fn from_config(config: &Config) -> Self {
+        let mut recognizers: Vec<Box<dyn GestureRecognizer>> = Vec::new();
+        if let Some(pinch) = config.pinch_zoom() {
+            recognizers.push(Box::new(PinchRecognizer::new(pinch)));
+        }
+        if let Some(scroll) = config.two_finger_scroll() {
+            recognizers.push(Box::new(ScrollRecognizer::new(scroll)));
+        }
+        if let Some(edge_swipe) = config.edge_swipe() {
+            let area = config.monitor_area;
+            recognizers.push(Box::new(EdgeSwipeRecognizer::new(
+                edge_swipe.clone(),
+                area.width().float(),
+                area.height().float(),
+            )));
+        }
+        if config.drag_hold().is_some() {
+            recognizers.push(Box::new(Drag));
+        }
+        recognizers.push(Box::new(Movement));
+        if config
+            .all_click_bindings()
+            .any(|binding| !binding.hold.is_zero())
+        {
+            recognizers.push(Box::new(LongPress));
+        }
+        // Before the edge guard, since the taps usually go into a corner.
+        if let Some(params) = config.maintenance_tap() {
+            recognizers.push(Box::new(MaintenanceTaps {
+                params: params.clone(),
+                releases: VecDeque::with_capacity(params.taps),
+            }));
+        }
+        if let Some(params) = config.edge_click_guard() {
+            let area = config.monitor_area;
+            recognizers.push(Box::new(EdgeGuard {
+                params,
+                width: area.width().float(),
+                height: area.height().float(),
+            }));
+        }
+        if config.sticky_drag() {
+            recognizers.push(Box::new(StickyDrag));
+        }
+        if let Some(params) = config.double_tap() {
+            recognizers.push(Box::new(DoubleTap {
+                params,
+                last_tap: None,
+            }));
+        }
+        recognizers.push(Box::new(Tap));
+
+        let registry = Self {
+            recognizers,
+            actions: Vec::new(),
+        };
+        log::debug!("Recognizing gestures: {}.", registry.names().join(", "));
+        registry
+    }
+
+    /// Names of the enabled gestures in the order they are recognized.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.recognizers
+            .iter()
+            .map(|recognizer| recognizer.name())
+            .collect()
+    }
+
+    /// If any gesture involves several fingers, so that the frames of secondary contacts are needed.
+    pub fn multi_finger(&self) -> bool {
+        self.recognizers
+            .iter()
+            .any(|recognizer| recognizer.multi_finger())
+    }
+
+    /// If several fingers are making a gesture, so that the first one must not move the pointer.
+    pub fn holds_pointer(&self) -> bool {
+        self.recognizers
+            .iter()
+            .any(|recognizer| recognizer.holds_pointer())
+    }
+
+    /// Pass a frame to the recognizers and collect the recognized actions, see [GestureRegistry::take_actions].
+    pub fn update(&mut self, frame: &TouchFrame, ctx: &mut GestureContext) {
+        for recognizer in self.recognizers.iter_mut() {
+            if recognizer.multi_finger() {
+                self.actions.extend(recognizer.recognize(frame, ctx));
+            }
+        }
+        if frame.contact != 0 || self.holds_pointer() {
+            return;
+        }
+        for recognizer in self.recognizers.iter_mut() {
+            if !recognizer.multi_finger() {
+                if let Some(action) = recognizer.recognize(frame, ctx) {
+                    self.actions.push(action);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The actions recognized since the last call.
+    pub fn take_actions(&mut self) -> std::vec::Drain<'_, GestureAction> {
+        self.actions.drain(..)
+    }
+
+    /// Forget the current touch in all recognizers.
+    pub fn reset(&mut self) {
+        for recognizer in self.recognizers.iter_mut() {
+            recognizer.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFile;
+    use crate::geo::AABB;
+    use std::time::Duration;
+
+    #[test]
+    fn test_gesture_registry() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = ConfigFile::default().build_for_area(area, area);
+        let mut registry = GestureRegistry::from_config(&config);
+        assert_eq!(vec!["movement", "long-press", "tap"], registry.names());
+        assert!(!registry.multi_finger());
+
+        // Holding the finger still for the right-click wait fires the right-click, and nothing fires on release.
+        let mut touch = DriverState::default();
+        let mut sticky_latched = false;
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (phase, offset) in [
+            (TouchPhase::Down, 0),
+            (TouchPhase::Move, 1000),
+            (TouchPhase::Move, 2000),
+            (TouchPhase::Up, 2100),
+        ] {
+            let frame = TouchFrame {
+                contact: 0,
+                phase,
+                time: start + Duration::from_millis(offset),
+                position: (500, 500).into(),
+                local_position: (500, 500).into(),
+            };
+            if phase != TouchPhase::Up {
+                touch.kinematics.update(frame.time, (2000, 2000).into());
+            }
+            let mut ctx = GestureContext {
+                config: &config,
+                touch: &mut touch,
+                sticky_latched: &mut sticky_latched,
+                noise: None,
+            };
+            registry.update(&frame, &mut ctx);
+            actions.extend(registry.take_actions());
+        }
+        assert_eq!(
+            vec![GestureAction::LongPress(ClickAction::Button(
+                EV_KEY::BTN_RIGHT
+            ))],
+            actions
+        );
+    }
+
+    #[test]
+    fn test_edge_guard() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"].as_table_mut().unwrap().insert(
+            String::from("edge_click_guard"),
+            toml::toml! {
+                edge_width = 20.0
+                min_duration = { secs = 0, nanos = 150000000 }
+            }
+            .into(),
+        );
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = config_file.build_for_area(area, area);
+        let mut registry = GestureRegistry::from_config(&config);
+        assert_eq!(
+            vec!["movement", "long-press", "edge-guard", "tap"],
+            registry.names()
+        );
+
+        // A short touch at the edge does not click, a longer one there and a short one further inside do.
+        let mut sticky_latched = false;
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (offset, x, duration) in [(0, 990, 50), (500, 990, 200), (1000, 500, 50)] {
+            let mut touch = DriverState::default();
+            for (phase, delay) in [(TouchPhase::Down, 0), (TouchPhase::Up, duration)] {
+                let frame = TouchFrame {
+                    contact: 0,
+                    phase,
+                    time: start + Duration::from_millis(offset + delay),
+                    position: (x, 500).into(),
+                    local_position: (x, 500).into(),
+                };
+                if phase == TouchPhase::Down {
+                    touch.kinematics.update(frame.time, (2000, 2000).into());
+                }
+                let mut ctx = GestureContext {
+                    config: &config,
+                    touch: &mut touch,
+                    sticky_latched: &mut sticky_latched,
+                    noise: None,
+                };
+                registry.update(&frame, &mut ctx);
+                actions.extend(registry.take_actions());
+            }
+        }
+        let click = GestureAction::Click(ClickAction::Button(EV_KEY::BTN_LEFT));
+        assert_eq!(
+            vec![GestureAction::SuppressClick, click.clone(), click],
+            actions
+        );
+    }
+
+    #[test]
+    fn test_maintenance_tap() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"].as_table_mut().unwrap().insert(
+            String::from("maintenance_tap"),
+            toml::toml! {
+                taps = 3
+                window = { secs = 2, nanos = 0 }
+                zone = { x1 = 0, y1 = 0, x2 = 100, y2 = 100 }
+                action = { keys = ["KEY_LEFTCTRL", "KEY_F12"] }
+            }
+            .into(),
+        );
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = config_file.build_for_area(area, area);
+        let mut registry = GestureRegistry::from_config(&config);
+        assert_eq!(
+            vec!["movement", "long-press", "maintenance-tap", "tap"],
+            registry.names()
+        );
+
+        // A tap outside of the corner breaks the series, and the taps of a series must be quick enough.
+        let mut sticky_latched = false;
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (offset, x) in [
+            (0, 50),
+            (500, 50),
+            (1000, 500),
+            (1500, 50),
+            (4000, 50),
+            (4500, 50),
+            (5000, 50),
+        ] {
+            let mut touch = DriverState::default();
+            for (phase, delay) in [(TouchPhase::Down, 0), (TouchPhase::Up, 50)] {
+                let frame = TouchFrame {
+                    contact: 0,
+                    phase,
+                    time: start + Duration::from_millis(offset + delay),
+                    position: (x, 50).into(),
+                    local_position: (x, 50).into(),
+                };
+                if phase == TouchPhase::Down {
+                    touch.kinematics.update(frame.time, (2000, 2000).into());
+                }
+                let mut ctx = GestureContext {
+                    config: &config,
+                    touch: &mut touch,
+                    sticky_latched: &mut sticky_latched,
+                    noise: None,
+                };
+                registry.update(&frame, &mut ctx);
+                actions.extend(registry.take_actions());
+            }
+        }
+        let click = GestureAction::Click(ClickAction::Button(EV_KEY::BTN_LEFT));
+        let mut expected = vec![click; 6];
+        expected.push(GestureAction::Maintenance(MaintenanceAction::Keys(vec![
+            EV_KEY::KEY_LEFTCTRL,
+            EV_KEY::KEY_F12,
+        ])));
+        assert_eq!(expected, actions);
+    }
+
+    #[test]
+    fn test_double_tap() {
+        let mut config_file = toml::Value::try_from(ConfigFile::default()).unwrap();
+        config_file["common"].as_table_mut().unwrap().insert(
+            String::from("double_tap"),
+            toml::toml! {
+                window = { secs = 0, nanos = 400000000 }
+                distance = 100.0
+                action = { key = "KEY_ENTER" }
+            }
+            .into(),
+        );
+        let config_file: ConfigFile = config_file.try_into().unwrap();
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config = config_file.build_for_area(area, area);
+        let mut registry = GestureRegistry::from_config(&config);
+        assert_eq!(
+            vec!["movement", "long-press", "double-tap", "tap"],
+            registry.names()
+        );
+
+        // The first tap clicks, the second one close by fires the double tap instead, and a third one starts over.
+        let mut sticky_latched = false;
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (offset, x) in [(0, 2000), (300, 2050), (600, 2000)] {
+            let mut touch = DriverState::default();
+            for (phase, delay) in [(TouchPhase::Down, 0), (TouchPhase::Up, 100)] {
+                let frame = TouchFrame {
+                    contact: 0,
+                    phase,
+                    time: start + Duration::from_millis(offset + delay),
+                    position: (500, 500).into(),
+                    local_position: (500, 500).into(),
+                };
+                if phase == TouchPhase::Down {
+                    touch.kinematics.update(frame.time, (x, 2000).into());
+                }
+                let mut ctx = GestureContext {
+                    config: &config,
+                    touch: &mut touch,
+                    sticky_latched: &mut sticky_latched,
+                    noise: None,
+                };
+                registry.update(&frame, &mut ctx);
+                actions.extend(registry.take_actions());
+            }
+        }
+        let click = GestureAction::Click(ClickAction::Button(EV_KEY::BTN_LEFT));
+        assert_eq!(
+            vec![
+                click.clone(),
+                GestureAction::DoubleTap(
+                    DoubleTapAction::Key(EV_KEY::KEY_ENTER),
+                    (500, 500).into()
+                ),
+                click
+            ],
+            actions
+        );
+    }
+}
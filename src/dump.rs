@@ -0,0 +1,208 @@
+//! Packet dumps: the raw packets of a touchscreen with the time they arrived, e.g. to attach to a bug report
+//! or to replay them later. Unlike a plain copy of the device node like `dumps/hidraw.bin`, the timing of
+//! the touches is kept, and malformed packets are kept as they arrived.
+//! A dump is replayed with [play], or without a virtual device with [crate::driver::replay_dry_run].
+//!
+//! A dump starts with [DUMP_MAGIC], followed by one frame per packet: the microseconds since the first packet
+//! as a little-endian `u64`, then the [RAW_PACKET_LEN] bytes of the packet.
+
+use anyhow::anyhow;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::EgalaxError;
+use crate::protocol::{RawPacket, RAW_PACKET_LEN};
+use crate::systemd;
+
+/// Identifies a packet dump and the version of its format.
+pub const DUMP_MAGIC: &[u8; 8] = b"EGXDUMP1";
+/// Length of a frame in a dump.
+const FRAME_LEN: usize = 8 + RAW_PACKET_LEN;
+/// How often a recording checks for a termination signal while no packets arrive.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A packet of a dump.
+#[derive(Debug, Clone, Copy)]
+pub struct DumpFrame {
+    /// Time since the first packet of the dump.
+    pub offset: Duration,
+    pub packet: RawPacket,
+}
+
+/// Writes packets into a dump.
+#[derive(Debug)]
+pub struct DumpWriter<W: Write> {
+    writer: W,
+    started: Option<Instant>,
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Start a dump by writing its header.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(DUMP_MAGIC)?;
+        Ok(Self {
+            writer,
+            started: None,
+        })
+    }
+
+    /// Add a packet that arrived at `time`.
+    pub fn write(&mut self, time: Instant, packet: RawPacket) -> io::Result<()> {
+        let started = *self.started.get_or_insert(time);
+        let offset = time.saturating_duration_since(started).as_micros() as u64;
+        self.writer.write_all(&offset.to_le_bytes())?;
+        self.writer.write_all(&packet.0)
+    }
+
+    /// Flush the dump and return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the packets of a dump.
+#[derive(Debug)]
+pub struct DumpReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Fails if the dump does not start with [DUMP_MAGIC].
+    pub fn new(mut reader: R) -> Result<Self, EgalaxError> {
+        let mut magic = [0; DUMP_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != DUMP_MAGIC {
+            return Err(anyhow!("not a packet dump").into());
+        }
+        Ok(Self { reader })
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<DumpFrame, EgalaxError>;
+
+    /// The next frame, or `None` at the end of the dump. A truncated last frame is an error.
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = [0; FRAME_LEN];
+        let mut read = 0;
+        while read < FRAME_LEN {
+            match self.reader.read(&mut frame[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => {
+                    return Some(Err(anyhow!("truncated frame at the end of the dump").into()))
+                }
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        let (offset, packet) = frame.split_at(8);
+        Some(Ok(DumpFrame {
+            offset: Duration::from_micros(u64::from_le_bytes(offset.try_into().unwrap())),
+            packet: RawPacket(packet.try_into().unwrap()),
+        }))
+    }
+}
+
+/// Record the packets read from `stream` into `dump` until the stream ends or [systemd::termination_requested].
+/// Returns the number of recorded packets.
+pub fn record<T, W>(stream: &mut T, dump: &mut DumpWriter<W>) -> Result<u64, EgalaxError>
+where
+    T: Read + AsRawFd,
+    W: Write,
+{
+    let fd = stream.as_raw_fd();
+    let mut buffer = [0; 64];
+    let mut pending = Vec::with_capacity(RAW_PACKET_LEN);
+    let mut packets = 0;
+
+    while !systemd::termination_requested() {
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, POLL_INTERVAL.as_millis() as i32) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => return Err(io::Error::from(e).into()),
+        }
+
+        let n = match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let time = Instant::now();
+        // A report usually arrives in one read, but packets are framed by length in case it does not.
+        for &byte in &buffer[..n] {
+            pending.push(byte);
+            if pending.len() == RAW_PACKET_LEN {
+                dump.write(time, RawPacket(pending[..].try_into().unwrap()))?;
+                pending.clear();
+                packets += 1;
+            }
+        }
+    }
+    Ok(packets)
+}
+
+/// Write the packets of a dump to `writer` with their original timing, i.e. each packet is written once its
+/// offset has passed. Returns the number of written packets.
+pub fn play<R, W>(dump: DumpReader<R>, writer: &mut W) -> Result<u64, EgalaxError>
+where
+    R: Read,
+    W: Write,
+{
+    let start = Instant::now();
+    let mut packets = 0;
+    for frame in dump {
+        let frame = frame?;
+        let deadline = start + frame.offset;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+        writer.write_all(&frame.packet.0)?;
+        writer.flush()?;
+        packets += 1;
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_roundtrip() {
+        let start = Instant::now();
+        let packets = [
+            RawPacket([0x02, 0x03, 0x3b, 0x01, 0x32, 0x01]),
+            // Malformed packets are kept as they are.
+            RawPacket([0xaa, 0x02, 0x35, 0x01, 0x39, 0x01]),
+        ];
+        let mut dump = DumpWriter::new(Vec::new()).unwrap();
+        dump.write(start, packets[0]).unwrap();
+        dump.write(start + Duration::from_millis(12), packets[1])
+            .unwrap();
+        let bytes = dump.finish().unwrap();
+        assert_eq!(DUMP_MAGIC.len() + 2 * FRAME_LEN, bytes.len());
+
+        let frames: Vec<DumpFrame> = DumpReader::new(&bytes[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(Duration::ZERO, frames[0].offset);
+        assert_eq!(Duration::from_millis(12), frames[1].offset);
+        assert_eq!(packets[1].0, frames[1].packet.0);
+
+        assert!(DumpReader::new(&bytes[1..]).is_err());
+        let truncated: Vec<_> = DumpReader::new(&bytes[..bytes.len() - 1])
+            .unwrap()
+            .collect();
+        assert!(truncated[1].is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Swiping in from an edge of the monitor, emitted as key combinations, e.g. to go back and forward in a kiosk browser.
+//!
+//! A swipe starts when the first finger lands close to an edge that has keys configured, and fires once the finger
+//! moved far enough away from that edge. It fires while the finger is still down, so nothing else fires on release.
+
+use evdev_rs::enums::EV_KEY;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::geo::Point2D;
+use crate::units::MonitorSpace;
+
+/// An edge of the monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Edge::Left => "left",
+            Edge::Right => "right",
+            Edge::Top => "top",
+            Edge::Bottom => "bottom",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Parameters for swiping in from the edges of the monitor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeSwipe {
+    /// How close to an edge in pixels a swipe must start.
+    pub edge_width: f32,
+    /// How far the finger must move away from the edge in pixels.
+    pub min_travel: f32,
+    /// The key combination that a swipe from each edge presses. Edges without keys do not swipe.
+    pub keys: BTreeMap<Edge, Vec<EV_KEY>>,
+}
+
+impl EdgeSwipe {
+    /// The keys that the virtual device must support.
+    pub fn keys(&self) -> impl Iterator<Item = EV_KEY> + '_ {
+        self.keys.values().flatten().copied()
+    }
+}
+
+impl fmt::Display for EdgeSwipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let edges = self
+            .keys
+            .iter()
+            .map(|(edge, keys)| {
+                let keys = keys
+                    .iter()
+                    .map(|key| format!("{:?}", key))
+                    .collect::<Vec<_>>()
+                    .join("+");
+                format!("{} {}", edge, keys)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        f.write_fmt(format_args!(
+            "{} within {}px after {}px",
+            edges, self.edge_width, self.min_travel
+        ))
+    }
+}
+
+/// Tracks where the first finger landed and recognizes when it swipes in from an edge.
+#[derive(Debug, Clone)]
+pub struct EdgeSwipeRecognizer {
+    params: EdgeSwipe,
+    /// Width and height of the monitor in pixels.
+    size: (f32, f32),
+    /// The edge and position where the current touch started, until the swipe fired.
+    start: Option<(Edge, Point2D<MonitorSpace>)>,
+}
+
+impl EdgeSwipeRecognizer {
+    pub fn new(params: EdgeSwipe, width: f32, height: f32) -> Self {
+        Self {
+            params,
+            size: (width, height),
+            start: None,
+        }
+    }
+
+    /// Forget the current touch.
+    pub fn reset(&mut self) {
+        self.start = None;
+    }
+
+    /// The finger landed. A swipe starts if it landed close to an edge with keys, the closest one in a corner.
+    pub fn touch_down(&mut self, position: Point2D<MonitorSpace>) {
+        let (width, height) = self.size;
+        let (x, y) = (position.x.float(), position.y.float());
+        self.start = [
+            (Edge::Left, x),
+            (Edge::Right, width - x),
+            (Edge::Top, y),
+            (Edge::Bottom, height - y),
+        ]
+        .into_iter()
+        .filter(|(edge, distance)| {
+            *distance <= self.params.edge_width && self.params.keys.contains_key(edge)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(edge, _)| (edge, position));
+    }
+
+    /// The finger moved. Returns the edge and its keys once the finger moved far enough away from the edge
+    /// where it landed, at most once per touch.
+    pub fn touch_moved(&mut self, position: Point2D<MonitorSpace>) -> Option<(Edge, &[EV_KEY])> {
+        let (edge, start) = self.start?;
+        let (dx, dy) = (
+            (position.x - start.x).float(),
+            (position.y - start.y).float(),
+        );
+        let travel = match edge {
+            Edge::Left => dx,
+            Edge::Right => -dx,
+            Edge::Top => dy,
+            Edge::Bottom => -dy,
+        };
+        if travel < self.params.min_travel {
+            return None;
+        }
+        self.start = None;
+        Some((edge, &self.params.keys[&edge]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_swipe_recognizer() {
+        let mut swipe = EdgeSwipeRecognizer::new(
+            EdgeSwipe {
+                edge_width: 20.0,
+                min_travel: 100.0,
+                keys: BTreeMap::from([
+                    (Edge::Left, vec![EV_KEY::KEY_BACK]),
+                    (Edge::Right, vec![EV_KEY::KEY_FORWARD]),
+                ]),
+            },
+            1000.0,
+            800.0,
+        );
+
+        // Swiping in from the left edge fires once the finger moved far enough, and only once.
+        swipe.touch_down((10, 400).into());
+        assert_eq!(None, swipe.touch_moved((60, 400).into()));
+        assert_eq!(
+            Some((Edge::Left, &[EV_KEY::KEY_BACK][..])),
+            swipe.touch_moved((120, 420).into())
+        );
+        assert_eq!(None, swipe.touch_moved((300, 420).into()));
+
+        // Moving along the right edge is not a swipe.
+        swipe.touch_down((990, 400).into());
+        assert_eq!(None, swipe.touch_moved((990, 600).into()));
+        assert_eq!(
+            Some((Edge::Right, &[EV_KEY::KEY_FORWARD][..])),
+            swipe.touch_moved((850, 600).into())
+        );
+
+        // Edges without keys and touches away from the edges do not swipe.
+        swipe.touch_down((500, 5).into());
+        assert_eq!(None, swipe.touch_moved((500, 300).into()));
+        swipe.touch_down((500, 400).into());
+        assert_eq!(None, swipe.touch_moved((800, 400).into()));
+    }
+}
@@ -1,9 +1,10 @@
 //! Our application errors.
 
+use serde::Serialize;
 use std::{io, time};
 use thiserror::Error;
 
-use crate::units::DimE;
+pub use egalax_protocol::error::ParsePacketError;
 
 /// General error type.
 #[derive(Error, Debug)]
@@ -12,6 +13,14 @@ pub enum EgalaxError {
     Device,
     #[error("Monitor \"{0}\" not found")]
     MonitorNotFound(String),
+    #[error("No eGalax touchscreen found, pass its hidraw device node explicitly")]
+    NoEgalaxDevice,
+    #[error("Multiple eGalax touchscreens found ({0}), pass one of their hidraw device nodes explicitly")]
+    AmbiguousEgalaxDevice(String),
+    #[error("Cannot open X display")]
+    XDisplay,
+    #[error("Nothing happened for {0:?}")]
+    Timeout(time::Duration),
     #[error("{0}")]
     Time(#[from] time::SystemTimeError),
     #[error("{0}")]
@@ -24,11 +33,17 @@ pub enum EgalaxError {
     Generic(#[from] anyhow::Error),
 }
 
-/// Errors that can happen during parsing of a packet
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum ParsePacketError {
-    #[error("Unexpected packet tag: {0}")]
-    UnexpectedTag(u8),
-    #[error("{0:?} value is out of range of given resolution")]
-    WrongResolution(DimE),
+/// Errors of the privileged helper that applies a config file.
+/// They are serialized so that a frontend running without privileges can tell the user what went wrong.
+#[derive(Error, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "error", content = "message", rename_all = "kebab-case")]
+pub enum ApplyConfigError {
+    #[error("'{0}' is not an allowed config location")]
+    ForbiddenPath(String),
+    #[error("Config is too large: {0} bytes")]
+    TooLarge(usize),
+    #[error("Config is invalid: {0}")]
+    Invalid(String),
+    #[error("Cannot write config: {0}")]
+    Write(String),
 }
@@ -0,0 +1,93 @@
+//! Audible feedback for emitted clicks, so that blind or low-vision users know that a touch registered.
+//!
+//! Only available with the `audio` feature. The feedback runs in its own thread so that a slow sound never delays input.
+
+use evdev_rs::enums::EV_KEY;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::{fmt, process, thread};
+use x11::xlib;
+
+use crate::x11::XConnection;
+
+/// How a click is made audible.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClickFeedback {
+    /// Ring the X bell with a volume between -100 and 100 percent relative to the base volume.
+    Bell(i8),
+    /// Run a command with `sh -c`, e.g. to play a sound file or forward the click to an accessibility tool.
+    /// The button is passed in the `EGALAX_CLICK` environment variable, e.g. `BTN_LEFT`.
+    Command(String),
+}
+
+impl fmt::Display for ClickFeedback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClickFeedback::Bell(percent) => f.write_fmt(format_args!("bell at {}%", percent)),
+            ClickFeedback::Command(command) => f.write_fmt(format_args!("`{}`", command)),
+        }
+    }
+}
+
+/// Handle to the thread that plays the feedback.
+#[derive(Debug)]
+pub struct FeedbackPlayer {
+    sender: Sender<EV_KEY>,
+}
+
+impl FeedbackPlayer {
+    pub fn spawn(feedback: ClickFeedback) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || feedback_loop(feedback, receiver));
+        Self { sender }
+    }
+
+    /// Notify that a button was clicked.
+    pub fn clicked(&self, button: EV_KEY) {
+        // If the thread is gone there is simply no feedback.
+        self.sender.send(button).ok();
+    }
+}
+
+fn feedback_loop(feedback: ClickFeedback, receiver: Receiver<EV_KEY>) {
+    // Only the bell needs a display, which is opened by the thread that uses it.
+    let connection = match feedback {
+        ClickFeedback::Bell(_) => match XConnection::open() {
+            Ok(connection) => Some(connection),
+            Err(e) => {
+                log::warn!("Could not open X display for click feedback: {}", e);
+                return;
+            }
+        },
+        ClickFeedback::Command(_) => None,
+    };
+
+    for button in receiver {
+        match &feedback {
+            ClickFeedback::Bell(percent) => {
+                if let Some(connection) = &connection {
+                    // SAFETY: the display is valid for the lifetime of the connection.
+                    unsafe {
+                        xlib::XBell(connection.display(), (*percent).clamp(-100, 100) as i32);
+                        xlib::XFlush(connection.display());
+                    }
+                }
+            }
+            ClickFeedback::Command(command) => {
+                let child = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("EGALAX_CLICK", format!("{:?}", button))
+                    .spawn();
+                match child {
+                    // Reap the command right away, the next click waits for it anyway.
+                    Ok(mut child) => {
+                        child.wait().ok();
+                    }
+                    Err(e) => log::warn!("Could not run click feedback command: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Tracking of the motion of a touch over time.
+//!
+//! The driver feeds every position of the current touch into a [Kinematics] tracker so that gestures can decide
+//! based on velocity, heading and travelled distance instead of comparing raw positions themselves.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::geo::Point2D;
+use crate::units::Space;
+
+/// A vector with floating point components, e.g. a velocity in units per second.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vector2D {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector2D {
+    /// Computes the length of the vector.
+    pub fn magnitude(&self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+}
+
+impl fmt::Display for Vector2D {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = format!("({:.1}, {:.1})", self.x, self.y);
+        f.write_str(&description)
+    }
+}
+
+/// Motion of a single touch in some coordinate space, from the first position until it is reset.
+#[derive(Debug, Clone, Copy)]
+pub struct Kinematics<S: Space> {
+    /// Time and position of the first sample.
+    origin: Option<(Instant, Point2D<S>)>,
+    /// Time and position of the latest sample.
+    last: Option<(Instant, Point2D<S>)>,
+    /// Number of samples since the last reset.
+    samples: usize,
+    /// Velocity between the last two samples in units per second.
+    velocity: Vector2D,
+    /// Change of velocity between the last samples in units per second squared.
+    acceleration: Vector2D,
+    /// Largest distance from the origin during the touch.
+    max_distance: f32,
+}
+
+impl<S: Space> Default for Kinematics<S> {
+    fn default() -> Self {
+        Self {
+            origin: None,
+            last: None,
+            samples: 0,
+            velocity: Vector2D::default(),
+            acceleration: Vector2D::default(),
+            max_distance: 0.0,
+        }
+    }
+}
+
+impl<S: Space> Kinematics<S> {
+    /// Add a new sample of the touch position.
+    pub fn update(&mut self, time: Instant, position: Point2D<S>) {
+        if let Some((last_time, last_position)) = self.last {
+            let dt = time.duration_since(last_time).as_secs_f32();
+            // Samples with identical timestamps carry no velocity information.
+            if dt > 0.0 {
+                let d = position - last_position;
+                let velocity = Vector2D {
+                    x: d.x.float() / dt,
+                    y: d.y.float() / dt,
+                };
+                self.acceleration = Vector2D {
+                    x: (velocity.x - self.velocity.x) / dt,
+                    y: (velocity.y - self.velocity.y) / dt,
+                };
+                self.velocity = velocity;
+            }
+        }
+
+        let (_, origin) = *self.origin.get_or_insert((time, position));
+        self.max_distance = self
+            .max_distance
+            .max(origin.euclidean_distance_to(&position));
+        self.last = Some((time, position));
+        self.samples += 1;
+    }
+
+    /// Forget all samples, e.g. when the finger is lifted.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Number of samples since the last reset.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The first position of the touch.
+    pub fn origin(&self) -> Option<Point2D<S>> {
+        self.origin.map(|(_, position)| position)
+    }
+
+    /// The latest position of the touch.
+    pub fn position(&self) -> Option<Point2D<S>> {
+        self.last.map(|(_, position)| position)
+    }
+
+    /// The time of the latest sample.
+    pub fn last_time(&self) -> Option<Instant> {
+        self.last.map(|(time, _)| time)
+    }
+
+    /// How long the touch has lasted until `now`.
+    pub fn duration(&self, now: Instant) -> Duration {
+        self.origin
+            .map_or(Duration::ZERO, |(time, _)| now.duration_since(time))
+    }
+
+    /// Velocity between the last two samples in units per second.
+    pub fn velocity(&self) -> Vector2D {
+        self.velocity
+    }
+
+    /// The magnitude of the velocity.
+    pub fn speed(&self) -> f32 {
+        self.velocity.magnitude()
+    }
+
+    /// Change of velocity between the last samples in units per second squared.
+    pub fn acceleration(&self) -> Vector2D {
+        self.acceleration
+    }
+
+    /// Direction of movement in radians, measured clockwise from the positive X axis since Y grows downward.
+    /// Returns `None` if the touch is not moving.
+    pub fn heading(&self) -> Option<f32> {
+        (self.speed() > 0.0).then(|| self.velocity.y.atan2(self.velocity.x))
+    }
+
+    /// Current distance from the first position.
+    pub fn distance_from_origin(&self) -> f32 {
+        match (self.origin, self.last) {
+            (Some((_, origin)), Some((_, last))) => origin.euclidean_distance_to(&last),
+            _ => 0.0,
+        }
+    }
+
+    /// Largest distance from the first position during the touch.
+    pub fn max_distance(&self) -> f32 {
+        self.max_distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::TouchSpace;
+
+    #[test]
+    fn test_kinematics_velocity_and_distance() {
+        let start = Instant::now();
+        let mut kinematics = Kinematics::<TouchSpace>::default();
+        assert_eq!(None, kinematics.heading());
+
+        kinematics.update(start, (0, 0).into());
+        kinematics.update(start + Duration::from_millis(100), (10, 0).into());
+        kinematics.update(start + Duration::from_millis(200), (30, 0).into());
+        kinematics.update(start + Duration::from_millis(300), (20, 0).into());
+
+        assert_eq!(4, kinematics.samples());
+        assert_eq!(Vector2D { x: -100.0, y: 0.0 }, kinematics.velocity());
+        assert_eq!(Some(std::f32::consts::PI), kinematics.heading());
+        assert_eq!(20.0, kinematics.distance_from_origin());
+        assert_eq!(30.0, kinematics.max_distance());
+        assert_eq!(
+            Duration::from_millis(300),
+            kinematics.duration(start + Duration::from_millis(300))
+        );
+    }
+}
@@ -1,6 +1,45 @@
+pub use egalax_protocol::{geo, units};
+
+#[cfg(feature = "mqtt")]
+pub mod analytics;
+pub mod calibration;
+pub mod calibrator;
+pub mod clock;
 pub mod config;
+pub mod config_reference;
+pub mod control;
+pub mod cursor;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod devices;
+pub mod doctor;
+pub mod drift;
 pub mod driver;
+pub mod dump;
+pub mod edge_swipe;
 pub mod error;
-pub mod geo;
+#[cfg(feature = "audio")]
+pub mod feedback;
+pub mod kinematics;
+pub mod lint;
+pub mod locate;
+pub mod noise;
+pub mod overlay;
+pub mod palm;
+pub mod pinch;
+pub mod pressure;
 pub mod protocol;
-pub mod units;
+pub mod pump;
+pub mod recorder;
+pub mod rotation;
+pub mod scroll;
+pub mod serial;
+pub mod simulate;
+pub mod smoothing;
+pub mod systemd;
+pub mod test_pattern;
+pub mod usage;
+#[cfg(feature = "wayland")]
+pub mod wayland;
+pub mod window;
+mod x11;
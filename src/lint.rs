@@ -0,0 +1,296 @@
+//! Lint rules for suspicious combinations of config values, used by the `check-config` and `doctor` subcommands.
+//!
+//! Every value of a config can be valid on its own while the combination makes no sense, e.g. a tap slop larger
+//! than the monitor turns every touch into a tap. These mistakes are easy to make when editing the file by hand
+//! and hard to notice until someone uses the kiosk, so they are reported as warnings or errors before.
+
+use crate::config::{Config, ConfigFile};
+use crate::doctor::{Finding, Severity};
+use crate::geo::AABB;
+use crate::smoothing::Filter;
+use crate::units::MonitorSpace;
+
+/// Lint a config file. The monitors are queried like the driver does, and if that fails the rules about zones are skipped.
+pub fn lint_config_file(config_file: ConfigFile) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let config = match config_file.clone().build() {
+        Ok(config) => config,
+        Err(e) => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!("Could not query the monitors, zones are not checked: {}", e),
+            ));
+            config_file.build_for_area(AABB::default(), AABB::default())
+        }
+    };
+
+    findings.extend(lint(&config));
+    if findings.is_empty() {
+        findings.push(Finding::new(
+            Severity::Ok,
+            "No suspicious combinations of config values.",
+        ));
+    }
+    findings
+}
+
+/// Apply all lint rules to a config. An empty monitor area means that the monitors are unknown.
+pub fn lint(config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    lint_calibration(config, &mut findings);
+    lint_timing(config, &mut findings);
+    lint_thresholds(config, &mut findings);
+    if config.monitor_area.area() > 0 {
+        lint_zones(config, &mut findings);
+    }
+    findings
+}
+
+fn lint_calibration(config: &Config, findings: &mut Vec<Finding>) {
+    let points = config.calibration_points();
+    if points.width().value() == 0 || points.height().value() == 0 {
+        findings.push(Finding::new(
+            Severity::Error,
+            format!(
+                "The calibration points {} span no area, so all touches end up on a single line. Run `calibrate` again.",
+                points
+            ),
+        ));
+    }
+}
+
+fn lint_timing(config: &Config, findings: &mut Vec<Finding>) {
+    let right_click_wait = config.right_click_wait();
+    if let Some(tap) = config.tap_to_click() {
+        if tap.max_duration >= right_click_wait {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "`tap_to_click.max_duration` ({}ms) is not shorter than `right_click_wait` ({}ms), \
+                    so a long tap right-clicks instead of clicking.",
+                    tap.max_duration.as_millis(),
+                    right_click_wait.as_millis()
+                ),
+            ));
+        }
+    }
+    if let Some(drag_hold) = config.drag_hold() {
+        if drag_hold >= right_click_wait {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "`drag_hold` ({}ms) is not shorter than `right_click_wait` ({}ms), \
+                    so holding still to drag right-clicks instead.",
+                    drag_hold.as_millis(),
+                    right_click_wait.as_millis()
+                ),
+            ));
+        }
+    }
+    if let Some(maintenance) = config.maintenance_tap() {
+        if maintenance.taps < 3 {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "`maintenance_tap.taps` is only {}, so ordinary taps may fire the maintenance action by accident.",
+                    maintenance.taps
+                ),
+            ));
+        }
+    }
+    if let Some(interpolation) = config.interpolation() {
+        if interpolation.interval.is_zero() {
+            findings.push(Finding::new(
+                Severity::Error,
+                "`interpolation.interval` is zero, so predicted positions would be emitted in a busy loop.",
+            ));
+        }
+    }
+}
+
+fn lint_thresholds(config: &Config, findings: &mut Vec<Finding>) {
+    let points = config.calibration_points();
+    let panel_size = points.width().value().min(points.height().value()) as f32;
+    if let Some(tap) = config.tap_to_click() {
+        if panel_size > 0.0 && tap.slop >= panel_size {
+            findings.push(Finding::new(
+                Severity::Error,
+                format!(
+                    "`tap_to_click.slop` ({}mm) is larger than the monitor, so every touch counts as a tap.",
+                    tap.slop * 0.1
+                ),
+            ));
+        }
+    }
+    if let Some(noise) = config.adaptive_noise() {
+        if noise.max_threshold <= config.has_moved_threshold() {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "`adaptive_noise.max_threshold` ({}mm) is not above `has_moved_threshold` ({}mm), \
+                    so the has-moved threshold is never raised.",
+                    noise.max_threshold * 0.1,
+                    config.has_moved_threshold() * 0.1
+                ),
+            ));
+        }
+    }
+    if config
+        .pressure_curve()
+        .is_some_and(|curve| !curve.is_valid())
+    {
+        findings.push(Finding::new(
+            Severity::Error,
+            "The control points of `pressure_curve` must lie between 0 and 1 with ascending inputs.",
+        ));
+    }
+    if let Some(serial) = config.serial().filter(|serial| serial.speed().is_none()) {
+        findings.push(Finding::new(
+            Severity::Error,
+            format!(
+                "`serial.baud_rate` ({}) is not a standard baud rate, so the serial port cannot be opened.",
+                serial.baud_rate
+            ),
+        ));
+    }
+    let invalid_filter = |filter| match filter {
+        Filter::Ema { alpha } => !(alpha > 0.0 && alpha <= 1.0),
+        Filter::OneEuro {
+            min_cutoff,
+            beta,
+            d_cutoff,
+        } => min_cutoff <= 0.0 || beta < 0.0 || d_cutoff <= 0.0,
+    };
+    if let Some(smoothing) = config.smoothing() {
+        if smoothing.filters().into_iter().any(invalid_filter) {
+            findings.push(Finding::new(
+                Severity::Error,
+                "`smoothing` needs an alpha in (0, 1] or positive cutoff frequencies, otherwise the cursor does not follow the finger.",
+            ));
+        }
+        if smoothing.bypass_speed.is_some_and(|speed| speed <= 0.0) {
+            findings.push(Finding::new(
+                Severity::Error,
+                "`smoothing.bypass_speed` is not positive, so the positions are never smoothed.",
+            ));
+        }
+    }
+}
+
+fn lint_zones(config: &Config, findings: &mut Vec<Finding>) {
+    let monitor = AABB::<MonitorSpace>::from((
+        0,
+        0,
+        config.monitor_area.width().value(),
+        config.monitor_area.height().value(),
+    ));
+    let mut zones: Vec<(String, AABB<MonitorSpace>)> = Vec::new();
+    for binding in config.all_click_bindings() {
+        if let Some(zone) = binding.zone {
+            zones.push((format!("Click binding '{}'", binding), zone));
+        }
+    }
+    if let Some(maintenance) = config.maintenance_tap() {
+        zones.push((String::from("Maintenance tap zone"), maintenance.zone));
+    }
+    if let Some(usage) = config.usage_statistics() {
+        for (name, zone) in &usage.zones {
+            zones.push((format!("Usage statistics zone '{}'", name), *zone));
+        }
+    }
+    #[cfg(feature = "mqtt")]
+    if let Some(analytics) = config.touch_analytics() {
+        for (name, zone) in &analytics.zones {
+            zones.push((format!("Touch analytics zone '{}'", name), *zone));
+        }
+    }
+
+    for (name, zone) in &zones {
+        match zone.intersect(monitor) {
+            None => findings.push(Finding::new(
+                Severity::Error,
+                format!(
+                    "{} at {} lies outside of the monitor {}, so it is never touched.",
+                    name, zone, monitor
+                ),
+            )),
+            Some(visible) if visible != *zone => findings.push(Finding::new(
+                Severity::Warning,
+                format!(
+                    "{} at {} extends beyond the monitor {}.",
+                    name, zone, monitor
+                ),
+            )),
+            Some(_) => {}
+        }
+    }
+
+    // Among bindings with the same hold duration the first one wins, so the others never fire where they overlap.
+    let bindings: Vec<_> = config
+        .click_bindings()
+        .iter()
+        .filter(|binding| binding.zone.is_some())
+        .collect();
+    for (i, first) in bindings.iter().enumerate() {
+        for second in &bindings[i + 1..] {
+            let overlap = first.zone.and_then(|zone| zone.intersect(second.zone?));
+            if first.hold == second.hold && overlap.is_some_and(|overlap| overlap.area() > 0) {
+                findings.push(Finding::new(
+                    Severity::Warning,
+                    format!(
+                        "Click bindings '{}' and '{}' overlap, so the second one never fires in the overlap.",
+                        first, second
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let config_file = ConfigFile::default();
+        assert!(lint(&config_file.clone().build_for_area(area, area)).is_empty());
+
+        let suspicious = r#"
+            [common.tap_to_click]
+            max_duration = { secs = 2, nanos = 0 }
+            slop = 50000.0
+
+            [[common.click_bindings]]
+            hold = { secs = 3, nanos = 0 }
+            action = { command = "notify-send admin" }
+            zone = { x1 = 0, y1 = 0, x2 = 100, y2 = 100 }
+
+            [[common.click_bindings]]
+            hold = { secs = 3, nanos = 0 }
+            action = { button = "BTN_MIDDLE" }
+            zone = { x1 = 50, y1 = 50, x2 = 150, y2 = 150 }
+
+            [[common.click_bindings]]
+            action = { button = "BTN_LEFT" }
+            zone = { x1 = 2000, y1 = 0, x2 = 2100, y2 = 100 }
+        "#;
+        let config = toml::to_string(&config_file).unwrap() + suspicious;
+        let config_file: ConfigFile = toml::from_str(&config).unwrap();
+        let findings = lint(&config_file.build_for_area(area, area));
+
+        let severities: Vec<_> = findings.iter().map(|finding| finding.severity).collect();
+        assert_eq!(
+            vec![
+                Severity::Warning,
+                Severity::Error,
+                Severity::Error,
+                Severity::Warning
+            ],
+            severities
+        );
+        assert!(findings[0].message.contains("right_click_wait"));
+        assert!(findings[3].message.contains("overlap"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Identifying which monitor a touchscreen is mapped to, e.g. when installing a wall of several screens.
+//!
+//! The mapped monitor flashes a few times and then shows where the next touch lands, so that both the mapping
+//! and the calibration can be checked at a glance.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use crate::calibration::{Calibration, TouchCloud};
+use crate::error::EgalaxError;
+use crate::geo::{Point2D, AABB};
+use crate::overlay::{Overlay, BLACK, GREEN, RED};
+use crate::protocol::{PacketFormat, TouchState, USBMessage};
+use crate::pump::PacketPump;
+use crate::units::ScreenSpace;
+
+/// Locating is aborted if nobody touches the screen for this long.
+pub const LOCATE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the monitor flashes, and how long each flash and each pause takes.
+const FLASHES: u32 = 3;
+const FLASH_DURATION: Duration = Duration::from_millis(300);
+/// How long the marker is shown where the touch landed.
+const MARKER_DURATION: Duration = Duration::from_secs(2);
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+const MARKER_RADIUS: i32 = 25;
+
+/// Collects the packets of the first finger until it is lifted.
+#[derive(Debug, Clone, Default)]
+pub struct TouchLocator {
+    cloud: TouchCloud,
+}
+
+impl TouchLocator {
+    /// Feed a message and return where the touch landed in screen space once the finger was lifted.
+    pub fn update(
+        &mut self,
+        message: &USBMessage,
+        calibration: &Calibration,
+        area: AABB<ScreenSpace>,
+    ) -> Option<Point2D<ScreenSpace>> {
+        if message.contact() != 0 {
+            return None;
+        }
+        let packet = message.packet();
+        match packet.touch_state() {
+            TouchState::IsTouching => {
+                self.cloud.push(packet.position());
+                None
+            }
+            TouchState::NotTouching => {
+                let position = self.cloud.compute_touch_coord()?;
+                self.cloud.clear();
+                Some(calibration.map_to(position, area))
+            }
+        }
+    }
+}
+
+/// If the monitor is lit by a flash at `elapsed` after locating started.
+fn flash_lit(elapsed: Duration) -> bool {
+    let period = elapsed.as_millis() / FLASH_DURATION.as_millis();
+    period < u128::from(2 * FLASHES) && period.is_multiple_of(2)
+}
+
+/// Flash the monitor area with `label` on it, then wait for a touch read from `stream` and mark where it landed.
+/// The packets of `stream` are in `format`.
+/// Returns the position of the touch. Fails if the stream ends or nobody touches the screen for [LOCATE_TIMEOUT].
+pub fn locate<T>(
+    stream: T,
+    format: PacketFormat,
+    area: AABB<ScreenSpace>,
+    calibration: &Calibration,
+    label: &str,
+) -> Result<Point2D<ScreenSpace>, EgalaxError>
+where
+    T: io::Read + AsRawFd + Send + 'static,
+{
+    log::trace!("Entering fn locate");
+
+    let overlay = Overlay::open(area)?;
+    let pump = PacketPump::spawn(stream, format);
+    let mut locator = TouchLocator::default();
+    let start = Instant::now();
+    let mut last_activity = start;
+
+    let touch = loop {
+        overlay.clear();
+        if flash_lit(start.elapsed()) {
+            overlay.set_color(GREEN);
+            overlay.fill();
+        }
+        overlay.set_color(BLACK);
+        overlay.draw_text_centered(label, area.height().value() / 3);
+        overlay.draw_text_centered(
+            "Touch this screen to check where touches land",
+            area.height().value() / 3 + 30,
+        );
+        overlay.flush();
+
+        let Some(message) = pump.recv_timeout(REDRAW_INTERVAL)? else {
+            if last_activity.elapsed() > LOCATE_TIMEOUT {
+                return Err(EgalaxError::Timeout(LOCATE_TIMEOUT));
+            }
+            continue;
+        };
+        last_activity = message.time();
+        if let Some(touch) = locator.update(&message, calibration, area) {
+            break touch;
+        }
+    };
+    log::info!("Touch landed at {}", touch);
+
+    overlay.clear();
+    overlay.set_color(BLACK);
+    overlay.draw_text_centered(label, area.height().value() / 3);
+    overlay.set_color(RED);
+    overlay.draw_target(touch, MARKER_RADIUS);
+    overlay.flush();
+    std::thread::sleep(MARKER_DURATION);
+
+    log::trace!("Leaving fn locate");
+    Ok(touch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::Origin;
+    use crate::protocol::USBPacket;
+    use crate::rotation::Rotation;
+
+    #[test]
+    fn test_locate_touch() {
+        let area = AABB::from((1000, 0, 2000, 1000));
+        let calibration = Calibration {
+            calibration_points: AABB::from((0, 0, 1000, 1000)),
+            origin: Origin::default(),
+            swap_axes: false,
+            rotation: Rotation::Normal,
+            affine: None,
+        };
+        assert!(flash_lit(Duration::ZERO));
+        assert!(!flash_lit(FLASH_DURATION));
+        assert!(!flash_lit(FLASH_DURATION * (2 * FLASHES)));
+
+        // The touch is reported in screen space when the finger is lifted.
+        let mut locator = TouchLocator::default();
+        let start = Instant::now();
+        let mut touches = Vec::new();
+        for (offset, state) in [
+            (0, TouchState::IsTouching),
+            (10, TouchState::IsTouching),
+            (20, TouchState::NotTouching),
+        ] {
+            let packet = USBPacket::new(state, (250, 500).into(), 12).unwrap();
+            let message = packet.with_time(start + Duration::from_millis(offset));
+            touches.extend(locator.update(&message, &calibration, area));
+        }
+        assert_eq!(vec![Point2D::from((1250, 500))], touches);
+    }
+}
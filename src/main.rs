@@ -1,23 +1,810 @@
-use egalax_rs::config::ConfigFile;
-use egalax_rs::driver::virtual_mouse;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use egalax_rs::calibrator::{self, CalibrateArgs, CalibrationMode};
+use egalax_rs::config::{self, ConfigFile, EffectiveValue, ValueSource};
+use egalax_rs::control::{self, ControlServer, DriverStatus, StatusReport};
+use egalax_rs::devices::{self, HidrawDevice};
+use egalax_rs::doctor::{self, Severity};
+use egalax_rs::driver::{self, virtual_mouse, virtual_mouse_device};
+use egalax_rs::dump::{self, DumpReader, DumpWriter};
+use egalax_rs::error::EgalaxError;
+use egalax_rs::lint;
+use egalax_rs::locate;
+use egalax_rs::serial;
+use egalax_rs::systemd::{self, Notifier};
+use egalax_rs::test_pattern;
+use egalax_rs::usage::{self, DailyCounts};
+use serde::Serialize;
+use std::error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::result::Result;
-use std::{error, fs::OpenOptions};
+use std::str::FromStr;
+use std::thread;
 
-const USAGE: &str = "Usage: egalax-rs /dev/hidraw.egalax";
+const CONFIG_PATH: &str = "./config.toml";
 
-/// Read configuration and delegate to virtual mouse function.
+/// Userspace driver for eGalax touchscreens. Runs the driver unless a subcommand is given.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Print JSON instead of text for informational subcommands.
+    #[arg(long, global = true)]
+    json: bool,
+    /// The config file.
+    #[arg(long, global = true, default_value = CONFIG_PATH)]
+    config: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+/// Options for running the driver.
+#[derive(Debug, clap::Args)]
+struct RunArgs {
+    /// Wait for the device node to appear instead of failing.
+    #[arg(long)]
+    wait_for_device: bool,
+    /// Notify systemd for Type=notify services and stop cleanly on SIGTERM.
+    #[arg(long)]
+    daemon: bool,
+    /// The hidraw node of the touchscreen. By default the eGalax touchscreen is detected by its USB vendor ID.
+    node: Option<PathBuf>,
+    /// Drive another touchscreen in the same process, optionally with its own config file for an independent
+    /// calibration, e.g. `--dev /dev/hidraw4=right.toml`. Can be repeated.
+    #[arg(long = "dev", value_name = "NODE[=CONFIG]")]
+    devices: Vec<DeviceArg>,
+    /// Drive every detected eGalax touchscreen with the same config file.
+    #[arg(long, conflicts_with_all = ["node", "devices"])]
+    all_devices: bool,
+}
+
+/// A touchscreen to drive and its config file, if it has one of its own.
+#[derive(Debug, Clone)]
+struct DeviceArg {
+    node: PathBuf,
+    config: Option<PathBuf>,
+}
+
+impl FromStr for DeviceArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (node, config) = match s.split_once('=') {
+            Some((node, config)) => (node, Some(PathBuf::from(config))),
+            None => (s, None),
+        };
+        if node.is_empty() {
+            return Err(String::from("the device node is missing"));
+        }
+        Ok(Self {
+            node: PathBuf::from(node),
+            config,
+        })
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the driver, which is also done without a subcommand.
+    Run(RunArgs),
+    /// Check the touchscreen, the config and the system for common problems.
+    Doctor {
+        /// The hidraw node of the touchscreen, detected if not given.
+        node: Option<PathBuf>,
+    },
+    /// List all hidraw devices and mark the ones made by eGalax.
+    ListDevices,
+    /// List all monitors with their area in screen space.
+    ListMonitors,
+    /// Query the status of a running driver.
+    Status(ControlArgs),
+    /// Open a REPL for debug commands to a running driver.
+    #[cfg(feature = "debug")]
+    Debug(ControlArgs),
+    /// Record a session of a running driver, or the packets of the touchscreen into a dump with --out.
+    #[command(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
+    Record {
+        #[command(subcommand)]
+        session: Option<RecordSession>,
+        #[command(flatten)]
+        control: ControlArgs,
+        /// Record the packets of the touchscreen with their timestamps into this file until interrupted.
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// The hidraw node of the touchscreen for --out, detected if not given.
+        node: Option<PathBuf>,
+    },
+    /// Play a dump made with `record --out` through the driver with its original timing.
+    Replay {
+        /// Only print the events that the driver would emit.
+        #[arg(long)]
+        dry_run: bool,
+        dump: PathBuf,
+    },
+    /// Validate a config from stdin and write it to TARGET.
+    ApplyConfig { target: String },
+    /// Show calibration targets on the touchscreen monitor and save the calibration to the config file.
+    Calibrate {
+        /// Show three targets and save an affine matrix, which also fits slightly rotated panels.
+        #[arg(long)]
+        affine: bool,
+        /// Describe the corners to touch on the terminal, for machines without a display.
+        #[arg(long)]
+        headless: bool,
+        /// Save all packets of the session as JSON to this file, even if the calibration fails.
+        #[arg(long, value_name = "CAPTURE")]
+        record: Option<PathBuf>,
+        /// The hidraw node of the touchscreen. By default the serial port of the config, or else the detected one.
+        node: Option<PathBuf>,
+    },
+    /// Measure the accuracy of the touchscreen with a grid of targets.
+    TestPattern {
+        /// The hidraw node of the touchscreen, detected if not given.
+        node: Option<PathBuf>,
+    },
+    /// Flash the monitor that the touchscreen is mapped to and show where the next touch lands.
+    Locate {
+        /// The hidraw node of the touchscreen, detected if not given.
+        node: Option<PathBuf>,
+    },
+    /// Print the effective configuration.
+    ShowConfig,
+    /// Lint a config file, by default the one given with --config.
+    CheckConfig { file: Option<PathBuf> },
+    /// Work with the locally saved usage statistics.
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommand,
+    },
+}
+
+/// Options for reaching a running driver.
+#[derive(Debug, clap::Args)]
+struct ControlArgs {
+    /// The touchscreen to address if the driver drives several, counted from 0 in the order they were given.
+    #[arg(long, value_name = "INDEX", default_value_t = 0)]
+    device: usize,
+}
+
+impl ControlArgs {
+    fn socket_path(&self) -> PathBuf {
+        control::device_socket_path(self.device)
+    }
+}
+
+/// Subcommands for the usage statistics.
+#[derive(Debug, Subcommand)]
+enum StatsCommand {
+    /// Write the touches per day, the touches per zone and the skipped packets as CSV.
+    Export {
+        /// Only export days from this date on, like 2024-03-17.
+        #[arg(long, value_parser = usage::parse_date)]
+        since: Option<String>,
+        /// Write to this file instead of stdout.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+/// Session recordings of a running driver.
+#[derive(Debug, Subcommand)]
+enum RecordSession {
+    /// Start recording to a new file in DIR, by default the working directory of the driver.
+    Start { dir: Option<PathBuf> },
+    /// Finish the recording.
+    Stop,
+}
+
+/// Dispatch to the subcommand, which is running the driver by default.
 fn main() -> Result<(), Box<dyn error::Error>> {
     env_logger::init();
 
-    let node_path = std::env::args().nth(1).expect(USAGE);
-    log::info!("Using raw device node '{}'", node_path);
+    let matches = Cli::command().get_matches();
+    let config_source = if given_on_command_line(&matches, "config") {
+        ValueSource::CommandLine
+    } else {
+        ValueSource::Default
+    };
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (json, config) = (cli.json, cli.config.as_path());
+    match cli.command {
+        None => run_driver(cli.run, config),
+        Some(Command::Run(run)) => run_driver(run, config),
+        Some(Command::Doctor { node }) => run_doctor(&device_node(node)?, config, json),
+        Some(Command::ListDevices) => list_devices(json),
+        Some(Command::ListMonitors) => list_monitors(json),
+        Some(Command::Status(control)) => print_status(&control.socket_path(), json),
+        #[cfg(feature = "debug")]
+        Some(Command::Debug(control)) => run_debug_repl(&control.socket_path()),
+        Some(Command::Record {
+            session: Some(session),
+            control,
+            ..
+        }) => send_record_command(&control.socket_path(), session),
+        Some(Command::Record {
+            session: None,
+            out,
+            node,
+            ..
+        }) => match out {
+            Some(out) => record_dump(&out, &device_node(node)?),
+            None => Err("record needs start, stop or --out FILE".into()),
+        },
+        Some(Command::Replay { dry_run, dump }) => replay(&dump, config, dry_run),
+        Some(Command::ApplyConfig { target }) => apply_config(&target, json),
+        Some(Command::Calibrate {
+            affine,
+            headless,
+            record,
+            node,
+        }) => {
+            let mode = if affine {
+                CalibrationMode::Affine
+            } else {
+                CalibrationMode::Corners
+            };
+            run_calibrate(
+                config,
+                &CalibrateArgs {
+                    node,
+                    mode,
+                    headless,
+                    record,
+                },
+            )
+        }
+        Some(Command::TestPattern { node }) => run_test_pattern(&device_node(node)?, config, json),
+        Some(Command::Locate { node }) => run_locate(&device_node(node)?, config, json),
+        Some(Command::ShowConfig) => show_config(config, config_source, json),
+        Some(Command::CheckConfig { file }) => {
+            check_config(file.as_deref().unwrap_or(config), json)
+        }
+        Some(Command::Stats {
+            command: StatsCommand::Export { since, out },
+        }) => export_stats(config, since.as_deref(), out.as_deref()),
+    }
+}
+
+/// If the argument was given on the command line, which for a global argument may also be after a subcommand.
+fn given_on_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine)
+        || matches
+            .subcommand()
+            .is_some_and(|(_, matches)| given_on_command_line(matches, id))
+}
+
+/// The device node given on the command line, or else the node of the detected eGalax touchscreen.
+fn device_node(node_path: Option<PathBuf>) -> Result<PathBuf, Box<dyn error::Error>> {
+    match node_path {
+        Some(node_path) => Ok(node_path),
+        None => {
+            let device = devices::find_egalax_device()?;
+            log::info!(
+                "Detected eGalax touchscreen '{}' at '{}'",
+                device.name,
+                device.node.display()
+            );
+            Ok(device.node)
+        }
+    }
+}
+
+/// Print a value as a single line of JSON.
+fn print_json<T: Serialize>(value: &T) -> Result<(), Box<dyn error::Error>> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+/// The touchscreens to drive: all detected ones, or else the given or detected node and the ones given with `--dev`.
+/// If the config has a serial port, it is driven instead of detecting a hidraw node.
+fn driven_devices(
+    run: &RunArgs,
+    config_path: &Path,
+) -> Result<Vec<DeviceArg>, Box<dyn error::Error>> {
+    if run.all_devices {
+        let devices: Vec<_> = devices::list_hidraw_devices()?
+            .into_iter()
+            .filter(HidrawDevice::is_egalax)
+            .map(|device| DeviceArg {
+                node: device.node,
+                config: None,
+            })
+            .collect();
+        if devices.is_empty() {
+            return Err(EgalaxError::NoEgalaxDevice.into());
+        }
+        return Ok(devices);
+    }
 
-    let mut device_node = OpenOptions::new().read(true).open(&node_path).unwrap();
-    log::info!("Opened device node '{}'", node_path);
+    let mut devices = Vec::new();
+    if run.node.is_some() || run.devices.is_empty() {
+        let serial = ConfigFile::from_file(config_path)
+            .ok()
+            .and_then(|config_file| config_file.serial().map(|serial| serial.path.clone()));
+        let node = match (run.node.clone(), serial) {
+            (None, Some(serial)) => serial,
+            (node, _) => device_node(node)?,
+        };
+        devices.push(DeviceArg { node, config: None });
+    }
+    devices.extend(run.devices.iter().cloned());
+    Ok(devices)
+}
+
+/// Read configuration and delegate to virtual mouse function.
+/// Each touchscreen is driven on its own thread with its own virtual device, calibration and control socket,
+/// see [control::device_socket_path]. Only the first one notifies systemd and serves D-Bus.
+fn run_driver(run: RunArgs, config_path: &Path) -> Result<(), Box<dyn error::Error>> {
+    let devices = driven_devices(&run, config_path)?;
 
-    let monitor_cfg = ConfigFile::from_file("./config.toml")?.build()?;
-    log::info!("Using monitor config:\n{}", monitor_cfg);
+    let mut notifier = if run.daemon {
+        systemd::handle_termination()?;
+        let notifier = Notifier::from_env()?;
+        if notifier.is_none() {
+            log::warn!(
+                "Running as daemon but $NOTIFY_SOCKET is not set, so systemd is not notified."
+            );
+        }
+        notifier
+    } else {
+        None
+    };
+
+    // Without a handler SIGHUP would kill the driver, with it the config is reloaded.
+    systemd::handle_reload()?;
+
+    let mut drivers = Vec::new();
+    for (index, device) in devices.into_iter().enumerate() {
+        let config_path = device.config.unwrap_or_else(|| config_path.to_path_buf());
+        log::info!(
+            "Using raw device node '{}' with config '{}'",
+            device.node.display(),
+            config_path.display()
+        );
+
+        let status = DriverStatus::new();
+        // The driver works without the control interface, so failing to set it up is not fatal.
+        let control = ControlServer::spawn(&control::device_socket_path(index), status.clone())
+            .map_err(|e| log::warn!("Could not start control interface: {}", e))
+            .ok();
+        // A daemon serves the system bus, since there may be no session bus yet.
+        #[cfg(feature = "dbus")]
+        let dbus = (index == 0)
+            .then(|| {
+                egalax_rs::dbus::DbusService::spawn(status.clone(), run.daemon)
+                    .map_err(|e| log::warn!("Could not start D-Bus interface: {}", e))
+                    .ok()
+            })
+            .flatten();
+
+        let notifier = notifier.take();
+        let node_path = device.node.clone();
+        let driver = thread::Builder::new()
+            .name(format!("device-{}", index))
+            .spawn(move || {
+                let _control = control;
+                #[cfg(feature = "dbus")]
+                let _dbus = dbus;
+                virtual_mouse_device(
+                    &node_path,
+                    &config_path,
+                    status,
+                    run.wait_for_device,
+                    notifier,
+                )
+            })?;
+        drivers.push((device.node, driver));
+    }
+
+    // A failing touchscreen does not stop the others, its error is reported once all have stopped.
+    let mut first_error = None;
+    for (node_path, driver) in drivers {
+        let result = driver
+            .join()
+            .unwrap_or_else(|_| Err(EgalaxError::Generic(anyhow::anyhow!("driver panicked"))));
+        if let Err(e) = result {
+            log::error!("Driver of '{}' stopped: {}", node_path.display(), e);
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
 
-    virtual_mouse(&mut device_node, monitor_cfg)?;
+/// Print the findings of all checks and fail if there was an error.
+fn run_doctor(
+    node_path: &Path,
+    config_path: &Path,
+    json: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    print_findings(&doctor::diagnose(node_path, config_path), json)
+        .map_err(|_| "doctor found errors".into())
+}
+
+/// Lint a config file for suspicious combinations of values, e.g. before deploying it to a kiosk.
+fn check_config(path: &Path, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let findings = lint::lint_config_file(ConfigFile::from_file(path)?);
+    print_findings(&findings, json).map_err(|_| "config check found errors".into())
+}
+
+/// Print findings and fail if one of them is an error.
+fn print_findings(findings: &[doctor::Finding], json: bool) -> Result<(), Box<dyn error::Error>> {
+    if json {
+        print_json(&findings)?;
+    } else {
+        for finding in findings {
+            println!("{}", finding);
+        }
+    }
+
+    if findings
+        .iter()
+        .any(|finding| finding.severity == Severity::Error)
+    {
+        return Err("found errors".into());
+    }
+    Ok(())
+}
+
+/// Print all hidraw devices and mark the ones made by eGalax.
+fn list_devices(json: bool) -> Result<(), Box<dyn error::Error>> {
+    let devices = devices::list_hidraw_devices()?;
+    if json {
+        return print_json(&devices);
+    }
+
+    for device in devices {
+        println!(
+            "{} {:04x}:{:04x} {}{}{}",
+            device.node.display(),
+            device.vendor_id,
+            device.product_id,
+            device.name,
+            device
+                .serial
+                .as_ref()
+                .map_or(String::new(), |serial| format!(" serial {}", serial)),
+            if device.is_egalax() { " (eGalax)" } else { "" }
+        );
+    }
     Ok(())
 }
+
+/// Print all monitors with their area in screen space.
+fn list_monitors(json: bool) -> Result<(), Box<dyn error::Error>> {
+    let monitors = devices::list_monitors()?;
+    if json {
+        return print_json(&monitors);
+    }
+
+    for monitor in monitors {
+        println!(
+            "{} {}{}",
+            monitor.name,
+            monitor.area,
+            if monitor.is_primary { " (primary)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+/// Validate a config from stdin and write it to a system location.
+/// This is meant to be run through pkexec, so errors are reported as JSON for the unprivileged caller with --json.
+fn apply_config(target: &str, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+
+    match config::apply_config(Path::new(target), &contents) {
+        Ok(()) if json => print_json(&serde_json::json!({ "applied": target })),
+        Ok(()) => {
+            println!("Applied config to {}.", target);
+            Ok(())
+        }
+        Err(e) => {
+            if json {
+                print_json(&e)?;
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// Calibrate the touchscreen like the `calibrate` program and save the calibration to the config file.
+fn run_calibrate(config_path: &Path, args: &CalibrateArgs) -> Result<(), Box<dyn error::Error>> {
+    let calibration = calibrator::calibrate_config(config_path, args)?;
+    println!(
+        "Saved calibration {} (origin {}, swap axes {}) to {}.",
+        calibration.calibration_points,
+        calibration.origin,
+        calibration.swap_axes,
+        config_path.display()
+    );
+    if let Some(affine) = calibration.affine {
+        println!("Calibration matrix: {}.", affine);
+    }
+    Ok(())
+}
+
+/// Measure the accuracy of the touchscreen with a grid of targets and print the report.
+fn run_test_pattern(
+    node_path: &Path,
+    config_path: &Path,
+    json: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let monitor_cfg = ConfigFile::from_file(config_path)?.build()?;
+    let device_node = serial::open_device(node_path, monitor_cfg.serial())?;
+    let report = test_pattern::run_test_pattern(
+        device_node,
+        serial::packet_format(monitor_cfg.serial()),
+        monitor_cfg.monitor_area,
+        &monitor_cfg.calibration(),
+    )?;
+
+    if json {
+        print_json(&report)
+    } else {
+        println!("{}", report);
+        Ok(())
+    }
+}
+
+/// Flash the monitor that a touchscreen is mapped to and print where the next touch lands,
+/// e.g. to tell the panels of a wall of screens apart.
+fn run_locate(
+    node_path: &Path,
+    config_path: &Path,
+    json: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let monitor_cfg = ConfigFile::from_file(config_path)?.build()?;
+    let device_node = serial::open_device(node_path, monitor_cfg.serial())?;
+    let touch = locate::locate(
+        device_node,
+        serial::packet_format(monitor_cfg.serial()),
+        monitor_cfg.monitor_area,
+        &monitor_cfg.calibration(),
+        &node_path.display().to_string(),
+    )?;
+
+    if json {
+        print_json(&serde_json::json!({
+            "device": node_path,
+            "monitor_area": monitor_cfg.monitor_area.to_string(),
+            "touch": touch,
+        }))
+    } else {
+        println!(
+            "{} is mapped to the monitor at {}, the touch landed at {}.",
+            node_path.display(),
+            monitor_cfg.monitor_area,
+            touch
+        );
+        Ok(())
+    }
+}
+
+/// Print the effective configuration and where each value comes from.
+/// The driver has no overrides besides the config file, so a value is either from the file or a default.
+fn show_config(
+    config_path: &Path,
+    config_source: ValueSource,
+    json: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut values = driver_settings(config_path, config_source);
+    values.extend(config::effective_values(&fs::read_to_string(config_path)?)?);
+    if json {
+        return print_json(&values);
+    }
+
+    println!("# Effective configuration from {}", config_path.display());
+    for value in values {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+/// The settings of the driver that are not in the config file but given as arguments or in the environment.
+fn driver_settings(config_path: &Path, config_source: ValueSource) -> Vec<EffectiveValue> {
+    let log_filter = std::env::var("RUST_LOG").ok();
+    vec![
+        EffectiveValue {
+            key: String::from("--config"),
+            value: toml::Value::String(config_path.display().to_string()),
+            source: config_source,
+        },
+        EffectiveValue {
+            key: String::from("RUST_LOG"),
+            source: match log_filter {
+                Some(_) => ValueSource::Environment,
+                None => ValueSource::Default,
+            },
+            // Without RUST_LOG env_logger only prints errors.
+            value: toml::Value::String(log_filter.unwrap_or_else(|| String::from("error"))),
+        },
+    ]
+}
+
+/// Export the usage statistics that the config points to as CSV.
+fn export_stats(
+    config_path: &Path,
+    since: Option<&str>,
+    out: Option<&Path>,
+) -> Result<(), Box<dyn error::Error>> {
+    let config = ConfigFile::from_file(config_path)?;
+    let statistics = config
+        .usage_statistics()
+        .ok_or("usage statistics are not enabled in the config")?;
+    let counts = DailyCounts::load(&statistics.path)?;
+    match out {
+        Some(out) => {
+            let mut file = BufWriter::new(File::create(out)?);
+            counts.write_csv(since, &mut file)?;
+            io::Write::flush(&mut file)?;
+        }
+        None => counts.write_csv(since, io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+/// Record the packets of the touchscreen with their timestamps into a dump until interrupted, see [dump].
+/// This reads the device node directly, so the driver should not run at the same time.
+fn record_dump(out: &Path, node_path: &Path) -> Result<(), Box<dyn error::Error>> {
+    systemd::handle_termination()?;
+    let mut device_node = OpenOptions::new().read(true).open(node_path)?;
+    let mut dump = DumpWriter::new(BufWriter::new(File::create(out)?))?;
+    println!(
+        "Recording packets from {} to {}, stop with Ctrl-C.",
+        node_path.display(),
+        out.display()
+    );
+
+    let packets = dump::record(&mut device_node, &mut dump)?;
+    dump.finish()?;
+    println!("Recorded {} packets to {}.", packets, out.display());
+    Ok(())
+}
+
+/// Replay a dump made with `record --out` with its original timing, see [dump].
+/// The packets are fed through a virtual device like those of a touchscreen, or with `dry_run` only the events
+/// that the driver would emit are printed.
+fn replay(
+    dump_path: &Path,
+    config_path: &Path,
+    dry_run: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    let monitor_cfg = ConfigFile::from_file(config_path)?.build()?;
+    let dump = DumpReader::new(BufReader::new(File::open(dump_path)?))?;
+
+    if dry_run {
+        let mut start = None;
+        let messages = driver::replay_dry_run(dump, monitor_cfg, |message, events| {
+            let start = *start.get_or_insert(message.time());
+            println!(
+                "{:>9.3}s {}",
+                (message.time() - start).as_secs_f32(),
+                message
+            );
+            for event in events {
+                println!("           {:?} {}", event.event_code, event.value);
+            }
+        })?;
+        println!("Replayed {} packets.", messages);
+        return Ok(());
+    }
+
+    // The driver reads the dump from a socket like from the device node while it is written in real time.
+    let (mut reader, mut writer) = UnixStream::pair()?;
+    let player = thread::spawn(move || dump::play(dump, &mut writer));
+    virtual_mouse(&mut reader, monitor_cfg)?;
+    let packets = player.join().map_err(|_| "replay thread panicked")??;
+    println!("Replayed {} packets.", packets);
+    Ok(())
+}
+
+/// Start or stop a session recording of a running driver and print its JSON response.
+fn send_record_command(
+    socket_path: &Path,
+    session: RecordSession,
+) -> Result<(), Box<dyn error::Error>> {
+    let command = match session {
+        RecordSession::Start { dir: Some(dir) } => format!("record start {}", dir.display()),
+        RecordSession::Start { dir: None } => String::from("record start"),
+        RecordSession::Stop => String::from("record stop"),
+    };
+    println!("{}", control::query(socket_path, &command)?);
+    Ok(())
+}
+
+/// Read debug commands from stdin, send them to a running driver and print its answers until end of input.
+#[cfg(feature = "debug")]
+fn run_debug_repl(socket_path: &Path) -> Result<(), Box<dyn error::Error>> {
+    use std::io::{BufRead, Write};
+
+    let stream = UnixStream::connect(socket_path)?;
+    let mut writer = stream.try_clone()?;
+    let mut answers = BufReader::new(stream).lines();
+    println!(
+        "Connected to {}. Commands: state, get OPTION, set OPTION VALUE, inject touch|release X Y [CONTACT].",
+        socket_path.display()
+    );
+
+    let mut stdout = io::stdout();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        write!(stdout, "egalax> ")?;
+        stdout.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        writeln!(writer, "debug {}", command)?;
+        match answers.next().transpose()? {
+            Some(answer) => println!("{}", answer),
+            None => return Err("the driver closed the connection".into()),
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// Query the status of a running driver.
+fn print_status(socket_path: &Path, json: bool) -> Result<(), Box<dyn error::Error>> {
+    let response = control::query(socket_path, "status")?;
+    if json {
+        // The driver already answers with JSON.
+        println!("{}", response);
+    } else {
+        let status: StatusReport = serde_json::from_str(&response)?;
+        println!("{}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["egalax-rs", "--daemon", "/dev/hidraw0"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.run.daemon);
+        assert_eq!(Some(PathBuf::from("/dev/hidraw0")), cli.run.node);
+
+        let cli =
+            Cli::try_parse_from(["egalax-rs", "calibrate", "--affine", "/dev/hidraw1"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Calibrate {
+                affine: true,
+                headless: false,
+                node: Some(_),
+                ..
+            })
+        ));
+
+        let matches = Cli::command()
+            .try_get_matches_from(["egalax-rs", "show-config", "--config", "other.toml"])
+            .unwrap();
+        assert!(given_on_command_line(&matches, "config"));
+        let matches = Cli::command()
+            .try_get_matches_from(["egalax-rs", "show-config"])
+            .unwrap();
+        assert!(!given_on_command_line(&matches, "config"));
+
+        let cli = Cli::try_parse_from(["egalax-rs", "status", "--device", "1"]).unwrap();
+        let Some(Command::Status(control)) = cli.command else {
+            panic!("expected the status subcommand");
+        };
+        assert_eq!(control::device_socket_path(1), control.socket_path());
+    }
+}
@@ -0,0 +1,135 @@
+//! Adaptive thresholds for noisy conditions, e.g. resistive panels of outdoor kiosks that get noisier
+//! as the temperature swings, or electromagnetic interference from nearby equipment.
+//!
+//! The [NoiseEstimator] measures how much the positions of a touch jitter and raises the has-moved threshold
+//! and the tap slop while the noise is high, so that jitter is not mistaken for movement.
+//! The thresholds never drop below the configured values.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::geo::Point2D;
+use crate::units::TouchSpace;
+
+/// Weight of a new sample in the running average of the noise.
+const SMOOTHING: f32 = 0.05;
+
+/// How far the thresholds follow the measured noise.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveNoise {
+    /// The thresholds are raised to this many times the measured noise.
+    pub multiplier: f32,
+    /// The thresholds are never raised above this, in touchscreen units (about 0.1mm).
+    pub max_threshold: f32,
+}
+
+impl fmt::Display for AdaptiveNoise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{}x the noise up to {}mm",
+            self.multiplier,
+            self.max_threshold * 0.1
+        ))
+    }
+}
+
+/// The measured noise and the thresholds currently in use, in touchscreen units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveThresholds {
+    pub noise: f32,
+    pub has_moved: f32,
+    /// The tap slop if tap-to-click is enabled.
+    pub tap_slop: Option<f32>,
+}
+
+impl fmt::Display for AdaptiveThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "noise {:.1}mm, has-moved {:.1}mm",
+            self.noise * 0.1,
+            self.has_moved * 0.1
+        ))?;
+        if let Some(tap_slop) = self.tap_slop {
+            f.write_fmt(format_args!(", tap slop {:.1}mm", tap_slop * 0.1))?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the noise from the positions of touches.
+///
+/// A finger moves smoothly, so the change of its velocity between packets is small even during fast swipes.
+/// The noise is the running average of that change, i.e. of the second difference of consecutive positions.
+#[derive(Debug, Clone)]
+pub struct NoiseEstimator {
+    params: AdaptiveNoise,
+    /// The last two positions of the current touch, the latest first.
+    previous: [Option<Point2D<TouchSpace>>; 2],
+    noise: f32,
+}
+
+impl NoiseEstimator {
+    pub fn new(params: AdaptiveNoise) -> Self {
+        Self {
+            params,
+            previous: [None, None],
+            noise: 0.0,
+        }
+    }
+
+    /// Record a position of the current touch.
+    pub fn observe(&mut self, position: Point2D<TouchSpace>) {
+        if let [Some(p1), Some(p2)] = self.previous {
+            let dx = position.x.float() - 2.0 * p1.x.float() + p2.x.float();
+            let dy = position.y.float() - 2.0 * p1.y.float() + p2.y.float();
+            let sample = (dx * dx + dy * dy).sqrt();
+            self.noise += SMOOTHING * (sample - self.noise);
+        }
+        self.previous = [Some(position), self.previous[0]];
+    }
+
+    /// Record that the current touch ended, so that the next one is not compared with it.
+    pub fn touch_ended(&mut self) {
+        self.previous = [None, None];
+    }
+
+    pub fn noise(&self) -> f32 {
+        self.noise
+    }
+
+    /// Raise a configured threshold according to the noise.
+    pub fn raise(&self, threshold: f32) -> f32 {
+        (self.params.multiplier * self.noise)
+            .min(self.params.max_threshold)
+            .max(threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_estimator() {
+        let mut estimator = NoiseEstimator::new(AdaptiveNoise {
+            multiplier: 3.0,
+            max_threshold: 100.0,
+        });
+
+        // A fast but smooth swipe is no noise.
+        for i in 0..100 {
+            estimator.observe((i * 50, 2000).into());
+        }
+        assert_eq!(0.0, estimator.noise());
+        assert_eq!(30.0, estimator.raise(30.0));
+        estimator.touch_ended();
+
+        // A finger held still on a noisy panel, jumping back and forth by 20 units.
+        for i in 0..200 {
+            estimator.observe((2000 + (i % 2) * 20, 2000).into());
+        }
+        assert!((estimator.noise() - 40.0).abs() < 1.0);
+        assert!((estimator.raise(30.0) - 100.0).abs() < f32::EPSILON);
+        assert_eq!(150.0, estimator.raise(150.0));
+    }
+}
@@ -0,0 +1,287 @@
+//! A minimal borderless X11 window covering the touchscreen monitor, used to give visual feedback.
+
+use std::ffi::CString;
+use std::os::raw::{c_int, c_uint};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{mem, ptr};
+use x11::xlib;
+
+use crate::error::EgalaxError;
+use crate::geo::{Point2D, AABB};
+use crate::units::ScreenSpace;
+
+/// Font we try to load for text. Falls back to the server's default font if it is not available.
+const FONT_NAME: &str = "10x20";
+/// Text shown on the startup splash screen.
+const SPLASH_TEXT: &str = "Touchscreen driver active - touch to test";
+/// How often the splash screen is redrawn while waiting for touches.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Colors as 24-bit RGB pixel values. This assumes a TrueColor visual, which every desktop uses nowadays.
+pub const WHITE: u64 = 0xffffff;
+pub const BLACK: u64 = 0x000000;
+pub const RED: u64 = 0xff0000;
+pub const GREEN: u64 = 0x00ff00;
+
+/// An override-redirect window placed over a monitor area in screen space.
+/// Drawing functions take points in screen space and translate them into window coordinates.
+pub struct Overlay {
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    gc: xlib::GC,
+    font: *mut xlib::XFontStruct,
+    area: AABB<ScreenSpace>,
+}
+
+impl Overlay {
+    /// Open a connection to the X server and map a window covering `area`.
+    pub fn open(area: AABB<ScreenSpace>) -> Result<Self, EgalaxError> {
+        log::trace!("Entering Overlay::open");
+
+        // SAFETY: all pointers passed to Xlib come either from Xlib itself or from live locals,
+        // and the display pointer is checked for null before use.
+        let overlay = unsafe {
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return Err(EgalaxError::XDisplay);
+            }
+            let screen = xlib::XDefaultScreen(display);
+            let root = xlib::XRootWindow(display, screen);
+
+            let mut attributes: xlib::XSetWindowAttributes = mem::zeroed();
+            attributes.override_redirect = xlib::True;
+            attributes.background_pixel = xlib::XWhitePixel(display, screen);
+
+            let window = xlib::XCreateWindow(
+                display,
+                root,
+                area.xrange().min().value(),
+                area.yrange().min().value(),
+                area.width().value() as c_uint,
+                area.height().value() as c_uint,
+                0,
+                xlib::CopyFromParent,
+                xlib::InputOutput as c_uint,
+                ptr::null_mut(),
+                xlib::CWOverrideRedirect | xlib::CWBackPixel,
+                &mut attributes,
+            );
+            xlib::XSelectInput(display, window, xlib::ExposureMask);
+            xlib::XMapRaised(display, window);
+
+            let gc = xlib::XCreateGC(display, window, 0, ptr::null_mut());
+            let font_name = CString::new(FONT_NAME).expect("font name contains no nul bytes");
+            let font = xlib::XLoadQueryFont(display, font_name.as_ptr());
+            if font.is_null() {
+                log::warn!("Font {} not available, using default font.", FONT_NAME);
+            } else {
+                xlib::XSetFont(display, gc, (*font).fid);
+            }
+
+            Overlay {
+                display,
+                window,
+                gc,
+                font,
+                area,
+            }
+        };
+
+        log::trace!("Leaving Overlay::open");
+        Ok(overlay)
+    }
+
+    /// The area of the screen space covered by the overlay.
+    pub fn area(&self) -> AABB<ScreenSpace> {
+        self.area
+    }
+
+    /// Translate a point in screen space into window coordinates.
+    fn to_window(&self, p: Point2D<ScreenSpace>) -> (c_int, c_int) {
+        let p = self.area.to_monitor(p);
+        (p.x.value(), p.y.value())
+    }
+
+    /// Fill the whole window with its background color.
+    pub fn clear(&self) {
+        unsafe {
+            xlib::XClearWindow(self.display, self.window);
+        }
+    }
+
+    /// Fill the whole window with the current color.
+    pub fn fill(&self) {
+        unsafe {
+            xlib::XFillRectangle(
+                self.display,
+                self.window,
+                self.gc,
+                0,
+                0,
+                self.area.width().value() as c_uint,
+                self.area.height().value() as c_uint,
+            );
+        }
+    }
+
+    /// Set the color used by subsequent drawing operations.
+    pub fn set_color(&self, rgb: u64) {
+        unsafe {
+            xlib::XSetForeground(self.display, self.gc, rgb as _);
+        }
+    }
+
+    /// Draw a line of text horizontally centered, with its baseline `y` pixels below the top of the window.
+    pub fn draw_text_centered(&self, text: &str, y: i32) {
+        let text = CString::new(text.replace('\0', "")).expect("nul bytes were removed");
+        let len = text.as_bytes().len() as c_int;
+
+        unsafe {
+            let text_width = if self.font.is_null() {
+                // The default font is usually 6 pixels wide.
+                len * 6
+            } else {
+                xlib::XTextWidth(self.font, text.as_ptr(), len)
+            };
+            let x = (self.area.width().value() - text_width) / 2;
+            xlib::XDrawString(self.display, self.window, self.gc, x, y, text.as_ptr(), len);
+        }
+    }
+
+    /// Draw a horizontally centered progress bar whose top is `y` pixels below the top of the window.
+    /// The bar is filled from the left according to `fraction`, which is clamped to [0, 1].
+    pub fn draw_progress_bar(&self, y: i32, width: i32, height: i32, fraction: f32) {
+        let x = (self.area.width().value() - width) / 2;
+        let filled = (width as f32 * fraction.clamp(0.0, 1.0)) as c_uint;
+
+        unsafe {
+            xlib::XDrawRectangle(
+                self.display,
+                self.window,
+                self.gc,
+                x,
+                y,
+                width as c_uint,
+                height as c_uint,
+            );
+            xlib::XFillRectangle(
+                self.display,
+                self.window,
+                self.gc,
+                x,
+                y,
+                filled,
+                height as c_uint,
+            );
+        }
+    }
+
+    /// Draw a target (filled circle with a crosshair) centered on a point in screen space.
+    pub fn draw_target(&self, center: Point2D<ScreenSpace>, radius: i32) {
+        let (x, y) = self.to_window(center);
+        let d = (2 * radius) as c_uint;
+
+        unsafe {
+            xlib::XFillArc(
+                self.display,
+                self.window,
+                self.gc,
+                x - radius,
+                y - radius,
+                d,
+                d,
+                0,
+                360 * 64,
+            );
+            xlib::XDrawLine(
+                self.display,
+                self.window,
+                self.gc,
+                x - 2 * radius,
+                y,
+                x + 2 * radius,
+                y,
+            );
+            xlib::XDrawLine(
+                self.display,
+                self.window,
+                self.gc,
+                x,
+                y - 2 * radius,
+                x,
+                y + 2 * radius,
+            );
+        }
+    }
+
+    /// Ring the X bell as an audible error cue.
+    pub fn bell(&self) {
+        unsafe {
+            xlib::XBell(self.display, 0);
+        }
+    }
+
+    /// Send all drawing requests to the X server and discard pending events.
+    /// We redraw everything periodically so we do not need to react to expose events.
+    pub fn flush(&self) {
+        unsafe {
+            let mut event: xlib::XEvent = mem::zeroed();
+            while xlib::XPending(self.display) > 0 {
+                xlib::XNextEvent(self.display, &mut event);
+            }
+            xlib::XFlush(self.display);
+        }
+    }
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.font.is_null() {
+                xlib::XFreeFont(self.display, self.font);
+            }
+            xlib::XFreeGC(self.display, self.gc);
+            xlib::XDestroyWindow(self.display, self.window);
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+/// Show a splash screen on the monitor area for `duration`, so that it is obvious the driver started.
+/// A target is shown in the middle of the monitor which then follows the touch positions received over `positions`.
+pub fn show_splash(
+    area: AABB<ScreenSpace>,
+    duration: Duration,
+    positions: Receiver<Point2D<ScreenSpace>>,
+) -> Result<(), EgalaxError> {
+    log::trace!("Entering fn show_splash");
+
+    let overlay = Overlay::open(area)?;
+    let deadline = Instant::now() + duration;
+    let mut target = area.midpoint();
+
+    loop {
+        overlay.clear();
+        overlay.set_color(BLACK);
+        overlay.draw_text_centered(SPLASH_TEXT, area.height().value() / 3);
+        overlay.set_color(RED);
+        overlay.draw_target(target, 20);
+        overlay.flush();
+
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        match positions.recv_timeout(REDRAW_INTERVAL.min(deadline - now)) {
+            // Only the most recent position is interesting.
+            Ok(position) => target = positions.try_iter().last().unwrap_or(position),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    log::trace!("Leaving fn show_splash");
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Rejection of palms that rest on the panel, which make the cursor jump around wildly.
+//!
+//! The controller reports no contact size, but a palm touches the panel in several places at once and the
+//! reported position jumps between them. A finger moves smoothly, even during fast swipes, so a touch is rejected
+//! if it jumps further than a finger could move between two packets, or if its positions scatter too far
+//! around a smooth path. Once rejected, a touch emits no further events until it ends.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::geo::Point2D;
+use crate::units::TouchSpace;
+
+/// Limits beyond which a touch is rejected as a palm, in touchscreen units (about 0.1mm).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PalmRejection {
+    /// Maximum distance between the positions of two consecutive packets.
+    pub max_jump: f32,
+    /// Maximum deviation of a position from the path through the previous two, i.e. how much positions scatter.
+    pub max_spread: f32,
+}
+
+impl fmt::Display for PalmRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "jumps over {}mm or spread over {}mm",
+            self.max_jump * 0.1,
+            self.max_spread * 0.1
+        ))
+    }
+}
+
+/// How many touches were rejected for which reason, to tune the limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PalmCounters {
+    pub touches: u64,
+    pub jumps: u64,
+    pub spreads: u64,
+}
+
+impl fmt::Display for PalmCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} of {} touches ({} jumps, {} spreads)",
+            self.jumps + self.spreads,
+            self.touches,
+            self.jumps,
+            self.spreads
+        ))
+    }
+}
+
+/// What the driver should do with a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PalmVerdict {
+    /// The touch looks like a finger.
+    Accept,
+    /// The touch was just recognized as a palm, so what it started must be cancelled.
+    Reject,
+    /// The touch was rejected before and is ignored until it ends.
+    Ignore,
+}
+
+/// Watches the positions of the current touch for signs of a palm.
+#[derive(Debug, Clone)]
+pub struct PalmDetector {
+    params: PalmRejection,
+    /// The last two positions of the current touch, the latest first.
+    previous: [Option<Point2D<TouchSpace>>; 2],
+    rejected: bool,
+    counters: PalmCounters,
+}
+
+impl PalmDetector {
+    pub fn new(params: PalmRejection) -> Self {
+        Self {
+            params,
+            previous: [None, None],
+            rejected: false,
+            counters: PalmCounters::default(),
+        }
+    }
+
+    /// Check a position of the current touch, or that the touch ended if there is no position.
+    pub fn observe(&mut self, position: Option<Point2D<TouchSpace>>) -> PalmVerdict {
+        let Some(position) = position else {
+            let rejected = self.rejected;
+            self.previous = [None, None];
+            self.rejected = false;
+            return if rejected {
+                PalmVerdict::Ignore
+            } else {
+                PalmVerdict::Accept
+            };
+        };
+        if self.rejected {
+            return PalmVerdict::Ignore;
+        }
+        if self.previous[0].is_none() {
+            self.counters.touches += 1;
+        }
+
+        let verdict = match self.previous {
+            [Some(p1), _] if p1.euclidean_distance_to(&position) > self.params.max_jump => {
+                self.counters.jumps += 1;
+                log::info!(
+                    "Rejecting palm that jumped {:.1}mm, more than {:.1}mm. Rejected {}.",
+                    p1.euclidean_distance_to(&position) * 0.1,
+                    self.params.max_jump * 0.1,
+                    self.counters
+                );
+                PalmVerdict::Reject
+            }
+            [Some(p1), Some(p2)] if spread(position, p1, p2) > self.params.max_spread => {
+                self.counters.spreads += 1;
+                log::info!(
+                    "Rejecting palm whose positions spread {:.1}mm, more than {:.1}mm. Rejected {}.",
+                    spread(position, p1, p2) * 0.1,
+                    self.params.max_spread * 0.1,
+                    self.counters
+                );
+                PalmVerdict::Reject
+            }
+            _ => PalmVerdict::Accept,
+        };
+        self.rejected = verdict == PalmVerdict::Reject;
+        self.previous = [Some(position), self.previous[0]];
+        verdict
+    }
+
+    pub fn counters(&self) -> PalmCounters {
+        self.counters
+    }
+}
+
+/// Deviation of a position from the straight continuation of the previous two, i.e. their second difference.
+fn spread(p0: Point2D<TouchSpace>, p1: Point2D<TouchSpace>, p2: Point2D<TouchSpace>) -> f32 {
+    let dx = p0.x.float() - 2.0 * p1.x.float() + p2.x.float();
+    let dy = p0.y.float() - 2.0 * p1.y.float() + p2.y.float();
+    (dx * dx + dy * dy).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palm_detector() {
+        let mut detector = PalmDetector::new(PalmRejection {
+            max_jump: 300.0,
+            max_spread: 150.0,
+        });
+
+        // A fast swipe is a finger.
+        for i in 0..20 {
+            let verdict = detector.observe(Some((i * 200, 2000).into()));
+            assert_eq!(PalmVerdict::Accept, verdict);
+        }
+        assert_eq!(PalmVerdict::Accept, detector.observe(None));
+
+        // A palm whose position jumps back and forth between two contacts.
+        assert_eq!(
+            PalmVerdict::Accept,
+            detector.observe(Some((1000, 1000).into()))
+        );
+        assert_eq!(
+            PalmVerdict::Accept,
+            detector.observe(Some((1100, 1000).into()))
+        );
+        assert_eq!(
+            PalmVerdict::Reject,
+            detector.observe(Some((1000, 1000).into()))
+        );
+        assert_eq!(
+            PalmVerdict::Ignore,
+            detector.observe(Some((1000, 1000).into()))
+        );
+        assert_eq!(PalmVerdict::Ignore, detector.observe(None));
+
+        // A palm that lands far away from the finger it replaces.
+        detector.observe(Some((1000, 1000).into()));
+        assert_eq!(
+            PalmVerdict::Reject,
+            detector.observe(Some((3000, 1000).into()))
+        );
+        detector.observe(None);
+
+        assert_eq!(
+            PalmCounters {
+                touches: 3,
+                jumps: 1,
+                spreads: 1
+            },
+            detector.counters()
+        );
+    }
+}
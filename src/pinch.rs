@@ -0,0 +1,172 @@
+//! Pinching with two fingers to zoom, emitted as Ctrl + wheel or as key combinations.
+//!
+//! Browsers and image viewers zoom on Ctrl + wheel, so by default every zoom step turns the wheel by one click
+//! while Ctrl is held. Applications that need something else, e.g. Ctrl + `+` and Ctrl + `-`, can be given
+//! key combinations instead.
+
+use evdev_rs::enums::EV_KEY;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::geo::Point2D;
+use crate::units::ScreenSpace;
+
+/// Parameters for zooming by pinching with two fingers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinchZoom {
+    /// How much the distance between the fingers must change in pixels for one zoom step.
+    pub step: f32,
+    /// The events of a zoom step.
+    #[serde(default)]
+    pub emit: PinchAction,
+}
+
+impl fmt::Display for PinchZoom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("{} every {}px", self.emit, self.step))
+    }
+}
+
+/// The events of a zoom step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinchAction {
+    /// Turn the wheel by one click while these keys are held, up to zoom in and down to zoom out.
+    Wheel(Vec<EV_KEY>),
+    /// Press the first key combination to zoom in and the second one to zoom out.
+    Keys(Vec<EV_KEY>, Vec<EV_KEY>),
+}
+
+impl Default for PinchAction {
+    fn default() -> Self {
+        PinchAction::Wheel(vec![EV_KEY::KEY_LEFTCTRL])
+    }
+}
+
+impl PinchAction {
+    /// The keys that the virtual device must support.
+    pub fn keys(&self) -> impl Iterator<Item = EV_KEY> + '_ {
+        let (first, second): (&[EV_KEY], &[EV_KEY]) = match self {
+            PinchAction::Wheel(modifiers) => (modifiers, &[]),
+            PinchAction::Keys(zoom_in, zoom_out) => (zoom_in, zoom_out),
+        };
+        first.iter().chain(second).copied()
+    }
+}
+
+impl fmt::Display for PinchAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let combination = |keys: &[EV_KEY]| {
+            keys.iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join("+")
+        };
+        match self {
+            PinchAction::Wheel(modifiers) if modifiers.is_empty() => f.write_str("wheel"),
+            PinchAction::Wheel(modifiers) => {
+                f.write_fmt(format_args!("{}+wheel", combination(modifiers)))
+            }
+            PinchAction::Keys(zoom_in, zoom_out) => f.write_fmt(format_args!(
+                "{} to zoom in, {} to zoom out",
+                combination(zoom_in),
+                combination(zoom_out)
+            )),
+        }
+    }
+}
+
+/// Direction of a zoom step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zoom {
+    /// The fingers spread apart.
+    In,
+    /// The fingers moved together.
+    Out,
+}
+
+/// Tracks the first two fingers and converts changes of their distance to zoom steps.
+#[derive(Debug, Clone)]
+pub struct PinchRecognizer {
+    step: f32,
+    /// Positions of the first two fingers while they are down.
+    fingers: [Option<Point2D<ScreenSpace>>; 2],
+    /// Distance between the fingers at the last zoom step, or when the second finger landed.
+    reference: Option<f32>,
+}
+
+impl PinchRecognizer {
+    pub fn new(params: &PinchZoom) -> Self {
+        Self {
+            step: params.step,
+            fingers: [None, None],
+            reference: None,
+        }
+    }
+
+    /// Forget all fingers, e.g. after the first one was rejected as a palm.
+    pub fn reset(&mut self) {
+        self.fingers = [None, None];
+        self.reference = None;
+    }
+
+    /// If two fingers are on the panel, so that the first one should not move the pointer.
+    pub fn is_pinching(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Update the position of a finger, or that it was lifted if there is no position.
+    /// Returns a zoom step once the distance between both fingers changed by the step size.
+    /// A fast pinch may cover several steps between packets, but only one is returned per packet.
+    pub fn update(&mut self, contact: u8, position: Option<Point2D<ScreenSpace>>) -> Option<Zoom> {
+        *self.fingers.get_mut(usize::from(contact))? = position;
+        let [Some(first), Some(second)] = self.fingers else {
+            self.reference = None;
+            return None;
+        };
+
+        let distance = first.euclidean_distance_to(&second);
+        let reference = *self.reference.get_or_insert(distance);
+        let zoom = if distance - reference >= self.step {
+            Zoom::In
+        } else if reference - distance >= self.step {
+            Zoom::Out
+        } else {
+            return None;
+        };
+        self.reference = Some(distance);
+        Some(zoom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinch_recognizer() {
+        let mut pinch = PinchRecognizer::new(&PinchZoom {
+            step: 50.0,
+            emit: PinchAction::default(),
+        });
+
+        assert_eq!(None, pinch.update(0, Some((400, 500).into())));
+        assert!(!pinch.is_pinching());
+        assert_eq!(None, pinch.update(1, Some((600, 500).into())));
+        assert!(pinch.is_pinching());
+
+        // Spreading the fingers by 100 pixels zooms in by two steps.
+        let steps: Vec<_> = (1..=10)
+            .filter_map(|i| pinch.update(1, Some((600 + i * 10, 500).into())))
+            .collect();
+        assert_eq!(vec![Zoom::In, Zoom::In], steps);
+
+        // Moving them together zooms out.
+        assert_eq!(None, pinch.update(0, Some((430, 500).into())));
+        assert_eq!(Some(Zoom::Out), pinch.update(0, Some((460, 500).into())));
+
+        // Lifting a finger ends pinching.
+        assert_eq!(None, pinch.update(1, None));
+        assert!(!pinch.is_pinching());
+    }
+}
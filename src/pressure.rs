@@ -0,0 +1,117 @@
+//! Transfer curves for the pressure of pen and pressure capable touchscreens, applied before ABS_PRESSURE is emitted.
+//!
+//! Overlays differ in stiffness, so the same stroke reports a light pressure on one and a heavy pressure on another.
+//! A curve maps the normalized pressure from 0 to 1 onto the pressure that is emitted, e.g. the soft preset makes
+//! light strokes count more on a stiff overlay.
+//!
+//! Only serial controllers report pressure, from 0 to [crate::protocol::MAX_PRESSURE]. Setting a curve also makes
+//! the virtual device report pressure, which stays at zero if the touchscreen never reports it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How the normalized pressure of a touch is mapped before it is emitted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PressureCurve {
+    /// Emit the pressure as it is.
+    Linear,
+    /// Light strokes count more, for stiff overlays.
+    Soft,
+    /// Light strokes count less, for soft overlays.
+    Hard,
+    /// Interpolate linearly between control points `[input, output]` with ascending inputs, all between 0 and 1.
+    /// Pressures outside the points get the output of the nearest one.
+    Points(Vec<[f32; 2]>),
+}
+
+impl PressureCurve {
+    /// Map a pressure between 0 and 1.
+    pub fn apply(&self, pressure: f32) -> f32 {
+        let pressure = pressure.clamp(0.0, 1.0);
+        match self {
+            PressureCurve::Linear => pressure,
+            PressureCurve::Soft => pressure.sqrt(),
+            PressureCurve::Hard => pressure * pressure,
+            PressureCurve::Points(points) => {
+                let (Some(first), Some(last)) = (points.first(), points.last()) else {
+                    return pressure;
+                };
+                if pressure <= first[0] {
+                    return first[1];
+                }
+                for pair in points.windows(2) {
+                    let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+                    if pressure <= x1 {
+                        let t = if x1 > x0 {
+                            (pressure - x0) / (x1 - x0)
+                        } else {
+                            1.0
+                        };
+                        return y0 + t * (y1 - y0);
+                    }
+                }
+                last[1]
+            }
+        }
+    }
+
+    /// Map a raw pressure between 0 and `max` onto the same range.
+    pub fn apply_raw(&self, raw: i32, max: i32) -> i32 {
+        if max <= 0 {
+            return raw;
+        }
+        (self.apply(raw as f32 / max as f32) * max as f32).round() as i32
+    }
+
+    /// Checks that the control points are within 0 to 1 and ascend, so that the curve is well defined.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            PressureCurve::Points(points) => {
+                points
+                    .iter()
+                    .flatten()
+                    .all(|value| (0.0..=1.0).contains(value))
+                    && points.windows(2).all(|pair| pair[0][0] < pair[1][0])
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for PressureCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PressureCurve::Linear => f.write_str("linear"),
+            PressureCurve::Soft => f.write_str("soft"),
+            PressureCurve::Hard => f.write_str("hard"),
+            PressureCurve::Points(points) => {
+                let points = points
+                    .iter()
+                    .map(|[x, y]| format!("{}→{}", x, y))
+                    .collect::<Vec<_>>();
+                f.write_fmt(format_args!("points {}", points.join(", ")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressure_curves() {
+        assert_eq!(0.5, PressureCurve::Soft.apply(0.25));
+        assert_eq!(0.25, PressureCurve::Hard.apply(0.5));
+        assert_eq!(1.0, PressureCurve::Linear.apply(1.5));
+
+        let curve = PressureCurve::Points(vec![[0.1, 0.0], [0.5, 0.8], [1.0, 1.0]]);
+        assert!(curve.is_valid());
+        assert_eq!(0.0, curve.apply(0.05));
+        assert!((curve.apply(0.3) - 0.4).abs() < 1e-6);
+        assert!((curve.apply(0.75) - 0.9).abs() < 1e-6);
+        assert_eq!(2048, PressureCurve::Soft.apply_raw(1024, 4096));
+        assert!(!PressureCurve::Points(vec![[0.5, 0.0], [0.2, 1.0]]).is_valid());
+    }
+}
@@ -1,216 +1,428 @@
-//! Implements parsing of the packets that are received from the hidraw interface.
+//! Implements reading of the packets that are received from the hidraw interface.
+//!
+//! Single packets are parsed by the [egalax_protocol] crate, which is re-exported here.
+//! Older controllers that are connected over a serial port frame their packets differently, see [PacketFormat].
 
-use evdev_rs::TimeVal;
-use std::fmt;
-
-use crate::{error::ParsePacketError, geo::Point2D, units::*};
+pub use egalax_protocol::packet::*;
 
-/// Length of a raw packet.
-pub const RAW_PACKET_LEN: usize = 6;
+use evdev_rs::TimeVal;
+use nix::time::{clock_gettime, ClockId};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::error::{EgalaxError, ParsePacketError};
+
+/// Size of the read buffer of a [PacketDecoder]. Fits several packets so that a single read can return more than one.
+const DECODER_BUF_LEN: usize = 16 * RAW_PACKET_LEN;
+
+/// Minimum time between warnings about packets with unknown tags, since some controllers send them continuously.
+const UNKNOWN_TAG_WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Convert a monotonic timestamp into a [TimeVal] on the CLOCK_MONOTONIC timeline.
+///
+/// On Linux an [Instant] is read from CLOCK_MONOTONIC but its value is opaque,
+/// so we subtract its age from a fresh reading of the clock.
+/// Note that the kernel stamps events injected through uinput itself, so for the virtual device this timestamp is informational.
+pub fn monotonic_timeval(time: Instant) -> Result<TimeVal, EgalaxError> {
+    let age = Instant::now().saturating_duration_since(time);
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC).map_err(io::Error::from)?;
+    let time = Duration::from(now).saturating_sub(age);
+    Ok(TimeVal::new(
+        time.as_secs() as i64,
+        time.subsec_micros() as i64,
+    ))
+}
 
-/// Type of raw packets.
-#[derive(Debug, Clone, Copy)]
-pub struct RawPacket(pub [u8; RAW_PACKET_LEN]);
+/// How the packets of a touchscreen are framed in the stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PacketFormat {
+    /// Reports of the hidraw interface of USB controllers, which are [RAW_PACKET_LEN] bytes long.
+    #[default]
+    Hidraw,
+    /// The serial protocol of older controllers, see [USBPacket::try_parse_serial]. Serial packets can report pressure.
+    Serial,
+}
 
-impl fmt::Display for RawPacket {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&format!(
-            "[{:#04x}, {:#04x}, {:#04x}, {:#04x}, {:#04x}, {:#04x}]",
-            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
-        ))
+impl PacketFormat {
+    /// The length of the shortest packet.
+    fn min_len(self) -> usize {
+        match self {
+            PacketFormat::Hidraw => RAW_PACKET_LEN,
+            PacketFormat::Serial => SERIAL_PACKET_LEN,
+        }
     }
 }
 
-/// A boolean indicating if a finger touch is detected.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TouchState {
-    IsTouching,
-    NotTouching,
+/// Streaming decoder that reads bytes from a stream and yields timestamped [USBMessage]s.
+///
+/// It owns the read buffer so that partial reads (e.g. from a fifo or a file) are reassembled into whole packets.
+/// If the stream gets out of step, invalid bytes are skipped one at a time until a valid packet is found again.
+/// Packets with an unknown tag, e.g. vendor packets that some controllers interleave, are skipped as a whole and counted.
+///
+/// A non-blocking stream, like the serial port of a [PacketFormat::Serial] touchscreen, may end a read in the middle
+/// of a packet. Then the decoder fails with [io::ErrorKind::WouldBlock] and the rest is decoded on the next call.
+pub struct PacketDecoder<R> {
+    reader: R,
+    format: PacketFormat,
+    expected_tag: Option<PacketTag>,
+    /// Number of skipped packets per unknown tag since the last call to [PacketDecoder::take_unknown_tags].
+    unknown_tags: BTreeMap<u8, u64>,
+    /// Time of the last warning about unknown tags and the number of packets skipped since then.
+    unknown_tag_warning: (Option<Instant>, u64),
+    buf: [u8; DECODER_BUF_LEN],
+    /// Start of the buffered bytes that have not been decoded yet.
+    start: usize,
+    /// End of the buffered bytes.
+    end: usize,
 }
 
-/// Type of packet tags that we currently support.
-#[repr(u8)]
-pub enum PacketTag {
-    TouchEvent = 0x2,
-}
+impl<R: io::Read> PacketDecoder<R> {
+    /// Create a decoder for touch event packets.
+    pub fn new(reader: R) -> Self {
+        Self::with_expected_tag(reader, Some(PacketTag::TouchEvent))
+    }
 
-/// A representation of a packet sent over USB.
-/// If we support more message types this should be extended to an enum with different packet variants.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct USBPacket {
-    touch_state: TouchState,
-    position: Point2D,
-    resolution: u8,
-}
+    /// Create a decoder that only accepts packets with the given tag (or any tag if `None`).
+    pub fn with_expected_tag(reader: R, expected_tag: Option<PacketTag>) -> Self {
+        Self {
+            reader,
+            format: PacketFormat::Hidraw,
+            expected_tag,
+            unknown_tags: BTreeMap::new(),
+            unknown_tag_warning: (None, 0),
+            buf: [0; DECODER_BUF_LEN],
+            start: 0,
+            end: 0,
+        }
+    }
 
-impl USBPacket {
-    pub fn with_time(self, time: TimeVal) -> USBMessage {
-        USBMessage { time, packet: self }
+    /// Create a decoder for touch event packets in the given format.
+    pub fn with_format(reader: R, format: PacketFormat) -> Self {
+        Self {
+            format,
+            ..Self::new(reader)
+        }
     }
 
-    pub fn touch_state(&self) -> TouchState {
-        self.touch_state
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
     }
 
-    pub fn position(&self) -> Point2D {
-        self.position
+    /// Consumes the decoder and returns the underlying stream. Buffered bytes are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
     }
 
-    pub fn resolution(&self) -> u8 {
-        self.resolution
+    /// Returns the number of skipped packets per unknown tag and resets the counts.
+    pub fn take_unknown_tags(&mut self) -> BTreeMap<u8, u64> {
+        std::mem::take(&mut self.unknown_tags)
     }
 
-    /// Parsing logic for a touch event packet.
-    /// Fails if the package is somehow malformed.
-    pub fn try_parse(
-        packet: RawPacket,
-        expected_tag: Option<PacketTag>,
-    ) -> Result<Self, ParsePacketError> {
-        log::trace!("Entering Packet::try_parse.");
+    /// Number of bytes that have been read but not decoded yet.
+    pub fn buffered(&self) -> usize {
+        self.end - self.start
+    }
 
-        if let Some(expected_tag) = expected_tag {
-            let raw_tag = packet.0[0];
-            if raw_tag != expected_tag as u8 {
-                return Err(ParsePacketError::UnexpectedTag(raw_tag));
+    /// Checks if enough bytes are buffered for another packet, so that it can be decoded without waiting for the stream.
+    /// They might still be skipped if they turn out not to be a valid packet.
+    pub fn has_buffered_packet(&self) -> bool {
+        self.buffered() >= self.format.min_len()
+    }
+
+    /// Read from the stream until a whole packet is available and return it as a message.
+    /// Returns `None` when the stream has ended.
+    pub fn next_message(&mut self) -> Result<Option<USBMessage>, EgalaxError> {
+        loop {
+            if let Some(packet) = self.decode_buffered() {
+                return Ok(Some(packet.with_time(Instant::now())));
             }
-        }
 
-        // Bitmasks for fields in the raw packet.
-        pub const TOUCH_STATE_MASK: u8 = 0x01;
-        pub const RESOLUTION_MASK: u8 = 0x06;
-
-        let resolution = match packet.0[1] & RESOLUTION_MASK {
-            0x00 => 11,
-            0x02 => 12,
-            0x04 => 13,
-            0x05 => 14,
-            _ => unreachable!("Only two bits should be left, match can never succeed"),
-        };
-
-        let touch_state = if (packet.0[1] & TOUCH_STATE_MASK) == 0x01 {
-            TouchState::IsTouching
-        } else {
-            TouchState::NotTouching
-        };
-
-        // X and Y coordinates are stored little-endian.
-        let y = ((packet.0[3] as u16) << 8) | (packet.0[2] as u16);
-        let x = ((packet.0[5] as u16) << 8) | (packet.0[4] as u16);
-
-        if y >> resolution != 0x00 {
-            return Err(ParsePacketError::WrongResolution(DimE::Y));
-        } else if x >> resolution != 0x00 {
-            return Err(ParsePacketError::WrongResolution(DimE::X));
+            // Move the incomplete rest to the front to make room for the next read.
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => {
+                    if self.end > 0 {
+                        log::warn!("Discarding {} trailing bytes at end of stream.", self.end);
+                    }
+                    return Ok(None);
+                }
+                Ok(n) => self.end += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
         }
+    }
 
-        let packet = USBPacket {
-            touch_state,
-            position: Point2D {
-                x: x.into(),
-                y: y.into(),
-            },
-            resolution,
-        };
+    /// Try to decode a packet from the buffered bytes, skipping over bytes that do not start a valid packet.
+    fn decode_buffered(&mut self) -> Option<Packet> {
+        if self.format == PacketFormat::Serial {
+            return self.decode_buffered_serial();
+        }
+        while self.end - self.start >= RAW_PACKET_LEN {
+            let mut raw_packet = RawPacket([0; RAW_PACKET_LEN]);
+            raw_packet
+                .0
+                .copy_from_slice(&self.buf[self.start..self.start + RAW_PACKET_LEN]);
+
+            match Packet::try_parse(raw_packet, self.expected_tag) {
+                Ok(packet) => {
+                    log::info!("Read raw packet: {}", raw_packet);
+                    self.start += RAW_PACKET_LEN;
+                    return Some(packet);
+                }
+                Err(ParsePacketError::UnexpectedTag(tag)) if self.is_framed() => {
+                    self.skip_unknown_packet(tag, raw_packet);
+                    self.start += RAW_PACKET_LEN;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Skipping byte {:#04x} to resynchronize: {}",
+                        self.buf[self.start],
+                        e
+                    );
+                    self.start += 1;
+                }
+            }
+        }
 
-        log::trace!("Leaving Packet::try_parse.");
-        Ok(packet)
+        None
     }
-}
 
-impl fmt::Display for USBPacket {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let touch = match self.touch_state {
-            TouchState::IsTouching => "1",
-            TouchState::NotTouching => "0",
-        };
-        let description = format!("Touch={}, Point={}", touch, self.position);
-        f.write_str(&description)
-    }
-}
+    /// Like [PacketDecoder::decode_buffered] for the serial format, which has no tags but a start bit on the first byte.
+    fn decode_buffered_serial(&mut self) -> Option<Packet> {
+        while self.end - self.start >= SERIAL_PACKET_LEN {
+            let len = serial_packet_len(self.buf[self.start]);
+            if self.end - self.start < len {
+                return None;
+            }
+            let mut raw_packet = [0; SERIAL_PACKET_LEN];
+            raw_packet.copy_from_slice(&self.buf[self.start..self.start + SERIAL_PACKET_LEN]);
+
+            let parsed = USBPacket::try_parse_serial(raw_packet).and_then(|packet| {
+                if len > SERIAL_PACKET_LEN {
+                    packet.with_serial_pressure(self.buf[self.start + SERIAL_PACKET_LEN])
+                } else {
+                    Ok(packet)
+                }
+            });
+            match parsed {
+                Ok(packet) => {
+                    log::info!(
+                        "Read serial packet: {:02x?}",
+                        &self.buf[self.start..self.start + len]
+                    );
+                    self.start += len;
+                    return Some(Packet::Touch(packet));
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Skipping serial byte {:#04x} to resynchronize: {}",
+                        self.buf[self.start],
+                        e
+                    );
+                    self.start += 1;
+                }
+            }
+        }
 
-/// Messages are timestamped to give them to evdev later.
-#[derive(Debug, Clone, Copy)]
-pub struct USBMessage {
-    time: TimeVal,
-    packet: USBPacket,
-}
+        None
+    }
 
-impl USBMessage {
-    pub fn time(&self) -> TimeVal {
-        self.time
+    /// Checks if the buffered packet is followed by the start of a valid packet or the end of the read,
+    /// so that it is probably a whole packet rather than the middle of one.
+    fn is_framed(&self) -> bool {
+        let next = self.start + RAW_PACKET_LEN;
+        let multi_touch = self.expected_tag == Some(PacketTag::TouchEvent)
+            && self.buf[next] == PacketTag::MultiTouch as u8;
+        next == self.end
+            || multi_touch
+            || self
+                .expected_tag
+                .is_some_and(|tag| self.buf[next] == tag as u8)
     }
 
-    pub fn packet(&self) -> &USBPacket {
-        &self.packet
+    /// Count a packet with an unknown tag and warn about it at most every [UNKNOWN_TAG_WARN_INTERVAL].
+    fn skip_unknown_packet(&mut self, tag: u8, raw_packet: RawPacket) {
+        *self.unknown_tags.entry(tag).or_default() += 1;
+        log::debug!("Skipping packet with unknown tag: {}", raw_packet);
+
+        let now = Instant::now();
+        let (last_warning, skipped) = &mut self.unknown_tag_warning;
+        *skipped += 1;
+        if last_warning.is_none_or(|time| now.duration_since(time) >= UNKNOWN_TAG_WARN_INTERVAL) {
+            log::warn!(
+                "Skipped {} packets with unknown tags, the last one with tag {:#04x}.",
+                skipped,
+                tag
+            );
+            *last_warning = Some(now);
+            *skipped = 0;
+        }
     }
 }
 
-impl fmt::Display for USBMessage {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let description = format!("Message at {:?}\nPacket: {}", self.time, self.packet);
-        f.write_str(&description)
+impl<R: io::Read> Iterator for PacketDecoder<R> {
+    type Item = Result<USBMessage, EgalaxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message().transpose()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::geo::Point2D;
+    use crate::units::TouchSpace;
+
+    /// A stream that only ever returns a single byte per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&b, rest)) if !buf.is_empty() => {
+                    buf[0] = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
 
     #[test]
-    fn test_parse_touch_upper_left() {
-        let raw_packet: RawPacket = RawPacket([0x02, 0x03, 0x3b, 0x01, 0x32, 0x01]);
+    fn test_decoder_partial_reads() {
+        let bytes = [
+            0x02, 0x03, 0x3b, 0x01, 0x32, 0x01, 0x02, 0x02, 0x35, 0x01, 0x39, 0x01,
+        ];
+        let decoder = PacketDecoder::new(Trickle(&bytes));
+        let positions: Vec<Point2D<TouchSpace>> = decoder
+            .map(|message| message.unwrap().packet().position())
+            .collect();
 
         assert_eq!(
-            Ok(USBPacket {
-                touch_state: TouchState::IsTouching,
-                position: (306, 315).into(),
-                resolution: 12
-            }),
-            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+            vec![Point2D::from((306, 315)), Point2D::from((313, 309))],
+            positions
         );
     }
 
     #[test]
-    fn test_parse_release_upper_left() {
-        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x01, 0x39, 0x01]);
+    fn test_decoder_serial() {
+        let bytes = [
+            0x3b, 0x83, 0x02, 0x32, 0x02, 0x3b, 0xc3, 0x02, 0x39, 0x02, 0x35, 0x40, 0x82, 0x02,
+            0x39, 0x02, 0x35,
+        ];
+        let decoder = PacketDecoder::with_format(Trickle(&bytes), PacketFormat::Serial);
+        let packets: Vec<USBPacket> = decoder.map(|message| *message.unwrap().packet()).collect();
 
         assert_eq!(
-            Ok(USBPacket {
-                touch_state: TouchState::IsTouching,
-                position: (313, 309).into(),
-                resolution: 12
-            }),
-            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+            vec![
+                Point2D::from((306, 315)),
+                Point2D::from((313, 309)),
+                Point2D::from((313, 309))
+            ],
+            packets.iter().map(USBPacket::position).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![None, Some(0x40), None],
+            packets.iter().map(USBPacket::pressure).collect::<Vec<_>>()
         );
+        assert_eq!(TouchState::NotTouching, packets[2].touch_state());
     }
 
-    #[test]
-    fn test_malformed_const() {
-        let raw_packet: RawPacket = RawPacket([0xaa, 0x02, 0x35, 0x01, 0x39, 0x01]);
+    /// A non-blocking stream that returns the given chunks, where `None` means that nothing has arrived yet.
+    struct NonBlocking<'a>(Vec<Option<&'a [u8]>>);
 
-        assert_eq!(
-            Err(ParsePacketError::UnexpectedTag(0xaa)),
-            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
-        );
+    impl io::Read for NonBlocking<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let Some(chunk) = self.0.remove(0) else {
+                return Err(io::ErrorKind::WouldBlock.into());
+            };
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
     }
 
     #[test]
-    fn test_malformed_res_y() {
-        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x11, 0x39, 0x01]);
-
-        assert_eq!(
-            Err(ParsePacketError::WrongResolution(DimE::Y)),
-            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+    fn test_decoder_does_not_block() {
+        let bytes = [0xc3, 0x02, 0x32, 0x02, 0x3b, 0x7f, 0x83, 0x02];
+        let mut decoder = PacketDecoder::with_format(
+            NonBlocking(vec![Some(&bytes[..3]), None, Some(&bytes[3..])]),
+            PacketFormat::Serial,
         );
+
+        // Only part of the first packet has arrived, so the decoder gives up instead of waiting for the rest.
+        assert!(matches!(
+            decoder.next_message(),
+            Err(EgalaxError::IO(e)) if e.kind() == io::ErrorKind::WouldBlock
+        ));
+        let message = decoder.next_message().unwrap().unwrap();
+        assert_eq!(Point2D::from((306, 315)), message.packet().position());
+        assert_eq!(Some(0x7f), message.packet().pressure());
+        assert!(!decoder.has_buffered_packet());
+        assert!(decoder.next_message().is_err());
     }
 
     #[test]
-    fn test_malformed_res_x() {
-        let raw_packet: RawPacket = RawPacket([0x02, 0x02, 0x35, 0x01, 0x39, 0x11]);
+    fn test_decoder_resync() {
+        let bytes = [0xaa, 0x01, 0x02, 0x03, 0x3b, 0x01, 0x32, 0x01, 0x02];
+        let mut decoder = PacketDecoder::new(&bytes[..]);
+
+        let message = decoder.next_message().unwrap().unwrap();
+        assert_eq!(Point2D::from((306, 315)), message.packet().position());
+        // The single trailing byte does not make up a packet.
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decoder_reads_multi_touch() {
+        let bytes = [
+            0x02, 0x03, 0x3b, 0x01, 0x32, 0x01, 0x06, 0x03, 0x3b, 0x01, 0x32, 0x01, 0x06, 0x0b,
+            0x00, 0x02, 0x00, 0x03,
+        ];
+        let messages: Vec<USBMessage> = PacketDecoder::new(&bytes[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
 
         assert_eq!(
-            Err(ParsePacketError::WrongResolution(DimE::X)),
-            USBPacket::try_parse(raw_packet, Some(PacketTag::TouchEvent))
+            vec![0, 0, 1],
+            messages.iter().map(USBMessage::contact).collect::<Vec<_>>()
         );
+        assert_eq!(Point2D::from((768, 512)), messages[2].packet().position());
+        assert_eq!(TouchState::IsTouching, messages[2].packet().touch_state());
+
+        let contact = Packet::try_parse(RawPacket(bytes[12..].try_into().unwrap()), None).unwrap();
+        assert_eq!(&bytes[12..], &contact.encode().0);
+        assert!(matches!(
+            Packet::try_parse(
+                RawPacket(bytes[6..12].try_into().unwrap()),
+                Some(PacketTag::TouchEvent)
+            ),
+            Ok(Packet::MultiTouch(Contact { id: 0, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_decoder_skips_unknown_tags() {
+        let bytes = [
+            0x0a, 0x02, 0x03, 0x3b, 0x01, 0x32, 0x02, 0x03, 0x3b, 0x01, 0x32, 0x01, 0x0a, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut decoder = PacketDecoder::new(&bytes[..]);
+
+        let message = decoder.next_message().unwrap().unwrap();
+        assert_eq!(Point2D::from((306, 315)), message.packet().position());
+        assert!(decoder.next_message().unwrap().is_none());
+        assert_eq!(Some(&2), decoder.take_unknown_tags().get(&0x0a));
+        assert!(decoder.take_unknown_tags().is_empty());
     }
 }
@@ -0,0 +1,165 @@
+//! A background thread that reads packets from a device node and forwards them over a channel.
+//!
+//! Frontends that need to react to other events (e.g. a GUI event loop) cannot block on reading the device.
+//! Instead they spawn a [PacketPump] and receive the decoded messages from its channel.
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::error::EgalaxError;
+use crate::protocol::{PacketDecoder, PacketFormat, USBMessage};
+
+/// How long the reader thread waits for data before checking if it should stop, in milliseconds.
+const POLL_TIMEOUT_MS: i32 = 100;
+
+/// Handle to a reader thread that decodes packets and sends them to a channel.
+/// The thread is stopped and joined when the pump is dropped.
+pub struct PacketPump {
+    receiver: Receiver<Result<USBMessage, EgalaxError>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PacketPump {
+    /// Spawn a thread that reads packets in `format` from `stream` until it ends, an error occurs, or the pump is stopped.
+    /// A read error is sent over the channel before the thread exits.
+    pub fn spawn<R>(stream: R, format: PacketFormat) -> Self
+    where
+        R: io::Read + AsRawFd + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            if let Err(e) = pump_packets(stream, format, &sender, &thread_stop) {
+                // If the receiver is gone nobody is interested in the error anymore.
+                sender.send(Err(e)).ok();
+            }
+        });
+
+        Self {
+            receiver,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// The channel on which decoded messages arrive.
+    /// It is disconnected once the reader thread has exited.
+    pub fn receiver(&self) -> &Receiver<Result<USBMessage, EgalaxError>> {
+        &self.receiver
+    }
+
+    /// Wait up to `timeout` for the next message, so that a frontend can redraw in between.
+    /// Returns `None` if nothing arrived in time, and fails with the read error or once the stream has ended.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<USBMessage>, EgalaxError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => message.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+            }
+        }
+    }
+
+    /// Signal the reader thread to stop and wait until it has exited.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("Packet reader thread panicked.");
+            }
+        }
+    }
+}
+
+impl Drop for PacketPump {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Loop of the reader thread. We poll with a timeout so that the stop signal is noticed even if no packets arrive.
+fn pump_packets<R>(
+    stream: R,
+    format: PacketFormat,
+    sender: &Sender<Result<USBMessage, EgalaxError>>,
+    stop: &AtomicBool,
+) -> Result<(), EgalaxError>
+where
+    R: io::Read + AsRawFd,
+{
+    log::trace!("Entering fn pump_packets");
+
+    let fd = stream.as_raw_fd();
+    let mut decoder = PacketDecoder::with_format(stream, format);
+
+    while !stop.load(Ordering::Relaxed) {
+        // Packets left over from a previous read can be decoded without waiting for the device.
+        if !decoder.has_buffered_packet() {
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, POLL_TIMEOUT_MS) {
+                Ok(0) | Err(Errno::EINTR) => continue,
+                Ok(_) => {}
+                Err(e) => return Err(io::Error::from(e).into()),
+            }
+        }
+
+        let message = match decoder.next_message() {
+            Ok(message) => message,
+            // A serial port is non-blocking and has only received part of a packet.
+            Err(EgalaxError::IO(e)) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        };
+        match message {
+            Some(message) => {
+                if sender.send(Ok(message)).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    log::trace!("Leaving fn pump_packets");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{TouchState, USBPacket};
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_pump_stops_cleanly() {
+        let (reader, mut writer) = UnixStream::pair().unwrap();
+        let pump = PacketPump::spawn(reader, PacketFormat::Hidraw);
+
+        let packet = USBPacket::new(TouchState::IsTouching, (400, 400).into(), 12).unwrap();
+        writer.write_all(&packet.encode().0).unwrap();
+        let message = pump.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(&packet, message.packet());
+        assert!(pump
+            .recv_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+
+        // The stream is still open, so the thread only exits because it was told to.
+        pump.stop();
+        drop(writer);
+    }
+}
@@ -0,0 +1,132 @@
+//! Recording of the packets the driver receives and the events it emits while it keeps running,
+//! so that an intermittent problem in the field can be captured when it happens and analyzed later.
+//!
+//! A recording is started and stopped with the `record start` and `record stop` control commands.
+//! Each line of the file is a [RecordedPacket] in JSON.
+
+use evdev_rs::InputEvent;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::EgalaxError;
+use crate::protocol::{USBMessage, RAW_PACKET_LEN};
+
+/// A packet and the events that the driver emitted for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedPacket {
+    /// Milliseconds since the start of the recording.
+    pub offset_ms: u64,
+    /// The raw packet, or `None` for events emitted without a packet, e.g. predicted positions.
+    pub packet: Option<[u8; RAW_PACKET_LEN]>,
+    pub events: Vec<InputEvent>,
+}
+
+/// Summary of a finished recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordingSummary {
+    pub path: PathBuf,
+    pub packets: u64,
+    pub duration_ms: u64,
+}
+
+/// A recording in progress, which writes to a file named after the time it started.
+#[derive(Debug)]
+pub struct SessionRecorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    started: Instant,
+    packets: u64,
+}
+
+impl SessionRecorder {
+    /// Create a new recording file in `dir`.
+    pub fn create(dir: &Path) -> Result<Self, EgalaxError> {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("egalax-session-{}.jsonl", millis));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        log::info!("Recording session to '{}'", path.display());
+
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            started: Instant::now(),
+            packets: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a packet and the events emitted for it. Without a message the events are recorded on their own.
+    pub fn record(
+        &mut self,
+        message: Option<&USBMessage>,
+        events: &[InputEvent],
+    ) -> io::Result<()> {
+        let time = message.map_or_else(Instant::now, USBMessage::time);
+        let recorded = RecordedPacket {
+            offset_ms: time.saturating_duration_since(self.started).as_millis() as u64,
+            packet: message.map(|message| message.encode().0),
+            events: events.to_vec(),
+        };
+        serde_json::to_writer(&mut self.writer, &recorded)?;
+        self.writer.write_all(b"\n")?;
+        if message.is_some() {
+            self.packets += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush the file and summarize the recording.
+    pub fn finish(mut self) -> Result<RecordingSummary, EgalaxError> {
+        self.writer.flush()?;
+        log::info!(
+            "Recorded {} packets to '{}'",
+            self.packets,
+            self.path.display()
+        );
+        Ok(RecordingSummary {
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            path: self.path,
+            packets: self.packets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{TouchState, USBPacket};
+    use std::fs;
+
+    #[test]
+    fn test_session_recorder() {
+        let dir = std::env::temp_dir().join(format!("egalax-rs-recorder-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut recorder = SessionRecorder::create(&dir).unwrap();
+        let path = recorder.path().to_path_buf();
+
+        let packet = USBPacket::new(TouchState::IsTouching, (100, 200).into(), 12).unwrap();
+        let message = packet.with_time(Instant::now());
+        recorder.record(Some(&message), &[]).unwrap();
+        recorder.record(None, &[]).unwrap();
+        let summary = recorder.finish().unwrap();
+        assert_eq!(1, summary.packets);
+
+        let lines: Vec<RecordedPacket> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(Some(packet.encode().0), lines[0].packet);
+        assert_eq!(None, lines[1].packet);
+    }
+}
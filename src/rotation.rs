@@ -0,0 +1,180 @@
+//! Watching the rotation of the touchscreen monitor so that a matching calibration profile can be chosen,
+//! e.g. for convertible or rotating kiosk enclosures.
+
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::{mem, slice, thread};
+use x11::{xlib, xrandr};
+
+use crate::config::MonitorDesignator;
+use crate::error::EgalaxError;
+pub use crate::geo::Rotation;
+use crate::x11::XConnection;
+
+/// Convert the rotation of an xrandr CRTC.
+fn rotation_from_xrandr(rotation: xrandr::Rotation) -> Rotation {
+    let rotation = rotation as c_int;
+    if rotation & xrandr::RR_Rotate_90 != 0 {
+        Rotation::Left
+    } else if rotation & xrandr::RR_Rotate_180 != 0 {
+        Rotation::Inverted
+    } else if rotation & xrandr::RR_Rotate_270 != 0 {
+        Rotation::Right
+    } else {
+        Rotation::Normal
+    }
+}
+
+/// Handle to a thread that reports changes to the rotation of a monitor.
+/// Only changes after spawning are reported, the rotation at that time is [RotationWatch::initial].
+#[derive(Debug)]
+pub struct RotationWatch {
+    receiver: Receiver<Rotation>,
+    initial: Option<Rotation>,
+}
+
+impl RotationWatch {
+    /// Connect to the X server, query the rotation of the given monitor and start watching it.
+    pub fn spawn(monitor: MonitorDesignator) -> Result<Self, EgalaxError> {
+        let connection = RandrConnection::open()?;
+        let initial = connection.rotation(&monitor);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || watch_rotation(connection, monitor, initial, sender));
+        Ok(Self { receiver, initial })
+    }
+
+    /// The rotation of the monitor when the watch was spawned, or `None` if the monitor was not found.
+    pub fn initial(&self) -> Option<Rotation> {
+        self.initial
+    }
+
+    /// Returns the latest rotation if it changed since the last call.
+    pub fn changed(&self) -> Option<Rotation> {
+        self.receiver.try_iter().last()
+    }
+}
+
+/// Connection to the X server that is notified when the screen configuration changes.
+struct RandrConnection {
+    connection: XConnection,
+    /// Base of the event numbers of the RandR extension.
+    event_base: c_int,
+}
+
+impl RandrConnection {
+    fn open() -> Result<Self, EgalaxError> {
+        let connection = XConnection::open()?;
+        // SAFETY: display and root window are valid for the lifetime of the connection.
+        unsafe {
+            let (mut event_base, mut error_base) = (0, 0);
+            if xrandr::XRRQueryExtension(connection.display(), &mut event_base, &mut error_base)
+                == 0
+            {
+                return Err(EgalaxError::XDisplay);
+            }
+            xrandr::XRRSelectInput(
+                connection.display(),
+                connection.root(),
+                xrandr::RRScreenChangeNotifyMask | xrandr::RRCrtcChangeNotifyMask,
+            );
+
+            Ok(Self {
+                connection,
+                event_base,
+            })
+        }
+    }
+
+    /// Block until the next RandR event arrives.
+    fn wait_for_change(&self) {
+        loop {
+            // SAFETY: the event is fully written by XNextEvent before it is read.
+            unsafe {
+                let mut event: xlib::XEvent = mem::zeroed();
+                xlib::XNextEvent(self.connection.display(), &mut event);
+                let event_type = event.get_type();
+                if event_type == self.event_base + xrandr::RRScreenChangeNotify {
+                    xrandr::XRRUpdateConfiguration(&mut event);
+                    return;
+                }
+                if event_type == self.event_base + xrandr::RRNotify {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Query the current rotation of the monitor's output, or `None` if it is not found or disabled.
+    fn rotation(&self, monitor: &MonitorDesignator) -> Option<Rotation> {
+        // SAFETY: all resources are obtained from Xlib, checked for null and freed exactly once.
+        unsafe {
+            let resources = xrandr::XRRGetScreenResourcesCurrent(
+                self.connection.display(),
+                self.connection.root(),
+            );
+            if resources.is_null() {
+                return None;
+            }
+            let primary =
+                xrandr::XRRGetOutputPrimary(self.connection.display(), self.connection.root());
+            let outputs =
+                slice::from_raw_parts((*resources).outputs, (*resources).noutput as usize);
+
+            let mut rotation = None;
+            for &output in outputs {
+                let info = xrandr::XRRGetOutputInfo(self.connection.display(), resources, output);
+                if info.is_null() {
+                    continue;
+                }
+                let matches = match monitor {
+                    MonitorDesignator::Primary => output == primary,
+                    MonitorDesignator::Named(name) => {
+                        CStr::from_ptr((*info).name).to_string_lossy() == name.as_str()
+                    }
+                };
+                if matches && (*info).crtc != 0 {
+                    let crtc =
+                        xrandr::XRRGetCrtcInfo(self.connection.display(), resources, (*info).crtc);
+                    if !crtc.is_null() {
+                        rotation = Some(rotation_from_xrandr((*crtc).rotation));
+                        xrandr::XRRFreeCrtcInfo(crtc);
+                    }
+                }
+                xrandr::XRRFreeOutputInfo(info);
+                if matches {
+                    break;
+                }
+            }
+
+            xrandr::XRRFreeScreenResources(resources);
+            rotation
+        }
+    }
+}
+
+/// Report the rotation of the monitor whenever it changes, until the receiver is dropped.
+fn watch_rotation(
+    connection: RandrConnection,
+    monitor: MonitorDesignator,
+    initial: Option<Rotation>,
+    sender: Sender<Rotation>,
+) {
+    log::trace!("Entering fn watch_rotation");
+
+    let mut last = initial;
+    loop {
+        if let Some(rotation) = connection.rotation(&monitor) {
+            if last != Some(rotation) {
+                last = Some(rotation);
+                log::info!("Monitor {} has rotation {}.", monitor, rotation);
+                if sender.send(rotation).is_err() {
+                    break;
+                }
+            }
+        }
+        connection.wait_for_change();
+    }
+
+    log::trace!("Leaving fn watch_rotation");
+}
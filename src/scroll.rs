@@ -0,0 +1,166 @@
+//! Scrolling with two fingers, emitted as high-resolution wheel events of the virtual device.
+//!
+//! While two fingers are on the panel, the motion of the point between them is converted to wheel events,
+//! like on a touchpad. Each packet only moves one of the fingers, so the point between them moves half as far.
+//! Wheel events are emitted in the kernel's high-resolution units of 1/120 of a wheel click,
+//! with a regular wheel event whenever a full click has been scrolled, for applications that ignore the former.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::geo::Point2D;
+use crate::units::ScreenSpace;
+
+/// High-resolution wheel units per wheel click, as defined by the kernel.
+pub const WHEEL_HI_RES_PER_CLICK: i32 = 120;
+
+/// Parameters for scrolling with two fingers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TwoFingerScroll {
+    /// Wheel clicks per 100 pixels that the fingers move.
+    pub speed: f32,
+    /// If the content follows the fingers like on a phone, instead of the fingers turning a wheel.
+    #[serde(default)]
+    pub natural: bool,
+}
+
+impl fmt::Display for TwoFingerScroll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "{} clicks per 100px{}",
+            self.speed,
+            if self.natural { ", natural" } else { "" }
+        ))
+    }
+}
+
+/// Wheel motion to emit for a packet, vertical and horizontal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrollDelta {
+    /// Full wheel clicks for REL_WHEEL and REL_HWHEEL. Positive values scroll up and right.
+    pub clicks: [i32; 2],
+    /// High-resolution units for REL_WHEEL_HI_RES and REL_HWHEEL_HI_RES.
+    pub hi_res: [i32; 2],
+}
+
+/// Tracks the first two fingers and converts their common motion to wheel events.
+#[derive(Debug, Clone)]
+pub struct ScrollRecognizer {
+    params: TwoFingerScroll,
+    /// Positions of the first two fingers while they are down.
+    fingers: [Option<Point2D<ScreenSpace>>; 2],
+    /// Scrolled high-resolution units that were not emitted yet because they are fractional, between -0.5 and 0.5.
+    fraction: [f32; 2],
+    /// High-resolution units emitted since the last full wheel click.
+    partial_click: [i32; 2],
+}
+
+impl ScrollRecognizer {
+    pub fn new(params: TwoFingerScroll) -> Self {
+        Self {
+            params,
+            fingers: [None, None],
+            fraction: [0.0; 2],
+            partial_click: [0; 2],
+        }
+    }
+
+    /// Forget all fingers, e.g. after the first one was rejected as a palm.
+    pub fn reset(&mut self) {
+        self.fingers = [None, None];
+    }
+
+    /// If two fingers are on the panel, so that the first one should not move the pointer.
+    pub fn is_scrolling(&self) -> bool {
+        self.fingers.iter().all(Option::is_some)
+    }
+
+    /// Update the position of a finger, or that it was lifted if there is no position.
+    /// Returns the wheel motion to emit if both fingers are down and one of them moved.
+    pub fn update(
+        &mut self,
+        contact: u8,
+        position: Option<Point2D<ScreenSpace>>,
+    ) -> Option<ScrollDelta> {
+        let finger = self.fingers.get_mut(usize::from(contact))?;
+        let previous = std::mem::replace(finger, position);
+        if !self.is_scrolling() {
+            self.fraction = [0.0; 2];
+            self.partial_click = [0; 2];
+            return None;
+        }
+        let (previous, position) = (previous?, position?);
+
+        // Fingers moving up scroll up, unless scrolling is natural.
+        let direction = if self.params.natural { 1.0 } else { -1.0 };
+        let scale = self.params.speed / 100.0 * WHEEL_HI_RES_PER_CLICK as f32 / 2.0;
+        let motion = [
+            (position.y - previous.y).float() * scale * direction,
+            (position.x - previous.x).float() * scale * -direction,
+        ];
+
+        let mut delta = ScrollDelta::default();
+        let axes = motion
+            .into_iter()
+            .zip(&mut self.fraction)
+            .zip(&mut self.partial_click)
+            .zip(delta.hi_res.iter_mut().zip(&mut delta.clicks));
+        for (((motion, fraction), partial_click), (hi_res, clicks)) in axes {
+            *fraction += motion;
+            let rounded = fraction.round();
+            *fraction -= rounded;
+            *hi_res = rounded as i32;
+
+            *partial_click += *hi_res;
+            *clicks = *partial_click / WHEEL_HI_RES_PER_CLICK;
+            *partial_click %= WHEEL_HI_RES_PER_CLICK;
+        }
+        (delta != ScrollDelta::default()).then_some(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scroll_recognizer() {
+        let mut scroll = ScrollRecognizer::new(TwoFingerScroll {
+            speed: 1.0,
+            natural: false,
+        });
+
+        // A single finger does not scroll.
+        assert_eq!(None, scroll.update(0, Some((500, 500).into())));
+        assert_eq!(None, scroll.update(0, Some((500, 400).into())));
+        assert!(!scroll.is_scrolling());
+
+        // Moving both fingers up by 100 pixels scrolls up by one click.
+        assert_eq!(None, scroll.update(1, Some((600, 400).into())));
+        assert!(scroll.is_scrolling());
+        let mut total = ScrollDelta::default();
+        for i in 1..=10 {
+            for contact in 0..2 {
+                let x = 500 + i32::from(contact) * 100;
+                if let Some(delta) = scroll.update(contact, Some((x, 400 - i * 10).into())) {
+                    for axis in 0..2 {
+                        total.clicks[axis] += delta.clicks[axis];
+                        total.hi_res[axis] += delta.hi_res[axis];
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            ScrollDelta {
+                clicks: [1, 0],
+                hi_res: [WHEEL_HI_RES_PER_CLICK, 0]
+            },
+            total
+        );
+
+        // Lifting a finger ends scrolling.
+        assert_eq!(None, scroll.update(1, None));
+        assert_eq!(None, scroll.update(0, Some((500, 100).into())));
+        assert!(!scroll.is_scrolling());
+    }
+}
@@ -0,0 +1,104 @@
+//! Touchscreens with older controllers that are connected over RS-232 or a USB-serial adapter instead of USB.
+//!
+//! They send their packets over a serial port, which is put into raw mode with the configured baud rate.
+//! The packets are then decoded with [PacketFormat::Serial], after which the driver and the calibration work the same
+//! as for USB touchscreens.
+
+use anyhow::anyhow;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::termios::{self, BaudRate, ControlFlags, FlushArg, SetArg};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::error::EgalaxError;
+use crate::protocol::PacketFormat;
+
+/// The serial port that a serial touchscreen is connected to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerialPort {
+    /// The device node of the port, e.g. `/dev/ttyS0` or `/dev/ttyUSB0`.
+    pub path: PathBuf,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+}
+
+/// Most serial controllers are set to 9600 baud.
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+impl SerialPort {
+    /// The baud rate as the terminal interface knows it, or `None` if it is not a standard one.
+    pub fn speed(&self) -> Option<BaudRate> {
+        let speed = match self.baud_rate {
+            1200 => BaudRate::B1200,
+            2400 => BaudRate::B2400,
+            4800 => BaudRate::B4800,
+            9600 => BaudRate::B9600,
+            19200 => BaudRate::B19200,
+            38400 => BaudRate::B38400,
+            57600 => BaudRate::B57600,
+            115200 => BaudRate::B115200,
+            _ => return None,
+        };
+        Some(speed)
+    }
+
+    /// Put an opened port into raw mode with the baud rate, so that the packets are read unchanged.
+    /// Bytes that were received before are discarded, since they probably end in the middle of a packet.
+    /// The port is made non-blocking, so that a packet that is still arriving does not block the reader,
+    /// see [crate::protocol::PacketDecoder].
+    pub fn configure(&self, port: &File) -> Result<(), EgalaxError> {
+        let speed = self.speed().ok_or_else(|| {
+            EgalaxError::Generic(anyhow!("unsupported baud rate {}", self.baud_rate))
+        })?;
+        let fd = port.as_raw_fd();
+        let mut attributes = termios::tcgetattr(fd).map_err(io::Error::from)?;
+        termios::cfmakeraw(&mut attributes);
+        termios::cfsetspeed(&mut attributes, speed).map_err(io::Error::from)?;
+        // The controllers only send, so there is no flow control and the modem lines are ignored.
+        attributes.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+        attributes.control_flags &= !ControlFlags::CRTSCTS;
+        termios::tcsetattr(fd, SetArg::TCSANOW, &attributes).map_err(io::Error::from)?;
+        termios::tcflush(fd, FlushArg::TCIFLUSH).map_err(io::Error::from)?;
+        let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(io::Error::from)?;
+        let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+        fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for SerialPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "'{}' at {} baud",
+            self.path.display(),
+            self.baud_rate
+        ))
+    }
+}
+
+/// The format of the packets of a touchscreen, which is the serial one if it is connected to a serial port.
+pub fn packet_format(serial: Option<&SerialPort>) -> PacketFormat {
+    match serial {
+        Some(_) => PacketFormat::Serial,
+        None => PacketFormat::Hidraw,
+    }
+}
+
+/// Open the touchscreen at `path`, which is a serial port if `serial` is given.
+pub fn open_device(path: &Path, serial: Option<&SerialPort>) -> Result<File, EgalaxError> {
+    prepare_device(OpenOptions::new().read(true).open(path)?, serial)
+}
+
+/// Use an already opened touchscreen, which is configured first if it is a serial port.
+pub fn prepare_device(device: File, serial: Option<&SerialPort>) -> Result<File, EgalaxError> {
+    if let Some(serial) = serial {
+        serial.configure(&device)?;
+    }
+    Ok(device)
+}
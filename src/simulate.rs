@@ -0,0 +1,342 @@
+//! Generators of synthetic touchscreen input, so that filters and gestures can be tested reproducibly without hardware.
+//!
+//! A [Gesture] generates a sequence of [TimedPacket]s which can be written to a fifo with [play]
+//! and then read by the driver like a real hidraw device. [Playback] options select a section of long sequences
+//! and can speed it up or loop it.
+
+use std::f32::consts::PI;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::EgalaxError;
+use crate::geo::{Point2D, AABB};
+use crate::protocol::{TouchState, USBPacket};
+use crate::units::{TouchSpace, UdimRepr};
+
+/// A packet together with the time at which it is sent, relative to the start of the sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedPacket {
+    pub offset: Duration,
+    pub packet: USBPacket,
+}
+
+/// Parameters shared by all generators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SynthConfig {
+    /// Packets per second while touching.
+    pub rate: f32,
+    /// Resolution of the generated packets in bits.
+    pub resolution: u8,
+    /// Seed for the random parts of a gesture. The same seed always generates the same packets.
+    pub seed: u64,
+}
+
+impl SynthConfig {
+    fn period(&self) -> Duration {
+        Duration::from_nanos((1e9 / self.rate as f64).round() as u64)
+    }
+
+    /// Number of touching packets for a gesture of the given duration, at least one.
+    fn samples(&self, duration: Duration) -> usize {
+        ((duration.as_secs_f32() * self.rate).ceil() as usize).max(1)
+    }
+}
+
+impl Default for SynthConfig {
+    fn default() -> Self {
+        Self {
+            // A real touchscreen sends about 100 packets per second.
+            rate: 100.0,
+            resolution: 12,
+            seed: 0x5eed,
+        }
+    }
+}
+
+/// A parameterized gesture. Every gesture ends with a release packet at its last position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    /// Move in a straight line.
+    Line {
+        from: Point2D<TouchSpace>,
+        to: Point2D<TouchSpace>,
+        duration: Duration,
+    },
+    /// Move once around a circle, starting to the right of the center.
+    Circle {
+        center: Point2D<TouchSpace>,
+        radius: f32,
+        duration: Duration,
+    },
+    /// Touch in one place with every packet randomly displaced by up to `jitter` units.
+    Tap {
+        position: Point2D<TouchSpace>,
+        jitter: f32,
+        duration: Duration,
+    },
+    /// Hold perfectly still.
+    LongPress {
+        position: Point2D<TouchSpace>,
+        duration: Duration,
+    },
+    /// Touch at random positions in an area, like electromagnetic interference.
+    Noise {
+        area: AABB<TouchSpace>,
+        duration: Duration,
+    },
+}
+
+impl Gesture {
+    /// Generate the packets of the gesture.
+    pub fn generate(&self, cfg: &SynthConfig) -> Result<Vec<TimedPacket>, EgalaxError> {
+        let mut rng = XorShift::new(cfg.seed);
+        let duration = match *self {
+            Gesture::Line { duration, .. }
+            | Gesture::Circle { duration, .. }
+            | Gesture::Tap { duration, .. }
+            | Gesture::LongPress { duration, .. }
+            | Gesture::Noise { duration, .. } => duration,
+        };
+        let samples = cfg.samples(duration);
+
+        let positions = (0..samples).map(|i| {
+            // Fraction of the gesture that is done, reaching 1 with the last sample.
+            let t = if samples > 1 {
+                i as f32 / (samples - 1) as f32
+            } else {
+                1.0
+            };
+            match *self {
+                Gesture::Line { from, to, .. } => (
+                    from.x.float() + (to.x - from.x).float() * t,
+                    from.y.float() + (to.y - from.y).float() * t,
+                ),
+                Gesture::Circle { center, radius, .. } => (
+                    center.x.float() + radius * (2.0 * PI * t).cos(),
+                    center.y.float() + radius * (2.0 * PI * t).sin(),
+                ),
+                Gesture::Tap {
+                    position, jitter, ..
+                } => (
+                    position.x.float() + jitter * rng.next_signed(),
+                    position.y.float() + jitter * rng.next_signed(),
+                ),
+                Gesture::LongPress { position, .. } => (position.x.float(), position.y.float()),
+                Gesture::Noise { area, .. } => (
+                    area.xrange().min().float() + area.width().float() * rng.next_unit(),
+                    area.yrange().min().float() + area.height().float() * rng.next_unit(),
+                ),
+            }
+        });
+
+        let mut packets = Vec::with_capacity(samples + 1);
+        for (i, (x, y)) in positions.enumerate() {
+            packets.push(TimedPacket {
+                offset: cfg.period() * i as u32,
+                packet: touch_packet(TouchState::IsTouching, x, y, cfg.resolution)?,
+            });
+        }
+
+        let last = packets[packets.len() - 1];
+        let position = last.packet.position();
+        packets.push(TimedPacket {
+            offset: last.offset + cfg.period(),
+            packet: USBPacket::new(TouchState::NotTouching, position, cfg.resolution)?,
+        });
+        Ok(packets)
+    }
+}
+
+/// Create a packet at the rounded position, clamped to the range of the resolution.
+fn touch_packet(
+    touch_state: TouchState,
+    x: f32,
+    y: f32,
+    resolution: u8,
+) -> Result<USBPacket, EgalaxError> {
+    let max = ((1 << resolution) - 1) as f32;
+    let position = Point2D::from((
+        x.round().clamp(0.0, max) as UdimRepr,
+        y.round().clamp(0.0, max) as UdimRepr,
+    ));
+    Ok(USBPacket::new(touch_state, position, resolution)?)
+}
+
+/// Concatenate sequences of packets, each one starting `pause` after the previous one ended.
+pub fn chain(sequences: &[Vec<TimedPacket>], pause: Duration) -> Vec<TimedPacket> {
+    let mut packets: Vec<TimedPacket> = Vec::new();
+    for sequence in sequences {
+        let start = packets
+            .last()
+            .map_or(Duration::ZERO, |last| last.offset + pause);
+        packets.extend(sequence.iter().map(|timed| TimedPacket {
+            offset: start + timed.offset,
+            packet: timed.packet,
+        }));
+    }
+    packets
+}
+
+/// How packets are played back by [play].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Playback {
+    /// Factor by which playback is faster than real time.
+    pub speed: f32,
+    /// Start over after the last packet until writing fails, e.g. because the reader is gone.
+    pub looped: bool,
+    /// Only packets with offsets from `start` up to and including `end` are played.
+    pub start: Duration,
+    pub end: Option<Duration>,
+}
+
+impl Playback {
+    /// Select the packets in the window and scale their offsets by the speed, so that the window starts at zero.
+    pub fn schedule(&self, packets: &[TimedPacket]) -> Vec<TimedPacket> {
+        packets
+            .iter()
+            .filter(|timed| {
+                timed.offset >= self.start && self.end.is_none_or(|end| timed.offset <= end)
+            })
+            .map(|timed| TimedPacket {
+                offset: (timed.offset - self.start).div_f64(self.speed as f64),
+                packet: timed.packet,
+            })
+            .collect()
+    }
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            looped: false,
+            start: Duration::ZERO,
+            end: None,
+        }
+    }
+}
+
+/// Write the packets to a stream in real time according to the playback options,
+/// i.e. each packet is written once its scheduled offset has passed.
+pub fn play<W: Write>(
+    writer: &mut W,
+    packets: &[TimedPacket],
+    playback: &Playback,
+) -> io::Result<()> {
+    log::trace!("Entering fn play");
+
+    let schedule = playback.schedule(packets);
+    if schedule.is_empty() {
+        log::warn!("No packets to play in the selected window.");
+    } else {
+        loop {
+            play_once(writer, &schedule)?;
+            if !playback.looped {
+                break;
+            }
+            log::debug!("Looping playback.");
+        }
+    }
+
+    log::trace!("Leaving fn play");
+    Ok(())
+}
+
+fn play_once<W: Write>(writer: &mut W, packets: &[TimedPacket]) -> io::Result<()> {
+    let start = Instant::now();
+    for timed in packets {
+        let deadline = start + timed.offset;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+        log::debug!("Sending packet {}", timed.packet);
+        writer.write_all(&timed.packet.encode().0)?;
+    }
+    writer.flush()
+}
+
+/// A small xorshift pseudo random number generator, so that gestures are reproducible without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in [0, 1).
+    fn next_unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A number in [-1, 1).
+    fn next_signed(&mut self) -> f32 {
+        self.next_unit() * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_line_and_noise() {
+        let cfg = SynthConfig::default();
+        let line = Gesture::Line {
+            from: (100, 100).into(),
+            to: (200, 300).into(),
+            duration: Duration::from_millis(100),
+        }
+        .generate(&cfg)
+        .unwrap();
+
+        // 10 touching packets and the release.
+        assert_eq!(11, line.len());
+        assert_eq!(Point2D::from((100, 100)), line[0].packet.position());
+        assert_eq!(Point2D::from((200, 300)), line[9].packet.position());
+        assert_eq!(TouchState::NotTouching, line[10].packet.touch_state());
+        assert_eq!(Duration::from_millis(100), line[10].offset);
+
+        let noise = Gesture::Noise {
+            area: AABB::from((0, 0, 4095, 4095)),
+            duration: Duration::from_millis(50),
+        };
+        assert_eq!(noise.generate(&cfg).unwrap(), noise.generate(&cfg).unwrap());
+
+        let both = chain(
+            &[line, noise.generate(&cfg).unwrap()],
+            Duration::from_secs(1),
+        );
+        assert_eq!(Duration::from_millis(1100), both[11].offset);
+    }
+
+    #[test]
+    fn test_playback_schedule() {
+        let packets = Gesture::LongPress {
+            position: (100, 100).into(),
+            duration: Duration::from_secs(1),
+        }
+        .generate(&SynthConfig::default())
+        .unwrap();
+        let playback = Playback {
+            speed: 2.0,
+            start: Duration::from_millis(200),
+            end: Some(Duration::from_millis(400)),
+            ..Playback::default()
+        };
+
+        let schedule = playback.schedule(&packets);
+        assert_eq!(21, schedule.len());
+        assert_eq!(Duration::ZERO, schedule[0].offset);
+        assert_eq!(Duration::from_millis(100), schedule[20].offset);
+    }
+}
@@ -0,0 +1,280 @@
+//! Smoothing of the positions of a touch against cursor jitter, applied before any events are generated.
+//!
+//! A plain exponential moving average removes jitter but makes the cursor lag behind fast swipes.
+//! The one-euro filter (Casiez et al., CHI 2012) avoids that by smoothing strongly while the finger is slow
+//! and less and less the faster it moves.
+//!
+//! Both axes can be smoothed differently, since some panels are much noisier along one axis, and the filter can
+//! be bypassed entirely while the finger moves fast, so that swipes are not rubber-banded.
+
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fmt;
+use std::time::Instant;
+
+use crate::geo::Point2D;
+use crate::units::{udim, ScreenSpace};
+
+/// How the positions of a touch are smoothed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Smoothing {
+    /// The filter of the X axis, and of the Y axis unless `y` is set.
+    #[serde(flatten)]
+    pub filter: Filter,
+    /// Another filter for the Y axis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<Filter>,
+    /// Positions are passed through unchanged while the finger moves faster than this in pixels per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bypass_speed: Option<f32>,
+}
+
+impl Smoothing {
+    /// The filters of the X and Y axis.
+    pub fn filters(&self) -> [Filter; 2] {
+        [self.filter, self.y.unwrap_or(self.filter)]
+    }
+}
+
+/// Which filter smooths an axis and how strongly.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "filter", rename_all = "kebab-case")]
+pub enum Filter {
+    /// Exponential moving average with a fixed weight of each new position.
+    Ema {
+        /// Weight of a new position between 0 (never move) and 1 (no smoothing).
+        alpha: f32,
+    },
+    /// One-euro filter whose cutoff frequency rises with the speed of the finger.
+    OneEuro {
+        /// Cutoff frequency in Hz while the finger is still. Lower values remove more jitter.
+        min_cutoff: f32,
+        /// How fast the cutoff frequency rises with the speed in pixels per second. Higher values reduce lag.
+        beta: f32,
+        /// Cutoff frequency in Hz for smoothing the speed itself.
+        #[serde(default = "default_d_cutoff")]
+        d_cutoff: f32,
+    },
+}
+
+fn default_d_cutoff() -> f32 {
+    1.0
+}
+
+impl Filter {
+    /// Scale the filter for positions in pixels that are `factor` times as large.
+    pub fn scale(&mut self, factor: f32) {
+        // The cutoff rises with the speed in pixels per second, which grows with the resolution.
+        if let Filter::OneEuro { beta, .. } = self {
+            *beta /= factor;
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Ema { alpha } => f.write_fmt(format_args!("EMA with alpha {}", alpha)),
+            Filter::OneEuro {
+                min_cutoff,
+                beta,
+                d_cutoff,
+            } => f.write_fmt(format_args!(
+                "one-euro with min cutoff {}Hz, beta {}, d cutoff {}Hz",
+                min_cutoff, beta, d_cutoff
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Smoothing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.y {
+            Some(y) => f.write_fmt(format_args!("X: {}, Y: {}", self.filter, y))?,
+            None => self.filter.fmt(f)?,
+        }
+        match self.bypass_speed {
+            Some(speed) => f.write_fmt(format_args!(", bypassed above {}px/s", speed)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State of the filter for one axis.
+#[derive(Debug, Clone, Copy)]
+struct AxisState {
+    /// The last raw position.
+    raw: f32,
+    /// The last smoothed position.
+    position: f32,
+    /// The last smoothed speed, only used by the one-euro filter.
+    speed: f32,
+}
+
+/// Smooths the positions of the current touch. The first position of a touch is passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct SmoothingFilter {
+    params: Smoothing,
+    /// Time of the last position and the state of both axes, or `None` between touches.
+    state: Option<(Instant, [AxisState; 2])>,
+}
+
+impl SmoothingFilter {
+    pub fn new(params: Smoothing) -> Self {
+        Self {
+            params,
+            state: None,
+        }
+    }
+
+    /// Smooth a position of the current touch.
+    pub fn filter(&mut self, now: Instant, position: Point2D<ScreenSpace>) -> Point2D<ScreenSpace> {
+        let raw = [position.x.float(), position.y.float()];
+        let axes = match self.state {
+            None => raw.map(|position| AxisState {
+                raw: position,
+                position,
+                speed: 0.0,
+            }),
+            Some((last, axes)) => {
+                let dt = now.saturating_duration_since(last).as_secs_f32();
+                // Packets with the same timestamp carry no information about the speed.
+                if dt <= 0.0 {
+                    axes
+                } else if self.is_fast(axes, raw, dt) {
+                    // Start from the raw position and speed, so that the filter does not jump back once the finger
+                    // slows down again.
+                    [0, 1].map(|i| AxisState {
+                        raw: raw[i],
+                        position: raw[i],
+                        speed: (raw[i] - axes[i].raw) / dt,
+                    })
+                } else {
+                    let filters = self.params.filters();
+                    [0, 1].map(|i| filter_axis(filters[i], axes[i], raw[i], dt))
+                }
+            }
+        };
+        self.state = Some((now, axes));
+
+        Point2D {
+            x: udim::round_from(axes[0].position),
+            y: udim::round_from(axes[1].position),
+        }
+    }
+
+    /// If the finger moved faster than the bypass speed since the last position.
+    fn is_fast(&self, axes: [AxisState; 2], raw: [f32; 2], dt: f32) -> bool {
+        self.params.bypass_speed.is_some_and(|bypass_speed| {
+            let distance = (raw[0] - axes[0].raw).hypot(raw[1] - axes[1].raw);
+            distance / dt > bypass_speed
+        })
+    }
+
+    /// Forget the current touch, so that the next one does not start from its position.
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+fn filter_axis(filter: Filter, state: AxisState, raw: f32, dt: f32) -> AxisState {
+    match filter {
+        Filter::Ema { alpha } => AxisState {
+            raw,
+            position: lerp(state.position, raw, alpha.clamp(0.0, 1.0)),
+            speed: 0.0,
+        },
+        Filter::OneEuro {
+            min_cutoff,
+            beta,
+            d_cutoff,
+        } => {
+            let raw_speed = (raw - state.position) / dt;
+            let speed = lerp(state.speed, raw_speed, cutoff_alpha(d_cutoff, dt));
+            let cutoff = min_cutoff + beta * speed.abs();
+            AxisState {
+                raw,
+                position: lerp(state.position, raw, cutoff_alpha(cutoff, dt)),
+                speed,
+            }
+        }
+    }
+}
+
+/// Weight of a new sample for a low-pass filter with the given cutoff frequency and sampling period.
+fn cutoff_alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff.max(f32::EPSILON));
+    1.0 / (1.0 + tau / dt)
+}
+
+fn lerp(from: f32, to: f32, alpha: f32) -> f32 {
+    from + alpha * (to - from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_one_euro_filter() {
+        let mut filter = SmoothingFilter::new(Smoothing {
+            filter: Filter::OneEuro {
+                min_cutoff: 1.0,
+                beta: 0.05,
+                d_cutoff: 1.0,
+            },
+            y: None,
+            bypass_speed: None,
+        });
+        let start = Instant::now();
+        let at = |i: u32| start + Duration::from_millis(10 * u64::from(i));
+
+        // A finger held still that jitters by 10 pixels is held almost still.
+        let first = filter.filter(at(0), (500, 500).into());
+        assert_eq!(Point2D::from((500, 500)), first);
+        for i in 1..100 {
+            let p = filter.filter(at(i), (500 + (i % 2) as i32 * 10, 500).into());
+            assert!((500..=510).contains(&p.x.value()));
+            if i > 50 {
+                assert!((p.x.value() - 505).abs() <= 1);
+            }
+        }
+        filter.reset();
+
+        // A fast swipe of 2000 pixels per second is followed closely.
+        for i in 0..50 {
+            let p = filter.filter(at(200 + i), (i as i32 * 20, 500).into());
+            assert!(i as i32 * 20 - p.x.value() < 40);
+        }
+    }
+
+    #[test]
+    fn test_per_axis_and_bypass() {
+        let smoothing: Smoothing = toml::from_str(
+            r#"
+            filter = "ema"
+            alpha = 0.5
+            y = { filter = "ema", alpha = 0.1 }
+            bypass_speed = 5000.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(Some(Filter::Ema { alpha: 0.1 }), smoothing.y);
+        let mut filter = SmoothingFilter::new(smoothing);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // The Y axis is smoothed more strongly than the X axis.
+        filter.filter(at(0), (500, 500).into());
+        let p = filter.filter(at(10), (520, 520).into());
+        assert_eq!(Point2D::from((510, 502)), p);
+
+        // A jump of 100 pixels in 10ms is faster than the bypass speed and passed through.
+        let p = filter.filter(at(20), (620, 520).into());
+        assert_eq!(Point2D::from((620, 520)), p);
+        // Slower motion is smoothed again from there.
+        let p = filter.filter(at(30), (630, 520).into());
+        assert_eq!(Point2D::from((625, 520)), p);
+    }
+}
@@ -0,0 +1,190 @@
+//! Integration with systemd for running the driver as a `Type=notify` service.
+//!
+//! The driver reports when it is ready and when it stops, and pings the watchdog while it reads packets,
+//! see sd_notify(3). Termination signals are turned into a regular shutdown so that stopping can be reported.
+//! SIGHUP asks the driver to reload its config, as is customary for daemons.
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Set by the signal handler when the service should stop.
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_termination(_signal: i32) {
+    TERMINATION_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Handle SIGTERM and SIGINT by setting a flag instead of exiting, see [termination_requested].
+/// Blocking system calls like poll are interrupted by the signals so that the flag is noticed right away.
+pub fn handle_termination() -> nix::Result<()> {
+    // Without SA_RESTART a blocking poll fails with EINTR.
+    let action = SigAction::new(
+        SigHandler::Handler(request_termination),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGTERM, Signal::SIGINT] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        unsafe { sigaction(signal, &action) }?;
+    }
+    Ok(())
+}
+
+/// Checks if a termination signal was received since [handle_termination] was called.
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Counts the reload requests, so that the driver of every touchscreen notices each of them.
+static RELOAD_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn request_reload(_signal: i32) {
+    RELOAD_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Handle SIGHUP by counting a reload request instead of exiting, see [reload_generation].
+pub fn handle_reload() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(request_reload),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only updates an atomic, which is async-signal-safe.
+    unsafe { sigaction(Signal::SIGHUP, &action) }?;
+    Ok(())
+}
+
+/// The number of reload requests so far. A reload was requested if it changed since it was last looked at.
+pub fn reload_generation() -> u64 {
+    RELOAD_GENERATION.load(Ordering::SeqCst)
+}
+
+/// Sends notifications to the service manager.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    /// The watchdog is pinged at this interval, which is half of the configured timeout.
+    watchdog_interval: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Notifier {
+    /// Connect to the socket in `$NOTIFY_SOCKET`, or return `None` if we were not started by a service manager.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let path = path.to_string_lossy();
+        // A leading @ denotes a socket in the abstract namespace.
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path.as_ref())?,
+        };
+
+        // The watchdog may be meant for another process of the service.
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        let watchdog_usec = env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| watchdog_pid.is_none_or(|pid| pid == process::id().to_string()))
+            .and_then(|usec| usec.parse().ok());
+
+        Ok(Some(Self::new(
+            address,
+            watchdog_usec.map(Duration::from_micros),
+        )?))
+    }
+
+    /// Notify the service manager at `address`, pinging its watchdog often enough for `watchdog_timeout`.
+    pub(crate) fn new(address: SocketAddr, watchdog_timeout: Option<Duration>) -> io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            address,
+            watchdog_interval: watchdog_timeout.map(|timeout| timeout / 2),
+            last_ping: Instant::now(),
+        })
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            log::warn!("Could not notify the service manager: {}", e);
+        }
+    }
+
+    /// Report that the virtual device exists and the driver is reading packets.
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={}", status));
+    }
+
+    /// Report what the driver is doing, e.g. waiting for an unplugged device.
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status));
+    }
+
+    /// Report that the driver is shutting down.
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// How often the watchdog must be pinged, if it is enabled.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Ping the watchdog if it is enabled and the last ping was at least half the timeout ago.
+    /// This is cheap enough to call for every packet.
+    pub fn ping_watchdog(&mut self) {
+        let Some(interval) = self.watchdog_interval else {
+            return;
+        };
+        let now = Instant::now();
+        if now.duration_since(self.last_ping) >= interval {
+            self.notify("WATCHDOG=1");
+            self.last_ping = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_notifier() {
+        let path = env::temp_dir().join(format!("egalax-rs-notify-{}.sock", process::id()));
+        fs::remove_file(&path).ok();
+        let manager = UnixDatagram::bind(&path).unwrap();
+        manager.set_nonblocking(true).unwrap();
+
+        let mut notifier = Notifier::new(
+            SocketAddr::from_pathname(&path).unwrap(),
+            Some(Duration::ZERO),
+        )
+        .unwrap();
+        notifier.ready("Driving /dev/hidraw0");
+        notifier.ping_watchdog();
+        notifier.stopping();
+
+        let mut buf = [0; 64];
+        let mut received = Vec::new();
+        while let Ok(len) = manager.recv(&mut buf) {
+            received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+        }
+        fs::remove_file(&path).ok();
+        assert_eq!(
+            vec![
+                "READY=1\nSTATUS=Driving /dev/hidraw0",
+                "WATCHDOG=1",
+                "STOPPING=1"
+            ],
+            received
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! A test pattern to check the accuracy of an installed touchscreen.
+//!
+//! A grid of targets is shown on the monitor one after another. For each target we measure how far the mapped touch
+//! lands from it and how long it took until it was touched, which results in a report for the QA of each unit.
+
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use crate::calibration::{Calibration, TouchCloud};
+use crate::error::EgalaxError;
+use crate::geo::{NormalizedPoint, Point2D, AABB};
+use crate::overlay::{Overlay, BLACK, RED};
+use crate::protocol::{PacketFormat, TouchState, USBMessage};
+use crate::pump::PacketPump;
+use crate::units::ScreenSpace;
+
+/// Number of targets per row and column.
+pub const GRID_SIZE: usize = 3;
+/// The test is aborted if nobody touches the screen for this long.
+pub const TEST_PATTERN_TIMEOUT: Duration = Duration::from_secs(60);
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const TARGET_RADIUS: i32 = 15;
+
+/// Positions of the targets, row by row, spread evenly between 10% and 90% of the monitor size.
+pub fn grid_positions(area: AABB<ScreenSpace>) -> Vec<Point2D<ScreenSpace>> {
+    let fraction = |i: usize| 0.1 + 0.8 * i as f32 / (GRID_SIZE - 1) as f32;
+    (0..GRID_SIZE)
+        .flat_map(|row| (0..GRID_SIZE).map(move |column| (fraction(column), fraction(row))))
+        .map(|(fx, fy)| area.denormalize(NormalizedPoint::new(fx, fy)))
+        .collect()
+}
+
+/// Measurement of a single target.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct TargetResult {
+    pub target: Point2D<ScreenSpace>,
+    /// Where the touch landed after applying the calibration.
+    pub touch: Point2D<ScreenSpace>,
+    /// Distance between target and touch in pixels.
+    pub error_px: f32,
+    /// Time from showing the target until it was touched.
+    pub latency_ms: u64,
+}
+
+/// Results of a whole test pattern.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestPatternReport {
+    pub targets: Vec<TargetResult>,
+    pub mean_error_px: f32,
+    pub max_error_px: f32,
+    pub mean_latency_ms: u64,
+}
+
+impl TestPatternReport {
+    pub fn new(targets: Vec<TargetResult>) -> Self {
+        let n = targets.len().max(1);
+        Self {
+            mean_error_px: targets.iter().map(|t| t.error_px).sum::<f32>() / n as f32,
+            max_error_px: targets.iter().map(|t| t.error_px).fold(0.0, f32::max),
+            mean_latency_ms: targets.iter().map(|t| t.latency_ms).sum::<u64>() / n as u64,
+            targets,
+        }
+    }
+}
+
+impl fmt::Display for TestPatternReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("    Target             Touch                Error  Latency\n")?;
+        for (i, result) in self.targets.iter().enumerate() {
+            f.write_fmt(format_args!(
+                "{:>2}. {:<18} {:<18} {:>6.1}px {:>6}ms\n",
+                i + 1,
+                result.target.to_string(),
+                result.touch.to_string(),
+                result.error_px,
+                result.latency_ms
+            ))?;
+        }
+        f.write_fmt(format_args!(
+            "Mean error: {:.1}px. Max error: {:.1}px. Mean latency: {}ms.",
+            self.mean_error_px, self.max_error_px, self.mean_latency_ms
+        ))
+    }
+}
+
+/// Progress of a test pattern, driven by touch packets.
+#[derive(Debug, Clone)]
+pub struct TestPatternSession {
+    targets: Vec<Point2D<ScreenSpace>>,
+    results: Vec<TargetResult>,
+    /// When the current target was shown and when it was first touched.
+    shown: Instant,
+    touched: Option<Instant>,
+    cloud: TouchCloud,
+}
+
+impl TestPatternSession {
+    pub fn new(targets: Vec<Point2D<ScreenSpace>>, now: Instant) -> Self {
+        Self {
+            targets,
+            results: Vec::new(),
+            shown: now,
+            touched: None,
+            cloud: TouchCloud::default(),
+        }
+    }
+
+    /// Index of the target that should be touched next, or `None` if all targets are done.
+    pub fn current_target(&self) -> Option<usize> {
+        (self.results.len() < self.targets.len()).then_some(self.results.len())
+    }
+
+    /// Feed a message into the session. A target is done when the finger is lifted.
+    pub fn update(
+        &mut self,
+        message: &USBMessage,
+        calibration: &Calibration,
+        area: AABB<ScreenSpace>,
+    ) {
+        let Some(index) = self.current_target() else {
+            return;
+        };
+        // Additional fingers are not measured.
+        if message.contact() != 0 {
+            return;
+        }
+        let packet = message.packet();
+
+        match packet.touch_state() {
+            TouchState::IsTouching => {
+                self.touched.get_or_insert(message.time());
+                self.cloud.push(packet.position());
+            }
+            TouchState::NotTouching => {
+                let (Some(position), Some(touched)) =
+                    (self.cloud.compute_touch_coord(), self.touched)
+                else {
+                    return;
+                };
+                let target = self.targets[index];
+                let touch = calibration.map_to(position, area);
+                let result = TargetResult {
+                    target,
+                    touch,
+                    error_px: target.euclidean_distance_to(&touch),
+                    latency_ms: touched.saturating_duration_since(self.shown).as_millis() as u64,
+                };
+                log::info!("Target {} touched at {}: {:?}", index + 1, touch, result);
+
+                self.results.push(result);
+                self.cloud.clear();
+                self.touched = None;
+                self.shown = message.time();
+            }
+        }
+    }
+
+    /// The report, once all targets are done.
+    pub fn report(&self) -> Option<TestPatternReport> {
+        self.current_target()
+            .is_none()
+            .then(|| TestPatternReport::new(self.results.clone()))
+    }
+}
+
+/// Show the grid of targets on the monitor area and measure the touches read from `stream`, whose packets are in `format`.
+/// Fails if the stream ends or nobody touches the screen for [TEST_PATTERN_TIMEOUT].
+pub fn run_test_pattern<T>(
+    stream: T,
+    format: PacketFormat,
+    area: AABB<ScreenSpace>,
+    calibration: &Calibration,
+) -> Result<TestPatternReport, EgalaxError>
+where
+    T: io::Read + AsRawFd + Send + 'static,
+{
+    log::trace!("Entering fn run_test_pattern");
+
+    let overlay = Overlay::open(area)?;
+    let pump = PacketPump::spawn(stream, format);
+    let mut session = TestPatternSession::new(grid_positions(area), Instant::now());
+    let mut last_activity = Instant::now();
+
+    let report = loop {
+        let Some(index) = session.current_target() else {
+            break session.report().expect("all targets are done");
+        };
+
+        overlay.clear();
+        overlay.set_color(BLACK);
+        overlay.draw_text_centered(
+            &format!(
+                "Tap the center of the red target ({}/{})",
+                index + 1,
+                session.targets.len()
+            ),
+            overlay.area().height().value() / 3,
+        );
+        overlay.set_color(RED);
+        overlay.draw_target(session.targets[index], TARGET_RADIUS);
+        overlay.flush();
+
+        let Some(message) = pump.recv_timeout(REDRAW_INTERVAL)? else {
+            if last_activity.elapsed() > TEST_PATTERN_TIMEOUT {
+                return Err(EgalaxError::Timeout(TEST_PATTERN_TIMEOUT));
+            }
+            continue;
+        };
+        last_activity = message.time();
+        session.update(&message, calibration, area);
+    };
+
+    log::trace!("Leaving fn run_test_pattern");
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::Origin;
+    use crate::protocol::USBPacket;
+    use crate::rotation::Rotation;
+
+    #[test]
+    fn test_session_measures_targets() {
+        let area = AABB::from((0, 0, 1000, 1000));
+        let calibration = Calibration {
+            calibration_points: AABB::from((0, 0, 1000, 1000)),
+            origin: Origin::default(),
+            swap_axes: false,
+            rotation: Rotation::Normal,
+            affine: None,
+        };
+        let targets = grid_positions(area);
+        assert_eq!(GRID_SIZE * GRID_SIZE, targets.len());
+        assert_eq!(Point2D::from((500, 100)), targets[1]);
+
+        let start = Instant::now();
+        let mut session = TestPatternSession::new(targets[..2].to_vec(), start);
+        let ms = |ms| start + Duration::from_millis(ms);
+        for (time, position) in [(300, (100, 104)), (700, (500, 100))] {
+            for (offset, state) in [(0, TouchState::IsTouching), (50, TouchState::NotTouching)] {
+                let packet = USBPacket::new(state, position.into(), 12).unwrap();
+                session.update(&packet.with_time(ms(time + offset)), &calibration, area);
+            }
+        }
+
+        let report = session.report().unwrap();
+        assert_eq!(
+            vec![300, 350],
+            report
+                .targets
+                .iter()
+                .map(|t| t.latency_ms)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(4.0, report.max_error_px);
+        assert_eq!(2.0, report.mean_error_px);
+    }
+}
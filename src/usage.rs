@@ -0,0 +1,422 @@
+//! Counting touches in total and per zone of the monitor, e.g. to schedule maintenance by actual usage.
+//!
+//! The counts are kept per day and saved to a JSON file, so they survive restarts of the driver.
+//! `egalax-rs stats export` writes them as CSV for offline analysis, since nothing is sent over the network.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs, io};
+
+use anyhow::anyhow;
+
+use crate::config::write_atomically;
+use crate::error::EgalaxError;
+use crate::geo::{Point2D, AABB};
+use crate::units::MonitorSpace;
+
+/// Named areas of the monitor in pixels relative to its upper left corner whose touches are counted separately.
+pub type Zones = BTreeMap<String, AABB<MonitorSpace>>;
+
+/// Number of touches in total and per zone.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TouchCounts {
+    pub touches: u64,
+    /// Touches per zone. Every configured zone is listed, also if it was not touched.
+    pub zones: BTreeMap<String, u64>,
+    /// Packets that were skipped because they could not be decoded.
+    /// Left out while 0, so that the payload of the MQTT analytics stays the same.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub errors: u64,
+}
+
+impl TouchCounts {
+    pub fn new(zones: &Zones) -> Self {
+        Self {
+            touches: 0,
+            zones: zones.keys().map(|name| (name.clone(), 0)).collect(),
+            errors: 0,
+        }
+    }
+
+    /// Count a touch that started at this position.
+    pub fn record(&mut self, zones: &Zones, position: Point2D<MonitorSpace>) {
+        self.touches += 1;
+        for (name, zone) in zones {
+            if zone.contains(&position) {
+                *self.zones.entry(name.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    fn add(&mut self, other: &TouchCounts) {
+        self.touches += other.touches;
+        self.errors += other.errors;
+        for (name, count) in &other.zones {
+            *self.zones.entry(name.clone()).or_default() += count;
+        }
+    }
+}
+
+/// Where usage statistics are saved and which zones are counted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageStatistics {
+    /// JSON file with the counts per day.
+    pub path: PathBuf,
+    /// How often the counts are saved while touches come in. They are also saved when the driver stops.
+    pub save_interval: Duration,
+    #[serde(default)]
+    pub zones: Zones,
+}
+
+impl fmt::Display for UsageStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "saved to '{}' every {}s with {} zones",
+            self.path.display(),
+            self.save_interval.as_secs(),
+            self.zones.len()
+        ))
+    }
+}
+
+/// Touch counts per day in UTC, keyed by dates like `2024-03-17`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyCounts {
+    pub days: BTreeMap<String, TouchCounts>,
+}
+
+impl DailyCounts {
+    /// Load the counts from a file. A missing file means that nothing was counted yet.
+    pub fn load(path: &Path) -> Result<Self, EgalaxError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|e| EgalaxError::Generic(e.into()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the counts to a file. The file is replaced atomically so that a crash cannot leave it half written,
+    /// see [write_atomically].
+    pub fn save(&self, path: &Path) -> Result<(), EgalaxError> {
+        let contents =
+            serde_json::to_string_pretty(self).map_err(|e| EgalaxError::Generic(e.into()))?;
+        write_atomically(path, contents.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, now: SystemTime, zones: &Zones, position: Point2D<MonitorSpace>) {
+        self.days
+            .entry(utc_date(now))
+            .or_insert_with(|| TouchCounts::new(zones))
+            .record(zones, position);
+    }
+
+    /// Count packets that were skipped because they could not be decoded.
+    pub fn record_errors(&mut self, now: SystemTime, zones: &Zones, errors: u64) {
+        self.days
+            .entry(utc_date(now))
+            .or_insert_with(|| TouchCounts::new(zones))
+            .errors += errors;
+    }
+
+    /// The counts of a single day.
+    pub fn day(&self, now: SystemTime) -> TouchCounts {
+        self.days.get(&utc_date(now)).cloned().unwrap_or_default()
+    }
+
+    /// The counts of all days together.
+    pub fn total(&self) -> TouchCounts {
+        let mut total = TouchCounts::default();
+        for counts in self.days.values() {
+            total.add(counts);
+        }
+        total
+    }
+
+    /// Write the counts of the days from `since` on as CSV with a row per day and a column per zone.
+    /// Zones that were added later are counted as 0 on the days before.
+    pub fn write_csv<W: io::Write>(&self, since: Option<&str>, mut out: W) -> io::Result<()> {
+        let days: Vec<_> = self
+            .days
+            .iter()
+            .filter(|(date, _)| since.is_none_or(|since| date.as_str() >= since))
+            .collect();
+        let mut zones: Vec<&String> = days
+            .iter()
+            .flat_map(|(_, counts)| counts.zones.keys())
+            .collect();
+        zones.sort();
+        zones.dedup();
+
+        write!(out, "date,touches,errors")?;
+        for zone in &zones {
+            write!(out, ",{}", csv_field(&format!("zone:{}", zone)))?;
+        }
+        writeln!(out)?;
+        for (date, counts) in days {
+            write!(out, "{},{},{}", date, counts.touches, counts.errors)?;
+            for zone in &zones {
+                write!(
+                    out,
+                    ",{}",
+                    counts.zones.get(*zone).copied().unwrap_or_default()
+                )?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+/// Quote a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
+
+/// Check that a date is written like `2024-03-17`, so that it can be compared with the keys of [DailyCounts].
+pub fn parse_date(date: &str) -> Result<String, EgalaxError> {
+    let bytes = date.as_bytes();
+    let valid = bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        });
+    if valid {
+        Ok(String::from(date))
+    } else {
+        Err(EgalaxError::Generic(anyhow!(
+            "'{}' is not a date like 2024-03-17",
+            date
+        )))
+    }
+}
+
+/// Counts touches and saves them periodically. Unsaved counts are saved when the tracker is dropped.
+///
+/// Writing and syncing the file can take a while on slow storage, so the counts are saved by a background thread
+/// and the driver does not miss packets in the meantime.
+#[derive(Debug)]
+pub struct UsageTracker {
+    config: UsageStatistics,
+    counts: DailyCounts,
+    last_saved: Instant,
+    unsaved: bool,
+    /// Sends the counts to the thread that saves them. Both are only `None` while the tracker is dropped.
+    saver: Option<Sender<DailyCounts>>,
+    saver_handle: Option<JoinHandle<()>>,
+}
+
+impl UsageTracker {
+    /// Continue counting from the saved counts.
+    pub fn load(config: UsageStatistics) -> Result<Self, EgalaxError> {
+        let counts = DailyCounts::load(&config.path)?;
+        let (saver, receiver) = mpsc::channel();
+        let path = config.path.clone();
+        let saver_handle = thread::spawn(move || save_loop(&path, receiver));
+        Ok(Self {
+            counts,
+            config,
+            last_saved: Instant::now(),
+            unsaved: false,
+            saver: Some(saver),
+            saver_handle: Some(saver_handle),
+        })
+    }
+
+    /// Count a touch that started at this position and save the counts if the save interval passed.
+    pub fn record(&mut self, position: Point2D<MonitorSpace>) {
+        self.counts
+            .record(SystemTime::now(), &self.config.zones, position);
+        self.unsaved = true;
+        if self.last_saved.elapsed() >= self.config.save_interval {
+            self.save();
+        }
+    }
+
+    /// Count packets that were skipped because they could not be decoded. They are saved with the next touch
+    /// or when the tracker is dropped.
+    pub fn record_errors(&mut self, errors: u64) {
+        if errors == 0 {
+            return;
+        }
+        self.counts
+            .record_errors(SystemTime::now(), &self.config.zones, errors);
+        self.unsaved = true;
+    }
+
+    pub fn counts(&self) -> &DailyCounts {
+        &self.counts
+    }
+
+    fn save(&mut self) {
+        if let Some(saver) = &self.saver {
+            // If the thread is gone it already logged why.
+            saver.send(self.counts.clone()).ok();
+        }
+        self.last_saved = Instant::now();
+        self.unsaved = false;
+    }
+}
+
+impl Drop for UsageTracker {
+    /// Save the unsaved counts and wait until they are written, so that they survive a shutdown.
+    fn drop(&mut self) {
+        if self.unsaved {
+            self.save();
+        }
+        self.saver = None;
+        if let Some(handle) = self.saver_handle.take() {
+            if handle.join().is_err() {
+                log::error!("Usage statistics thread panicked.");
+            }
+        }
+    }
+}
+
+/// Loop of the thread that saves the counts until the tracker is dropped.
+fn save_loop(path: &Path, receiver: Receiver<DailyCounts>) {
+    log::trace!("Entering fn save_loop");
+
+    while let Ok(counts) = receiver.recv() {
+        // Only the latest counts matter if saving fell behind.
+        let counts = receiver.try_iter().last().unwrap_or(counts);
+        if let Err(e) = counts.save(path) {
+            log::warn!(
+                "Could not save usage statistics to '{}': {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    log::trace!("Leaving fn save_loop");
+}
+
+/// The date of a time in UTC, e.g. `2024-03-17`.
+pub fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Convert days since 1970-01-01 to a date in the proleptic Gregorian calendar.
+/// See Howard Hinnant's `civil_from_days` at <https://howardhinnant.github.io/date_algorithms.html>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_counts() {
+        let zones = Zones::from([
+            (String::from("banner"), AABB::from((0, 0, 1920, 200))),
+            (String::from("menu"), AABB::from((0, 200, 400, 1080))),
+        ]);
+        let day = |days: u64| UNIX_EPOCH + Duration::from_secs(days * 86400 + 3600);
+        assert_eq!("1970-01-01", utc_date(day(0)));
+        assert_eq!("2024-02-29", utc_date(day(19782)));
+
+        let mut counts = DailyCounts::default();
+        for (days, position) in [
+            (19782, (100, 100)),
+            (19782, (100, 500)),
+            (19783, (1000, 800)),
+        ] {
+            counts.record(day(days), &zones, position.into());
+        }
+        assert_eq!(2, counts.day(day(19782)).touches);
+        let total = counts.total();
+        assert_eq!(3, total.touches);
+        assert_eq!(Some(&0), counts.day(day(19783)).zones.get("banner"));
+        assert_eq!(
+            BTreeMap::from([(String::from("banner"), 1), (String::from("menu"), 1)]),
+            total.zones
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("egalax-rs-usage-{}.json", std::process::id()));
+        counts.save(&path).unwrap();
+        assert_eq!(counts, DailyCounts::load(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(DailyCounts::default(), DailyCounts::load(&path).unwrap());
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let zones = Zones::from([(String::from("menu, left"), AABB::from((0, 0, 400, 1080)))]);
+        let day = |days: u64| UNIX_EPOCH + Duration::from_secs(days * 86400);
+        let mut counts = DailyCounts::default();
+        counts.record(day(19782), &zones, (100, 100).into());
+        counts.record(day(19783), &zones, (1000, 100).into());
+        counts.record_errors(day(19783), &zones, 2);
+
+        let mut csv = Vec::new();
+        counts.write_csv(None, &mut csv).unwrap();
+        assert_eq!(
+            "date,touches,errors,\"zone:menu, left\"\n2024-02-29,1,0,1\n2024-03-01,1,2,0\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let since = parse_date("2024-03-01").unwrap();
+        let mut csv = Vec::new();
+        counts.write_csv(Some(&since), &mut csv).unwrap();
+        assert_eq!(
+            "date,touches,errors,\"zone:menu, left\"\n2024-03-01,1,2,0\n",
+            String::from_utf8(csv).unwrap()
+        );
+        assert!(parse_date("1.3.2024").is_err());
+    }
+
+    #[test]
+    fn test_tracker_saves_on_drop() {
+        let path = std::env::temp_dir().join(format!(
+            "egalax-rs-usage-tracker-{}.json",
+            std::process::id()
+        ));
+        let mut tracker = UsageTracker::load(UsageStatistics {
+            path: path.clone(),
+            save_interval: Duration::from_secs(3600),
+            zones: Zones::new(),
+        })
+        .unwrap();
+        tracker.record((100, 100).into());
+        tracker.record_errors(2);
+
+        // The save interval has not passed, so the counts are only written once the tracker is dropped.
+        let expected = tracker.counts().total();
+        drop(tracker);
+        assert_eq!(expected, DailyCounts::load(&path).unwrap().total());
+        fs::remove_file(&path).unwrap();
+    }
+}
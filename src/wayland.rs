@@ -0,0 +1,152 @@
+//! Monitor geometry from a Wayland compositor, for kiosks that run without an X server.
+//!
+//! Only available with the `wayland` feature. The outputs are read with the `xdg-output` protocol, which gives their
+//! position and size in the logical coordinates that compositors map absolute pointer devices to.
+
+use anyhow::anyhow;
+use std::{env, fmt};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_output::WlOutput, wl_registry::WlRegistry};
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1;
+use wayland_protocols::xdg::xdg_output::zv1::client::zxdg_output_v1::{self, ZxdgOutputV1};
+
+use crate::devices::MonitorInfo;
+use crate::error::EgalaxError;
+use crate::geo::AABB;
+use crate::units::udim;
+
+/// Checks if we run in a Wayland session, in which case X (if available at all) is only Xwayland
+/// and xrandr does not know the real layout of the outputs.
+pub fn is_wayland_session() -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_some()
+        || env::var("XDG_SESSION_TYPE").is_ok_and(|session| session == "wayland")
+}
+
+/// What we learned about an output so far.
+#[derive(Debug, Default)]
+struct Output {
+    name: Option<String>,
+    position: Option<(i32, i32)>,
+    size: Option<(i32, i32)>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    outputs: Vec<Output>,
+}
+
+/// List all outputs of the compositor. Wayland has no primary output, so the first one is reported as primary.
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, EgalaxError> {
+    log::trace!("Entering fn wayland::list_monitors");
+
+    let connection = Connection::connect_to_env().map_err(wayland_error)?;
+    let (globals, mut queue) = registry_queue_init::<State>(&connection).map_err(wayland_error)?;
+    let qh = queue.handle();
+    let manager: ZxdgOutputManagerV1 = globals.bind(&qh, 1..=3, ()).map_err(wayland_error)?;
+
+    let mut state = State::default();
+    let wl_outputs = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == WlOutput::interface().name)
+            .map(|global| {
+                globals.registry().bind::<WlOutput, _, _>(
+                    global.name,
+                    global.version.min(4),
+                    &qh,
+                    (),
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    for (index, wl_output) in wl_outputs.iter().enumerate() {
+        state.outputs.push(Output::default());
+        manager.get_xdg_output(wl_output, &qh, index);
+    }
+    queue.roundtrip(&mut state).map_err(wayland_error)?;
+
+    let monitors = state
+        .outputs
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, output)| {
+            let ((x, y), (width, height)) = (output.position?, output.size?);
+            Some(MonitorInfo {
+                name: output.name.unwrap_or_else(|| format!("output-{}", index)),
+                is_primary: index == 0,
+                area: AABB::new_wh(
+                    udim::new(x),
+                    udim::new(y),
+                    udim::new(width),
+                    udim::new(height),
+                ),
+            })
+        })
+        .collect();
+
+    log::trace!("Leaving fn wayland::list_monitors");
+    Ok(monitors)
+}
+
+fn wayland_error(e: impl fmt::Display) -> EgalaxError {
+    EgalaxError::Generic(anyhow!("Wayland: {}", e))
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: <WlRegistry as Proxy>::Event,
+        _data: &GlobalListContents,
+        _connection: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Outputs that appear later are not of interest, the config is built once.
+    }
+}
+
+impl Dispatch<WlOutput, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _output: &WlOutput,
+        _event: <WlOutput as Proxy>::Event,
+        _data: &(),
+        _connection: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The xdg-output of the output tells us everything we need.
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _manager: &ZxdgOutputManagerV1,
+        _event: <ZxdgOutputManagerV1 as Proxy>::Event,
+        _data: &(),
+        _connection: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, usize> for State {
+    fn event(
+        state: &mut Self,
+        _xdg_output: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        index: &usize,
+        _connection: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let output = &mut state.outputs[*index];
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => output.position = Some((x, y)),
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                output.size = Some((width, height))
+            }
+            zxdg_output_v1::Event::Name { name } => output.name = Some(name),
+            _ => {}
+        }
+    }
+}
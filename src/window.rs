@@ -0,0 +1,166 @@
+//! Watching the focused X11 window so that click bindings can be switched per application,
+//! e.g. to disable the right-click emulation inside a drawing application.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_uchar, c_ulong};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::{mem, ptr, thread};
+use x11::xlib;
+
+use crate::error::EgalaxError;
+use crate::x11::XConnection;
+
+/// Handle to a thread that reports the class of the focused window whenever the focus changes.
+/// The current class is reported once right after spawning.
+#[derive(Debug)]
+pub struct WindowWatch {
+    receiver: Receiver<Option<String>>,
+}
+
+impl WindowWatch {
+    /// Connect to the X server and start watching the focus.
+    /// This relies on the window manager to maintain `_NET_ACTIVE_WINDOW`, which all EWMH compliant ones do.
+    pub fn spawn() -> Result<Self, EgalaxError> {
+        let connection = FocusConnection::open()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || watch_window(connection, sender));
+        Ok(Self { receiver })
+    }
+
+    /// Returns the latest window class if the focus changed since the last call.
+    /// The class is `None` if no window is focused or it has no class.
+    pub fn changed(&self) -> Option<Option<String>> {
+        self.receiver.try_iter().last()
+    }
+}
+
+/// Connection to the X server that is notified when the focus changes.
+struct FocusConnection {
+    connection: XConnection,
+    net_active_window: xlib::Atom,
+}
+
+/// The focused window can be destroyed before we query its class, which is a harmless error.
+/// Without a handler Xlib would terminate the process.
+unsafe extern "C" fn ignore_x_error(
+    _display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    log::debug!("Ignoring X error {}", (*event).error_code);
+    0
+}
+
+impl FocusConnection {
+    fn open() -> Result<Self, EgalaxError> {
+        let connection = XConnection::open()?;
+        let atom_name = CString::new("_NET_ACTIVE_WINDOW").expect("no nul bytes");
+        // SAFETY: display and root window are valid for the lifetime of the connection.
+        unsafe {
+            xlib::XSetErrorHandler(Some(ignore_x_error));
+            let net_active_window =
+                xlib::XInternAtom(connection.display(), atom_name.as_ptr(), xlib::False);
+            xlib::XSelectInput(
+                connection.display(),
+                connection.root(),
+                xlib::PropertyChangeMask,
+            );
+
+            Ok(Self {
+                connection,
+                net_active_window,
+            })
+        }
+    }
+
+    /// Block until the focused window changes.
+    fn wait_for_change(&self) {
+        loop {
+            // SAFETY: the event is fully written by XNextEvent before it is read.
+            unsafe {
+                let mut event: xlib::XEvent = mem::zeroed();
+                xlib::XNextEvent(self.connection.display(), &mut event);
+                if event.get_type() == xlib::PropertyNotify
+                    && event.property.atom == self.net_active_window
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Query the class of the focused window.
+    fn active_class(&self) -> Option<String> {
+        // SAFETY: all resources are obtained from Xlib, checked for null and freed exactly once.
+        unsafe {
+            let mut actual_type = 0;
+            let mut actual_format = 0;
+            let mut items = 0;
+            let mut bytes_after = 0;
+            let mut data: *mut c_uchar = ptr::null_mut();
+            let status = xlib::XGetWindowProperty(
+                self.connection.display(),
+                self.connection.root(),
+                self.net_active_window,
+                0,
+                1,
+                xlib::False,
+                xlib::XA_WINDOW,
+                &mut actual_type,
+                &mut actual_format,
+                &mut items,
+                &mut bytes_after,
+                &mut data,
+            );
+            if status != xlib::Success as c_int || data.is_null() {
+                return None;
+            }
+            // Format 32 properties are returned as longs.
+            let window = if items == 1 {
+                *(data as *const c_ulong)
+            } else {
+                0
+            };
+            xlib::XFree(data.cast());
+            if window == 0 {
+                return None;
+            }
+
+            let mut hint: xlib::XClassHint = mem::zeroed();
+            if xlib::XGetClassHint(self.connection.display(), window, &mut hint) == 0 {
+                return None;
+            }
+            let class = (!hint.res_class.is_null()).then(|| {
+                CStr::from_ptr(hint.res_class)
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            if !hint.res_name.is_null() {
+                xlib::XFree(hint.res_name.cast());
+            }
+            if !hint.res_class.is_null() {
+                xlib::XFree(hint.res_class.cast());
+            }
+            class
+        }
+    }
+}
+
+/// Report the class of the focused window whenever it changes, until the receiver is dropped.
+fn watch_window(connection: FocusConnection, sender: Sender<Option<String>>) {
+    log::trace!("Entering fn watch_window");
+
+    let mut last = None;
+    loop {
+        let class = connection.active_class();
+        if last.as_ref() != Some(&class) {
+            log::debug!("Focused window has class {:?}.", class);
+            last = Some(class.clone());
+            if sender.send(class).is_err() {
+                break;
+            }
+        }
+        connection.wait_for_change();
+    }
+
+    log::trace!("Leaving fn watch_window");
+}
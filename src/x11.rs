@@ -0,0 +1,48 @@
+//! The connection to the X server that the threads watching or drawing on the screen each open for themselves.
+
+use std::ptr;
+use x11::xlib;
+
+use crate::error::EgalaxError;
+
+/// Owned connection to the X server so that it can be moved into a thread.
+/// The display is closed when the connection is dropped.
+pub(crate) struct XConnection {
+    display: *mut xlib::Display,
+    root: xlib::Window,
+}
+
+// SAFETY: the connection is only ever used by the thread that owns it.
+unsafe impl Send for XConnection {}
+
+impl XConnection {
+    pub(crate) fn open() -> Result<Self, EgalaxError> {
+        // SAFETY: the display pointer is checked for null before use.
+        unsafe {
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return Err(EgalaxError::XDisplay);
+            }
+            let root = xlib::XDefaultRootWindow(display);
+            Ok(Self { display, root })
+        }
+    }
+
+    /// The display, which is valid for the lifetime of the connection.
+    pub(crate) fn display(&self) -> *mut xlib::Display {
+        self.display
+    }
+
+    pub(crate) fn root(&self) -> xlib::Window {
+        self.root
+    }
+}
+
+impl Drop for XConnection {
+    fn drop(&mut self) {
+        // SAFETY: the display was opened by us and is not used afterwards.
+        unsafe {
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}